@@ -26,6 +26,8 @@ export type MOTLYRefSegment = string | number;
 export interface MOTLYRef {
   linkTo: MOTLYRefSegment[];
   linkUps: number;
+  /** Source location of the statement that made the reference (Rust engine only). */
+  location?: MOTLYLocation;
 }
 
 /** An environment variable reference (e.g. `@env.API_KEY`). */
@@ -70,6 +72,12 @@ export interface MOTLYError {
   begin: { line: number; column: number; offset: number };
   /** End of the offending region (0-based, exclusive). */
   end: { line: number; column: number; offset: number };
+  /** Which parse the positions refer to, when known (Rust engine only). */
+  parseId?: number;
+  /** Name of the source that parse read, when the session knows it (Rust engine only). */
+  source?: string;
+  /** `source:line:col` of `begin`, 1-based, for display (Rust engine only). */
+  at?: string;
 }
 
 /** An error from schema validation. */
@@ -82,6 +90,10 @@ export interface MOTLYSchemaError {
   path: string[];
   /** Source location of the offending node (if available). */
   location?: MOTLYLocation;
+  /** Name of the source `location` is in, when the session knows it (Rust engine only). */
+  source?: string;
+  /** `source:line:col` of `location`, 1-based, for display (Rust engine only). */
+  at?: string;
 }
 
 /** Format a MOTLYRef for display (e.g. `$^^.parent.name`). */
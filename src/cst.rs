@@ -0,0 +1,669 @@
+/// Lossless concrete syntax tree.
+///
+/// Unlike `parser::parse`, which produces only the semantic `Statement` list,
+/// the CST keeps every byte of the input: whitespace, comments, commas and the
+/// exact spelling of every value. Printing the tree reproduces the input, and
+/// `SyntaxTree::to_statements` converts it to the same `Vec<Statement>` the
+/// parser produces.
+use crate::ast::*;
+use crate::error::{MOTLYError, Position};
+use crate::lexer::{tokenize, Token, TokenKind};
+use crate::parser;
+
+/// The kind of an interior CST node.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NodeKind {
+    /// The whole input: a statement list.
+    Document,
+    /// `name = value [{ ... }]`
+    SetEq,
+    /// `name := value [{ ... }]`
+    AssignBoth,
+    /// `name: { ... }`
+    ReplaceProperties,
+    /// `name { ... }`
+    UpdateProperties,
+    /// `name`
+    Define,
+    /// `-name`
+    Delete,
+    /// `-...`
+    ClearAll,
+    /// A dotted property path: `a.b.c`
+    PropName,
+    /// A single scalar or reference value token.
+    Value,
+    /// `[ ... ]`
+    Array,
+    /// One element of an array, with optional properties.
+    ArrayElement,
+    /// `{ ... }` — a nested statement list.
+    Properties,
+    /// Source the parser could not make sense of, kept verbatim.
+    Error,
+}
+
+impl NodeKind {
+    /// True for the node kinds that correspond to a `Statement`.
+    pub fn is_statement(self) -> bool {
+        matches!(
+            self,
+            NodeKind::SetEq
+                | NodeKind::AssignBoth
+                | NodeKind::ReplaceProperties
+                | NodeKind::UpdateProperties
+                | NodeKind::Define
+                | NodeKind::Delete
+                | NodeKind::ClearAll
+        )
+    }
+}
+
+/// A leaf of the CST: one lexical token and its exact text.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SyntaxToken {
+    pub kind: TokenKind,
+    pub text: String,
+    pub span: Span,
+}
+
+/// A child of a CST node.
+#[derive(Debug, Clone, PartialEq)]
+pub enum SyntaxElement {
+    Node(SyntaxNode),
+    Token(SyntaxToken),
+}
+
+/// An interior CST node.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SyntaxNode {
+    pub kind: NodeKind,
+    pub children: Vec<SyntaxElement>,
+}
+
+/// The result of a lossless parse: the tree plus any syntax errors.
+/// A tree is produced even for invalid input; unparseable regions are
+/// kept in `NodeKind::Error` nodes.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SyntaxTree {
+    pub root: SyntaxNode,
+    pub errors: Vec<MOTLYError>,
+}
+
+impl SyntaxNode {
+    fn new(kind: NodeKind) -> Self {
+        SyntaxNode { kind, children: Vec::new() }
+    }
+
+    /// The exact source text covered by this node.
+    pub fn text(&self) -> String {
+        let mut buf = String::new();
+        self.write_text(&mut buf);
+        buf
+    }
+
+    fn write_text(&self, buf: &mut String) {
+        for child in &self.children {
+            match child {
+                SyntaxElement::Node(n) => n.write_text(buf),
+                SyntaxElement::Token(t) => buf.push_str(&t.text),
+            }
+        }
+    }
+
+    /// Source span from the first to the last token. None for an empty node.
+    pub fn span(&self) -> Option<Span> {
+        Some(Span {
+            begin: self.first_token()?.span.begin,
+            end: self.last_token()?.span.end,
+        })
+    }
+
+    pub fn first_token(&self) -> Option<&SyntaxToken> {
+        self.children.iter().find_map(|c| match c {
+            SyntaxElement::Node(n) => n.first_token(),
+            SyntaxElement::Token(t) => Some(t),
+        })
+    }
+
+    pub fn last_token(&self) -> Option<&SyntaxToken> {
+        self.children.iter().rev().find_map(|c| match c {
+            SyntaxElement::Node(n) => n.last_token(),
+            SyntaxElement::Token(t) => Some(t),
+        })
+    }
+
+    /// Every token under this node, in source order.
+    pub fn tokens(&self) -> Vec<&SyntaxToken> {
+        let mut out = Vec::new();
+        self.collect_tokens(&mut out);
+        out
+    }
+
+    fn collect_tokens<'a>(&'a self, out: &mut Vec<&'a SyntaxToken>) {
+        for child in &self.children {
+            match child {
+                SyntaxElement::Node(n) => n.collect_tokens(out),
+                SyntaxElement::Token(t) => out.push(t),
+            }
+        }
+    }
+
+    /// Direct child nodes (skipping tokens).
+    pub fn child_nodes(&self) -> impl Iterator<Item = &SyntaxNode> {
+        self.children.iter().filter_map(|c| match c {
+            SyntaxElement::Node(n) => Some(n),
+            SyntaxElement::Token(_) => None,
+        })
+    }
+
+    /// The property path of a statement node, decoded (backticks and
+    /// escapes resolved). Empty for `-...` and non-statement nodes.
+    pub fn path(&self) -> Vec<String> {
+        let Some(name) = self.child(NodeKind::PropName) else {
+            return Vec::new();
+        };
+        name.tokens()
+            .into_iter()
+            .filter(|t| t.kind != TokenKind::Dot)
+            .filter_map(|t| parser::parse_identifier_at(&t.text, 0).ok().map(|(name, _)| name))
+            .collect()
+    }
+
+    /// The first direct child node of the given kind.
+    pub fn child(&self, kind: NodeKind) -> Option<&SyntaxNode> {
+        self.child_nodes().find(|n| n.kind == kind)
+    }
+}
+
+impl SyntaxTree {
+    /// Print the tree back to source. Always equal to the parsed input.
+    pub fn text(&self) -> String {
+        self.root.text()
+    }
+
+    /// Convert to the parser's statement list. Returns the first syntax
+    /// error if the tree contains any; see `recovered_statements` for a
+    /// best-effort conversion of invalid input.
+    pub fn to_statements(&self) -> Result<Vec<Statement>, MOTLYError> {
+        if let Some(err) = self.errors.first() {
+            return Err(err.clone());
+        }
+        let source = self.text();
+        convert_list(&source, &self.root.children)
+    }
+
+    /// The statements that parsed, skipping the regions in `Error` nodes.
+    /// For a tree without errors this equals `to_statements`.
+    pub fn recovered_statements(&self) -> Vec<Statement> {
+        let source = self.text();
+        convert_list(&source, &self.root.children)
+            .expect("statement nodes are only built from input the parser accepted")
+    }
+}
+
+/// Parse MOTLY source into a lossless syntax tree.
+pub fn parse(input: &str) -> SyntaxTree {
+    let tokens = tokenize(input);
+    let end = tokens.last().map(|t| t.span.end).unwrap_or(Position::ZERO);
+    let mut p = CstParser {
+        input,
+        tokens,
+        pos: 0,
+        end,
+        errors: Vec::new(),
+    };
+    let mut root = SyntaxNode::new(NodeKind::Document);
+    p.parse_statement_list(&mut root.children, false);
+    SyntaxTree { root, errors: p.errors }
+}
+
+// ── CST parser ──────────────────────────────────────────────────────
+//
+// Mirrors `parser::Parser` statement by statement, but works on the token
+// stream and keeps trivia. Trivia the parser skips inside a construct is
+// kept inside that construct's node; trivia between constructs belongs to
+// the enclosing list. Individual values are decoded with the parser's own
+// routines so errors and edge cases match exactly.
+
+struct CstParser<'a> {
+    input: &'a str,
+    tokens: Vec<Token>,
+    pos: usize,
+    end: Position,
+    errors: Vec<MOTLYError>,
+}
+
+impl<'a> CstParser<'a> {
+    fn peek(&self) -> Option<TokenKind> {
+        self.tokens.get(self.pos).map(|t| t.kind)
+    }
+
+    /// Kind of the next non-trivia token, without consuming anything.
+    fn peek_past_trivia(&self) -> Option<TokenKind> {
+        self.tokens[self.pos..]
+            .iter()
+            .find(|t| !t.kind.is_trivia())
+            .map(|t| t.kind)
+    }
+
+    fn position(&self) -> Position {
+        self.tokens.get(self.pos).map(|t| t.span.begin).unwrap_or(self.end)
+    }
+
+    fn make_token(&self, tok: &Token) -> SyntaxToken {
+        SyntaxToken {
+            kind: tok.kind,
+            text: tok.text(self.input).to_string(),
+            span: tok.span,
+        }
+    }
+
+    fn bump(&mut self, out: &mut Vec<SyntaxElement>) {
+        let tok = self.make_token(&self.tokens[self.pos]);
+        out.push(SyntaxElement::Token(tok));
+        self.pos += 1;
+    }
+
+    /// Equivalent of `Parser::skip_ws`.
+    fn trivia(&mut self, out: &mut Vec<SyntaxElement>) {
+        while self.peek().is_some_and(TokenKind::is_trivia) {
+            self.bump(out);
+        }
+    }
+
+    /// Equivalent of `Parser::skip_ws_and_commas`.
+    fn trivia_and_commas(&mut self, out: &mut Vec<SyntaxElement>) {
+        loop {
+            self.trivia(out);
+            if self.peek() == Some(TokenKind::Comma) {
+                self.bump(out);
+            } else {
+                break;
+            }
+        }
+    }
+
+    fn error_point(&self, message: &str) -> MOTLYError {
+        let pos = self.position();
+        MOTLYError::syntax_error(message.to_string(), pos, pos)
+    }
+
+    fn error_span(&self, message: &str, begin: Position) -> MOTLYError {
+        MOTLYError::syntax_error(message.to_string(), begin, self.position())
+    }
+
+    /// Consume tokens up to byte offset `end` (as reported by the parser).
+    fn bump_until(&mut self, end: usize, out: &mut Vec<SyntaxElement>) {
+        while self.tokens.get(self.pos).is_some_and(|t| t.span.begin.offset < end) {
+            self.bump(out);
+        }
+    }
+
+    // ── Statement lists ─────────────────────────────────────────────
+
+    fn parse_statement_list(&mut self, out: &mut Vec<SyntaxElement>, in_block: bool) {
+        loop {
+            self.trivia_and_commas(out);
+            match self.peek() {
+                None => return,
+                Some(TokenKind::RBrace) if in_block => return,
+                _ => {}
+            }
+            let start = self.pos;
+            match self.parse_statement() {
+                Ok(node) => out.push(SyntaxElement::Node(node)),
+                Err(err) => {
+                    self.errors.push(err);
+                    let node = self.recover(start, in_block);
+                    out.push(SyntaxElement::Node(node));
+                }
+            }
+        }
+    }
+
+    /// Wrap the tokens of a failed statement in an Error node and skip to
+    /// the next statement boundary. Everything up to the error point belongs
+    /// to the failed statement. If the error point is the start of a new
+    /// line that looks like a statement, parsing resumes there; otherwise it
+    /// resumes after the next line break or comma outside brackets opened
+    /// since the error, or before a `}` that closes the enclosing block.
+    fn recover(&mut self, start: usize, in_block: bool) -> SyntaxNode {
+        let error_at = self.pos.min(self.tokens.len());
+        let mut i = error_at.max(start + 1).min(self.tokens.len());
+        let on_new_line = error_at > start
+            && self.tokens[error_at - 1].kind == TokenKind::Whitespace
+            && self.tokens[error_at - 1].text(self.input).contains('\n');
+        let statement_like = matches!(
+            self.peek(),
+            Some(TokenKind::Bare | TokenKind::Backtick | TokenKind::Minus | TokenKind::ClearAll)
+        );
+        if on_new_line && statement_like {
+            i = error_at;
+        } else {
+            let mut open: Vec<TokenKind> = Vec::new();
+            while i < self.tokens.len() {
+                let tok = &self.tokens[i];
+                match tok.kind {
+                    TokenKind::LBrace | TokenKind::LBracket => open.push(tok.kind),
+                    TokenKind::RBrace | TokenKind::RBracket => {
+                        let opener = if tok.kind == TokenKind::RBrace {
+                            TokenKind::LBrace
+                        } else {
+                            TokenKind::LBracket
+                        };
+                        if let Some(at) = open.iter().rposition(|k| *k == opener) {
+                            open.truncate(at);
+                        } else if tok.kind == TokenKind::RBrace && in_block {
+                            break;
+                        }
+                    }
+                    TokenKind::Whitespace if open.is_empty() && tok.text(self.input).contains('\n') => break,
+                    TokenKind::Comma if open.is_empty() => break,
+                    _ => {}
+                }
+                i += 1;
+            }
+        }
+        self.pos = start;
+        let mut node = SyntaxNode::new(NodeKind::Error);
+        while self.pos < i {
+            self.bump(&mut node.children);
+        }
+        node
+    }
+
+    // ── Statements ──────────────────────────────────────────────────
+
+    fn parse_statement(&mut self) -> Result<SyntaxNode, MOTLYError> {
+        match self.peek() {
+            Some(TokenKind::ClearAll) => {
+                let mut node = SyntaxNode::new(NodeKind::ClearAll);
+                self.bump(&mut node.children);
+                return Ok(node);
+            }
+            Some(TokenKind::Minus) => {
+                let mut node = SyntaxNode::new(NodeKind::Delete);
+                self.bump(&mut node.children);
+                let path = self.parse_prop_name()?;
+                node.children.push(SyntaxElement::Node(path));
+                return Ok(node);
+            }
+            _ => {}
+        }
+
+        let path = self.parse_prop_name()?;
+        let kind = match self.peek_past_trivia() {
+            Some(TokenKind::ColonEquals) => NodeKind::AssignBoth,
+            Some(TokenKind::Equals) => NodeKind::SetEq,
+            Some(TokenKind::Colon) => NodeKind::ReplaceProperties,
+            Some(TokenKind::LBrace) => NodeKind::UpdateProperties,
+            // Trailing trivia stays with the enclosing list
+            _ => {
+                let mut node = SyntaxNode::new(NodeKind::Define);
+                node.children.push(SyntaxElement::Node(path));
+                return Ok(node);
+            }
+        };
+
+        let mut node = SyntaxNode::new(kind);
+        node.children.push(SyntaxElement::Node(path));
+        self.trivia(&mut node.children);
+
+        match kind {
+            NodeKind::AssignBoth | NodeKind::SetEq => {
+                let op_begin = self.position();
+                self.bump(&mut node.children);
+                self.trivia(&mut node.children);
+                if kind == NodeKind::SetEq && self.peek() == Some(TokenKind::LBrace) {
+                    return Err(self.error_span(
+                        "Expected a value after '='; use ':' for property-only replacement",
+                        op_begin,
+                    ));
+                }
+                let value = self.parse_value(true)?;
+                node.children.push(SyntaxElement::Node(value));
+                self.optional_properties(&mut node)?;
+            }
+            NodeKind::ReplaceProperties => {
+                self.bump(&mut node.children);
+                self.trivia(&mut node.children);
+                let props = self.parse_properties()?;
+                node.children.push(SyntaxElement::Node(props));
+            }
+            _ => {
+                let props = self.parse_properties()?;
+                node.children.push(SyntaxElement::Node(props));
+            }
+        }
+        Ok(node)
+    }
+
+    /// `{ ... }` after a value, possibly separated by trivia.
+    fn optional_properties(&mut self, node: &mut SyntaxNode) -> Result<(), MOTLYError> {
+        if self.peek_past_trivia() == Some(TokenKind::LBrace) {
+            self.trivia(&mut node.children);
+            let props = self.parse_properties()?;
+            node.children.push(SyntaxElement::Node(props));
+        }
+        Ok(())
+    }
+
+    fn parse_prop_name(&mut self) -> Result<SyntaxNode, MOTLYError> {
+        let mut node = SyntaxNode::new(NodeKind::PropName);
+        self.parse_identifier(&mut node)?;
+        while self.peek() == Some(TokenKind::Dot) {
+            self.bump(&mut node.children);
+            self.parse_identifier(&mut node)?;
+        }
+        Ok(node)
+    }
+
+    fn parse_identifier(&mut self, node: &mut SyntaxNode) -> Result<(), MOTLYError> {
+        let offset = self.position().offset;
+        let (_, end) = parser::parse_identifier_at(self.input, offset)?;
+        self.bump_until(end, &mut node.children);
+        Ok(())
+    }
+
+    // ── Values ──────────────────────────────────────────────────────
+
+    fn parse_value(&mut self, allow_arrays: bool) -> Result<SyntaxNode, MOTLYError> {
+        if allow_arrays && self.peek() == Some(TokenKind::LBracket) {
+            return self.parse_array();
+        }
+        let mut node = SyntaxNode::new(NodeKind::Value);
+        let offset = self.position().offset;
+        let (_, end) = parser::parse_value_at(self.input, offset)?;
+        self.bump_until(end, &mut node.children);
+        Ok(node)
+    }
+
+    fn parse_array(&mut self) -> Result<SyntaxNode, MOTLYError> {
+        let mut node = SyntaxNode::new(NodeKind::Array);
+        let begin = self.position();
+        self.bump(&mut node.children); // [
+        self.trivia(&mut node.children);
+
+        if self.peek() == Some(TokenKind::RBracket) {
+            self.bump(&mut node.children);
+            return Ok(node);
+        }
+
+        let first = self.parse_array_element()?;
+        node.children.push(SyntaxElement::Node(first));
+
+        loop {
+            self.trivia(&mut node.children);
+            match self.peek() {
+                Some(TokenKind::RBracket) => {
+                    self.bump(&mut node.children);
+                    return Ok(node);
+                }
+                Some(TokenKind::Comma) => {
+                    self.bump(&mut node.children);
+                    self.trivia(&mut node.children);
+                    if self.peek() == Some(TokenKind::RBracket) {
+                        self.bump(&mut node.children);
+                        return Ok(node);
+                    }
+                    let el = self.parse_array_element()?;
+                    node.children.push(SyntaxElement::Node(el));
+                }
+                None => return Err(self.error_span("Unclosed '['", begin)),
+                Some(_) => return Err(self.error_point("Expected ',' or ']' in array")),
+            }
+        }
+    }
+
+    fn parse_array_element(&mut self) -> Result<SyntaxNode, MOTLYError> {
+        let mut node = SyntaxNode::new(NodeKind::ArrayElement);
+        match self.peek() {
+            Some(TokenKind::LBrace) => {
+                let props = self.parse_properties()?;
+                node.children.push(SyntaxElement::Node(props));
+                return Ok(node);
+            }
+            Some(TokenKind::LBracket) => {
+                let arr = self.parse_array()?;
+                node.children.push(SyntaxElement::Node(arr));
+            }
+            _ => {
+                let value = self.parse_value(false)?;
+                node.children.push(SyntaxElement::Node(value));
+            }
+        }
+        self.optional_properties(&mut node)?;
+        Ok(node)
+    }
+
+    fn parse_properties(&mut self) -> Result<SyntaxNode, MOTLYError> {
+        let mut node = SyntaxNode::new(NodeKind::Properties);
+        let begin = self.position();
+        if self.peek() != Some(TokenKind::LBrace) {
+            return Err(self.error_point("Expected '{'"));
+        }
+        self.bump(&mut node.children);
+
+        loop {
+            self.trivia_and_commas(&mut node.children);
+            match self.peek() {
+                Some(TokenKind::RBrace) => {
+                    self.bump(&mut node.children);
+                    return Ok(node);
+                }
+                None => return Err(self.error_span("Unclosed '{'", begin)),
+                Some(_) => {
+                    let start = self.pos;
+                    match self.parse_statement() {
+                        Ok(stmt) => node.children.push(SyntaxElement::Node(stmt)),
+                        Err(err) => {
+                            self.errors.push(err);
+                            let bad = self.recover(start, true);
+                            node.children.push(SyntaxElement::Node(bad));
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
+
+// ── Conversion to statements ────────────────────────────────────────
+
+fn convert_list(source: &str, children: &[SyntaxElement]) -> Result<Vec<Statement>, MOTLYError> {
+    let mut stmts = Vec::new();
+    for child in children {
+        if let SyntaxElement::Node(node) = child {
+            if node.kind.is_statement() {
+                stmts.push(convert_statement(source, node)?);
+            }
+        }
+    }
+    Ok(stmts)
+}
+
+fn convert_statement(source: &str, node: &SyntaxNode) -> Result<Statement, MOTLYError> {
+    let span = node.span().unwrap();
+
+    if node.kind == NodeKind::ClearAll {
+        return Ok(Statement::ClearAll { span });
+    }
+
+    let path = convert_path(source, node.child(NodeKind::PropName).unwrap())?;
+    let properties = match node.child(NodeKind::Properties) {
+        Some(props) => Some(convert_properties(source, props)?),
+        None => None,
+    };
+
+    Ok(match node.kind {
+        NodeKind::SetEq | NodeKind::AssignBoth => {
+            let value = convert_value(source, value_node(node).unwrap())?;
+            if node.kind == NodeKind::SetEq {
+                Statement::SetEq { path, value, properties, span }
+            } else {
+                Statement::AssignBoth { path, value, properties, span }
+            }
+        }
+        NodeKind::ReplaceProperties => Statement::ReplaceProperties {
+            path,
+            properties: properties.unwrap(),
+            span,
+        },
+        NodeKind::UpdateProperties => Statement::UpdateProperties {
+            path,
+            properties: properties.unwrap(),
+            span,
+        },
+        NodeKind::Define => Statement::Define { path, deleted: false, span },
+        NodeKind::Delete => Statement::Define { path, deleted: true, span },
+        _ => unreachable!("not a statement node"),
+    })
+}
+
+/// The Value or Array child of a statement or array element.
+pub(crate) fn value_node(node: &SyntaxNode) -> Option<&SyntaxNode> {
+    node.child_nodes()
+        .find(|n| matches!(n.kind, NodeKind::Value | NodeKind::Array))
+}
+
+fn convert_properties(source: &str, node: &SyntaxNode) -> Result<Vec<Statement>, MOTLYError> {
+    convert_list(source, &node.children)
+}
+
+fn convert_path(source: &str, node: &SyntaxNode) -> Result<Vec<String>, MOTLYError> {
+    node.tokens()
+        .into_iter()
+        .filter(|t| t.kind != TokenKind::Dot)
+        .map(|t| parser::parse_identifier_at(source, t.span.begin.offset).map(|(name, _)| name))
+        .collect()
+}
+
+fn convert_value(source: &str, node: &SyntaxNode) -> Result<TagValue, MOTLYError> {
+    if node.kind == NodeKind::Array {
+        return convert_array(source, node).map(TagValue::Array);
+    }
+    let offset = node.span().unwrap().begin.offset;
+    parser::parse_value_at(source, offset).map(|(value, _)| value)
+}
+
+fn convert_array(source: &str, node: &SyntaxNode) -> Result<Vec<ArrayElement>, MOTLYError> {
+    let mut elements = Vec::new();
+    for child in &node.children {
+        let SyntaxElement::Node(el) = child else { continue };
+        let value = match value_node(el) {
+            Some(v) => Some(convert_value(source, v)?),
+            None => None,
+        };
+        let properties = match el.child(NodeKind::Properties) {
+            Some(props) => Some(convert_properties(source, props)?),
+            None => None,
+        };
+        elements.push(ArrayElement {
+            value,
+            properties,
+            span: el.span().unwrap(),
+        });
+    }
+    Ok(elements)
+}
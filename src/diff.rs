@@ -0,0 +1,319 @@
+/// Structural diff between two interpreted trees.
+///
+/// Textual diffs of configuration are noisy: reordered properties,
+/// reformatted blocks and values moved into clones all show up as changes.
+/// `diff` compares the trees instead and reports what a reader of the
+/// value would notice, with the locations of both sides.
+///
+/// Deleted nodes count as absent. Array elements are matched by content,
+/// so an element that moved is reported once as moved; elements that were
+/// replaced in place are compared as nodes.
+use crate::tree::{format_ref_display, EqValue, MOTLYDataNode, MOTLYLocation, MOTLYNode, Scalar};
+
+/// What kind of change a `Change` is.
+#[derive(Debug, Clone, PartialEq)]
+pub enum ChangeKind {
+    /// The node exists only in the new tree.
+    Added,
+    /// The node exists only in the old tree.
+    Removed,
+    /// Same type of value, different value.
+    ValueChanged,
+    /// The value has a different type (see `type_name`).
+    TypeChanged,
+    /// A reference points somewhere else.
+    LinkRetargeted,
+    /// An array element exists only in the new array.
+    ElementInserted,
+    /// An array element exists only in the old array.
+    ElementRemoved,
+    /// An equal element sits at a different index.
+    ElementMoved { from: usize, to: usize },
+}
+
+impl ChangeKind {
+    /// The kebab-case name used in JSON output, e.g. `value-changed`.
+    pub fn name(&self) -> &'static str {
+        match self {
+            ChangeKind::Added => "added",
+            ChangeKind::Removed => "removed",
+            ChangeKind::ValueChanged => "value-changed",
+            ChangeKind::TypeChanged => "type-changed",
+            ChangeKind::LinkRetargeted => "link-retargeted",
+            ChangeKind::ElementInserted => "element-inserted",
+            ChangeKind::ElementRemoved => "element-removed",
+            ChangeKind::ElementMoved { .. } => "element-moved",
+        }
+    }
+}
+
+/// One difference between two trees.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Change {
+    pub kind: ChangeKind,
+    /// Path of the changed node. Array elements are `[i]` segments, indexed
+    /// in the tree the element is in (the new tree for moves).
+    pub path: Vec<String>,
+    /// The node in the old tree. For value, type and link changes only the
+    /// node's own value is kept, without its properties.
+    pub before: Option<MOTLYNode>,
+    /// The node in the new tree, shaped like `before`.
+    pub after: Option<MOTLYNode>,
+}
+
+impl Change {
+    /// Where the node was defined in the old tree.
+    pub fn before_location(&self) -> Option<&MOTLYLocation> {
+        self.before.as_ref()?.location()
+    }
+
+    /// Where the node was defined in the new tree.
+    pub fn after_location(&self) -> Option<&MOTLYLocation> {
+        self.after.as_ref()?.location()
+    }
+}
+
+/// The changes that turn `a` into `b`, depth first in key order.
+pub fn diff(a: &MOTLYDataNode, b: &MOTLYDataNode) -> Vec<Change> {
+    let mut changes = Vec::new();
+    let mut path = Vec::new();
+    diff_data(a, b, &mut path, &mut changes);
+    changes
+}
+
+/// The type of a node's value slot: `link`, `none` (no value), `string`,
+/// `number`, `boolean`, `date`, `env` or `array`.
+pub fn type_name(node: &MOTLYNode) -> &'static str {
+    match node {
+        MOTLYNode::Ref { .. } => "link",
+        MOTLYNode::Data(data) => match &data.eq {
+            None => "none",
+            Some(EqValue::Scalar(Scalar::String(_))) => "string",
+            Some(EqValue::Scalar(Scalar::Number(_))) => "number",
+            Some(EqValue::Scalar(Scalar::Boolean(_))) => "boolean",
+            Some(EqValue::Scalar(Scalar::Date(_))) => "date",
+            Some(EqValue::EnvRef(_)) => "env",
+            Some(EqValue::Array(_)) => "array",
+        },
+    }
+}
+
+/// A short, one-line rendering of a node for human-readable output, e.g.
+/// `8080`, `"text"`, `$^.defaults`, `[3 items]` or `{ ... }`.
+pub fn summary(node: &MOTLYNode) -> String {
+    let data = match node {
+        MOTLYNode::Ref { link_to, link_ups, .. } => return format_ref_display(*link_ups, link_to),
+        MOTLYNode::Data(data) => data,
+    };
+    let value = match &data.eq {
+        None => None,
+        Some(EqValue::Scalar(Scalar::String(s))) => Some(format!("{:?}", s)),
+        Some(EqValue::Scalar(Scalar::Number(n))) => Some(format!("{}", n)),
+        Some(EqValue::Scalar(Scalar::Boolean(b))) => Some(format!("@{}", b)),
+        Some(EqValue::Scalar(Scalar::Date(d))) => Some(format!("@{}", d)),
+        Some(EqValue::EnvRef(name)) => Some(format!("@env.{}", name)),
+        Some(EqValue::Array(items)) if items.len() == 1 => Some("[1 item]".to_string()),
+        Some(EqValue::Array(items)) => Some(format!("[{} items]", items.len())),
+    };
+    let has_properties = data.properties.as_ref().is_some_and(|p| !p.is_empty());
+    match (value, has_properties) {
+        (Some(value), true) => format!("{} {{ ... }}", value),
+        (Some(value), false) => value,
+        (None, true) => "{ ... }".to_string(),
+        (None, false) => "(no value)".to_string(),
+    }
+}
+
+/// A path for display: `server.ports[1].tls`.
+pub fn format_path(path: &[String]) -> String {
+    let mut out = String::new();
+    for segment in path {
+        if !out.is_empty() && !segment.starts_with('[') {
+            out.push('.');
+        }
+        out.push_str(segment);
+    }
+    out
+}
+
+fn present(node: Option<&MOTLYNode>) -> Option<&MOTLYNode> {
+    node.filter(|n| !matches!(n, MOTLYNode::Data(data) if data.deleted))
+}
+
+/// The node's value slot (array elements included), without its properties.
+fn own_value(node: &MOTLYNode) -> MOTLYNode {
+    match node {
+        MOTLYNode::Data(data) => MOTLYNode::Data(MOTLYDataNode {
+            eq: data.eq.clone(),
+            properties: None,
+            deleted: false,
+            location: data.location,
+        }),
+        link => link.clone(),
+    }
+}
+
+fn change(kind: ChangeKind, path: &[String], before: Option<MOTLYNode>, after: Option<MOTLYNode>) -> Change {
+    Change { kind, path: path.to_vec(), before, after }
+}
+
+fn diff_data(a: &MOTLYDataNode, b: &MOTLYDataNode, path: &mut Vec<String>, changes: &mut Vec<Change>) {
+    let empty = Default::default();
+    let a_props = a.properties.as_ref().unwrap_or(&empty);
+    let b_props = b.properties.as_ref().unwrap_or(&empty);
+    let mut keys: Vec<&String> = a_props.keys().chain(b_props.keys()).collect();
+    keys.sort();
+    keys.dedup();
+
+    for key in keys {
+        path.push(key.clone());
+        match (present(a_props.get(key)), present(b_props.get(key))) {
+            (Some(x), Some(y)) => diff_node(x, y, path, changes),
+            (Some(x), None) => changes.push(change(ChangeKind::Removed, path, Some(x.clone()), None)),
+            (None, Some(y)) => changes.push(change(ChangeKind::Added, path, None, Some(y.clone()))),
+            (None, None) => {}
+        }
+        path.pop();
+    }
+}
+
+fn diff_node(a: &MOTLYNode, b: &MOTLYNode, path: &mut Vec<String>, changes: &mut Vec<Change>) {
+    match (a, b) {
+        (MOTLYNode::Ref { .. }, MOTLYNode::Ref { .. }) => {
+            if !same(a, b) {
+                changes.push(change(ChangeKind::LinkRetargeted, path, Some(a.clone()), Some(b.clone())));
+            }
+        }
+        (MOTLYNode::Data(x), MOTLYNode::Data(y)) => {
+            if type_name(a) != type_name(b) {
+                changes.push(change(ChangeKind::TypeChanged, path, Some(own_value(a)), Some(own_value(b))));
+            } else if let (Some(EqValue::Array(xs)), Some(EqValue::Array(ys))) = (&x.eq, &y.eq) {
+                diff_array(xs, ys, path, changes);
+            } else if x.eq != y.eq {
+                changes.push(change(ChangeKind::ValueChanged, path, Some(own_value(a)), Some(own_value(b))));
+            }
+            diff_data(x, y, path, changes);
+        }
+        _ => changes.push(change(ChangeKind::TypeChanged, path, Some(own_value(a)), Some(own_value(b)))),
+    }
+}
+
+/// Match equal elements (longest common subsequence). A leftover element
+/// equal to one left over on the other side moved; other leftovers between
+/// the same two matches are compared in place, and the rest were inserted
+/// or removed.
+fn diff_array(a: &[MOTLYNode], b: &[MOTLYNode], path: &mut Vec<String>, changes: &mut Vec<Change>) {
+    let matched = common_subsequence(a, b);
+    let bounds: Vec<(usize, usize)> = matched.iter().copied().chain(std::iter::once((a.len(), b.len()))).collect();
+    let mut gaps: Vec<(Vec<usize>, Vec<usize>)> = Vec::new();
+    let (mut i, mut j) = (0, 0);
+    for (mi, mj) in bounds {
+        gaps.push(((i..mi).collect(), (j..mj).collect()));
+        (i, j) = (mi + 1, mj + 1);
+    }
+
+    let mut moves: Vec<(usize, usize)> = Vec::new();
+    for gap in 0..gaps.len() {
+        let mut k = 0;
+        while k < gaps[gap].0.len() {
+            let from = gaps[gap].0[k];
+            let to = gaps.iter_mut().find_map(|(_, bs)| {
+                let pos = bs.iter().position(|&to| same(&a[from], &b[to]))?;
+                Some(bs.remove(pos))
+            });
+            match to {
+                Some(to) => {
+                    moves.push((from, to));
+                    gaps[gap].0.remove(k);
+                }
+                None => k += 1,
+            }
+        }
+    }
+
+    for (from, to) in moves {
+        path.push(format!("[{}]", to));
+        changes.push(change(ChangeKind::ElementMoved { from, to }, path, Some(a[from].clone()), Some(b[to].clone())));
+        path.pop();
+    }
+    let mut removed = Vec::new();
+    let mut inserted = Vec::new();
+    for (xs, ys) in gaps {
+        let paired = xs.len().min(ys.len());
+        for (&x, &y) in xs.iter().zip(&ys) {
+            path.push(format!("[{}]", y));
+            diff_node(&a[x], &b[y], path, changes);
+            path.pop();
+        }
+        removed.extend_from_slice(&xs[paired..]);
+        inserted.extend_from_slice(&ys[paired..]);
+    }
+    for from in removed {
+        path.push(format!("[{}]", from));
+        changes.push(change(ChangeKind::ElementRemoved, path, Some(a[from].clone()), None));
+        path.pop();
+    }
+    for to in inserted {
+        path.push(format!("[{}]", to));
+        changes.push(change(ChangeKind::ElementInserted, path, None, Some(b[to].clone())));
+        path.pop();
+    }
+}
+
+/// Index pairs of a longest common subsequence of equal elements.
+fn common_subsequence(a: &[MOTLYNode], b: &[MOTLYNode]) -> Vec<(usize, usize)> {
+    let mut lengths = vec![vec![0usize; b.len() + 1]; a.len() + 1];
+    for i in (0..a.len()).rev() {
+        for j in (0..b.len()).rev() {
+            lengths[i][j] = if same(&a[i], &b[j]) {
+                lengths[i + 1][j + 1] + 1
+            } else {
+                lengths[i + 1][j].max(lengths[i][j + 1])
+            };
+        }
+    }
+    let mut pairs = Vec::new();
+    let (mut i, mut j) = (0, 0);
+    while i < a.len() && j < b.len() {
+        if same(&a[i], &b[j]) {
+            pairs.push((i, j));
+            i += 1;
+            j += 1;
+        } else if lengths[i + 1][j] >= lengths[i][j + 1] {
+            i += 1;
+        } else {
+            j += 1;
+        }
+    }
+    pairs
+}
+
+/// Structural equality, ignoring locations.
+pub(crate) fn same(a: &MOTLYNode, b: &MOTLYNode) -> bool {
+    match (a, b) {
+        (MOTLYNode::Ref { link_to: x, link_ups: xu, .. }, MOTLYNode::Ref { link_to: y, link_ups: yu, .. }) => {
+            x == y && xu == yu
+        }
+        (MOTLYNode::Data(x), MOTLYNode::Data(y)) => same_data(x, y),
+        _ => false,
+    }
+}
+
+fn same_data(x: &MOTLYDataNode, y: &MOTLYDataNode) -> bool {
+    let props_same = match (&x.properties, &y.properties) {
+        (Some(p), Some(q)) => p.len() == q.len() && p.iter().zip(q).all(|((k, v), (l, w))| k == l && same(v, w)),
+        (None, None) => true,
+        (Some(p), None) | (None, Some(p)) => p.is_empty(),
+    };
+    same_eq(&x.eq, &y.eq) && props_same && x.deleted == y.deleted
+}
+
+/// Value-slot equality, ignoring the locations of array elements.
+pub(crate) fn same_eq(x: &Option<EqValue>, y: &Option<EqValue>) -> bool {
+    match (x, y) {
+        (Some(EqValue::Array(xs)), Some(EqValue::Array(ys))) => {
+            xs.len() == ys.len() && xs.iter().zip(ys).all(|(p, q)| same(p, q))
+        }
+        (p, q) => p == q,
+    }
+}
@@ -0,0 +1,273 @@
+/// Comment-preserving edits to MOTLY source.
+///
+/// Each operation locates the statements affecting a path in the lossless
+/// CST and returns the minimal list of text replacements needed, leaving
+/// every other byte (comments, layout, spelling of other values) untouched.
+use crate::ast::ScalarValue;
+use crate::cst::{self, NodeKind, SyntaxNode};
+use crate::error::{MOTLYError, Position};
+use crate::lexer::TokenKind;
+use crate::writer::{write_path, write_value};
+
+/// Replace the text between `begin` and `end` with `new_text`.
+/// An insertion has `begin == end`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TextEdit {
+    pub begin: Position,
+    pub end: Position,
+    pub new_text: String,
+}
+
+/// Apply non-overlapping edits (in any order) to `source`.
+pub fn apply_edits(source: &str, edits: &[TextEdit]) -> String {
+    let mut sorted: Vec<&TextEdit> = edits.iter().collect();
+    sorted.sort_by_key(|e| (e.begin.offset, e.end.offset));
+    let mut out = String::with_capacity(source.len());
+    let mut at = 0;
+    for edit in sorted {
+        out.push_str(&source[at..edit.begin.offset]);
+        out.push_str(&edit.new_text);
+        at = edit.end.offset;
+    }
+    out.push_str(&source[at..]);
+    out
+}
+
+/// Set the value of `path`. The value written by the statement that
+/// currently determines it is replaced in place; if no statement does,
+/// `rest.of.path = value` is added to the innermost existing block for the
+/// path (or the end of the file).
+pub fn set_value(source: &str, path: &[&str], value: &ScalarValue) -> Result<Vec<TextEdit>, MOTLYError> {
+    let tree = parse(source)?;
+    let target: Vec<String> = path.iter().map(|s| s.to_string()).collect();
+    let mut found = None;
+    find_value(&tree.root, &[], &target, &mut found);
+    let new_text = write_value(value);
+    if let Some(node) = found {
+        let span = node.span().unwrap();
+        let end = Position::from_offset(source, text_end(source, node));
+        return Ok(vec![TextEdit { begin: span.begin, end, new_text }]);
+    }
+    Ok(vec![insert(source, &tree.root, &target, &new_text)])
+}
+
+/// Add `name = value` inside the properties of `parent`. Fails with
+/// `edit-property-exists` if the property is already mentioned.
+pub fn add_property(
+    source: &str,
+    parent: &[&str],
+    name: &str,
+    value: &ScalarValue,
+) -> Result<Vec<TextEdit>, MOTLYError> {
+    let tree = parse(source)?;
+    let mut target: Vec<String> = parent.iter().map(|s| s.to_string()).collect();
+    target.push(name.to_string());
+    let mut existing = Vec::new();
+    find_statements(&tree.root, &[], &target, &mut existing);
+    if !existing.is_empty() {
+        return Err(edit_error(
+            "edit-property-exists",
+            format!("Property '{}' already exists", target.join(".")),
+        ));
+    }
+    Ok(vec![insert(source, &tree.root, &target, &write_value(value))])
+}
+
+/// Delete every statement that writes `path` or something beneath it.
+/// Statements alone on their line are removed with the line, including a
+/// trailing comment. Fails with `edit-path-not-found` if nothing matches.
+pub fn delete_property(source: &str, path: &[&str]) -> Result<Vec<TextEdit>, MOTLYError> {
+    let tree = parse(source)?;
+    let target: Vec<String> = path.iter().map(|s| s.to_string()).collect();
+    let mut found = Vec::new();
+    find_statements(&tree.root, &[], &target, &mut found);
+    if found.is_empty() {
+        return Err(edit_error(
+            "edit-path-not-found",
+            format!("No statement writes '{}'", target.join(".")),
+        ));
+    }
+    Ok(found.into_iter().map(|node| removal(source, node)).collect())
+}
+
+fn parse(source: &str) -> Result<cst::SyntaxTree, MOTLYError> {
+    let tree = cst::parse(source);
+    match tree.errors.first() {
+        Some(err) => Err(err.clone()),
+        None => Ok(tree),
+    }
+}
+
+fn edit_error(code: &str, message: String) -> MOTLYError {
+    MOTLYError::general(code, message)
+}
+
+fn statements(list: &SyntaxNode) -> impl Iterator<Item = &SyntaxNode> {
+    list.child_nodes().filter(|n| n.kind.is_statement())
+}
+
+fn joined(prefix: &[String], node: &SyntaxNode) -> Vec<String> {
+    let mut full = prefix.to_vec();
+    full.extend(node.path());
+    full
+}
+
+/// Track the value node of the last statement that sets `target`, forgetting
+/// it whenever a later statement would discard it.
+fn find_value<'t>(list: &'t SyntaxNode, prefix: &[String], target: &[String], found: &mut Option<&'t SyntaxNode>) {
+    for stmt in statements(list) {
+        if stmt.kind == NodeKind::ClearAll {
+            *found = None;
+            continue;
+        }
+        let full = joined(prefix, stmt);
+        if full == target {
+            match stmt.kind {
+                NodeKind::SetEq | NodeKind::AssignBoth => {
+                    *found = stmt.child_nodes().find(|n| matches!(n.kind, NodeKind::Value | NodeKind::Array));
+                }
+                NodeKind::Delete => *found = None,
+                _ => {}
+            }
+        } else if target.starts_with(&full) {
+            if matches!(stmt.kind, NodeKind::Delete | NodeKind::AssignBoth | NodeKind::ReplaceProperties) {
+                *found = None;
+            }
+            if let Some(block) = stmt.child(NodeKind::Properties) {
+                find_value(block, &full, target, found);
+            }
+        }
+    }
+}
+
+/// Every statement writing `target` or a descendant of it.
+fn find_statements<'t>(list: &'t SyntaxNode, prefix: &[String], target: &[String], found: &mut Vec<&'t SyntaxNode>) {
+    for stmt in statements(list) {
+        if stmt.kind == NodeKind::ClearAll {
+            continue;
+        }
+        let full = joined(prefix, stmt);
+        if full.starts_with(target) {
+            found.push(stmt);
+        } else if target.starts_with(&full) {
+            if let Some(block) = stmt.child(NodeKind::Properties) {
+                find_statements(block, &full, target, found);
+            }
+        }
+    }
+}
+
+/// The last properties block among the deepest statements that are
+/// ancestors of `target`, with the path it belongs to.
+fn deepest_block<'t>(
+    list: &'t SyntaxNode,
+    prefix: &[String],
+    target: &[String],
+    best: &mut Option<(usize, &'t SyntaxNode)>,
+) {
+    for stmt in statements(list) {
+        let full = joined(prefix, stmt);
+        if full.len() >= target.len() || !target.starts_with(&full) {
+            continue;
+        }
+        if let Some(block) = stmt.child(NodeKind::Properties) {
+            if best.is_none_or(|(depth, _)| full.len() >= depth) {
+                *best = Some((full.len(), block));
+            }
+            deepest_block(block, &full, target, best);
+        }
+    }
+}
+
+/// Insert `rest.of.target = value` in the best existing block.
+fn insert(source: &str, root: &SyntaxNode, target: &[String], value: &str) -> TextEdit {
+    let mut best = None;
+    deepest_block(root, &[], target, &mut best);
+    let (depth, block) = match best {
+        Some((depth, block)) => (depth, Some(block)),
+        None => (0, None),
+    };
+    let statement = format!("{} = {}", write_path(&target[depth..]), value);
+
+    let Some(block) = block else {
+        let at = Position::from_offset(source, source.len());
+        let new_text = if source.is_empty() || source.ends_with('\n') {
+            format!("{}\n", statement)
+        } else {
+            format!("\n{}\n", statement)
+        };
+        return TextEdit { begin: at, end: at, new_text };
+    };
+
+    let close = block.last_token().unwrap().span.begin.offset;
+    let close_line = line_start(source, close);
+    let last = statements(block).last();
+    if source[close_line..close].trim().is_empty() {
+        // `}` on its own line: add a line above it, indented like the last statement
+        let indent = match last {
+            Some(stmt) => leading_indent(source, stmt.span().unwrap().begin.offset),
+            None => None,
+        };
+        let indent = indent.unwrap_or_else(|| format!("{}  ", &source[close_line..close]));
+        let at = Position::from_offset(source, close_line);
+        return TextEdit { begin: at, end: at, new_text: format!("{}{}\n", indent, statement) };
+    }
+    match last {
+        Some(stmt) => {
+            let at = Position::from_offset(source, stmt.span().unwrap().end.offset);
+            TextEdit { begin: at, end: at, new_text: format!(", {}", statement) }
+        }
+        None => {
+            let at = Position::from_offset(source, block.first_token().unwrap().span.end.offset);
+            TextEdit { begin: at, end: at, new_text: format!(" {} ", statement) }
+        }
+    }
+}
+
+fn line_start(source: &str, offset: usize) -> usize {
+    source[..offset].rfind('\n').map(|i| i + 1).unwrap_or(0)
+}
+
+/// The whitespace before `offset` on its line, if nothing else precedes it.
+fn leading_indent(source: &str, offset: usize) -> Option<String> {
+    let before = &source[line_start(source, offset)..offset];
+    before.trim().is_empty().then(|| before.to_string())
+}
+
+/// Where `node`'s own text ends. A heredoc token also holds the line break
+/// after `>>>`, if there is one, which belongs to the line and not the value.
+fn text_end(source: &str, node: &SyntaxNode) -> usize {
+    let end = node.span().unwrap().end.offset;
+    if !node.last_token().is_some_and(|t| t.kind == TokenKind::Heredoc) {
+        return end;
+    }
+    let text = &source[..end];
+    let text = text.strip_suffix('\n').map(|t| t.strip_suffix('\r').unwrap_or(t)).unwrap_or(text);
+    text.len()
+}
+
+/// The text to remove for a deleted statement. A statement alone on its
+/// line takes the whole line with it; otherwise the statement and the
+/// separator after it are removed.
+fn removal(source: &str, stmt: &SyntaxNode) -> TextEdit {
+    let begin = stmt.span().unwrap().begin.offset;
+    let end = text_end(source, stmt);
+    let line_end = source[end..].find('\n').map(|i| end + i).unwrap_or(source.len());
+    let rest = source[end..line_end].trim_start();
+    let rest = rest.strip_prefix(',').unwrap_or(rest).trim_start();
+    let alone = source[line_start(source, begin)..begin].trim().is_empty()
+        && (rest.is_empty() || rest.starts_with('#'));
+
+    let (from, to) = if alone {
+        (line_start(source, begin), (line_end + 1).min(source.len()))
+    } else {
+        let after = &source[end..line_end];
+        let skipped = after.len() - after.trim_start_matches([' ', '\t', ',']).len();
+        (begin, end + skipped)
+    };
+    TextEdit {
+        begin: Position::from_offset(source, from),
+        end: Position::from_offset(source, to),
+        new_text: String::new(),
+    }
+}
@@ -0,0 +1,225 @@
+/// Resolving `@env.NAME` references.
+///
+/// The interpreter keeps `@env.NAME` as `EqValue::EnvRef`. `resolve_env`
+/// replaces each one with the variable's value from an `EnvProvider`: the
+/// process environment, a `HashMap`, or a dotenv-style file (`DotEnv`).
+///
+/// A variable is always a string. `EnvOptions::hints` name the paths
+/// whose value should be read as another type, the way a schema that says
+/// `port=number` would; `schema_hints` reads them from such a schema.
+/// Unset variables are left as references, and reported as errors when
+/// `EnvOptions::strict` is set.
+use std::collections::HashMap;
+
+use crate::error::{MOTLYError, Position};
+use crate::mot::ValueType;
+use crate::tree::{EqValue, MOTLYDataNode, MOTLYNode, Scalar};
+use crate::validate::schema_fields;
+
+/// Where `@env` values come from.
+pub trait EnvProvider {
+    /// The value of variable `name`, if it is set.
+    fn get(&self, name: &str) -> Option<String>;
+}
+
+/// The environment of this process.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ProcessEnv;
+
+impl EnvProvider for ProcessEnv {
+    fn get(&self, name: &str) -> Option<String> {
+        std::env::var(name).ok()
+    }
+}
+
+impl EnvProvider for HashMap<String, String> {
+    fn get(&self, name: &str) -> Option<String> {
+        HashMap::get(self, name).cloned()
+    }
+}
+
+/// Variables read from a dotenv-style file: `NAME=value` lines, with
+/// optional `export`, `#` comments, and values that may be single-quoted
+/// (literal) or double-quoted (with `\n`, `\t`, `\"` and `\\` escapes).
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct DotEnv {
+    pub vars: HashMap<String, String>,
+}
+
+impl DotEnv {
+    pub fn parse(text: &str) -> Result<DotEnv, MOTLYError> {
+        let mut vars = HashMap::new();
+        let mut offset = 0;
+        for (line_no, line) in text.split('\n').enumerate() {
+            let start = offset;
+            offset += line.len() + 1;
+            let line = line.trim_end_matches('\r');
+            let trimmed = line.trim_start();
+            if trimmed.is_empty() || trimmed.starts_with('#') {
+                continue;
+            }
+            let column = line.len() - trimmed.len();
+            let error = |message: &str| {
+                let at = Position { line: line_no, column, offset: start + column };
+                let end = Position { line: line_no, column: line.len(), offset: start + line.len() };
+                MOTLYError::new("invalid-env-file", message.to_string(), at, end)
+            };
+            let entry = trimmed.strip_prefix("export ").map_or(trimmed, str::trim_start);
+            let Some((name, value)) = entry.split_once('=') else {
+                return Err(error("Expected NAME=value"));
+            };
+            let name = name.trim_end();
+            let valid = name.starts_with(|c: char| c.is_ascii_alphabetic() || c == '_')
+                && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_');
+            if !valid {
+                return Err(error(&format!("Invalid variable name '{}'", name)));
+            }
+            let value = dotenv_value(value.trim()).ok_or_else(|| error("Unterminated quoted value"))?;
+            vars.insert(name.to_string(), value);
+        }
+        Ok(DotEnv { vars })
+    }
+}
+
+impl EnvProvider for DotEnv {
+    fn get(&self, name: &str) -> Option<String> {
+        self.vars.get(name).cloned()
+    }
+}
+
+/// The value part of a dotenv line; None if a quote is not closed.
+fn dotenv_value(raw: &str) -> Option<String> {
+    if let Some(rest) = raw.strip_prefix('\'') {
+        return rest.find('\'').map(|end| rest[..end].to_string());
+    }
+    if let Some(rest) = raw.strip_prefix('"') {
+        let mut out = String::new();
+        let mut chars = rest.chars();
+        while let Some(c) = chars.next() {
+            match c {
+                '"' => return Some(out),
+                '\\' => match chars.next()? {
+                    'n' => out.push('\n'),
+                    't' => out.push('\t'),
+                    'r' => out.push('\r'),
+                    other => out.push(other),
+                },
+                c => out.push(c),
+            }
+        }
+        return None;
+    }
+    // Unquoted: a ` #` starts a comment
+    let value = raw.find(" #").map_or(raw, |i| &raw[..i]);
+    Some(value.trim_end().to_string())
+}
+
+/// How `resolve_env` treats unset variables and what types to read.
+#[derive(Debug, Clone, Default)]
+pub struct EnvOptions {
+    /// Report each unset variable as an `env-unset` error. Either way the
+    /// node keeps its `@env` reference.
+    pub strict: bool,
+    /// The type to read the value at each path as (paths as in
+    /// `diff::Change`, with `[i]` for array elements). Values at other
+    /// paths, or hinted `String` or `Array`, stay strings.
+    pub hints: HashMap<Vec<String>, ValueType>,
+}
+
+/// Replace the `@env` references in `root` with values from `provider`.
+/// Returns an `env-unset` error for each unset variable when strict, and
+/// an `env-type` error for each value that is not of its hinted type;
+/// such a value is kept as a string.
+pub fn resolve_env(root: &mut MOTLYDataNode, provider: &dyn EnvProvider, options: &EnvOptions) -> Vec<MOTLYError> {
+    let mut errors = Vec::new();
+    resolve_node(root, &mut Vec::new(), provider, options, &mut errors);
+    errors
+}
+
+fn resolve_node(
+    node: &mut MOTLYDataNode,
+    path: &mut Vec<String>,
+    provider: &dyn EnvProvider,
+    options: &EnvOptions,
+    errors: &mut Vec<MOTLYError>,
+) {
+    if node.deleted {
+        return;
+    }
+    if let Some(EqValue::EnvRef(name)) = &node.eq {
+        match provider.get(name) {
+            Some(value) => {
+                let hint = options.hints.get(path.as_slice()).copied();
+                let scalar = coerce(&value, hint).unwrap_or_else(|| {
+                    let message = format!("Environment variable {} is '{}', which is not a {}", name, value, type_word(hint));
+                    errors.push(MOTLYError::located("env-type", message, node.location.as_ref()));
+                    Scalar::String(value)
+                });
+                node.eq = Some(EqValue::Scalar(scalar));
+            }
+            None if options.strict => {
+                let message = format!("Environment variable {} is not set", name);
+                errors.push(MOTLYError::located("env-unset", message, node.location.as_ref()));
+            }
+            None => {}
+        }
+    }
+    if let Some(EqValue::Array(items)) = &mut node.eq {
+        for (i, item) in items.iter_mut().enumerate() {
+            if let MOTLYNode::Data(data) = item {
+                path.push(format!("[{}]", i));
+                resolve_node(data, path, provider, options, errors);
+                path.pop();
+            }
+        }
+    }
+    for (key, child) in node.properties.iter_mut().flatten() {
+        if let MOTLYNode::Data(data) = child {
+            path.push(key.clone());
+            resolve_node(data, path, provider, options, errors);
+            path.pop();
+        }
+    }
+}
+
+/// `value` read as `hint`; None if it is not one.
+fn coerce(value: &str, hint: Option<ValueType>) -> Option<Scalar> {
+    match hint {
+        None | Some(ValueType::String) | Some(ValueType::Array) => Some(Scalar::String(value.to_string())),
+        Some(ValueType::Number) => value.trim().parse::<f64>().ok().filter(|n| n.is_finite()).map(Scalar::Number),
+        Some(ValueType::Boolean) => match value.trim() {
+            "true" => Some(Scalar::Boolean(true)),
+            "false" => Some(Scalar::Boolean(false)),
+            _ => None,
+        },
+        Some(ValueType::Date) => crate::parser::parse_date(value.trim()).map(|_| Scalar::Date(value.trim().to_string())),
+    }
+}
+
+fn type_word(hint: Option<ValueType>) -> &'static str {
+    match hint {
+        Some(ValueType::Number) => "number",
+        Some(ValueType::Boolean) => "boolean",
+        Some(ValueType::Date) => "date",
+        _ => "string",
+    }
+}
+
+// ── Hints from a schema ─────────────────────────────────────────────
+
+/// Coercion hints from a schema: each property it declares as a
+/// `number`, `integer`, `boolean` or `date` (directly or through named
+/// types), in either schema dialect.
+pub fn schema_hints(schema: &MOTLYDataNode) -> HashMap<Vec<String>, ValueType> {
+    let mut hints = HashMap::new();
+    for field in schema_fields(schema) {
+        let hint = match field.value_type() {
+            Some("number") | Some("integer") => ValueType::Number,
+            Some("boolean") => ValueType::Boolean,
+            Some("date") => ValueType::Date,
+            _ => continue,
+        };
+        hints.insert(field.path, hint);
+    }
+    hints
+}
@@ -1,5 +1,7 @@
 use std::fmt;
 
+use crate::tree::MOTLYLocation;
+
 /// A 0-based position in the source text.
 #[derive(Debug, Clone, Copy, PartialEq)]
 pub struct Position {
@@ -11,6 +13,23 @@ pub struct Position {
     pub offset: usize,
 }
 
+impl Position {
+    /// The start of the input, and the position of errors that have none.
+    pub const ZERO: Position = Position { line: 0, column: 0, offset: 0 };
+
+    /// The position of a byte offset within `input`.
+    pub fn from_offset(input: &str, offset: usize) -> Position {
+        let consumed = &input[..offset];
+        let line = consumed.matches('\n').count();
+        let last_newline = consumed.rfind('\n').map(|i| i + 1).unwrap_or(0);
+        Position {
+            line,
+            column: offset - last_newline,
+            offset,
+        }
+    }
+}
+
 /// A parse error with span information (begin..end).
 #[derive(Debug, Clone, PartialEq)]
 pub struct MOTLYError {
@@ -20,17 +39,36 @@ pub struct MOTLYError {
     pub begin: Position,
     /// End of the offending region (exclusive)
     pub end: Position,
+    /// Which parse() call the positions refer to, when known. Set by
+    /// sessions and the interpreter; a bare `parse()` leaves it `None`.
+    pub parse_id: Option<u32>,
 }
 
 impl MOTLYError {
-    pub fn syntax_error(message: String, begin: Position, end: Position) -> Self {
-        MOTLYError {
-            code: "tag-parse-syntax-error".to_string(),
-            message,
-            begin,
-            end,
+    /// An error spanning `begin..end` of a source whose parse is filled in
+    /// later, if at all.
+    pub fn new(code: &str, message: String, begin: Position, end: Position) -> Self {
+        MOTLYError { code: code.to_string(), message, begin, end, parse_id: None }
+    }
+
+    /// An error about no place in particular: a limit on the whole input
+    /// or result, or a misused API.
+    pub fn general(code: &str, message: String) -> Self {
+        MOTLYError::new(code, message, Position::ZERO, Position::ZERO)
+    }
+
+    /// An error spanning `location`, the node it is about, or a general
+    /// one if the node has no location.
+    pub fn located(code: &str, message: String, location: Option<&MOTLYLocation>) -> Self {
+        match location {
+            Some(loc) => MOTLYError { parse_id: Some(loc.parse_id), ..MOTLYError::new(code, message, loc.begin, loc.end) },
+            None => MOTLYError::general(code, message),
         }
     }
+
+    pub fn syntax_error(message: String, begin: Position, end: Position) -> Self {
+        MOTLYError::new("tag-parse-syntax-error", message, begin, end)
+    }
 }
 
 impl fmt::Display for MOTLYError {
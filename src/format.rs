@@ -0,0 +1,312 @@
+/// Canonical source formatter.
+///
+/// Works on the lossless CST so comments survive. Values are printed with
+/// their original spelling; only layout changes. The formatted output parses
+/// to the same statements (apart from spans) as the input, and formatting it
+/// again is a no-op.
+use crate::cst::{self, NodeKind, SyntaxElement, SyntaxNode};
+use crate::error::MOTLYError;
+use crate::lexer::TokenKind;
+
+/// Layout settings for `format`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct FormatOptions {
+    /// Spaces per indentation level.
+    pub indent_width: usize,
+    /// Arrays that would extend past this column are wrapped one element per line.
+    pub max_width: usize,
+}
+
+impl Default for FormatOptions {
+    fn default() -> Self {
+        FormatOptions { indent_width: 2, max_width: 80 }
+    }
+}
+
+/// Format MOTLY source. Returns the first syntax error if the input does not parse.
+pub fn format(input: &str, options: &FormatOptions) -> Result<String, MOTLYError> {
+    let tree = cst::parse(input);
+    if let Some(err) = tree.errors.into_iter().next() {
+        return Err(err);
+    }
+    let mut printer = Printer { out: String::new(), options, depth: 0 };
+    printer.statement_list(&tree.root);
+    Ok(printer.out)
+}
+
+/// An entry in a statement list or wrapped array, with comments attached.
+enum Item<'a> {
+    Node {
+        node: &'a SyntaxNode,
+        trailing: Option<&'a str>,
+    },
+    Comment(&'a str),
+    Blank,
+}
+
+/// Group the children of a list node into items. A comment on the same line
+/// as the preceding node is attached to it; any other comment stands on its
+/// own line. Runs of blank lines collapse to one.
+fn items(node: &SyntaxNode, wanted: fn(NodeKind) -> bool) -> Vec<Item<'_>> {
+    let mut items: Vec<Item> = Vec::new();
+    let mut line_broken = true;
+    // A heredoc value ends with its own line break
+    let mut carried = 0;
+    for child in &node.children {
+        match child {
+            SyntaxElement::Token(t) => match t.kind {
+                TokenKind::Whitespace => {
+                    let breaks = t.text.matches('\n').count() + std::mem::take(&mut carried);
+                    if breaks > 0 {
+                        line_broken = true;
+                    }
+                    if breaks > 1 && !items.is_empty() && !matches!(items.last(), Some(Item::Blank)) {
+                        items.push(Item::Blank);
+                    }
+                }
+                kind if kind.is_comment() => match items.last_mut() {
+                    _ if std::mem::take(&mut carried) > 0 => items.push(Item::Comment(&t.text)),
+                    Some(Item::Node { trailing: trailing @ None, .. }) if !line_broken => {
+                        *trailing = Some(&t.text);
+                    }
+                    _ => items.push(Item::Comment(&t.text)),
+                },
+                _ => {}
+            },
+            SyntaxElement::Node(n) if wanted(n.kind) => {
+                line_broken = n.text().ends_with('\n');
+                carried = usize::from(line_broken);
+                items.push(Item::Node { node: n, trailing: None });
+            }
+            SyntaxElement::Node(_) => {}
+        }
+    }
+    if matches!(items.last(), Some(Item::Blank)) {
+        items.pop();
+    }
+    items
+}
+
+/// Comments inside a statement or array element header, e.g. between `=`
+/// and the value. They are moved to their own lines before the node.
+fn inner_comments(node: &SyntaxNode) -> Vec<&str> {
+    node.children
+        .iter()
+        .filter_map(|child| match child {
+            SyntaxElement::Token(t) if t.kind.is_comment() => Some(t.text.as_str()),
+            _ => None,
+        })
+        .collect()
+}
+
+fn has_comments(node: &SyntaxNode) -> bool {
+    node.tokens().iter().any(|t| t.kind.is_comment())
+}
+
+struct Printer<'o> {
+    out: String,
+    options: &'o FormatOptions,
+    depth: usize,
+}
+
+impl Printer<'_> {
+    fn indent(&mut self) {
+        for _ in 0..self.depth * self.options.indent_width {
+            self.out.push(' ');
+        }
+    }
+
+    fn at_line_start(&self) -> bool {
+        self.out.is_empty() || self.out.ends_with('\n')
+    }
+
+    fn newline(&mut self) {
+        if !self.at_line_start() {
+            self.out.push('\n');
+        }
+    }
+
+    /// A separating space, or indentation if a heredoc left us at the start of a line.
+    fn space(&mut self) {
+        if self.at_line_start() {
+            self.indent();
+        } else {
+            self.out.push(' ');
+        }
+    }
+
+    fn column(&self) -> usize {
+        let line_start = self.out.rfind('\n').map(|i| i + 1).unwrap_or(0);
+        self.out[line_start..].chars().count()
+    }
+
+    fn comment_line(&mut self, text: &str) {
+        self.indent();
+        self.out.push_str(text);
+        self.out.push('\n');
+    }
+
+    /// Statements of a document or properties block, one per line.
+    fn statement_list(&mut self, list: &SyntaxNode) {
+        for item in items(list, NodeKind::is_statement) {
+            match item {
+                Item::Node { node, trailing } => {
+                    for comment in inner_comments(node) {
+                        self.comment_line(comment);
+                    }
+                    self.indent();
+                    self.statement(node);
+                    if let Some(comment) = trailing {
+                        self.space();
+                        self.out.push_str(comment);
+                    }
+                    self.newline();
+                }
+                Item::Comment(text) => self.comment_line(text),
+                Item::Blank => self.out.push('\n'),
+            }
+        }
+    }
+
+    fn statement(&mut self, node: &SyntaxNode) {
+        let path = node.child(NodeKind::PropName).map(|p| p.text()).unwrap_or_default();
+        match node.kind {
+            NodeKind::ClearAll => self.out.push_str("-..."),
+            NodeKind::Delete => {
+                self.out.push('-');
+                self.out.push_str(&path);
+            }
+            NodeKind::Define => self.out.push_str(&path),
+            NodeKind::SetEq | NodeKind::AssignBoth => {
+                self.out.push_str(&path);
+                self.out.push_str(if node.kind == NodeKind::SetEq { " = " } else { " := " });
+                self.value_and_properties(node);
+            }
+            NodeKind::ReplaceProperties => {
+                self.out.push_str(&path);
+                self.out.push(':');
+                self.space();
+                self.properties(node.child(NodeKind::Properties).unwrap());
+            }
+            _ => {
+                self.out.push_str(&path);
+                self.space();
+                self.properties(node.child(NodeKind::Properties).unwrap());
+            }
+        }
+    }
+
+    /// The value of a statement or array element, then its block if any.
+    fn value_and_properties(&mut self, node: &SyntaxNode) {
+        let mut has_value = false;
+        for child in node.child_nodes() {
+            match child.kind {
+                NodeKind::Value => {
+                    has_value = true;
+                    self.out.push_str(&child.text());
+                }
+                NodeKind::Array => {
+                    has_value = true;
+                    self.array(child);
+                }
+                NodeKind::Properties => {
+                    if has_value {
+                        self.space();
+                    }
+                    self.properties(child);
+                }
+                _ => {}
+            }
+        }
+    }
+
+    fn properties(&mut self, block: &SyntaxNode) {
+        if !block.child_nodes().any(|n| n.kind.is_statement()) && !has_comments(block) {
+            self.out.push_str("{}");
+            return;
+        }
+        self.out.push_str("{\n");
+        self.depth += 1;
+        self.statement_list(block);
+        self.depth -= 1;
+        self.indent();
+        self.out.push('}');
+    }
+
+    fn array(&mut self, array: &SyntaxNode) {
+        if let Some(flat) = flat_array(array) {
+            if self.column() + flat.len() <= self.options.max_width {
+                self.out.push_str(&flat);
+                return;
+            }
+        }
+        let items = items(array, |k| k == NodeKind::ArrayElement);
+        if items.is_empty() {
+            self.out.push_str("[]");
+            return;
+        }
+        self.out.push_str("[\n");
+        self.depth += 1;
+        for item in items {
+            match item {
+                Item::Node { node, trailing } => {
+                    for comment in inner_comments(node) {
+                        self.comment_line(comment);
+                    }
+                    self.indent();
+                    self.value_and_properties(node);
+                    if self.at_line_start() {
+                        self.indent();
+                    }
+                    self.out.push(',');
+                    if let Some(comment) = trailing {
+                        self.out.push(' ');
+                        self.out.push_str(comment);
+                    }
+                    self.out.push('\n');
+                }
+                Item::Comment(text) => self.comment_line(text),
+                Item::Blank => self.out.push('\n'),
+            }
+        }
+        self.depth -= 1;
+        self.indent();
+        self.out.push(']');
+    }
+}
+
+/// Single-line rendering of an array, if it has no comments, no multi-line
+/// values and no non-empty property blocks.
+fn flat_array(array: &SyntaxNode) -> Option<String> {
+    if has_comments(array) {
+        return None;
+    }
+    let mut parts = Vec::new();
+    for element in array.child_nodes() {
+        let mut part = String::new();
+        for child in element.child_nodes() {
+            match child.kind {
+                NodeKind::Value => {
+                    let text = child.text();
+                    if text.contains('\n') {
+                        return None;
+                    }
+                    part.push_str(&text);
+                }
+                NodeKind::Array => part.push_str(&flat_array(child)?),
+                NodeKind::Properties => {
+                    if child.child_nodes().next().is_some() {
+                        return None;
+                    }
+                    if !part.is_empty() {
+                        part.push(' ');
+                    }
+                    part.push_str("{}");
+                }
+                _ => {}
+            }
+        }
+        parts.push(part);
+    }
+    Some(format!("[{}]", parts.join(", ")))
+}
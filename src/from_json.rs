@@ -1,3 +1,4 @@
+use crate::error::{MOTLYError, Position};
 use crate::tree::*;
 use std::collections::BTreeMap;
 
@@ -31,6 +32,122 @@ pub fn from_wire(input: &str) -> Result<MOTLYDataNode, String> {
     Ok(value)
 }
 
+/// An ordinary JSON value. Object members keep their order.
+#[derive(Debug, Clone, PartialEq)]
+pub enum JsonValue {
+    Null,
+    Boolean(bool),
+    Number(f64),
+    String(String),
+    Array(Vec<JsonValue>),
+    Object(Vec<(String, JsonValue)>),
+}
+
+impl JsonValue {
+    /// The member `key` of an object.
+    pub fn get(&self, key: &str) -> Option<&JsonValue> {
+        match self {
+            JsonValue::Object(members) => members.iter().find(|(k, _)| k == key).map(|(_, v)| v),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(&self) -> Option<&str> {
+        match self {
+            JsonValue::String(s) => Some(s),
+            _ => None,
+        }
+    }
+
+    pub fn as_f64(&self) -> Option<f64> {
+        match self {
+            JsonValue::Number(n) => Some(*n),
+            _ => None,
+        }
+    }
+
+    pub fn as_array(&self) -> Option<&[JsonValue]> {
+        match self {
+            JsonValue::Array(items) => Some(items),
+            _ => None,
+        }
+    }
+}
+
+/// Parse any JSON document.
+pub fn parse_json_value(input: &str) -> Result<JsonValue, MOTLYError> {
+    let mut p = JsonParser::new(input);
+    let value = p.parse_value().and_then(|value| {
+        p.skip_ws();
+        if p.pos < p.input.len() {
+            return Err(format!("Trailing content at position {}", p.pos));
+        }
+        Ok(value)
+    });
+    value.map_err(|message| json_error(input, p.pos, message))
+}
+
+fn json_error(input: &str, offset: usize, message: String) -> MOTLYError {
+    let at = Position::from_offset(input, offset.min(input.len()));
+    MOTLYError::new("invalid-json", message, at, at)
+}
+
+/// How `from_plain_json` reads a document.
+#[derive(Debug, Clone)]
+pub struct PlainJsonOptions {
+    /// Read strings that are ISO dates (`"2024-01-15"`,
+    /// `"2024-01-15T10:30:00Z"`) as dates. When false they stay strings.
+    pub detect_dates: bool,
+}
+
+impl Default for PlainJsonOptions {
+    fn default() -> Self {
+        PlainJsonOptions { detect_dates: true }
+    }
+}
+
+/// Read an ordinary JSON document (`{"server": {"port": 8080}}`) as a
+/// tree: objects become properties, arrays arrays, `null` a node with no
+/// value. The document must be an object.
+pub fn from_plain_json(input: &str, options: &PlainJsonOptions) -> Result<MOTLYDataNode, MOTLYError> {
+    match parse_json_value(input)? {
+        value @ JsonValue::Object(_) => Ok(plain_node(value, options)),
+        _ => {
+            let start = input.len() - input.trim_start().len();
+            Err(json_error(input, start, "The document must be an object".to_string()))
+        }
+    }
+}
+
+/// MOTLY source for an ordinary JSON document; see `from_plain_json`.
+pub fn plain_json_to_motly(input: &str, options: &PlainJsonOptions) -> Result<String, MOTLYError> {
+    Ok(crate::writer::to_motly(&from_plain_json(input, options)?))
+}
+
+fn plain_node(value: JsonValue, options: &PlainJsonOptions) -> MOTLYDataNode {
+    let scalar = |scalar| MOTLYDataNode::with_eq(EqValue::Scalar(scalar));
+    match value {
+        JsonValue::Null => MOTLYDataNode::new(),
+        JsonValue::Boolean(b) => scalar(Scalar::Boolean(b)),
+        JsonValue::Number(n) => scalar(Scalar::Number(n)),
+        JsonValue::String(s) => match crate::parser::parse_date(&s) {
+            Some(_) if options.detect_dates => scalar(Scalar::Date(s)),
+            _ => scalar(Scalar::String(s)),
+        },
+        JsonValue::Array(items) => MOTLYDataNode::with_eq(EqValue::Array(
+            items.into_iter().map(|item| MOTLYNode::Data(plain_node(item, options))).collect(),
+        )),
+        JsonValue::Object(members) => {
+            let mut node = MOTLYDataNode::new();
+            let properties = node.get_or_create_properties();
+            for (key, member) in members {
+                properties.insert(key, MOTLYNode::Data(plain_node(member, options)));
+            }
+            node
+        }
+    }
+}
+
 struct JsonParser<'a> {
     input: &'a [u8],
     pos: usize,
@@ -203,9 +320,16 @@ impl<'a> JsonParser<'a> {
         }
         let num_str = std::str::from_utf8(&self.input[start..self.pos])
             .map_err(|_| "Invalid number encoding")?;
-        num_str
+        let n = num_str
             .parse::<f64>()
-            .map_err(|e| format!("Invalid number \"{}\": {}", num_str, e))
+            .map_err(|e| format!("Invalid number \"{}\": {}", num_str, e))?;
+        // Rust reads a literal too large for f64 as infinity, which MOTLY has no number for
+        if !n.is_finite() {
+            let message = format!("Number {} is out of range", num_str);
+            self.pos = start;
+            return Err(message);
+        }
+        Ok(n)
     }
 
     fn parse_usize(&mut self) -> Result<usize, String> {
@@ -234,6 +358,58 @@ impl<'a> JsonParser<'a> {
         }
     }
 
+    /// Parse any JSON value.
+    fn parse_value(&mut self) -> Result<JsonValue, String> {
+        match self.peek() {
+            Some(b'{') => {
+                self.expect(b'{')?;
+                let mut members = Vec::new();
+                if self.peek() != Some(b'}') {
+                    loop {
+                        let key = self.parse_string()?;
+                        self.expect(b':')?;
+                        members.push((key, self.parse_value()?));
+                        self.skip_ws();
+                        if self.pos < self.input.len() && self.input[self.pos] == b',' {
+                            self.pos += 1;
+                        } else {
+                            break;
+                        }
+                    }
+                }
+                self.expect(b'}')?;
+                Ok(JsonValue::Object(members))
+            }
+            Some(b'[') => {
+                self.expect(b'[')?;
+                let mut items = Vec::new();
+                if self.peek() != Some(b']') {
+                    loop {
+                        items.push(self.parse_value()?);
+                        self.skip_ws();
+                        if self.pos < self.input.len() && self.input[self.pos] == b',' {
+                            self.pos += 1;
+                        } else {
+                            break;
+                        }
+                    }
+                }
+                self.expect(b']')?;
+                Ok(JsonValue::Array(items))
+            }
+            Some(b'n') => {
+                self.parse_literal(b"null")?;
+                Ok(JsonValue::Null)
+            }
+            _ => Ok(match self.parse_scalar()? {
+                Scalar::String(s) => JsonValue::String(s),
+                Scalar::Number(n) => JsonValue::Number(n),
+                Scalar::Boolean(b) => JsonValue::Boolean(b),
+                Scalar::Date(_) => unreachable!("JSON has no date literal"),
+            }),
+        }
+    }
+
     /// Parse a JSON object that represents a MOTLYDataNode.
     /// Nodes have optional "deleted", "eq", "properties", and "location" keys.
     fn parse_data_node(&mut self) -> Result<MOTLYDataNode, String> {
@@ -293,8 +469,8 @@ impl<'a> JsonParser<'a> {
     fn parse_location(&mut self) -> Result<MOTLYLocation, String> {
         self.expect(b'{')?;
         let mut parse_id: u32 = 0;
-        let mut begin = crate::error::Position { line: 0, column: 0, offset: 0 };
-        let mut end = crate::error::Position { line: 0, column: 0, offset: 0 };
+        let mut begin = crate::error::Position::ZERO;
+        let mut end = crate::error::Position::ZERO;
 
         if self.peek() != Some(b'}') {
             loop {
@@ -360,31 +536,32 @@ impl<'a> JsonParser<'a> {
             let key = self.parse_string()?;
 
             if key == "linkTo" || key == "linkUps" {
-                // This is a Ref — parse both keys in either order
-                self.expect(b':')?;
+                // This is a Ref — read its keys in any order
                 let mut link_to: Option<Vec<RefSegment>> = None;
                 let mut link_ups: Option<usize> = None;
+                let mut location: Option<MOTLYLocation> = None;
+                let mut key = key;
 
-                if key == "linkTo" {
-                    link_to = Some(self.parse_ref_segments()?);
-                } else {
-                    link_ups = Some(self.parse_usize()?);
-                }
-
-                // Check for second key
-                self.skip_ws();
-                if self.pos < self.input.len() && self.input[self.pos] == b',' {
-                    self.pos += 1;
-                    let key2 = self.parse_string()?;
+                loop {
                     self.expect(b':')?;
-                    if key2 == "linkTo" && link_to.is_none() {
+                    if key == "linkTo" && link_to.is_none() {
                         link_to = Some(self.parse_ref_segments()?);
-                    } else if key2 == "linkUps" && link_ups.is_none() {
+                    } else if key == "linkUps" && link_ups.is_none() {
                         link_ups = Some(self.parse_usize()?);
+                    } else if key == "location" && self.wire && location.is_none() {
+                        location = Some(self.parse_location()?);
                     } else {
                         // Unknown or duplicate key — skip its value
                         self.skip_json_value()?;
                     }
+
+                    self.skip_ws();
+                    if self.pos < self.input.len() && self.input[self.pos] == b',' {
+                        self.pos += 1;
+                        key = self.parse_string()?;
+                    } else {
+                        break;
+                    }
                 }
 
                 self.expect(b'}')?;
@@ -392,6 +569,7 @@ impl<'a> JsonParser<'a> {
                 return Ok(MOTLYNode::Ref {
                     link_to,
                     link_ups: link_ups.unwrap_or(0),
+                    location,
                 });
             }
 
@@ -696,6 +874,7 @@ mod tests {
             MOTLYNode::Ref {
                 link_to: vec![RefSegment::Name("parent".to_string()), RefSegment::Name("name".to_string())],
                 link_ups: 1,
+                location: None,
             },
         );
         v.properties = Some(props);
@@ -726,6 +905,7 @@ mod tests {
             MOTLYNode::Ref {
                 link_to: vec![RefSegment::Name("root".to_string())],
                 link_ups: 0,
+                location: None,
             },
         ];
         let v = MOTLYDataNode::with_eq(EqValue::Array(arr));
@@ -760,7 +940,7 @@ mod tests {
 
     #[test]
     fn round_trip_negative_number() {
-        let v = MOTLYDataNode::with_eq(EqValue::Scalar(Scalar::Number(-3.14)));
+        let v = MOTLYDataNode::with_eq(EqValue::Scalar(Scalar::Number(-2.75)));
         let json = v.to_json();
         let v2 = from_json(&json).unwrap();
         assert_eq!(v, v2);
@@ -782,4 +962,29 @@ mod tests {
         };
         assert_eq!(sub.eq, Some(EqValue::Scalar(Scalar::Number(42.0))));
     }
+
+    #[test]
+    fn parse_plain_json_value() {
+        let v = parse_json_value(r#" {"b": [1, null, "x\n"], "a": {"t": true}} "#).unwrap();
+        assert_eq!(
+            v,
+            JsonValue::Object(vec![
+                (
+                    "b".to_string(),
+                    JsonValue::Array(vec![JsonValue::Number(1.0), JsonValue::Null, JsonValue::String("x\n".to_string())]),
+                ),
+                ("a".to_string(), JsonValue::Object(vec![("t".to_string(), JsonValue::Boolean(true))])),
+            ])
+        );
+        let err = parse_json_value("{\n  \"a\": 1,\n}").unwrap_err();
+        assert_eq!((err.code.as_str(), err.begin.line, err.begin.column), ("invalid-json", 2, 0));
+    }
+
+    #[test]
+    fn reject_numbers_out_of_range() {
+        let err = from_plain_json("{\"e\": 1e400}", &PlainJsonOptions::default()).unwrap_err();
+        assert_eq!((err.code.as_str(), err.begin.line, err.begin.column), ("invalid-json", 0, 6));
+        assert!(parse_json_value("[-1e400]").is_err());
+        assert_eq!(parse_json_value("1e300").unwrap(), JsonValue::Number(1e300));
+    }
 }
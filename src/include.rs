@@ -0,0 +1,156 @@
+/// `#! include` directives for composing a configuration from several files.
+///
+/// ```motly
+/// #! include="../shared/base.motly"
+/// #! include="db.motly" at=database
+/// ```
+///
+/// Like other `#!` directives, the text after `#!` is read as MOTLY. The
+/// included source is interpreted before the statements of the including
+/// file, as its own parse; with `at`, its statements apply under that path
+/// (dotted, e.g. `at="services.db"`). Absolute references inside a mounted
+/// file still start from the root, so mountable fragments should use `^`.
+///
+/// Names are resolved by a `SourceLoader`: `FileLoader` reads the file
+/// system relative to the including file, `MemoryLoader` serves a fixed set
+/// of named sources.
+use std::collections::HashMap;
+use std::path::{Component, Path, PathBuf};
+
+use crate::ast::{ScalarValue, Span, Statement, TagValue};
+use crate::error::MOTLYError;
+use crate::lexer::{tokenize, TokenKind};
+use crate::parser;
+
+/// Finds and reads included sources.
+pub trait SourceLoader {
+    /// The name of the source that `target`, as written in an include in
+    /// source `from`, refers to. Two includes of the same source must
+    /// resolve to the same name, which is how cycles are detected.
+    fn resolve(&self, from: &str, target: &str) -> String;
+
+    /// The text of the source called `name`.
+    fn load(&self, name: &str) -> Result<String, String>;
+}
+
+/// Loads files from disk. Relative targets are resolved against the
+/// directory of the including file.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct FileLoader;
+
+impl SourceLoader for FileLoader {
+    fn resolve(&self, from: &str, target: &str) -> String {
+        let base = Path::new(from).parent().unwrap_or(Path::new(""));
+        normalize(&base.join(target)).to_string_lossy().into_owned()
+    }
+
+    fn load(&self, name: &str) -> Result<String, String> {
+        std::fs::read_to_string(name).map_err(|err| err.to_string())
+    }
+}
+
+/// Serves sources from memory, resolving names like relative file paths.
+#[derive(Debug, Clone, Default)]
+pub struct MemoryLoader {
+    sources: HashMap<String, String>,
+}
+
+impl MemoryLoader {
+    pub fn new() -> Self {
+        MemoryLoader::default()
+    }
+
+    pub fn add(&mut self, name: impl Into<String>, text: impl Into<String>) {
+        self.sources.insert(name.into(), text.into());
+    }
+}
+
+impl SourceLoader for MemoryLoader {
+    fn resolve(&self, from: &str, target: &str) -> String {
+        FileLoader.resolve(from, target)
+    }
+
+    fn load(&self, name: &str) -> Result<String, String> {
+        self.sources.get(name).cloned().ok_or_else(|| "no such source".to_string())
+    }
+}
+
+/// Remove `.` and `..` components without touching the file system, so a
+/// file reached by different relative routes gets one name.
+fn normalize(path: &Path) -> PathBuf {
+    let mut out = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::CurDir => {}
+            Component::ParentDir if matches!(out.components().next_back(), Some(Component::Normal(_))) => {
+                out.pop();
+            }
+            other => out.push(other),
+        }
+    }
+    out
+}
+
+/// One `#! include` directive.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Include {
+    /// The source to include, as written.
+    pub target: String,
+    /// Where to mount it; empty for the root.
+    pub at: Vec<String>,
+    /// The directive line.
+    pub span: Span,
+}
+
+/// The include directives in `source`, in order. A directive whose key is
+/// `include` that cannot be understood is reported as `invalid-include`;
+/// other directives are left to the tools that use them.
+pub fn include_directives(source: &str) -> (Vec<Include>, Vec<MOTLYError>) {
+    let mut includes = Vec::new();
+    let mut errors = Vec::new();
+    for token in tokenize(source) {
+        if token.kind != TokenKind::Directive {
+            continue;
+        }
+        let body = &token.text(source)[2..];
+        let Some(rest) = body.trim_start().strip_prefix("include") else {
+            continue;
+        };
+        // `#! included_by=ops` is some other tool's directive
+        if !rest.starts_with(|c: char| c == '=' || c.is_whitespace()) {
+            continue;
+        }
+        match read_include(body) {
+            Some((target, at)) => includes.push(Include { target, at, span: token.span }),
+            None => errors.push(MOTLYError::new(
+                "invalid-include",
+                "Expected #! include=\"path\" with an optional at=path".to_string(),
+                token.span.begin,
+                token.span.end,
+            )),
+        }
+    }
+    (includes, errors)
+}
+
+fn read_include(body: &str) -> Option<(String, Vec<String>)> {
+    let mut target = None;
+    let mut at = Vec::new();
+    for stmt in parser::parse(body).ok()? {
+        let Statement::SetEq { path, value: TagValue::Scalar(ScalarValue::String(value)), properties: None, .. } = stmt
+        else {
+            return None;
+        };
+        match path.as_slice() {
+            [key] if key == "include" && target.is_none() => target = Some(value),
+            [key] if key == "at" && at.is_empty() => {
+                at = value.split('.').map(str::to_string).collect();
+                if at.iter().any(String::is_empty) {
+                    return None;
+                }
+            }
+            _ => return None,
+        }
+    }
+    target.map(|target| (target, at))
+}
@@ -1,15 +1,52 @@
 use crate::ast::*;
-use crate::error::{MOTLYError, Position};
+use crate::diff::format_path;
+use crate::error::MOTLYError;
+use crate::parser::ParseLimits;
 use crate::tree::*;
+use std::cell::Cell;
 use std::collections::{BTreeMap, BTreeSet, HashMap, VecDeque};
 
 // ── Types ──────────────────────────────────────────────────────────
 
 /// Session-level options that control parsing behavior.
+///
+/// The `max_*` limits guard against hostile input; `None` (the default)
+/// means unlimited. Each produces its own error code when exceeded.
 #[derive(Debug, Clone, Default)]
 pub struct SessionOptions {
     /// When true, `$`-references produce errors. `:= $ref` (clone) is always allowed.
     pub disable_references: bool,
+    /// Maximum nesting depth of a parse (`depth-limit-exceeded`). Dotted
+    /// path segments and array levels count; see `ParseLimits::max_depth`.
+    pub max_depth: Option<usize>,
+    /// Maximum size of one parse's input in bytes (`input-limit-exceeded`).
+    pub max_input_bytes: Option<usize>,
+    /// Maximum number of statements in one parse, nested ones included
+    /// (`statement-limit-exceeded`).
+    pub max_statements: Option<usize>,
+    /// Maximum number of nodes in the tree after interpretation
+    /// (`node-limit-exceeded`).
+    pub max_nodes: Option<usize>,
+    /// Maximum total bytes copied by `:= $ref` clones during one execution,
+    /// as estimated by the in-memory size of the copied nodes and strings
+    /// (`clone-limit-exceeded`). Clones over the limit are not performed.
+    pub max_clone_bytes: Option<usize>,
+    /// Record every transformer applied to each path (see `Provenance::traced`).
+    pub trace: bool,
+    /// Record the key, value and properties spans of every statement, for
+    /// editors (see `MOTLYSession::spans`).
+    pub spans: bool,
+}
+
+impl SessionOptions {
+    /// The limits that apply while parsing.
+    pub fn parse_limits(&self) -> ParseLimits {
+        ParseLimits {
+            max_depth: self.max_depth,
+            max_input_bytes: self.max_input_bytes,
+            max_statements: self.max_statements,
+        }
+    }
 }
 
 /// Per-parse execution context, combining the parse ID with session options.
@@ -20,7 +57,7 @@ pub struct ExecContext {
 }
 
 /// A flattened operation with absolute path — output of Phase 1.
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct Transformer {
     pub path: Vec<String>,
     pub op: TransformerOp,
@@ -29,7 +66,7 @@ pub struct Transformer {
 }
 
 /// The operation a transformer performs.
-#[derive(Debug)]
+#[derive(Debug, Clone, PartialEq)]
 pub enum TransformerOp {
     /// Merge semantics: set value, preserve existing properties, first-appearance location.
     SetValue { value: TagValue },
@@ -61,11 +98,133 @@ pub struct TopoSortResult {
     pub cycles: Vec<usize>,
 }
 
+/// Where the nodes of an executed tree came from, recorded by
+/// `execute_chunked_with_provenance`.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Provenance {
+    /// The parse whose statement last wrote each path. Defining a node that
+    /// already exists is not a write; replacing or deleting a node forgets
+    /// the writers beneath it.
+    pub writers: BTreeMap<Vec<String>, u32>,
+    /// With tracing on, every transformer applied to each path, in
+    /// execution order.
+    pub trace: Option<BTreeMap<Vec<String>, Vec<TraceEntry>>>,
+    /// Every clone, in execution order: the path it wrote and the path it
+    /// copied, with `[i]` segments for array elements.
+    pub clones: Vec<(Vec<String>, Vec<String>)>,
+    applied: usize,
+}
+
+/// One transformer applied to a path.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TraceEntry {
+    /// Position in execution order, across all paths.
+    pub seq: usize,
+    pub op: TransformerOp,
+    pub parse_id: u32,
+    pub span: Span,
+    /// For a clone, the path of the subtree it copied (e.g. `defaults.server`).
+    pub clone_source: Option<String>,
+}
+
+impl TraceEntry {
+    /// Whether this operation discards what was beneath its path.
+    pub fn replaces_subtree(&self) -> bool {
+        !matches!(self.op, TransformerOp::SetValue { .. } | TransformerOp::Define)
+    }
+}
+
+impl Provenance {
+    /// A provenance that also keeps the full trace.
+    pub fn traced() -> Self {
+        Provenance { trace: Some(BTreeMap::new()), ..Provenance::default() }
+    }
+
+    fn record(&mut self, t: &Transformer) {
+        let seq = self.applied;
+        self.applied += 1;
+        let source = match &t.op {
+            TransformerOp::Clone { ups, ref_path } => clone_source_path(&t.path, *ups, ref_path),
+            _ => None,
+        };
+        if let Some(source) = &source {
+            self.clones.push((t.path.clone(), source.clone()));
+        }
+        if let Some(trace) = &mut self.trace {
+            let clone_source = source.as_deref().map(format_path);
+            trace.entry(t.path.clone()).or_default().push(TraceEntry {
+                seq,
+                op: t.op.clone(),
+                parse_id: t.parse_id,
+                span: t.span,
+                clone_source,
+            });
+        }
+        match t.op {
+            TransformerOp::Define => {
+                self.writers.entry(t.path.clone()).or_insert(t.parse_id);
+                return;
+            }
+            TransformerOp::SetValue { .. } => {}
+            _ => self.writers.retain(|path, _| !(path.len() > t.path.len() && path.starts_with(&t.path))),
+        }
+        self.writers.insert(t.path.clone(), t.parse_id);
+    }
+
+    /// How the node at `path` got its value: the traced entries for the
+    /// path itself plus those of ancestors that replaced the subtree it is
+    /// in (an ancestor's `:=` or clone, say), in execution order, each with
+    /// the path it applied to. Empty without tracing.
+    pub fn history(&self, path: &[String]) -> Vec<(&[String], &TraceEntry)> {
+        let Some(trace) = &self.trace else {
+            return Vec::new();
+        };
+        let mut entries = Vec::new();
+        for len in 0..=path.len() {
+            if let Some((key, list)) = trace.get_key_value(&path[..len]) {
+                for entry in list {
+                    if len == path.len() || entry.replaces_subtree() {
+                        entries.push((key.as_slice(), entry));
+                    }
+                }
+            }
+        }
+        entries.sort_by_key(|(_, entry)| entry.seq);
+        entries
+    }
+}
+
+/// The path a clone reference designates, written out from the root.
+/// Relative references count their ups from the statement's parent, as in
+/// `resolve_clone_target`.
+fn clone_source_path(stmt_path: &[String], ups: usize, ref_path: &[RefPathSegment]) -> Option<Vec<String>> {
+    let base = if ups == 0 { &[][..] } else { &stmt_path[..stmt_path.len().checked_sub(1 + ups)?] };
+    let mut out = base.to_vec();
+    for seg in ref_path {
+        match seg {
+            RefPathSegment::Name(name) => out.push(name.clone()),
+            RefPathSegment::Index(idx) => out.push(format!("[{}]", idx)),
+        }
+    }
+    Some(out)
+}
+
+/// Per-transformer context during Phase 4, sharing the execution-wide
+/// clone budget.
+struct ApplyContext<'a> {
+    parse_id: u32,
+    options: &'a SessionOptions,
+    clone_bytes: &'a Cell<usize>,
+}
+
 /// A clone that failed to resolve, tracked for circular dependency detection.
 struct FailedClone {
     source_path: String,
     target_path: String,
     error_index: usize,
+    /// The clone statement.
+    span: Span,
+    parse_id: u32,
 }
 
 // ── Phase 1: Flatten ───────────────────────────────────────────────
@@ -400,24 +559,102 @@ pub fn execute_chunked(
     order: &[usize],
     root: &mut MOTLYDataNode,
     options: &SessionOptions,
+) -> Vec<MOTLYError> {
+    execute_chunked_with_provenance(transformers, splits, order, root, options, &mut Provenance::default())
+}
+
+/// `execute_chunked`, also recording which parse wrote each node.
+pub fn execute_chunked_with_provenance(
+    transformers: &[Transformer],
+    splits: &[usize],
+    order: &[usize],
+    root: &mut MOTLYDataNode,
+    options: &SessionOptions,
+    provenance: &mut Provenance,
 ) -> Vec<MOTLYError> {
     let mut errors = Vec::new();
     let mut failed_clones: Vec<FailedClone> = Vec::new();
+    let clone_bytes = Cell::new(0);
 
     for &chunk_idx in order {
         let start = splits[chunk_idx];
         let end = splits[chunk_idx + 1];
         for t in &transformers[start..end] {
-            apply_transformer(t, root, options, &mut errors, &mut failed_clones);
+            let ctx = ApplyContext { parse_id: t.parse_id, options, clone_bytes: &clone_bytes };
+            let first_new = errors.len();
+            apply_transformer(t, root, &ctx, &mut errors, &mut failed_clones);
+            for err in &mut errors[first_new..] {
+                err.parse_id.get_or_insert(t.parse_id);
+            }
+            provenance.record(t);
         }
     }
 
     replace_circular_clone_errors(&mut errors, &failed_clones);
+
+    if let Some(max) = options.max_nodes {
+        let nodes = count_nodes(root);
+        if nodes > max {
+            errors.push(MOTLYError::general(
+                "node-limit-exceeded",
+                format!("Result has {} nodes; the limit is {}", nodes, max),
+            ));
+        }
+    }
     errors
 }
 
+/// Number of nodes in a tree, counting links and array elements.
+fn count_nodes(node: &MOTLYDataNode) -> usize {
+    fn count_child(child: &MOTLYNode) -> usize {
+        match child {
+            MOTLYNode::Data(d) => count_nodes(d),
+            MOTLYNode::Ref { .. } => 1,
+        }
+    }
+    let mut n = 1;
+    if let Some(EqValue::Array(arr)) = &node.eq {
+        n += arr.iter().map(count_child).sum::<usize>();
+    }
+    if let Some(props) = &node.properties {
+        n += props.values().map(count_child).sum::<usize>();
+    }
+    n
+}
+
+/// Estimated heap and inline size of a subtree, for the clone budget.
+fn estimate_bytes(node: &MOTLYDataNode) -> usize {
+    fn child_bytes(child: &MOTLYNode) -> usize {
+        match child {
+            MOTLYNode::Data(d) => estimate_bytes(d),
+            MOTLYNode::Ref { link_to, .. } => {
+                std::mem::size_of::<MOTLYNode>()
+                    + link_to
+                        .iter()
+                        .map(|seg| match seg {
+                            RefSegment::Name(name) => name.len(),
+                            RefSegment::Index(_) => 0,
+                        } + std::mem::size_of::<RefSegment>())
+                        .sum::<usize>()
+            }
+        }
+    }
+    let mut n = std::mem::size_of::<MOTLYNode>();
+    match &node.eq {
+        Some(EqValue::Scalar(Scalar::String(s))) | Some(EqValue::Scalar(Scalar::Date(s))) => n += s.len(),
+        Some(EqValue::EnvRef(name)) => n += name.len(),
+        Some(EqValue::Array(arr)) => n += arr.iter().map(child_bytes).sum::<usize>(),
+        _ => {}
+    }
+    if let Some(props) = &node.properties {
+        n += props.iter().map(|(key, child)| key.len() + child_bytes(child)).sum::<usize>();
+    }
+    n
+}
+
 /// Detect circular dependencies among failed clones and replace their
-/// unresolved-clone-reference errors with a single circular-reference error.
+/// unresolved-clone-reference errors with a single circular-reference error,
+/// placed on the first clone of the cycle and naming where each one is.
 fn replace_circular_clone_errors(errors: &mut Vec<MOTLYError>, failed_clones: &[FailedClone]) {
     if failed_clones.len() < 2 {
         return;
@@ -472,7 +709,18 @@ fn replace_circular_clone_errors(errors: &mut Vec<MOTLYError>, failed_clones: &[
                 // Build descriptive message
                 let display_path = |p: &str| p.replace('\0', ".");
                 let parts: Vec<String> = cycle_members.iter().map(|m| display_path(&m.source_path)).collect();
-                let refs: Vec<String> = cycle_members.iter().map(|m| format!("${}", display_path(&m.target_path))).collect();
+                let first = cycle_members[0];
+                let refs: Vec<String> = cycle_members
+                    .iter()
+                    .map(|m| {
+                        let at = format!("{}:{}", m.span.begin.line + 1, m.span.begin.column + 1);
+                        if m.parse_id == first.parse_id {
+                            format!("${} (at {})", display_path(&m.target_path), at)
+                        } else {
+                            format!("${} (at {} in parse {})", display_path(&m.target_path), at, m.parse_id)
+                        }
+                    })
+                    .collect();
                 let mut desc = parts[0].clone();
                 for i in 0..refs.len() {
                     desc.push_str(&format!(" clones {}", refs[i]));
@@ -486,13 +734,10 @@ fn replace_circular_clone_errors(errors: &mut Vec<MOTLYError>, failed_clones: &[
                 }
 
                 // Replace the first cycle member's error with the circular-reference error
-                let first_idx = cycle_members[0].error_index;
-                let zero = Position { line: 0, column: 0, offset: 0 };
-                errors[first_idx] = MOTLYError {
-                    code: "circular-reference".to_string(),
-                    message: format!("Circular clone dependency: {}", desc),
-                    begin: zero,
-                    end: zero,
+                let message = format!("Circular clone dependency: {}", desc);
+                errors[first.error_index] = MOTLYError {
+                    parse_id: Some(first.parse_id),
+                    ..MOTLYError::new("circular-reference", message, first.span.begin, first.span.end)
                 };
             }
         }
@@ -515,29 +760,27 @@ fn replace_circular_clone_errors(errors: &mut Vec<MOTLYError>, failed_clones: &[
 fn apply_transformer(
     t: &Transformer,
     root: &mut MOTLYDataNode,
-    options: &SessionOptions,
+    ctx: &ApplyContext,
     errors: &mut Vec<MOTLYError>,
     failed_clones: &mut Vec<FailedClone>,
 ) {
-    let ctx = ExecContext { parse_id: t.parse_id, options: options.clone() };
-
     match &t.op {
         TransformerOp::SetValue { value } =>
-            apply_set_value(&t.path, value, root, &ctx, t.span, errors),
+            apply_set_value(&t.path, value, root, ctx, t.span, errors),
         TransformerOp::AssignValue { value } =>
-            apply_assign_value(&t.path, value, root, &ctx, t.span, errors),
+            apply_assign_value(&t.path, value, root, ctx, t.span, errors),
         TransformerOp::ClearProperties =>
-            apply_clear_properties(&t.path, root, &ctx, t.span, errors),
+            apply_clear_properties(&t.path, root, ctx, t.span, errors),
         TransformerOp::ClearAll =>
-            apply_clear_all(&t.path, root, &ctx, t.span, errors),
+            apply_clear_all(&t.path, root, ctx, t.span, errors),
         TransformerOp::Define =>
-            apply_define(&t.path, root, &ctx, t.span, errors),
+            apply_define(&t.path, root, ctx, t.span, errors),
         TransformerOp::Delete =>
-            apply_delete(&t.path, root, &ctx, t.span, errors),
+            apply_delete(&t.path, root, ctx, t.span, errors),
         TransformerOp::Link { ups, ref_path } =>
-            apply_link(&t.path, *ups, ref_path, root, &ctx, t.span, errors),
+            apply_link(&t.path, *ups, ref_path, root, ctx, t.span, errors),
         TransformerOp::Clone { ups, ref_path } =>
-            apply_clone(&t.path, *ups, ref_path, root, &ctx, t.span, errors, failed_clones),
+            apply_clone(&t.path, *ups, ref_path, root, ctx, t.span, errors, failed_clones),
     }
 }
 
@@ -546,7 +789,7 @@ fn apply_set_value(
     path: &[String],
     value: &TagValue,
     root: &mut MOTLYDataNode,
-    ctx: &ExecContext,
+    ctx: &ApplyContext,
     span: Span,
     errors: &mut Vec<MOTLYError>,
 ) {
@@ -569,7 +812,7 @@ fn apply_assign_value(
     path: &[String],
     value: &TagValue,
     root: &mut MOTLYDataNode,
-    ctx: &ExecContext,
+    ctx: &ApplyContext,
     span: Span,
     errors: &mut Vec<MOTLYError>,
 ) {
@@ -592,7 +835,7 @@ fn apply_assign_value(
 fn apply_clear_properties(
     path: &[String],
     root: &mut MOTLYDataNode,
-    ctx: &ExecContext,
+    ctx: &ApplyContext,
     span: Span,
     errors: &mut Vec<MOTLYError>,
 ) {
@@ -621,7 +864,7 @@ fn apply_clear_properties(
 fn apply_clear_all(
     path: &[String],
     root: &mut MOTLYDataNode,
-    ctx: &ExecContext,
+    ctx: &ApplyContext,
     span: Span,
     errors: &mut Vec<MOTLYError>,
 ) {
@@ -651,7 +894,7 @@ fn apply_clear_all(
 fn apply_define(
     path: &[String],
     root: &mut MOTLYDataNode,
-    ctx: &ExecContext,
+    ctx: &ApplyContext,
     span: Span,
     errors: &mut Vec<MOTLYError>,
 ) {
@@ -671,7 +914,7 @@ fn apply_define(
 fn apply_delete(
     path: &[String],
     root: &mut MOTLYDataNode,
-    ctx: &ExecContext,
+    ctx: &ApplyContext,
     span: Span,
     errors: &mut Vec<MOTLYError>,
 ) {
@@ -689,22 +932,22 @@ fn apply_link(
     ups: usize,
     ref_path: &[RefPathSegment],
     root: &mut MOTLYDataNode,
-    ctx: &ExecContext,
+    ctx: &ApplyContext,
     span: Span,
     errors: &mut Vec<MOTLYError>,
 ) {
     if ctx.options.disable_references {
-        errors.push(MOTLYError {
-            code: "ref-not-allowed".to_string(),
-            message: "References are not allowed in this session. Use := for cloning.".to_string(),
-            begin: span.begin,
-            end: span.end,
-        });
+        errors.push(MOTLYError::new(
+            "ref-not-allowed",
+            "References are not allowed in this session. Use := for cloning.".to_string(),
+            span.begin,
+            span.end,
+        ));
     }
     let result = build_access_path(root, path, ctx, span, errors);
     if result.is_none() { return; }
     let (write_key, parent) = result.unwrap();
-    parent.get_or_create_properties().insert(write_key, make_ref(ups, ref_path));
+    parent.get_or_create_properties().insert(write_key, make_ref(ups, ref_path, make_location(ctx, span)));
 }
 
 /// Resolve a reference target, deep-copy it, and place the clone at path.
@@ -714,7 +957,7 @@ fn apply_clone(
     ups: usize,
     ref_path: &[RefPathSegment],
     root: &mut MOTLYDataNode,
-    ctx: &ExecContext,
+    ctx: &ApplyContext,
     span: Span,
     errors: &mut Vec<MOTLYError>,
     failed_clones: &mut Vec<FailedClone>,
@@ -725,17 +968,18 @@ fn apply_clone(
     }
 
     // Now resolve and clone (immutable borrow of root)
-    let cloned = resolve_and_clone(root, path, ups, ref_path);
+    let cloned = resolve_and_clone(root, path, ups, ref_path, ctx, span);
 
     match cloned {
         Ok(mut cloned) => {
-            sanitize_cloned_refs(&mut cloned, 0, errors);
+            sanitize_cloned_refs(&mut cloned, 0, span, errors);
             cloned.location = Some(make_location(ctx, span));
             let result = build_access_path(root, path, ctx, span, errors);
             if let Some((write_key, parent)) = result {
                 parent.get_or_create_properties().insert(write_key, MOTLYNode::Data(cloned));
             }
         }
+        Err(err) if err.error.code == "clone-limit-exceeded" => errors.push(err.into()),
         Err(err) => {
             let error_index = errors.len();
             errors.push(err.into());
@@ -743,6 +987,8 @@ fn apply_clone(
                 source_path: serialize_path(path),
                 target_path: resolve_ref_target_key(path, ups, ref_path),
                 error_index,
+                span,
+                parse_id: ctx.parse_id,
             });
         }
     }
@@ -756,7 +1002,7 @@ fn execute_statement(
     stmt: &Statement,
     node: &mut MOTLYDataNode,
     errors: &mut Vec<MOTLYError>,
-    ctx: &ExecContext,
+    ctx: &ApplyContext,
 ) {
     match stmt {
         Statement::SetEq { path, value, properties, span } =>
@@ -782,31 +1028,30 @@ fn execute_set_eq(
     value: &TagValue,
     properties: Option<&[Statement]>,
     errors: &mut Vec<MOTLYError>,
-    ctx: &ExecContext,
+    ctx: &ApplyContext,
     span: Span,
 ) {
     // Special case: reference value → insert as MOTLYNode::Ref
     if let TagValue::Scalar(ScalarValue::Reference { ups, path: ref_path }) = value {
         if ctx.options.disable_references {
-            errors.push(MOTLYError {
-                code: "ref-not-allowed".to_string(),
-                message: "References are not allowed in this session. Use := for cloning.".to_string(),
-                begin: span.begin,
-                end: span.end,
-            });
+            errors.push(MOTLYError::new(
+                "ref-not-allowed",
+                "References are not allowed in this session. Use := for cloning.".to_string(),
+                span.begin,
+                span.end,
+            ));
         }
         if properties.is_some() {
-            let zero = Position { line: 0, column: 0, offset: 0 };
-            errors.push(MOTLYError {
-                code: "ref-with-properties".to_string(),
-                message: "Cannot add properties to a reference. Did you mean := (clone)?".to_string(),
-                begin: zero,
-                end: zero,
-            });
+            errors.push(MOTLYError::new(
+                "ref-with-properties",
+                "Cannot add properties to a reference. Did you mean := (clone)?".to_string(),
+                span.begin,
+                span.end,
+            ));
         }
         let result = build_access_path(node, path, ctx, span, errors);
         if let Some((write_key, parent)) = result {
-            parent.get_or_create_properties().insert(write_key, make_ref(*ups, ref_path));
+            parent.get_or_create_properties().insert(write_key, make_ref(*ups, ref_path, make_location(ctx, span)));
         }
         return;
     }
@@ -832,14 +1077,14 @@ fn execute_assign_both(
     value: &TagValue,
     properties: Option<&[Statement]>,
     errors: &mut Vec<MOTLYError>,
-    ctx: &ExecContext,
+    ctx: &ApplyContext,
     span: Span,
 ) {
     if let TagValue::Scalar(ScalarValue::Reference { ups, path: ref_path }) = value {
-        let cloned = resolve_and_clone(node, path, *ups, ref_path);
+        let cloned = resolve_and_clone(node, path, *ups, ref_path, ctx, span);
         match cloned {
             Ok(mut cloned) => {
-                sanitize_cloned_refs(&mut cloned, 0, errors);
+                sanitize_cloned_refs(&mut cloned, 0, span, errors);
                 if let Some(prop_stmts) = properties {
                     cloned.properties = Some(BTreeMap::new());
                     for s in prop_stmts {
@@ -877,7 +1122,7 @@ fn execute_replace_properties(
     path: &[String],
     properties: &[Statement],
     errors: &mut Vec<MOTLYError>,
-    ctx: &ExecContext,
+    ctx: &ApplyContext,
     span: Span,
 ) {
     let access = build_access_path(node, path, ctx, span, errors);
@@ -904,7 +1149,7 @@ fn execute_update_properties(
     path: &[String],
     properties: &[Statement],
     errors: &mut Vec<MOTLYError>,
-    ctx: &ExecContext,
+    ctx: &ApplyContext,
     span: Span,
 ) {
     let access = build_access_path(node, path, ctx, span, errors);
@@ -913,12 +1158,12 @@ fn execute_update_properties(
     let props = parent.get_or_create_properties();
 
     if let Some(MOTLYNode::Ref { .. }) = props.get(&write_key) {
-        errors.push(MOTLYError {
-            code: "write-through-link".to_string(),
-            message: format!("Cannot write through link reference \"{}\"", write_key),
-            begin: span.begin,
-            end: span.end,
-        });
+        errors.push(MOTLYError::new(
+            "write-through-link",
+            format!("Cannot write through link reference \"{}\"", write_key),
+            span.begin,
+            span.end,
+        ));
         return;
     }
 
@@ -935,7 +1180,7 @@ fn execute_define(
     path: &[String],
     deleted: bool,
     errors: &mut Vec<MOTLYError>,
-    ctx: &ExecContext,
+    ctx: &ApplyContext,
     span: Span,
 ) {
     let access = build_access_path(node, path, ctx, span, errors);
@@ -959,7 +1204,7 @@ fn execute_define(
 // ── Shared helpers ─────────────────────────────────────────────────
 
 /// Build a MOTLYLocation from a parse_id and span.
-fn make_location(ctx: &ExecContext, span: Span) -> MOTLYLocation {
+fn make_location(ctx: &ApplyContext, span: Span) -> MOTLYLocation {
     MOTLYLocation {
         parse_id: ctx.parse_id,
         begin: span.begin,
@@ -968,7 +1213,7 @@ fn make_location(ctx: &ExecContext, span: Span) -> MOTLYLocation {
 }
 
 /// Set location on a node only if it doesn't already have one (first-appearance rule).
-fn set_first_location(node: &mut MOTLYDataNode, ctx: &ExecContext, span: Span) {
+fn set_first_location(node: &mut MOTLYDataNode, ctx: &ApplyContext, span: Span) {
     if node.location.is_none() {
         node.location = Some(make_location(ctx, span));
     }
@@ -979,7 +1224,7 @@ fn set_first_location(node: &mut MOTLYDataNode, ctx: &ExecContext, span: Span) {
 fn build_access_path<'a>(
     node: &'a mut MOTLYDataNode,
     path: &[String],
-    ctx: &ExecContext,
+    ctx: &ApplyContext,
     span: Span,
     errors: &mut Vec<MOTLYError>,
 ) -> Option<(String, &'a mut MOTLYDataNode)> {
@@ -992,12 +1237,12 @@ fn build_access_path<'a>(
 
         // Check for write-through-link
         if let Some(MOTLYNode::Ref { .. }) = props.get(segment) {
-            errors.push(MOTLYError {
-                code: "write-through-link".to_string(),
-                message: format!("Cannot write through link reference \"{}\"", segment),
-                begin: span.begin,
-                end: span.end,
-            });
+            errors.push(MOTLYError::new(
+                "write-through-link",
+                format!("Cannot write through link reference \"{}\"", segment),
+                span.begin,
+                span.end,
+            ));
             return None;
         }
 
@@ -1016,7 +1261,7 @@ fn build_access_path<'a>(
 }
 
 /// Set the eq slot on a target node from a TagValue.
-fn set_eq_slot(target: &mut MOTLYDataNode, value: &TagValue, errors: &mut Vec<MOTLYError>, ctx: &ExecContext) {
+fn set_eq_slot(target: &mut MOTLYDataNode, value: &TagValue, errors: &mut Vec<MOTLYError>, ctx: &ApplyContext) {
     match value {
         TagValue::Array(elements) => {
             target.eq = Some(EqValue::Array(resolve_array(elements, errors, ctx)));
@@ -1051,7 +1296,7 @@ fn set_eq_slot(target: &mut MOTLYDataNode, value: &TagValue, errors: &mut Vec<MO
 fn resolve_array(
     elements: &[ArrayElement],
     errors: &mut Vec<MOTLYError>,
-    ctx: &ExecContext,
+    ctx: &ApplyContext,
 ) -> Vec<MOTLYNode> {
     elements.iter().map(|el| resolve_array_element(el, errors, ctx)).collect()
 }
@@ -1059,28 +1304,27 @@ fn resolve_array(
 fn resolve_array_element(
     el: &ArrayElement,
     errors: &mut Vec<MOTLYError>,
-    ctx: &ExecContext,
+    ctx: &ApplyContext,
 ) -> MOTLYNode {
     // Check if the element value is a reference → becomes MOTLYNode::Ref
     if let Some(TagValue::Scalar(ScalarValue::Reference { ups, path })) = &el.value {
         if ctx.options.disable_references {
-            errors.push(MOTLYError {
-                code: "ref-not-allowed".to_string(),
-                message: "References are not allowed in this session. Use := for cloning.".to_string(),
-                begin: el.span.begin,
-                end: el.span.end,
-            });
+            errors.push(MOTLYError::new(
+                "ref-not-allowed",
+                "References are not allowed in this session. Use := for cloning.".to_string(),
+                el.span.begin,
+                el.span.end,
+            ));
         }
         if el.properties.is_some() {
-            let zero = Position { line: 0, column: 0, offset: 0 };
-            errors.push(MOTLYError {
-                code: "ref-with-properties".to_string(),
-                message: "Cannot add properties to a reference. Did you mean := (clone)?".to_string(),
-                begin: zero,
-                end: zero,
-            });
+            errors.push(MOTLYError::new(
+                "ref-with-properties",
+                "Cannot add properties to a reference. Did you mean := (clone)?".to_string(),
+                el.span.begin,
+                el.span.end,
+            ));
         }
-        return make_ref(*ups, path);
+        return make_ref(*ups, path, make_location(ctx, el.span));
     }
 
     let mut node = MOTLYDataNode::new();
@@ -1100,10 +1344,11 @@ fn resolve_array_element(
 }
 
 /// Convert AST RefPathSegments to tree RefSegments and build a MOTLYNode::Ref.
-fn make_ref(ups: usize, path: &[RefPathSegment]) -> MOTLYNode {
+fn make_ref(ups: usize, path: &[RefPathSegment], location: MOTLYLocation) -> MOTLYNode {
     MOTLYNode::Ref {
         link_to: convert_segments(path),
         link_ups: ups,
+        location: Some(location),
     }
 }
 
@@ -1119,27 +1364,59 @@ fn convert_segments(path: &[RefPathSegment]) -> Vec<RefSegment> {
 
 // ── Clone support ──────────────────────────────────────────────────
 
-/// Resolve a reference path in the tree and return a deep clone.
+/// Resolve a reference path in the tree and return a deep clone, charged
+/// against the execution's clone budget.
 /// Follows link references when encountered along the path or at the target.
 fn resolve_and_clone(
     root: &MOTLYDataNode,
     stmt_path: &[String],
     ups: usize,
     ref_path: &[RefPathSegment],
+    ctx: &ApplyContext,
+    span: Span,
 ) -> Result<MOTLYDataNode, CloneError> {
+    let target = resolve_clone_target(root, stmt_path, ups, ref_path).map_err(|err| err.at(span))?;
+    if let Some(max) = ctx.options.max_clone_bytes {
+        let total = ctx.clone_bytes.get() + estimate_bytes(target);
+        if total > max {
+            return Err(CloneError {
+                error: MOTLYError::new(
+                    "clone-limit-exceeded",
+                    format!(
+                        "Clone of {} would copy more than the {} byte clone limit",
+                        format_ref_display(ups, &convert_segments(ref_path)),
+                        max
+                    ),
+                    span.begin,
+                    span.end,
+                ),
+            });
+        }
+        ctx.clone_bytes.set(total);
+    }
+    Ok(target.clone())
+}
+
+/// Find the node a clone reference points to, without copying anything.
+fn resolve_clone_target<'a>(
+    root: &'a MOTLYDataNode,
+    stmt_path: &[String],
+    ups: usize,
+    ref_path: &[RefPathSegment],
+) -> Result<&'a MOTLYDataNode, CloneError> {
     let ref_str = format_ref_display(ups, &convert_segments(ref_path));
 
     let mut current = if ups == 0 {
-        root.clone()
+        root
     } else {
         let context_len = stmt_path.len().checked_sub(1 + ups);
         match context_len {
             Some(len) => {
-                let mut cur = root.clone();
+                let mut cur = root;
                 for seg in &stmt_path[..len] {
                     cur = match cur.properties.as_ref().and_then(|p| p.get(seg)) {
-                        Some(MOTLYNode::Data(child)) => child.clone(),
-                        Some(MOTLYNode::Ref { link_to, link_ups }) => {
+                        Some(MOTLYNode::Data(child)) => child,
+                        Some(MOTLYNode::Ref { link_to, link_ups, .. }) => {
                             match resolve_ref_from_root(root, *link_ups, link_to) {
                                 Some(resolved) => resolved,
                                 None => {
@@ -1174,8 +1451,8 @@ fn resolve_and_clone(
         current = match seg {
             RefPathSegment::Name(name) => {
                 match current.properties.as_ref().and_then(|p| p.get(name.as_str())) {
-                    Some(MOTLYNode::Data(child)) => child.clone(),
-                    Some(MOTLYNode::Ref { link_to, link_ups }) => {
+                    Some(MOTLYNode::Data(child)) => child,
+                    Some(MOTLYNode::Ref { link_to, link_ups, .. }) => {
                         match resolve_ref_from_root(root, *link_ups, link_to) {
                             Some(resolved) => resolved,
                             None => {
@@ -1204,8 +1481,8 @@ fn resolve_and_clone(
                             )));
                         }
                         match &arr[*idx] {
-                            MOTLYNode::Data(child) => child.clone(),
-                            MOTLYNode::Ref { link_to, link_ups } => {
+                            MOTLYNode::Data(child) => child,
+                            MOTLYNode::Ref { link_to, link_ups, .. } => {
                                 match resolve_ref_from_root(root, *link_ups, link_to) {
                                     Some(resolved) => resolved,
                                     None => {
@@ -1236,6 +1513,15 @@ struct CloneError {
     error: MOTLYError,
 }
 
+impl CloneError {
+    /// Place the error on the clone statement.
+    fn at(mut self, span: Span) -> CloneError {
+        self.error.begin = span.begin;
+        self.error.end = span.end;
+        self
+    }
+}
+
 impl From<CloneError> for MOTLYError {
     fn from(ce: CloneError) -> MOTLYError {
         ce.error
@@ -1243,49 +1529,41 @@ impl From<CloneError> for MOTLYError {
 }
 
 fn clone_error(message: String) -> CloneError {
-    let zero = Position { line: 0, column: 0, offset: 0 };
     CloneError {
-        error: MOTLYError {
-            code: "unresolved-clone-reference".to_string(),
-            message,
-            begin: zero,
-            end: zero,
-        },
+        error: MOTLYError::general("unresolved-clone-reference", message),
     }
 }
 
 /// Follow a MOTLYRef from root to its concrete MOTLYDataNode target.
 /// Only handles absolute refs (ups == 0). Returns None on failure or cycle.
-fn resolve_ref_from_root(
-    root: &MOTLYDataNode,
+fn resolve_ref_from_root<'a>(
+    root: &'a MOTLYDataNode,
     ups: usize,
     segments: &[RefSegment],
-) -> Option<MOTLYDataNode> {
+) -> Option<&'a MOTLYDataNode> {
     resolve_ref_from_root_inner(root, ups, segments, &mut Vec::new())
 }
 
-fn resolve_ref_from_root_inner(
-    root: &MOTLYDataNode,
+fn resolve_ref_from_root_inner<'a>(
+    root: &'a MOTLYDataNode,
     ups: usize,
     segments: &[RefSegment],
     visited: &mut Vec<String>,
-) -> Option<MOTLYDataNode> {
+) -> Option<&'a MOTLYDataNode> {
     if ups > 0 { return None; }
 
     let key = format_ref_display(ups, segments);
     if visited.contains(&key) { return None; } // cycle
     visited.push(key);
 
-    let mut current = root.clone();
+    let mut current = root;
 
     for seg in segments {
         let pv = match seg {
-            RefSegment::Name(name) => {
-                current.properties.as_ref()?.get(name)?.clone()
-            }
+            RefSegment::Name(name) => current.properties.as_ref()?.get(name)?,
             RefSegment::Index(idx) => {
                 match &current.eq {
-                    Some(EqValue::Array(arr)) if *idx < arr.len() => arr[*idx].clone(),
+                    Some(EqValue::Array(arr)) if *idx < arr.len() => &arr[*idx],
                     _ => return None,
                 }
             }
@@ -1293,8 +1571,8 @@ fn resolve_ref_from_root_inner(
 
         match pv {
             MOTLYNode::Data(d) => current = d,
-            MOTLYNode::Ref { link_to, link_ups } => {
-                current = resolve_ref_from_root_inner(root, link_ups, &link_to, visited)?;
+            MOTLYNode::Ref { link_to, link_ups, .. } => {
+                current = resolve_ref_from_root_inner(root, *link_ups, link_to, visited)?;
             }
         }
     }
@@ -1303,41 +1581,40 @@ fn resolve_ref_from_root_inner(
 }
 
 /// Walk a cloned subtree and null out any relative (^) references that
-/// escape the clone boundary.
-fn sanitize_cloned_refs(node: &mut MOTLYDataNode, depth: usize, errors: &mut Vec<MOTLYError>) {
+/// escape the clone boundary. Errors are reported at the clone statement.
+fn sanitize_cloned_refs(node: &mut MOTLYDataNode, depth: usize, span: Span, errors: &mut Vec<MOTLYError>) {
     if let Some(EqValue::Array(ref mut arr)) = node.eq {
         for elem in arr.iter_mut() {
-            sanitize_cloned_pv(elem, depth + 1, errors);
+            sanitize_cloned_pv(elem, depth + 1, span, errors);
         }
     }
     if let Some(ref mut props) = node.properties {
         for (_key, child) in props.iter_mut() {
-            sanitize_cloned_pv(child, depth + 1, errors);
+            sanitize_cloned_pv(child, depth + 1, span, errors);
         }
     }
 }
 
-fn sanitize_cloned_pv(pv: &mut MOTLYNode, depth: usize, errors: &mut Vec<MOTLYError>) {
+fn sanitize_cloned_pv(pv: &mut MOTLYNode, depth: usize, span: Span, errors: &mut Vec<MOTLYError>) {
     match pv {
-        MOTLYNode::Ref { ref link_to, link_ups } => {
+        MOTLYNode::Ref { ref link_to, link_ups, .. } => {
             let ups = *link_ups;
             if ups > 0 && ups > depth {
                 let display = format_ref_display(ups, link_to);
-                let zero = Position { line: 0, column: 0, offset: 0 };
-                errors.push(MOTLYError {
-                    code: "clone-reference-out-of-scope".to_string(),
-                    message: format!(
+                errors.push(MOTLYError::new(
+                    "clone-reference-out-of-scope",
+                    format!(
                         "Cloned reference \"{}\" escapes the clone boundary ({} level(s) up from depth {})",
                         display, ups, depth
                     ),
-                    begin: zero,
-                    end: zero,
-                });
+                    span.begin,
+                    span.end,
+                ));
                 *pv = MOTLYNode::Data(MOTLYDataNode::new());
             }
         }
         MOTLYNode::Data(node) => {
-            sanitize_cloned_refs(node, depth, errors);
+            sanitize_cloned_refs(node, depth, span, errors);
         }
     }
 }
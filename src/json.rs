@@ -1,4 +1,9 @@
+use std::collections::HashMap;
+
 use crate::error::MOTLYError;
+use crate::from_json::JsonValue;
+use crate::secret::{Secrets, REDACTED};
+use crate::source::SourceMap;
 use crate::tree::*;
 
 /// JSON formatting style.
@@ -10,24 +15,68 @@ pub enum JsonStyle {
     Pretty,
 }
 
-struct JsonWriter {
+/// What `to_plain_json` writes for a node with both a value and
+/// properties.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ValueAndProperties {
+    /// An object of the properties, with the value first under this key.
+    Key(String),
+    /// An object of the properties; the value is left out.
+    Properties,
+    /// The value; the properties are left out.
+    Value,
+}
+
+/// Options for `to_plain_json`.
+#[derive(Clone)]
+pub struct PlainOptions {
+    pub style: JsonStyle,
+    /// Values for `@env` references. A reference not in the map is `null`.
+    pub env: HashMap<String, String>,
+    pub value_and_properties: ValueAndProperties,
+    /// Write `***` for the values of these (found before links were
+    /// followed and env references resolved; see `secret::Secrets`).
+    pub redact: Option<Secrets>,
+}
+
+impl Default for PlainOptions {
+    fn default() -> Self {
+        PlainOptions {
+            style: JsonStyle::Compact,
+            env: HashMap::new(),
+            value_and_properties: ValueAndProperties::Key("$value".to_string()),
+            redact: None,
+        }
+    }
+}
+
+struct JsonWriter<'a> {
     buf: String,
     style: JsonStyle,
     depth: usize,
     /// When true, dates are wrapped as `{"$date": "..."}` instead of plain strings.
     wire: bool,
+    /// Values to write as `***`, by the path of the node being written.
+    secrets: Option<&'a Secrets>,
+    path: Vec<String>,
 }
 
-impl JsonWriter {
+impl<'a> JsonWriter<'a> {
     fn new(style: JsonStyle) -> Self {
         JsonWriter {
             buf: String::new(),
             style,
             depth: 0,
             wire: false,
+            secrets: None,
+            path: Vec::new(),
         }
     }
 
+    fn is_secret(&self) -> bool {
+        self.secrets.is_some_and(|secrets| secrets.contains(&self.path))
+    }
+
     fn is_pretty(&self) -> bool {
         matches!(self.style, JsonStyle::Pretty)
     }
@@ -64,7 +113,12 @@ impl JsonWriter {
         if let Some(ref eq) = node.eq {
             self.entry_sep(&mut first);
             self.write_key("eq");
-            self.write_eq(eq);
+            // A reference names the variable, not its value
+            if self.is_secret() && !matches!(eq, EqValue::EnvRef(_)) {
+                self.write_string_value(REDACTED);
+            } else {
+                self.write_eq(eq);
+            }
         }
 
         // "properties": { ... }
@@ -75,18 +129,7 @@ impl JsonWriter {
         }
 
         // "location": { ... }
-        if self.wire {
-            if let Some(ref loc) = node.location {
-                self.entry_sep(&mut first);
-                self.write_key("location");
-                write!(
-                    &mut self.buf,
-                    "{{\"parseId\":{},\"begin\":{{\"line\":{},\"column\":{},\"offset\":{}}},\"end\":{{\"line\":{},\"column\":{},\"offset\":{}}}}}",
-                    loc.parse_id, loc.begin.line, loc.begin.column, loc.begin.offset,
-                    loc.end.line, loc.end.column, loc.end.offset
-                ).unwrap();
-            }
-        }
+        self.write_location(&node.location, &mut first);
 
         self.depth -= 1;
         self.newline();
@@ -96,8 +139,8 @@ impl JsonWriter {
     fn write_node(&mut self, node: &MOTLYNode) {
         match node {
             MOTLYNode::Data(n) => self.write_data_node(n),
-            MOTLYNode::Ref { link_to, link_ups } => {
-                // Serialize as {"linkTo": [...], "linkUps": N}
+            MOTLYNode::Ref { link_to, link_ups, location } => {
+                // Serialize as {"linkTo": [...], "linkUps": N, "location": {...}}
                 self.buf.push('{');
                 self.depth += 1;
                 let mut first = true;
@@ -107,6 +150,7 @@ impl JsonWriter {
                 self.entry_sep(&mut first);
                 self.write_key("linkUps");
                 write!(&mut self.buf, "{}", link_ups).unwrap();
+                self.write_location(location, &mut first);
                 self.depth -= 1;
                 self.newline();
                 self.buf.push('}');
@@ -114,6 +158,23 @@ impl JsonWriter {
         }
     }
 
+    /// Locations are only part of the wire format.
+    fn write_location(&mut self, location: &Option<MOTLYLocation>, first: &mut bool) {
+        if !self.wire {
+            return;
+        }
+        if let Some(loc) = location {
+            self.entry_sep(first);
+            self.write_key("location");
+            write!(
+                &mut self.buf,
+                "{{\"parseId\":{},\"begin\":{{\"line\":{},\"column\":{},\"offset\":{}}},\"end\":{{\"line\":{},\"column\":{},\"offset\":{}}}}}",
+                loc.parse_id, loc.begin.line, loc.begin.column, loc.begin.offset,
+                loc.end.line, loc.end.column, loc.end.offset
+            ).unwrap();
+        }
+    }
+
     fn write_eq(&mut self, eq: &EqValue) {
         match eq {
             EqValue::Scalar(scalar) => self.write_scalar(scalar),
@@ -172,7 +233,9 @@ impl JsonWriter {
                 self.buf.push(',');
             }
             self.newline();
+            self.path.push(format!("[{}]", i));
             self.write_node(item);
+            self.path.pop();
         }
 
         self.depth -= 1;
@@ -190,14 +253,137 @@ impl JsonWriter {
         for (key, value) in props {
             self.entry_sep(&mut first);
             self.write_key(key);
+            self.path.push(key.clone());
             self.write_node(value);
+            self.path.pop();
+        }
+
+        self.depth -= 1;
+        self.newline();
+        self.buf.push('}');
+    }
+
+    /// A node as a bare JSON value: scalars as themselves, properties as an
+    /// object, `null` for a node with neither. The tree has its links
+    /// resolved.
+    fn write_plain(&mut self, node: &MOTLYDataNode, options: &PlainOptions) {
+        match (&node.eq, &node.properties) {
+            (Some(eq), Some(props)) => match &options.value_and_properties {
+                ValueAndProperties::Key(key) => self.write_plain_object(Some((key, eq)), props, options),
+                ValueAndProperties::Properties => self.write_plain_object(None, props, options),
+                ValueAndProperties::Value => self.write_plain_eq(eq, options),
+            },
+            (Some(eq), None) => self.write_plain_eq(eq, options),
+            (None, Some(props)) => self.write_plain_object(None, props, options),
+            (None, None) => self.buf.push_str("null"),
+        }
+    }
+
+    fn write_plain_eq(&mut self, eq: &EqValue, options: &PlainOptions) {
+        if self.is_secret() {
+            self.write_string_value(REDACTED);
+            return;
+        }
+        match eq {
+            EqValue::Scalar(scalar) => self.write_scalar(scalar),
+            EqValue::EnvRef(name) => match options.env.get(name) {
+                Some(value) => self.write_string_value(value),
+                None => self.buf.push_str("null"),
+            },
+            EqValue::Array(arr) => {
+                self.buf.push('[');
+                self.depth += 1;
+                for (i, item) in arr.iter().enumerate() {
+                    if i > 0 {
+                        self.buf.push(',');
+                    }
+                    self.newline();
+                    self.path.push(format!("[{}]", i));
+                    match item {
+                        MOTLYNode::Data(data) => self.write_plain(data, options),
+                        MOTLYNode::Ref { .. } => self.buf.push_str("null"),
+                    }
+                    self.path.pop();
+                }
+                self.depth -= 1;
+                if !arr.is_empty() {
+                    self.newline();
+                }
+                self.buf.push(']');
+            }
         }
+    }
 
+    fn write_plain_object(
+        &mut self,
+        value: Option<(&String, &EqValue)>,
+        props: &std::collections::BTreeMap<String, MOTLYNode>,
+        options: &PlainOptions,
+    ) {
+        let props: Vec<(&String, &MOTLYDataNode)> =
+            props.iter().filter_map(|(key, child)| child.as_data_node().filter(|c| !c.deleted).map(|c| (key, c))).collect();
+        if value.is_none() && props.is_empty() {
+            self.buf.push_str("{}");
+            return;
+        }
+        self.buf.push('{');
+        self.depth += 1;
+        let mut first = true;
+        if let Some((key, eq)) = value {
+            self.entry_sep(&mut first);
+            self.write_key(key);
+            self.write_plain_eq(eq, options);
+        }
+        for (key, child) in props {
+            self.entry_sep(&mut first);
+            self.write_key(key);
+            self.path.push(key.clone());
+            self.write_plain(child, options);
+            self.path.pop();
+        }
         self.depth -= 1;
         self.newline();
         self.buf.push('}');
     }
 
+    fn write_json_value(&mut self, value: &JsonValue) {
+        match value {
+            JsonValue::Null => self.buf.push_str("null"),
+            JsonValue::Boolean(b) => self.buf.push_str(if *b { "true" } else { "false" }),
+            JsonValue::Number(n) => self.write_number(*n),
+            JsonValue::String(s) => self.write_string_value(s),
+            JsonValue::Array(items) => {
+                self.buf.push('[');
+                self.depth += 1;
+                let mut first = true;
+                for item in items {
+                    self.entry_sep(&mut first);
+                    self.write_json_value(item);
+                }
+                self.depth -= 1;
+                if !items.is_empty() {
+                    self.newline();
+                }
+                self.buf.push(']');
+            }
+            JsonValue::Object(members) => {
+                self.buf.push('{');
+                self.depth += 1;
+                let mut first = true;
+                for (key, member) in members {
+                    self.entry_sep(&mut first);
+                    self.write_key(key);
+                    self.write_json_value(member);
+                }
+                self.depth -= 1;
+                if !members.is_empty() {
+                    self.newline();
+                }
+                self.buf.push('}');
+            }
+        }
+    }
+
     fn entry_sep(&mut self, first: &mut bool) {
         if *first {
             *first = false;
@@ -266,6 +452,15 @@ pub fn to_json_pretty(node: &MOTLYDataNode) -> String {
     w.buf
 }
 
+/// Serialize a MOTLYDataNode as `to_json` does, writing `"***"` as the
+/// value of each node in `secrets`.
+pub fn to_json_redacted(node: &MOTLYDataNode, secrets: &Secrets, style: JsonStyle) -> String {
+    let mut w = JsonWriter::new(style);
+    w.secrets = Some(secrets);
+    w.write_data_node(node);
+    w.buf
+}
+
 /// Serialize a MOTLYDataNode to the internal wire format.
 ///
 /// "Wire format" is the JSON dialect used to transfer data between the
@@ -283,17 +478,66 @@ pub fn to_wire(node: &MOTLYDataNode) -> String {
     w.buf
 }
 
+/// Serialize a tree as the plain JSON its values describe, for consumers
+/// that do not know MOTLY: links are followed, `@env` references take their
+/// value from `options.env`, deleted nodes are left out and scalars are
+/// bare JSON values (dates are strings). A node with both a value and
+/// properties is written as `options.value_and_properties` says.
+pub fn to_plain_json(node: &MOTLYDataNode, options: &PlainOptions) -> String {
+    let mut w = JsonWriter::new(options.style);
+    w.secrets = options.redact.as_ref();
+    w.write_plain(&crate::query::resolve_links(node), options);
+    w.buf
+}
+
+/// Serialize an ordinary JSON value, members in order.
+pub fn json_value_to_string(value: &JsonValue, style: JsonStyle) -> String {
+    let mut w = JsonWriter::new(style);
+    w.write_json_value(value);
+    w.buf
+}
+
+/// Serialize the nodes a query selected as a pretty-printed JSON array, each
+/// as in `to_json`.
+pub fn query_matches_to_json(matches: &[crate::query::QueryMatch]) -> String {
+    let mut w = JsonWriter::new(JsonStyle::Pretty);
+    w.buf.push('[');
+    w.depth += 1;
+    let mut first = true;
+    for m in matches {
+        w.entry_sep(&mut first);
+        w.write_data_node(&m.node);
+    }
+    w.depth -= 1;
+    if !matches.is_empty() {
+        w.newline();
+    }
+    w.buf.push(']');
+    w.buf
+}
+
 /// Serialize a parse result (parseId + errors) to a JSON object string.
 pub fn parse_result_to_json(parse_id: u32, errors: &[MOTLYError]) -> String {
+    parse_result_to_json_with_sources(parse_id, errors, None)
+}
+
+/// Like `parse_result_to_json`, naming the source of each error.
+pub fn parse_result_to_json_with_sources(parse_id: u32, errors: &[MOTLYError], sources: Option<&SourceMap>) -> String {
     let mut buf = String::new();
     write!(&mut buf, "{{\"parseId\":{},\"errors\":", parse_id).unwrap();
-    buf.push_str(&errors_to_json(errors));
+    buf.push_str(&errors_to_json_with_sources(errors, sources));
     buf.push('}');
     buf
 }
 
 /// Serialize a list of parse errors to a JSON array string.
 pub fn errors_to_json(errors: &[MOTLYError]) -> String {
+    errors_to_json_with_sources(errors, None)
+}
+
+/// Serialize parse errors, adding `"source"` (the file name) and `"at"`
+/// (`file:line:col`) for errors whose parse is in `sources`.
+pub fn errors_to_json_with_sources(errors: &[MOTLYError], sources: Option<&SourceMap>) -> String {
     let mut w = JsonWriter::new(JsonStyle::Compact);
     w.buf.push('[');
     for (i, err) in errors.iter().enumerate() {
@@ -312,6 +556,12 @@ pub fn errors_to_json(errors: &[MOTLYError]) -> String {
         w.buf.push(',');
         w.write_key("end");
         write_position(&mut w, &err.end);
+        if let Some(parse_id) = err.parse_id {
+            w.buf.push(',');
+            w.write_key("parseId");
+            write!(&mut w.buf, "{}", parse_id).unwrap();
+            write_source(&mut w, sources, parse_id, &err.begin);
+        }
         w.buf.push('}');
     }
     w.buf.push(']');
@@ -327,24 +577,71 @@ fn write_position(w: &mut JsonWriter, pos: &crate::error::Position) {
     .unwrap();
 }
 
+fn write_location(w: &mut JsonWriter, loc: &MOTLYLocation) {
+    write!(&mut w.buf, "{{\"parseId\":{},\"begin\":", loc.parse_id).unwrap();
+    write_position(w, &loc.begin);
+    w.buf.push_str(",\"end\":");
+    write_position(w, &loc.end);
+    w.buf.push('}');
+}
+
+/// `,"source":"file","at":"file:line:col"` if the parse has a known source.
+fn write_source(w: &mut JsonWriter, sources: Option<&SourceMap>, parse_id: u32, pos: &crate::error::Position) {
+    let Some(sources) = sources.filter(|s| s.get(parse_id).is_some()) else {
+        return;
+    };
+    w.buf.push(',');
+    w.write_key("source");
+    w.write_string_value(&sources.name(parse_id));
+    w.buf.push(',');
+    w.write_key("at");
+    w.write_string_value(&sources.format_position(Some(parse_id), pos));
+}
+
+/// Shared body of schema and reference validation errors.
+fn write_tree_error(
+    w: &mut JsonWriter,
+    code: &str,
+    message: &str,
+    path: &[String],
+    location: Option<&MOTLYLocation>,
+    sources: Option<&SourceMap>,
+) {
+    w.buf.push('{');
+    w.write_key("code");
+    w.write_string_value(code);
+    w.buf.push(',');
+    w.write_key("message");
+    w.write_string_value(message);
+    w.buf.push(',');
+    w.write_key("path");
+    write_string_array(w, path);
+    if let Some(loc) = location {
+        w.buf.push(',');
+        w.write_key("location");
+        write_location(w, loc);
+        write_source(w, sources, loc.parse_id, &loc.begin);
+    }
+    w.buf.push('}');
+}
+
 /// Serialize schema validation errors to a JSON array string.
 pub fn schema_errors_to_json(errors: &[crate::validate::SchemaError]) -> String {
+    schema_errors_to_json_with_sources(errors, None)
+}
+
+/// Like `schema_errors_to_json`, naming the source of located errors.
+pub fn schema_errors_to_json_with_sources(
+    errors: &[crate::validate::SchemaError],
+    sources: Option<&SourceMap>,
+) -> String {
     let mut w = JsonWriter::new(JsonStyle::Compact);
     w.buf.push('[');
     for (i, err) in errors.iter().enumerate() {
         if i > 0 {
             w.buf.push(',');
         }
-        w.buf.push('{');
-        w.write_key("code");
-        w.write_string_value(err.code);
-        w.buf.push(',');
-        w.write_key("message");
-        w.write_string_value(&err.message);
-        w.buf.push(',');
-        w.write_key("path");
-        write_string_array(&mut w, &err.path);
-        w.buf.push('}');
+        write_tree_error(&mut w, err.code, &err.message, &err.path, err.location.as_ref(), sources);
     }
     w.buf.push(']');
     w.buf
@@ -352,21 +649,70 @@ pub fn schema_errors_to_json(errors: &[crate::validate::SchemaError]) -> String
 
 /// Serialize reference validation errors to a JSON array string.
 pub fn validation_errors_to_json(errors: &[crate::validate::ValidationError]) -> String {
+    validation_errors_to_json_with_sources(errors, None)
+}
+
+/// Like `validation_errors_to_json`, naming the source of located errors.
+pub fn validation_errors_to_json_with_sources(
+    errors: &[crate::validate::ValidationError],
+    sources: Option<&SourceMap>,
+) -> String {
     let mut w = JsonWriter::new(JsonStyle::Compact);
     w.buf.push('[');
     for (i, err) in errors.iter().enumerate() {
+        if i > 0 {
+            w.buf.push(',');
+        }
+        write_tree_error(&mut w, err.code, &err.message, &err.path, err.location.as_ref(), sources);
+    }
+    w.buf.push(']');
+    w.buf
+}
+
+/// Serialize a structural diff to a JSON array string. Each change has
+/// `"kind"`, `"path"`, `"before"`/`"after"` (the nodes, as in `to_json`) and
+/// `"beforeLocation"`/`"afterLocation"`, with `"beforeAt"`/`"afterAt"`
+/// (`file:line:col`) when the parse is in the old or new tree's sources.
+/// Moves also have `"from"` and `"to"`.
+pub fn changes_to_json(
+    changes: &[crate::diff::Change],
+    before_sources: Option<&SourceMap>,
+    after_sources: Option<&SourceMap>,
+) -> String {
+    let mut w = JsonWriter::new(JsonStyle::Compact);
+    w.buf.push('[');
+    for (i, change) in changes.iter().enumerate() {
         if i > 0 {
             w.buf.push(',');
         }
         w.buf.push('{');
-        w.write_key("code");
-        w.write_string_value(err.code);
-        w.buf.push(',');
-        w.write_key("message");
-        w.write_string_value(&err.message);
+        w.write_key("kind");
+        w.write_string_value(change.kind.name());
         w.buf.push(',');
         w.write_key("path");
-        write_string_array(&mut w, &err.path);
+        write_string_array(&mut w, &change.path);
+        if let crate::diff::ChangeKind::ElementMoved { from, to } = change.kind {
+            write!(&mut w.buf, ",\"from\":{},\"to\":{}", from, to).unwrap();
+        }
+        for (key, node, sources) in [
+            ("before", &change.before, before_sources),
+            ("after", &change.after, after_sources),
+        ] {
+            let Some(node) = node else { continue };
+            w.buf.push(',');
+            w.write_key(key);
+            w.write_node(node);
+            if let Some(loc) = node.location() {
+                w.buf.push(',');
+                w.write_key(&format!("{}Location", key));
+                write_location(&mut w, loc);
+                if let Some(sources) = sources.filter(|s| s.get(loc.parse_id).is_some()) {
+                    w.buf.push(',');
+                    w.write_key(&format!("{}At", key));
+                    w.write_string_value(&sources.format_position(Some(loc.parse_id), &loc.begin));
+                }
+            }
+        }
         w.buf.push('}');
     }
     w.buf.push(']');
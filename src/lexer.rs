@@ -0,0 +1,501 @@
+/// Lossless tokenizer for MOTLY source.
+/// Every byte of the input belongs to exactly one token, including
+/// whitespace, comments and anything the parser would reject, so the
+/// token stream is suitable for syntax highlighting and linting as well
+/// as for the CST.
+///
+/// Token kinds are context-sensitive in the same places the parser is:
+/// `123` is a `Number` after `=` but a `Bare` name in key position, and
+/// `-` is a `Minus` deletion marker before a name but part of a `Number`
+/// in a value.
+use crate::ast::Span;
+use crate::error::Position;
+use crate::parser::is_bare_char;
+
+/// The kind of a lexical token.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TokenKind {
+    /// A run of spaces, tabs, carriage returns and newlines.
+    Whitespace,
+    /// `# ...` to end of line.
+    Comment,
+    /// `#! ...` to end of line — a comment to the parser, by convention a
+    /// directive for tools (e.g. `#! schema=app-config`).
+    Directive,
+    /// `,` — optional statement / array element separator.
+    Comma,
+    /// `=`
+    Equals,
+    /// `:`
+    Colon,
+    /// `:=`
+    ColonEquals,
+    /// `-` — the deletion marker in `-name`.
+    Minus,
+    /// `-...`
+    ClearAll,
+    /// `.` — property path separator.
+    Dot,
+    LBrace,
+    RBrace,
+    LBracket,
+    RBracket,
+    /// Bare string or identifier: `hello`, `v2`, `123` in key position.
+    Bare,
+    /// `` `quoted name` ``
+    Backtick,
+    /// `"..."`
+    DoubleQuoted,
+    /// `'...'`
+    SingleQuoted,
+    /// `"""..."""`
+    TripleDoubleQuoted,
+    /// `'''...'''`
+    TripleSingleQuoted,
+    /// `<<<` ... `>>>`
+    Heredoc,
+    /// `42`, `-1.5`, `1e10`
+    Number,
+    /// `@true`
+    True,
+    /// `@false`
+    False,
+    /// `@none`
+    None,
+    /// `@2024-01-15`, `@2024-01-15T10:30:00Z`
+    Date,
+    /// `@env.NAME`
+    Env,
+    /// `$name`, `$^^.path[0].sub`
+    Reference,
+    /// An unrecognized character or an unterminated string.
+    Error,
+}
+
+impl TokenKind {
+    /// True for tokens the parser skips between meaningful tokens.
+    pub fn is_trivia(self) -> bool {
+        matches!(self, TokenKind::Whitespace | TokenKind::Comment | TokenKind::Directive)
+    }
+
+    /// True for `#` comments, including `#!` directives.
+    pub fn is_comment(self) -> bool {
+        matches!(self, TokenKind::Comment | TokenKind::Directive)
+    }
+}
+
+/// A token with its exact source span.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Token {
+    pub kind: TokenKind,
+    pub span: Span,
+}
+
+impl Token {
+    /// The source text covered by this token.
+    pub fn text<'a>(&self, input: &'a str) -> &'a str {
+        &input[self.span.begin.offset..self.span.end.offset]
+    }
+}
+
+/// Split `input` into tokens. Concatenating the text of every token
+/// reproduces `input` exactly.
+pub fn tokenize(input: &str) -> Vec<Token> {
+    let mut lexer = Lexer {
+        input,
+        pos: 0,
+        line: 0,
+        line_start: 0,
+        value_next: false,
+        brackets: Vec::new(),
+    };
+    let mut tokens = Vec::new();
+    while lexer.pos < input.len() {
+        tokens.push(lexer.next_token());
+    }
+    tokens
+}
+
+/// Lexer state. Numbers, dates and the `-` marker are context-sensitive, so
+/// the lexer tracks whether a value is expected (after `=`, `:=`, `[`, or
+/// `,` inside an array) and the stack of open brackets.
+struct Lexer<'a> {
+    input: &'a str,
+    pos: usize,
+    line: usize,
+    line_start: usize,
+    value_next: bool,
+    brackets: Vec<char>,
+}
+
+impl<'a> Lexer<'a> {
+    fn remaining(&self) -> &'a str {
+        &self.input[self.pos..]
+    }
+
+    fn peek_char(&self) -> Option<char> {
+        self.remaining().chars().next()
+    }
+
+    fn char_at(&self, offset: usize) -> Option<char> {
+        self.input.get(offset..).and_then(|s| s.chars().next())
+    }
+
+    fn starts_with(&self, s: &str) -> bool {
+        self.remaining().starts_with(s)
+    }
+
+    fn position(&self) -> Position {
+        Position {
+            line: self.line,
+            column: self.pos - self.line_start,
+            offset: self.pos,
+        }
+    }
+
+    /// Advance to byte offset `end`, keeping line bookkeeping in sync.
+    fn advance_to(&mut self, end: usize) {
+        for (i, ch) in self.input[self.pos..end].char_indices() {
+            if ch == '\n' {
+                self.line += 1;
+                self.line_start = self.pos + i + 1;
+            }
+        }
+        self.pos = end;
+    }
+
+    fn next_token(&mut self) -> Token {
+        let begin = self.position();
+        let (kind, end) = self.scan();
+        debug_assert!(end > self.pos, "lexer must make progress");
+        self.advance_to(end);
+
+        match kind {
+            TokenKind::Whitespace | TokenKind::Comment | TokenKind::Directive => {}
+            TokenKind::Equals | TokenKind::ColonEquals => self.value_next = true,
+            TokenKind::LBracket => {
+                self.brackets.push('[');
+                self.value_next = true;
+            }
+            TokenKind::LBrace => {
+                self.brackets.push('{');
+                self.value_next = false;
+            }
+            TokenKind::RBracket | TokenKind::RBrace => {
+                self.brackets.pop();
+                self.value_next = false;
+            }
+            TokenKind::Comma => self.value_next = self.brackets.last() == Some(&'['),
+            _ => self.value_next = false,
+        }
+
+        Token {
+            kind,
+            span: Span { begin, end: self.position() },
+        }
+    }
+
+    /// Classify the token at the current position and return its end offset.
+    fn scan(&self) -> (TokenKind, usize) {
+        let start = self.pos;
+        let ch = self.peek_char().unwrap();
+
+        match ch {
+            ' ' | '\t' | '\r' | '\n' => {
+                let len = self
+                    .remaining()
+                    .find(|c: char| !matches!(c, ' ' | '\t' | '\r' | '\n'))
+                    .unwrap_or(self.remaining().len());
+                (TokenKind::Whitespace, start + len)
+            }
+            '#' if self.starts_with("#!") => (TokenKind::Directive, self.line_end(start)),
+            '#' => (TokenKind::Comment, self.line_end(start)),
+            ',' => (TokenKind::Comma, start + 1),
+            '{' => (TokenKind::LBrace, start + 1),
+            '}' => (TokenKind::RBrace, start + 1),
+            '[' => (TokenKind::LBracket, start + 1),
+            ']' => (TokenKind::RBracket, start + 1),
+            '=' => (TokenKind::Equals, start + 1),
+            ':' if self.starts_with(":=") => (TokenKind::ColonEquals, start + 2),
+            ':' => (TokenKind::Colon, start + 1),
+            '<' if self.starts_with("<<<") => self.scan_heredoc(),
+            '"' if self.starts_with("\"\"\"") => self.scan_triple(true),
+            '\'' if self.starts_with("'''") => self.scan_triple(false),
+            '"' => self.scan_quoted(start, '"', TokenKind::DoubleQuoted, true),
+            '\'' => self.scan_quoted(start, '\'', TokenKind::SingleQuoted, false),
+            '`' => self.scan_quoted(start, '`', TokenKind::Backtick, true),
+            '@' => self.scan_at(),
+            '$' => self.scan_reference(),
+            '-' if !self.value_next && self.starts_with("-...") => (TokenKind::ClearAll, start + 4),
+            '-' | '.' if self.value_next => self.scan_number_or_bare(),
+            c if c.is_ascii_digit() && self.value_next => self.scan_number_or_bare(),
+            '-' => (TokenKind::Minus, start + 1),
+            '.' => (TokenKind::Dot, start + 1),
+            c if is_bare_char(c) => (TokenKind::Bare, self.bare_end(start)),
+            c => (TokenKind::Error, start + c.len_utf8()),
+        }
+    }
+
+    /// Offset of the next `\r` or `\n` at or after `from` (or end of input).
+    fn line_end(&self, from: usize) -> usize {
+        self.input[from..]
+            .find(['\r', '\n'])
+            .map(|i| from + i)
+            .unwrap_or(self.input.len())
+    }
+
+    fn bare_end(&self, from: usize) -> usize {
+        self.input[from..]
+            .find(|c: char| !is_bare_char(c))
+            .map(|i| from + i)
+            .unwrap_or(self.input.len())
+    }
+
+    fn digits_end(&self, from: usize) -> usize {
+        self.input[from..]
+            .find(|c: char| !c.is_ascii_digit())
+            .map(|i| from + i)
+            .unwrap_or(self.input.len())
+    }
+
+    /// Single-line quoted string. A backslash pairs with the next character
+    /// (for raw `'...'` strings it may not pair with a line break).
+    fn scan_quoted(&self, from: usize, quote: char, kind: TokenKind, escapes_newline: bool) -> (TokenKind, usize) {
+        let mut i = from + 1;
+        while let Some(ch) = self.char_at(i) {
+            match ch {
+                '\r' | '\n' => return (TokenKind::Error, i),
+                '\\' => match self.char_at(i + 1) {
+                    Some('\r' | '\n') if !escapes_newline => return (TokenKind::Error, i + 1),
+                    Some(next) => i += 1 + next.len_utf8(),
+                    None => return (TokenKind::Error, i + 1),
+                },
+                c if c == quote => return (kind, i + 1),
+                c => i += c.len_utf8(),
+            }
+        }
+        (TokenKind::Error, self.input.len())
+    }
+
+    /// `"""..."""` or `'''...'''`; may span lines.
+    fn scan_triple(&self, double: bool) -> (TokenKind, usize) {
+        let delim: &str = if double { "\"\"\"" } else { "'''" };
+        let kind = if double { TokenKind::TripleDoubleQuoted } else { TokenKind::TripleSingleQuoted };
+        let mut i = self.pos + 3;
+        while let Some(ch) = self.char_at(i) {
+            if self.input[i..].starts_with(delim) {
+                return (kind, i + 3);
+            }
+            match ch {
+                '\\' => match self.char_at(i + 1) {
+                    Some(next) => i += 1 + next.len_utf8(),
+                    None => return (TokenKind::Error, i + 1),
+                },
+                c => i += c.len_utf8(),
+            }
+        }
+        (TokenKind::Error, self.input.len())
+    }
+
+    /// `<<<` newline, lines, then a line containing only `>>>`.
+    /// The token includes the line break after the terminator, matching
+    /// what the parser consumes.
+    fn scan_heredoc(&self) -> (TokenKind, usize) {
+        let mut i = self.pos + 3;
+        while let Some(' ' | '\t') = self.char_at(i) {
+            i += 1;
+        }
+        if self.char_at(i) == Some('\r') {
+            i += 1;
+        }
+        if self.char_at(i) != Some('\n') {
+            return (TokenKind::Error, self.pos + 3);
+        }
+        i += 1;
+        while i < self.input.len() {
+            let line_end = self.input[i..].find('\n').map(|n| i + n).unwrap_or(self.input.len());
+            let line = &self.input[i..line_end];
+            let next = if line_end < self.input.len() { line_end + 1 } else { line_end };
+            if line.trim() == ">>>" {
+                return (TokenKind::Heredoc, next);
+            }
+            i = next;
+        }
+        (TokenKind::Error, self.input.len())
+    }
+
+    /// `@true`, `@false`, `@none`, `@env.NAME`, or `@date`.
+    fn scan_at(&self) -> (TokenKind, usize) {
+        let start = self.pos;
+        let after = start + 1;
+        for (word, kind) in [("true", TokenKind::True), ("false", TokenKind::False), ("none", TokenKind::None)] {
+            let end = after + word.len();
+            if self.input[after..].starts_with(word) && !self.char_at(end).is_some_and(is_bare_char) {
+                return (kind, end);
+            }
+        }
+        if self.input[after..].starts_with("env.") {
+            let name_start = after + 4;
+            return match self.char_at(name_start) {
+                Some('`') => match self.scan_quoted(name_start, '`', TokenKind::Backtick, true) {
+                    (TokenKind::Backtick, end) => (TokenKind::Env, end),
+                    (_, end) => (TokenKind::Error, end),
+                },
+                Some(c) if is_bare_char(c) => (TokenKind::Env, self.bare_end(name_start)),
+                _ => (TokenKind::Error, name_start),
+            };
+        }
+        if self.char_at(after).is_some_and(|c| c.is_ascii_digit()) {
+            return match self.date_end(after) {
+                Some(end) => (TokenKind::Date, end),
+                None => {
+                    let len = self.input[after..]
+                        .find(|c: char| !(c.is_ascii_digit() || matches!(c, '-' | ':' | '.' | '+' | 'T' | 'Z')))
+                        .unwrap_or(self.input.len() - after);
+                    (TokenKind::Error, after + len)
+                }
+            };
+        }
+        (TokenKind::Error, self.bare_end(after).max(after))
+    }
+
+    /// `YYYY-MM-DD[THH:MM[:SS[.frac]][Z|+HH[:]MM]]`, as `Parser::parse_date`.
+    fn date_end(&self, start: usize) -> Option<usize> {
+        let digits = |i: usize, n: usize| -> Option<usize> {
+            (0..n).all(|k| self.char_at(i + k).is_some_and(|c| c.is_ascii_digit())).then_some(i + n)
+        };
+        let char_is = |i: usize, c: char| -> Option<usize> { (self.char_at(i) == Some(c)).then_some(i + 1) };
+        let mut i = digits(start, 4)?;
+        i = digits(char_is(i, '-')?, 2)?;
+        i = digits(char_is(i, '-')?, 2)?;
+        if self.char_at(i) != Some('T') {
+            return Some(i);
+        }
+        i = digits(i + 1, 2)?;
+        i = digits(char_is(i, ':')?, 2)?;
+        if self.char_at(i) == Some(':') {
+            i = digits(i + 1, 2)?;
+            if self.char_at(i) == Some('.') {
+                let end = self.digits_end(i + 1);
+                if end == i + 1 {
+                    return None;
+                }
+                i = end;
+            }
+        }
+        match self.char_at(i) {
+            Some('Z') => Some(i + 1),
+            Some('+' | '-') => {
+                i = digits(i + 1, 2)?;
+                if self.char_at(i) == Some(':') {
+                    i += 1;
+                }
+                digits(i, 2)
+            }
+            _ => Some(i),
+        }
+    }
+
+    /// `$` `^`* then an optional dotted path with `[n]` indices.
+    fn scan_reference(&self) -> (TokenKind, usize) {
+        let mut i = self.pos + 1;
+        let mut ups = 0;
+        while self.char_at(i) == Some('^') {
+            i += 1;
+            ups += 1;
+        }
+        if ups > 0 {
+            if self.char_at(i) != Some('.') {
+                return (TokenKind::Reference, i);
+            }
+            i += 1;
+        }
+        loop {
+            match self.identifier_end(i) {
+                Some(end) => i = end,
+                None => return (TokenKind::Reference, i),
+            }
+            if self.char_at(i) == Some('[') {
+                let mut j = i + 1;
+                while let Some(' ' | '\t' | '\r' | '\n') = self.char_at(j) {
+                    j += 1;
+                }
+                let digits = self.digits_end(j);
+                let mut k = digits;
+                while let Some(' ' | '\t' | '\r' | '\n') = self.char_at(k) {
+                    k += 1;
+                }
+                if digits > j && self.char_at(k) == Some(']') {
+                    i = k + 1;
+                } else {
+                    return (TokenKind::Reference, i);
+                }
+            }
+            if self.char_at(i) == Some('.')
+                && self.char_at(i + 1).is_some_and(|c| c == '`' || is_bare_char(c))
+            {
+                i += 1;
+            } else {
+                return (TokenKind::Reference, i);
+            }
+        }
+    }
+
+    /// End of a bare or backtick identifier starting at `from`.
+    fn identifier_end(&self, from: usize) -> Option<usize> {
+        match self.char_at(from) {
+            Some('`') => match self.scan_quoted(from, '`', TokenKind::Backtick, true) {
+                (TokenKind::Backtick, end) => Some(end),
+                _ => None,
+            },
+            Some(c) if is_bare_char(c) => Some(self.bare_end(from)),
+            _ => None,
+        }
+    }
+
+    /// In value position: a number, or a bare string that happens to start
+    /// with digits (`123abc`). Mirrors `Parser::parse_number_or_string`.
+    fn scan_number_or_bare(&self) -> (TokenKind, usize) {
+        let start = self.pos;
+        let mut i = start;
+        let has_minus = self.char_at(i) == Some('-');
+        if has_minus {
+            i += 1;
+        }
+        let int_end = self.digits_end(i);
+        let has_int = int_end > i;
+        i = int_end;
+        if self.char_at(i) == Some('.') {
+            let frac_end = self.digits_end(i + 1);
+            if frac_end > i + 1 {
+                i = frac_end;
+            } else if !has_int {
+                return self.bare_fallback(start);
+            }
+        } else if !has_int {
+            return self.bare_fallback(start);
+        }
+        if let Some('e' | 'E') = self.char_at(i) {
+            let mut j = i + 1;
+            if let Some('+' | '-') = self.char_at(j) {
+                j += 1;
+            }
+            let exp_end = self.digits_end(j);
+            if exp_end > j {
+                i = exp_end;
+            }
+        }
+        if self.char_at(i).is_some_and(|c| is_bare_char(c) && !c.is_ascii_digit()) {
+            return self.bare_fallback(start);
+        }
+        (TokenKind::Number, i)
+    }
+
+    fn bare_fallback(&self, start: usize) -> (TokenKind, usize) {
+        match self.char_at(start) {
+            Some('-') => (TokenKind::Minus, start + 1),
+            Some('.') => (TokenKind::Dot, start + 1),
+            _ => (TokenKind::Bare, self.bare_end(start)),
+        }
+    }
+}
@@ -1,21 +1,57 @@
 pub mod ast;
+pub mod cst;
+pub mod diff;
+pub mod edit;
+pub mod env;
 pub mod error;
+pub mod format;
 pub mod from_json;
+pub mod include;
 pub mod interpreter;
 pub mod json;
+pub mod lexer;
+#[cfg(feature = "lsp")]
+pub mod lsp;
+pub mod merge;
+pub mod mot;
 pub mod parser;
+pub mod patch;
+pub mod query;
+pub mod secret;
+pub mod session;
+pub mod source;
+pub mod spans;
+#[cfg(feature = "toml")]
+pub mod toml;
+pub mod tracked;
 pub mod tree;
 pub mod validate;
+pub mod writer;
+#[cfg(feature = "yaml")]
+pub mod yaml;
 
 use error::MOTLYError;
 use tree::MOTLYDataNode;
 
 pub use interpreter::{
-    ExecContext, SessionOptions, Transformer,
-    flatten, chunk, topo_sort, execute_chunked,
+    ExecContext, SessionOptions, Transformer, Provenance,
+    flatten, chunk, topo_sort, execute_chunked, execute_chunked_with_provenance,
     ChunkResult, TopoSortResult,
 };
+pub use diff::{diff, Change, ChangeKind};
+pub use env::{resolve_env, DotEnv, EnvOptions, EnvProvider, ProcessEnv};
+pub use include::{FileLoader, MemoryLoader, SourceLoader};
+pub use merge::{merge, Conflict, ConflictKind, MergeResult};
+pub use mot::{build_mot, Mot, ValueType};
+pub use patch::{patch, patch_source};
+pub use query::{query, Query, QueryMatch};
+pub use secret::Secrets;
+pub use session::{MOTLYParseResult, MOTLYSession};
+pub use source::{SourceFile, SourceMap};
+pub use spans::{NodeSpans, SpanPart, SpanTable};
+pub use tracked::{ReadTracker, TrackedMot, Unread};
 pub use validate::{validate_references, validate_schema, SchemaError, ValidationError};
+pub use writer::{to_motly, write_statements};
 
 // ── Core API ───────────────────────────────────────────────────────
 
@@ -28,25 +64,22 @@ pub struct MOTLYResult {
 /// Parse MOTLY source and execute against the given value using the four-phase
 /// interpreter. Returns the updated value and any errors.
 pub fn parse_motly(input: &str, mut value: MOTLYDataNode, ctx: &ExecContext) -> MOTLYResult {
-    match parser::parse(input) {
-        Ok(stmts) => {
-            let transformers = flatten(&stmts, ctx);
-            let chunk_result = chunk(&transformers);
-            let sort_result = topo_sort(&chunk_result.deps);
-            let errors = execute_chunked(
-                &transformers,
-                &chunk_result.splits,
-                &sort_result.order,
-                &mut value,
-                &ctx.options,
-            );
-            MOTLYResult { value, errors }
-        }
-        Err(err) => MOTLYResult {
-            value,
-            errors: vec![err],
-        },
+    // Syntax errors don't stop interpretation: the statements that parsed still run.
+    let (stmts, mut errors) = parser::parse_recovering(input, &ctx.options.parse_limits());
+    for err in &mut errors {
+        err.parse_id = Some(ctx.parse_id);
     }
+    let transformers = flatten(&stmts, ctx);
+    let chunk_result = chunk(&transformers);
+    let sort_result = topo_sort(&chunk_result.deps);
+    errors.extend(execute_chunked(
+        &transformers,
+        &chunk_result.splits,
+        &sort_result.order,
+        &mut value,
+        &ctx.options,
+    ));
+    MOTLYResult { value, errors }
 }
 
 // ── WASM FFI ────────────────────────────────────────────────────────
@@ -73,33 +106,19 @@ pub unsafe extern "C" fn dealloc(ptr: *mut u8, len: usize) {
 }
 
 // ── Session-based WASM FFI ──────────────────────────────────────────
-
-use crate::ast::Statement;
+//
+// Thin wrappers over MOTLYSession, keyed by integer session ID.
 
 use std::cell::{Cell, RefCell};
 use std::collections::HashMap;
 
-struct AccumulatedParse {
-    stmts: Vec<Statement>,
-    parse_id: u32,
-}
-
-struct Session {
-    accumulated: Vec<AccumulatedParse>,
-    value: MOTLYDataNode,
-    schema: Option<MOTLYDataNode>,
-    next_parse_id: u32,
-    options: SessionOptions,
-    finished: bool,
-}
-
 // WASM is single-threaded, so thread_local is just a convenient safe wrapper.
 thread_local! {
-    static SESSIONS: RefCell<HashMap<u32, Session>> = RefCell::new(HashMap::new());
+    static SESSIONS: RefCell<HashMap<u32, MOTLYSession>> = RefCell::new(HashMap::new());
     static NEXT_SESSION_ID: Cell<u32> = const { Cell::new(1) };
 }
 
-fn with_sessions<R>(f: impl FnOnce(&mut HashMap<u32, Session>) -> R) -> R {
+fn with_sessions<R>(f: impl FnOnce(&mut HashMap<u32, MOTLYSession>) -> R) -> R {
     SESSIONS.with(|s| f(&mut s.borrow_mut()))
 }
 
@@ -124,25 +143,15 @@ pub extern "C" fn wasm_session_new() -> u32 {
 pub extern "C" fn wasm_session_new_with_options(flags: u32) -> u32 {
     let options = SessionOptions {
         disable_references: flags & 1 != 0,
+        ..SessionOptions::default()
     };
     let id = next_id();
-    with_sessions(|s| {
-        s.insert(
-            id,
-            Session {
-                accumulated: Vec::new(),
-                value: MOTLYDataNode::new(),
-                schema: None,
-                next_parse_id: 0,
-                options,
-                finished: false,
-            },
-        )
-    });
+    with_sessions(|s| s.insert(id, MOTLYSession::new(options)));
     id
 }
 
-/// Parse source and accumulate statements. Returns only syntax errors.
+/// Parse source and accumulate statements (those recovered after a syntax
+/// error included). Returns only syntax errors.
 /// Returns a pointer to a null-terminated JSON object: `{"parseId":N,"errors":[...]}`.
 ///
 /// # Safety
@@ -157,112 +166,60 @@ pub unsafe extern "C" fn wasm_session_parse(
         let slice = std::slice::from_raw_parts(src_ptr, src_len);
         std::str::from_utf8_unchecked(slice)
     };
-    let parse_id = with_sessions(|s| match s.get_mut(&id) {
-        Some(session) if session.finished => None,
-        Some(session) => {
-            let pid = session.next_parse_id;
-            session.next_parse_id += 1;
-            Some(pid)
-        }
-        None => None,
-    });
-    let parse_id = match parse_id {
-        Some(pid) => pid,
-        None => {
-            return string_to_c_ptr(json::parse_result_to_json(0, &[MOTLYError {
-                code: "session-error".to_string(),
-                message: "Session is spent after finish() — create a new session".to_string(),
-                begin: error::Position { line: 0, column: 0, offset: 0 },
-                end: error::Position { line: 0, column: 0, offset: 0 },
-            }]));
-        }
+    string_to_c_ptr(session_parse(id, None, input))
+}
+
+/// Like `wasm_session_parse`, recording `name` (typically a file path) as
+/// the source of this parse. Errors from it, including those reported by
+/// finish and validation, carry `"source"` and `"at": "name:line:col"`.
+///
+/// # Safety
+/// `name_ptr` and `src_ptr` must point to valid UTF-8 byte sequences of
+/// length `name_len` and `src_len`.
+#[no_mangle]
+pub unsafe extern "C" fn wasm_session_parse_named(
+    id: u32,
+    name_ptr: *const u8,
+    name_len: usize,
+    src_ptr: *const u8,
+    src_len: usize,
+) -> *const u8 {
+    let (name, input) = unsafe {
+        let name = std::slice::from_raw_parts(name_ptr, name_len);
+        let src = std::slice::from_raw_parts(src_ptr, src_len);
+        (std::str::from_utf8_unchecked(name), std::str::from_utf8_unchecked(src))
     };
-    match parser::parse(input) {
-        Ok(stmts) => {
-            with_sessions(|s| {
-                if let Some(session) = s.get_mut(&id) {
-                    session.accumulated.push(AccumulatedParse { stmts, parse_id });
-                }
-            });
-            let json_str = json::parse_result_to_json(parse_id, &[]);
-            string_to_c_ptr(json_str)
-        }
-        Err(err) => {
-            let json_str = json::parse_result_to_json(parse_id, &[err]);
-            string_to_c_ptr(json_str)
+    string_to_c_ptr(session_parse(id, Some(name), input))
+}
+
+fn session_parse(id: u32, name: Option<&str>, input: &str) -> String {
+    with_sessions(|s| match s.get_mut(&id) {
+        Some(session) => {
+            let result = match name {
+                Some(name) => session.parse_named(name, input),
+                None => session.parse(input),
+            };
+            json::parse_result_to_json_with_sources(result.parse_id, &result.errors, Some(session.sources()))
         }
-    }
+        None => json::parse_result_to_json(0, &[MOTLYError::general(
+            "session-error",
+            format!("No session with id {}", id),
+        )]),
+    })
 }
 
 /// Interpret all accumulated statements using the four-phase engine,
 /// validate references, and store the result.
-/// Returns a pointer to a null-terminated JSON object: `{"errors":[...]}`.
+/// Returns a pointer to a null-terminated JSON array of errors.
 #[no_mangle]
 pub extern "C" fn wasm_session_finish(id: u32) -> *const u8 {
-    let session_data = with_sessions(|s| {
-        match s.get_mut(&id) {
-            Some(session) if session.finished => None,
-            Some(session) => {
-                session.finished = true;
-                let accumulated = std::mem::take(&mut session.accumulated);
-                let options = session.options.clone();
-                Some((accumulated, options))
-            }
-            None => None,
-        }
-    });
-    let (accumulated, options) = match session_data {
-        Some(data) => data,
-        None => return string_to_c_ptr("{\"errors\":[]}".to_string()),
-    };
-
-    let mut root = MOTLYDataNode::new();
-    let mut all_errors: Vec<MOTLYError> = Vec::new();
-
-    // Phase 1: Flatten all accumulated statements into transformers
-    let mut all_transformers: Vec<Transformer> = Vec::new();
-    for ap in &accumulated {
-        let ctx = ExecContext { parse_id: ap.parse_id, options: options.clone() };
-        all_transformers.extend(flatten(&ap.stmts, &ctx));
-    }
-
-    // Phase 2: Chunk
-    let chunk_result = chunk(&all_transformers);
-
-    // Phase 3: Topo-sort
-    let sort_result = topo_sort(&chunk_result.deps);
-
-    // Phase 4: Execute
-    let exec_errors = execute_chunked(
-        &all_transformers,
-        &chunk_result.splits,
-        &sort_result.order,
-        &mut root,
-        &options,
-    );
-    all_errors.extend(exec_errors);
-
-    // Validate references (unless disabled)
-    if !options.disable_references {
-        let ref_errors = validate_references(&root);
-        for re in ref_errors {
-            all_errors.push(MOTLYError {
-                code: re.code.to_string(),
-                message: re.message,
-                begin: error::Position { line: 0, column: 0, offset: 0 },
-                end: error::Position { line: 0, column: 0, offset: 0 },
-            });
-        }
-    }
-
-    with_sessions(|s| {
-        if let Some(session) = s.get_mut(&id) {
-            session.value = root;
+    with_sessions(|s| match s.get_mut(&id) {
+        Some(session) => {
+            let errors = session.finish();
+            string_to_c_ptr(json::errors_to_json_with_sources(&errors, Some(session.sources())))
         }
-    });
-
-    let json_str = json::errors_to_json(&all_errors);
-    string_to_c_ptr(json_str)
+        None => string_to_c_ptr("[]".to_string()),
+    })
 }
 
 /// Parse MOTLY source as a schema and store it in the session.
@@ -280,22 +237,11 @@ pub unsafe extern "C" fn wasm_session_parse_schema(
         let slice = std::slice::from_raw_parts(src_ptr, src_len);
         std::str::from_utf8_unchecked(slice)
     };
-    let ctx = with_sessions(|s| match s.get_mut(&id) {
-        Some(session) => {
-            let pid = session.next_parse_id;
-            session.next_parse_id += 1;
-            ExecContext { parse_id: pid, options: SessionOptions { disable_references: true } }
-        }
-        None => ExecContext { parse_id: 0, options: SessionOptions::default() },
-    });
-    let result = parse_motly(input, MOTLYDataNode::new(), &ctx);
-    with_sessions(|s| {
-        if let Some(session) = s.get_mut(&id) {
-            session.schema = Some(result.value);
-        }
+    let result = with_sessions(|s| match s.get_mut(&id) {
+        Some(session) => session.parse_schema(input),
+        None => MOTLYSession::default().parse_schema(input),
     });
-    let json_str = json::parse_result_to_json(ctx.parse_id, &result.errors);
-    string_to_c_ptr(json_str)
+    string_to_c_ptr(json::parse_result_to_json(result.parse_id, &result.errors))
 }
 
 /// Reset the session's value to empty, keeping the schema.
@@ -303,7 +249,7 @@ pub unsafe extern "C" fn wasm_session_parse_schema(
 pub extern "C" fn wasm_session_reset(id: u32) {
     with_sessions(|s| {
         if let Some(session) = s.get_mut(&id) {
-            session.value = MOTLYDataNode::new();
+            session.reset();
         }
     });
 }
@@ -313,7 +259,7 @@ pub extern "C" fn wasm_session_reset(id: u32) {
 #[no_mangle]
 pub extern "C" fn wasm_session_get_value(id: u32) -> *const u8 {
     with_sessions(|s| match s.get(&id) {
-        Some(session) => string_to_c_ptr(json::to_wire(&session.value)),
+        Some(session) => string_to_c_ptr(json::to_wire(session.value())),
         None => string_to_c_ptr("{}".to_string()),
     })
 }
@@ -324,8 +270,8 @@ pub extern "C" fn wasm_session_get_value(id: u32) -> *const u8 {
 pub extern "C" fn wasm_session_validate_refs(id: u32) -> *const u8 {
     with_sessions(|s| match s.get(&id) {
         Some(session) => {
-            let errors = validate_references(&session.value);
-            string_to_c_ptr(json::validation_errors_to_json(&errors))
+            let errors = session.validate_references();
+            string_to_c_ptr(json::validation_errors_to_json_with_sources(&errors, Some(session.sources())))
         }
         None => string_to_c_ptr("[]".to_string()),
     })
@@ -337,13 +283,10 @@ pub extern "C" fn wasm_session_validate_refs(id: u32) -> *const u8 {
 #[no_mangle]
 pub extern "C" fn wasm_session_validate_schema(id: u32) -> *const u8 {
     with_sessions(|s| match s.get(&id) {
-        Some(session) => match &session.schema {
-            Some(schema) => {
-                let errors = validate_schema(&session.value, schema);
-                string_to_c_ptr(json::schema_errors_to_json(&errors))
-            }
-            None => string_to_c_ptr("[]".to_string()),
-        },
+        Some(session) => {
+            let errors = session.validate_schema();
+            string_to_c_ptr(json::schema_errors_to_json_with_sources(&errors, Some(session.sources())))
+        }
         None => string_to_c_ptr("[]".to_string()),
     })
 }
@@ -373,55 +316,17 @@ fn parse_motly_0(input: &str, value: MOTLYDataNode) -> MOTLYResult {
     parse_motly_n(input, value, 0)
 }
 
-/// Full session lifecycle for tests: parse all inputs, run four-phase interpreter,
-/// optionally validate refs. Returns (value, all_errors).
+/// Full session lifecycle for tests: parse all inputs and finish.
+/// Returns (value, all_errors), syntax errors first.
 #[cfg(test)]
-fn session_finish_ex(inputs: &[&str], options: SessionOptions, validate_refs: bool) -> (MOTLYDataNode, Vec<MOTLYError>) {
-    let mut root = MOTLYDataNode::new();
+fn session_finish(inputs: &[&str], options: SessionOptions) -> (MOTLYDataNode, Vec<MOTLYError>) {
+    let mut session = MOTLYSession::new(options);
     let mut all_errors: Vec<MOTLYError> = Vec::new();
-    let mut all_transformers: Vec<Transformer> = Vec::new();
-
-    for (i, input) in inputs.iter().enumerate() {
-        match parser::parse(input) {
-            Ok(stmts) => {
-                let ctx = ExecContext { parse_id: i as u32, options: options.clone() };
-                all_transformers.extend(flatten(&stmts, &ctx));
-            }
-            Err(err) => {
-                all_errors.push(err);
-            }
-        }
+    for input in inputs {
+        all_errors.extend(session.parse(input).errors);
     }
-
-    let chunk_result = chunk(&all_transformers);
-    let sort_result = topo_sort(&chunk_result.deps);
-    let exec_errors = execute_chunked(
-        &all_transformers,
-        &chunk_result.splits,
-        &sort_result.order,
-        &mut root,
-        &options,
-    );
-    all_errors.extend(exec_errors);
-
-    if validate_refs && !options.disable_references {
-        let ref_errors = validate_references(&root);
-        for re in ref_errors {
-            all_errors.push(MOTLYError {
-                code: re.code.to_string(),
-                message: re.message,
-                begin: error::Position { line: 0, column: 0, offset: 0 },
-                end: error::Position { line: 0, column: 0, offset: 0 },
-            });
-        }
-    }
-
-    (root, all_errors)
-}
-
-#[cfg(test)]
-fn session_finish(inputs: &[&str], options: SessionOptions) -> (MOTLYDataNode, Vec<MOTLYError>) {
-    session_finish_ex(inputs, options, true)
+    all_errors.extend(session.finish());
+    (session.value().clone(), all_errors)
 }
 
 #[cfg(test)]
@@ -0,0 +1,554 @@
+/// A Language Server Protocol server for MOTLY (cargo feature `lsp`).
+///
+/// `motly lsp` runs `run` over stdin and stdout. The server keeps the text
+/// of open documents (full sync) and provides:
+///
+/// - diagnostics, published on open, change and save: syntax errors,
+///   interpreter and reference errors, problems with `#! include`
+///   directives, and, when the document names its schema with
+///   `#! schema=name url="path"`, problems loading the schema and what
+///   `validate_schema` reports;
+/// - document symbols, one per property statement, nested as written;
+/// - folding ranges for `{ }` blocks, `[ ]` arrays and multi-line strings
+///   (heredocs and triple-quoted strings).
+///
+/// A document is checked with the sources it includes, read from the open
+/// documents when they are open and from disk otherwise. Only problems in
+/// the document itself are reported; an included file shows its own when
+/// it is opened.
+///
+/// `Server::handle` turns one message into the messages to send back, so
+/// a client can be scripted in-process; `run` adds the `Content-Length`
+/// framing of the base protocol.
+use std::collections::BTreeMap;
+use std::io::{self, BufRead, Write};
+
+use crate::ast::{ScalarValue, Span, Statement, TagValue};
+use crate::cst::{self, NodeKind, SyntaxNode};
+use crate::error::MOTLYError;
+use crate::from_json::{parse_json_value, JsonValue};
+use crate::include::{FileLoader, SourceLoader};
+use crate::json::{json_value_to_string, JsonStyle};
+use crate::lexer::{tokenize, TokenKind};
+use crate::session::MOTLYSession;
+use crate::SessionOptions;
+
+// ── Checking a document ─────────────────────────────────────────────
+
+/// The problems in `text`, the source called `name`, with the sources it
+/// includes read through `loader`. Positions are in `text`.
+pub fn check(name: &str, text: &str, loader: &dyn SourceLoader) -> Vec<MOTLYError> {
+    let mut session = MOTLYSession::new(SessionOptions::default());
+    // The document is the session's first parse
+    let mut errors: Vec<MOTLYError> =
+        session.parse_with_includes(name, text, loader).into_iter().flat_map(|r| r.errors).collect();
+    errors.extend(session.finish());
+
+    if let Some((url, span)) = schema_url(text) {
+        let target = loader.resolve(name, &url);
+        let problem = match loader.load(&target) {
+            Ok(schema) => {
+                let parsed = session.parse_schema(&schema);
+                parsed.errors.first().map(|err| ("invalid-schema", format!("Schema {} has errors: {}", target, err.message)))
+            }
+            Err(reason) => Some(("schema-not-found", format!("Cannot load schema {}: {}", target, reason))),
+        };
+        match problem {
+            Some((code, message)) => {
+                errors.push(MOTLYError { parse_id: Some(0), ..MOTLYError::new(code, message, span.begin, span.end) })
+            }
+            None => errors.extend(
+                session
+                    .validate_schema()
+                    .into_iter()
+                    .map(|err| MOTLYError::located(err.code, err.message, err.location.as_ref())),
+            ),
+        }
+    }
+    errors.retain(|err| err.parse_id.is_none_or(|id| id == 0));
+    errors
+}
+
+/// The `url` of a `#! schema=name url="path"` directive, and its span.
+fn schema_url(text: &str) -> Option<(String, Span)> {
+    tokenize(text).into_iter().filter(|t| t.kind == TokenKind::Directive).find_map(|token| {
+        let stmts = crate::parser::parse(&token.text(text)[2..]).ok()?;
+        let names_schema = stmts.iter().any(|s| matches!(s, Statement::SetEq { path, .. } if path == &["schema"]));
+        let url = stmts.into_iter().find_map(|stmt| match stmt {
+            Statement::SetEq { path, value: TagValue::Scalar(ScalarValue::String(url)), .. } if path == ["url"] => Some(url),
+            _ => None,
+        })?;
+        names_schema.then_some((url, token.span))
+    })
+}
+
+// ── Document symbols ────────────────────────────────────────────────
+
+/// What a symbol's value is, as the LSP `SymbolKind` it is shown as.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SymbolKind {
+    /// A flag, or a property without a value.
+    Property,
+    /// Properties and no value.
+    Object,
+    Array,
+    String,
+    Number,
+    Boolean,
+    /// A date.
+    Constant,
+    /// A reference or an `@env` value.
+    Variable,
+    /// `@none`
+    Null,
+}
+
+impl SymbolKind {
+    /// The number LSP uses for this kind.
+    pub fn code(self) -> u32 {
+        match self {
+            SymbolKind::Property => 7,
+            SymbolKind::Variable => 13,
+            SymbolKind::Constant => 14,
+            SymbolKind::String => 15,
+            SymbolKind::Number => 16,
+            SymbolKind::Boolean => 17,
+            SymbolKind::Array => 18,
+            SymbolKind::Object => 19,
+            SymbolKind::Null => 21,
+        }
+    }
+}
+
+/// A property statement, or an array element with properties (`[i]`).
+#[derive(Debug, Clone, PartialEq)]
+pub struct Symbol {
+    /// The property name as written, e.g. `server.port`.
+    pub name: String,
+    pub kind: SymbolKind,
+    /// The value as written, for single-line scalar values.
+    pub detail: Option<String>,
+    /// The whole statement.
+    pub range: Span,
+    /// The property name.
+    pub selection: Span,
+    pub children: Vec<Symbol>,
+}
+
+/// The symbols of `text`, nested as its blocks are. Deletions and source
+/// that does not parse are left out.
+pub fn document_symbols(text: &str) -> Vec<Symbol> {
+    list_symbols(&cst::parse(text).root)
+}
+
+fn list_symbols(list: &SyntaxNode) -> Vec<Symbol> {
+    let mut symbols = Vec::new();
+    for stmt in list.child_nodes().filter(|n| n.kind.is_statement()) {
+        if matches!(stmt.kind, NodeKind::Delete | NodeKind::ClearAll) {
+            continue;
+        }
+        let (Some(range), Some(name)) = (stmt.span(), stmt.child(NodeKind::PropName)) else {
+            continue;
+        };
+        let Some(selection) = name.span() else { continue };
+        let (kind, detail, children) = contents(stmt);
+        symbols.push(Symbol { name: name.text(), kind, detail, range, selection, children });
+    }
+    symbols
+}
+
+/// The kind, detail and children of a statement or array element.
+fn contents(node: &SyntaxNode) -> (SymbolKind, Option<String>, Vec<Symbol>) {
+    let value = cst::value_node(node);
+    let properties = node.child(NodeKind::Properties);
+    let mut children = Vec::new();
+    if let Some(array) = value.filter(|v| v.kind == NodeKind::Array) {
+        let elements = array.child_nodes().filter(|n| n.kind == NodeKind::ArrayElement);
+        for (i, el) in elements.enumerate() {
+            let (Some(range), Some(_)) = (el.span(), el.child(NodeKind::Properties)) else {
+                continue;
+            };
+            let selection = cst::value_node(el).and_then(SyntaxNode::span).unwrap_or(range);
+            let (kind, detail, grandchildren) = contents(el);
+            children.push(Symbol { name: format!("[{}]", i), kind, detail, range, selection, children: grandchildren });
+        }
+    }
+    if let Some(block) = properties {
+        children.extend(list_symbols(block));
+    }
+
+    let token = value.filter(|v| v.kind == NodeKind::Value).and_then(SyntaxNode::first_token);
+    let kind = match (value.map(|v| v.kind), token.map(|t| t.kind)) {
+        (Some(NodeKind::Array), _) => SymbolKind::Array,
+        (_, Some(TokenKind::Number)) => SymbolKind::Number,
+        (_, Some(TokenKind::True | TokenKind::False)) => SymbolKind::Boolean,
+        (_, Some(TokenKind::Date)) => SymbolKind::Constant,
+        (_, Some(TokenKind::Reference | TokenKind::Env)) => SymbolKind::Variable,
+        (_, Some(TokenKind::None)) => SymbolKind::Null,
+        (_, Some(_)) => SymbolKind::String,
+        _ if properties.is_some() => SymbolKind::Object,
+        _ => SymbolKind::Property,
+    };
+    let detail = token.map(|t| t.text.clone()).filter(|text| !text.contains('\n'));
+    (kind, detail, children)
+}
+
+// ── Folding ranges ──────────────────────────────────────────────────
+
+/// Lines that fold away, from the line after `start_line` through
+/// `end_line`. The closing `}`, `]` or `>>>` stays visible.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FoldingRange {
+    pub start_line: usize,
+    pub end_line: usize,
+}
+
+/// The multi-line blocks, arrays and strings of `text`, by start line.
+pub fn folding_ranges(text: &str) -> Vec<FoldingRange> {
+    let mut ranges = Vec::new();
+    let mut open = Vec::new();
+    let mut fold = |start: usize, close: usize| {
+        if close > start + 1 {
+            ranges.push(FoldingRange { start_line: start, end_line: close - 1 });
+        }
+    };
+    for token in tokenize(text) {
+        let Span { begin, end } = token.span;
+        match token.kind {
+            TokenKind::LBrace | TokenKind::LBracket => open.push(begin.line),
+            TokenKind::RBrace | TokenKind::RBracket => {
+                if let Some(start) = open.pop() {
+                    fold(start, begin.line);
+                }
+            }
+            TokenKind::Heredoc | TokenKind::TripleDoubleQuoted | TokenKind::TripleSingleQuoted => {
+                // A heredoc ends with the newline after its `>>>`
+                let close = if token.text(text).ends_with('\n') { end.line - 1 } else { end.line };
+                fold(begin.line, close);
+            }
+            _ => {}
+        }
+    }
+    ranges.sort_by_key(|r| (r.start_line, r.end_line));
+    ranges
+}
+
+// ── The server ──────────────────────────────────────────────────────
+
+const PARSE_ERROR: f64 = -32700.0;
+const INVALID_REQUEST: f64 = -32600.0;
+const METHOD_NOT_FOUND: f64 = -32601.0;
+
+/// The state of a language server: its open documents, by URI.
+#[derive(Debug, Default)]
+pub struct Server {
+    documents: BTreeMap<String, String>,
+    shut_down: bool,
+    exit: Option<i32>,
+}
+
+impl Server {
+    pub fn new() -> Server {
+        Server::default()
+    }
+
+    /// The process exit code, once the client has sent `exit`: 0 after a
+    /// `shutdown` request, 1 without one.
+    pub fn exit_code(&self) -> Option<i32> {
+        self.exit
+    }
+
+    /// Handle one message body; returns the bodies to send back.
+    pub fn handle_text(&mut self, body: &str) -> Vec<String> {
+        let replies = match parse_json_value(body) {
+            Ok(message) => self.handle(&message),
+            Err(err) => vec![error_response(JsonValue::Null, PARSE_ERROR, &err.message)],
+        };
+        replies.iter().map(|reply| json_value_to_string(reply, JsonStyle::Compact)).collect()
+    }
+
+    /// Handle one message; returns the responses and notifications to send.
+    pub fn handle(&mut self, message: &JsonValue) -> Vec<JsonValue> {
+        let id = message.get("id").cloned();
+        let params = message.get("params").cloned().unwrap_or(JsonValue::Null);
+        let Some(method) = message.get("method").and_then(JsonValue::as_str) else {
+            // A response to a request we never make, or not a message at all
+            return match id {
+                Some(id) if message.get("result").is_none() && message.get("error").is_none() => {
+                    vec![error_response(id, INVALID_REQUEST, "Missing method")]
+                }
+                _ => Vec::new(),
+            };
+        };
+        let uri = params.get("textDocument").and_then(|doc| doc.get("uri")).and_then(JsonValue::as_str).map(str::to_string);
+
+        match (method, id) {
+            ("initialize", Some(id)) => vec![response(id, capabilities())],
+            ("shutdown", Some(id)) => {
+                self.shut_down = true;
+                vec![response(id, JsonValue::Null)]
+            }
+            ("exit", _) => {
+                self.exit = Some(if self.shut_down { 0 } else { 1 });
+                Vec::new()
+            }
+            ("textDocument/didOpen", None) => {
+                let text = params.get("textDocument").and_then(|doc| doc.get("text")).and_then(JsonValue::as_str);
+                match (uri, text) {
+                    (Some(uri), Some(text)) => {
+                        self.documents.insert(uri, text.to_string());
+                        self.publish_all()
+                    }
+                    _ => Vec::new(),
+                }
+            }
+            ("textDocument/didChange", None) => {
+                // Full sync: the last change is the whole text
+                let changes = params.get("contentChanges").and_then(JsonValue::as_array).unwrap_or_default();
+                let text = changes.last().and_then(|change| change.get("text")).and_then(JsonValue::as_str);
+                match (uri, text) {
+                    (Some(uri), Some(text)) if self.documents.contains_key(&uri) => {
+                        self.documents.insert(uri, text.to_string());
+                        self.publish_all()
+                    }
+                    _ => Vec::new(),
+                }
+            }
+            // A saved file may be included by, or be the schema of, another
+            ("textDocument/didSave", None) => self.publish_all(),
+            ("textDocument/didClose", None) => match uri {
+                Some(uri) if self.documents.remove(&uri).is_some() => {
+                    let mut messages = vec![diagnostics_notification(&uri, JsonValue::Array(Vec::new()))];
+                    messages.extend(self.publish_all());
+                    messages
+                }
+                _ => Vec::new(),
+            },
+            ("textDocument/documentSymbol", Some(id)) => {
+                let result = self.document(uri.as_deref()).map_or(JsonValue::Null, |text| {
+                    let lines = LineIndex::new(text);
+                    JsonValue::Array(document_symbols(text).iter().map(|s| symbol_json(s, &lines)).collect())
+                });
+                vec![response(id, result)]
+            }
+            ("textDocument/foldingRange", Some(id)) => {
+                let result = self.document(uri.as_deref()).map_or(JsonValue::Null, |text| {
+                    let ranges = folding_ranges(text).into_iter().map(|r| {
+                        object(vec![("startLine", number(r.start_line)), ("endLine", number(r.end_line))])
+                    });
+                    JsonValue::Array(ranges.collect())
+                });
+                vec![response(id, result)]
+            }
+            (method, Some(id)) => vec![error_response(id, METHOD_NOT_FOUND, &format!("Unknown method {}", method))],
+            // Other notifications, `initialized` and `$/` ones included, need nothing
+            (_, None) => Vec::new(),
+        }
+    }
+
+    fn document(&self, uri: Option<&str>) -> Option<&str> {
+        self.documents.get(uri?).map(String::as_str)
+    }
+
+    /// Diagnostics for every open document: a change to one can change
+    /// the others that include it.
+    fn publish_all(&self) -> Vec<JsonValue> {
+        let loader = DocumentLoader { server: self };
+        self.documents
+            .iter()
+            .map(|(uri, text)| {
+                let lines = LineIndex::new(text);
+                let errors = check(&uri_to_path(uri), text, &loader);
+                diagnostics_notification(uri, JsonValue::Array(errors.iter().map(|e| diagnostic(e, &lines)).collect()))
+            })
+            .collect()
+    }
+}
+
+/// Reads open documents from the server and the rest from disk.
+struct DocumentLoader<'a> {
+    server: &'a Server,
+}
+
+impl SourceLoader for DocumentLoader<'_> {
+    fn resolve(&self, from: &str, target: &str) -> String {
+        FileLoader.resolve(from, target)
+    }
+
+    fn load(&self, name: &str) -> Result<String, String> {
+        match self.server.documents.iter().find(|(uri, _)| uri_to_path(uri) == name) {
+            Some((_, text)) => Ok(text.clone()),
+            None => FileLoader.load(name),
+        }
+    }
+}
+
+/// The file path of a `file:` URI; other URIs are their own name.
+pub fn uri_to_path(uri: &str) -> String {
+    let Some(path) = uri.strip_prefix("file://") else {
+        return uri.to_string();
+    };
+    let bytes = path.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let hex = bytes.get(i + 1..i + 3).and_then(|h| u8::from_str_radix(std::str::from_utf8(h).ok()?, 16).ok());
+        match (bytes[i], hex) {
+            (b'%', Some(byte)) => {
+                decoded.push(byte);
+                i += 3;
+            }
+            (byte, _) => {
+                decoded.push(byte);
+                i += 1;
+            }
+        }
+    }
+    let path = String::from_utf8_lossy(&decoded).into_owned();
+    // file:///C:/dir is C:/dir on Windows
+    match path.as_bytes() {
+        [b'/', drive, b':', ..] if drive.is_ascii_alphabetic() => path[1..].to_string(),
+        _ => path,
+    }
+}
+
+// ── Messages ────────────────────────────────────────────────────────
+
+fn object(members: Vec<(&str, JsonValue)>) -> JsonValue {
+    JsonValue::Object(members.into_iter().map(|(key, value)| (key.to_string(), value)).collect())
+}
+
+fn number(n: usize) -> JsonValue {
+    JsonValue::Number(n as f64)
+}
+
+fn string(s: &str) -> JsonValue {
+    JsonValue::String(s.to_string())
+}
+
+fn response(id: JsonValue, result: JsonValue) -> JsonValue {
+    object(vec![("jsonrpc", string("2.0")), ("id", id), ("result", result)])
+}
+
+fn error_response(id: JsonValue, code: f64, message: &str) -> JsonValue {
+    let error = object(vec![("code", JsonValue::Number(code)), ("message", string(message))]);
+    object(vec![("jsonrpc", string("2.0")), ("id", id), ("error", error)])
+}
+
+fn diagnostics_notification(uri: &str, diagnostics: JsonValue) -> JsonValue {
+    let params = object(vec![("uri", string(uri)), ("diagnostics", diagnostics)]);
+    object(vec![("jsonrpc", string("2.0")), ("method", string("textDocument/publishDiagnostics")), ("params", params)])
+}
+
+fn capabilities() -> JsonValue {
+    let sync = object(vec![("openClose", JsonValue::Boolean(true)), ("change", number(1)), ("save", JsonValue::Boolean(true))]);
+    let capabilities = object(vec![
+        ("textDocumentSync", sync),
+        ("documentSymbolProvider", JsonValue::Boolean(true)),
+        ("foldingRangeProvider", JsonValue::Boolean(true)),
+    ]);
+    let info = object(vec![("name", string("motly")), ("version", string(env!("CARGO_PKG_VERSION")))]);
+    object(vec![("capabilities", capabilities), ("serverInfo", info)])
+}
+
+fn diagnostic(err: &MOTLYError, lines: &LineIndex) -> JsonValue {
+    object(vec![
+        ("range", lines.range(err.begin.offset, err.end.offset)),
+        ("severity", number(1)),
+        ("code", string(&err.code)),
+        ("source", string("motly")),
+        ("message", string(&err.message)),
+    ])
+}
+
+fn symbol_json(symbol: &Symbol, lines: &LineIndex) -> JsonValue {
+    let mut members = vec![("name", string(&symbol.name))];
+    if let Some(detail) = &symbol.detail {
+        members.push(("detail", string(detail)));
+    }
+    members.extend([
+        ("kind", number(symbol.kind.code() as usize)),
+        ("range", lines.range(symbol.range.begin.offset, symbol.range.end.offset)),
+        ("selectionRange", lines.range(symbol.selection.begin.offset, symbol.selection.end.offset)),
+        ("children", JsonValue::Array(symbol.children.iter().map(|c| symbol_json(c, lines)).collect())),
+    ]);
+    object(members)
+}
+
+/// Converts byte offsets to LSP positions, whose characters are UTF-16
+/// code units.
+struct LineIndex<'a> {
+    text: &'a str,
+    starts: Vec<usize>,
+}
+
+impl<'a> LineIndex<'a> {
+    fn new(text: &'a str) -> LineIndex<'a> {
+        let starts = std::iter::once(0).chain(text.match_indices('\n').map(|(i, _)| i + 1)).collect();
+        LineIndex { text, starts }
+    }
+
+    fn position(&self, offset: usize) -> JsonValue {
+        let mut offset = offset.min(self.text.len());
+        while !self.text.is_char_boundary(offset) {
+            offset -= 1;
+        }
+        let line = self.starts.partition_point(|&start| start <= offset) - 1;
+        let character = self.text[self.starts[line]..offset].encode_utf16().count();
+        object(vec![("line", number(line)), ("character", number(character))])
+    }
+
+    fn range(&self, begin: usize, end: usize) -> JsonValue {
+        object(vec![("start", self.position(begin)), ("end", self.position(end))])
+    }
+}
+
+// ── Transport ───────────────────────────────────────────────────────
+
+/// Read one message body, after its `Content-Length` header. None at the
+/// end of the input.
+pub fn read_message(input: &mut impl BufRead) -> io::Result<Option<String>> {
+    let mut length = None;
+    loop {
+        let mut line = String::new();
+        if input.read_line(&mut line)? == 0 {
+            return Ok(None);
+        }
+        let line = line.trim_end();
+        if line.is_empty() {
+            break;
+        }
+        if let Some((name, value)) = line.split_once(':') {
+            if name.eq_ignore_ascii_case("content-length") {
+                length = value.trim().parse::<usize>().ok();
+            }
+        }
+    }
+    let Some(length) = length else {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, "message without Content-Length"));
+    };
+    let mut body = vec![0; length];
+    input.read_exact(&mut body)?;
+    String::from_utf8(body).map(Some).map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))
+}
+
+/// Write one message body with its header.
+pub fn write_message(output: &mut impl Write, body: &str) -> io::Result<()> {
+    write!(output, "Content-Length: {}\r\n\r\n{}", body.len(), body)?;
+    output.flush()
+}
+
+/// Serve one client until it sends `exit` or closes the input. Returns
+/// the exit code: 0 after an orderly shutdown, 1 otherwise.
+pub fn run(mut input: impl BufRead, mut output: impl Write) -> io::Result<i32> {
+    let mut server = Server::new();
+    while let Some(body) = read_message(&mut input)? {
+        for reply in server.handle_text(&body) {
+            write_message(&mut output, &reply)?;
+        }
+        if let Some(code) = server.exit_code() {
+            return Ok(code);
+        }
+    }
+    Ok(1)
+}
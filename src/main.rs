@@ -1,28 +1,715 @@
-use motly_rust::{parse_motly, ExecContext, SessionOptions, tree::MOTLYDataNode};
+use motly_rust::{DotEnv, EnvOptions, FileLoader, MOTLYSession, Secrets, SessionOptions, SourceMap};
+use motly_rust::diff::{diff, format_path, summary, type_name, Change, ChangeKind};
+use motly_rust::interpreter::TransformerOp;
+use motly_rust::tree::{format_ref_display, MOTLYDataNode, MOTLYNode};
+use motly_rust::error::MOTLYError;
+use motly_rust::format::{format, FormatOptions};
+use motly_rust::from_json::{plain_json_to_motly, PlainJsonOptions};
+use motly_rust::json::{changes_to_json, query_matches_to_json, to_json_redacted, to_plain_json, JsonStyle, PlainOptions};
+use motly_rust::merge::{merge, Conflict, ConflictKind};
+use motly_rust::patch::patch_source;
+use motly_rust::query::Query;
+use motly_rust::secret::REDACTED;
+use motly_rust::writer::{query_matches_to_motly, to_motly};
 
+use std::collections::HashMap;
 use std::io::{self, Read};
 
+const USAGE: &str = "usage: motly [--format json|plain] [--env-file FILE] [--env NAME=VALUE] [--redact] [--schema FILE] [FILE...]
+                          parse the files in order (stdin if none), with their
+                          #! include directives, into one value and print it as
+                          MOTLY's JSON form, or with plain as ordinary JSON: links
+                          followed, a node with a value and properties as
+                          {\"$value\": ..., ...}. With plain or an --env option,
+                          @env values come from the environment, then the
+                          dotenv FILEs and NAME=VALUEs in order. --redact prints
+                          *** for values from @env and, with --schema, for the
+                          properties the schema types as SecretString; explain,
+                          convert, get and diff take --redact and --schema too
+       motly explain [--redact] [--schema FILE] PATH [FILE...]
+                          show every statement that shaped the node at PATH
+                          (dotted, e.g. server.port) and its final value
+       motly convert [--from FORMAT] [--to FORMAT] [--date-strings] [--redact] [--schema FILE] [FILE]
+                          convert a file (stdin if none) between formats: motly
+                          (the default for both), json and, when built with the
+                          feature of that name, yaml and toml. JSON input is an
+                          ordinary document, with ISO date strings read as dates
+                          unless --date-strings; JSON output is MOTLY's JSON
+                          form. What TOML cannot express is listed on stderr
+       motly get [--motly] [--redact] [--schema FILE] QUERY [FILE...]
+                          print the nodes QUERY selects (e.g. servers.*.port,
+                          items[?region == us].name, ..host) as a JSON array,
+                          or as MOTLY statements; exit 1 if nothing matches
+       motly diff [--json] [--redact] [--schema FILE] OLD NEW
+                          compare the values of two files; exit 1 if they differ
+       motly merge [-o OUT] BASE OURS THEIRS
+                          three-way merge of two edits of BASE, written as MOTLY
+                          to OUT (stdout if none); conflicts keep ours, are
+                          listed on stderr and exit 1. The output is the merged
+                          value, not the merged text: comments are dropped, :=
+                          clones are written out as copies, the file is
+                          reformatted, and a conflict leaves no markers in OUT,
+                          only ours
+       motly patch OLD NEW
+                          print the MOTLY statements that, parsed after OLD,
+                          give the value of NEW
+       motly fmt [--check] [FILE...]
+                          format files in place (stdin to stdout if no files);
+                          with --check, report unformatted input and exit 1
+       motly lsp [--stdio]
+                          run the language server on stdin and stdout (when
+                          built with the lsp feature)";
+
 fn main() {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    match args.first().map(String::as_str) {
+        Some("fmt") => run_fmt(&args[1..]),
+        Some("explain") => run_explain(&args[1..]),
+        Some("get") => run_get(&args[1..]),
+        Some("convert") => run_convert(&args[1..]),
+        Some("diff") => run_diff(&args[1..]),
+        Some("patch") => run_patch(&args[1..]),
+        Some("merge") => run_merge(&args[1..]),
+        #[cfg(feature = "lsp")]
+        Some("lsp") => run_lsp(&args[1..]),
+        Some("-h") | Some("--help") => println!("{}", USAGE),
+        Some("--format") | Some("--env-file") | Some("--env") | Some("--redact") | Some("--schema") => run_json(&args),
+        Some(flag) if flag.starts_with('-') => usage_error(&format!("unknown option '{}'", flag)),
+        _ => run_json(&args),
+    }
+}
+
+#[cfg(feature = "lsp")]
+fn run_lsp(args: &[String]) {
+    // Editors often pass --stdio; stdio is the only transport
+    if let Some(arg) = args.iter().find(|arg| *arg != "--stdio") {
+        usage_error(&format!("unexpected argument '{}'", arg));
+    }
+    let stdin = io::stdin();
+    match motly_rust::lsp::run(stdin.lock(), io::stdout().lock()) {
+        Ok(code) => std::process::exit(code),
+        Err(err) => {
+            eprintln!("motly lsp: {}", err);
+            std::process::exit(1);
+        }
+    }
+}
+
+fn usage_error(message: &str) -> ! {
+    eprintln!("motly: {}", message);
+    eprintln!("{}", USAGE);
+    std::process::exit(2);
+}
+
+fn read_stdin() -> String {
     let mut input = String::new();
     io::stdin().read_to_string(&mut input).unwrap();
+    input
+}
+
+/// Parse the files (or stdin), following includes, and finish. Exits
+/// after printing any errors; unresolved references are only warned
+/// about, since the value is complete without their targets.
+fn load_session(files: &[String], options: SessionOptions) -> MOTLYSession {
+    let mut session = MOTLYSession::new(options);
+    let mut errors = Vec::new();
+    if files.is_empty() {
+        let results = session.parse_with_includes("<stdin>", &read_stdin(), &FileLoader);
+        errors.extend(results.into_iter().flat_map(|r| r.errors));
+    }
+    for file in files {
+        match std::fs::read_to_string(file) {
+            Ok(text) => {
+                let results = session.parse_with_includes(file, &text, &FileLoader);
+                errors.extend(results.into_iter().flat_map(|r| r.errors));
+            }
+            Err(err) => {
+                eprintln!("{}: {}", file, err);
+                std::process::exit(1);
+            }
+        }
+    }
+    errors.extend(session.finish());
+    let (warnings, errors): (Vec<_>, Vec<_>) = errors.into_iter().partition(|err| err.code == "unresolved-reference");
+    print_diagnostics(session.sources(), &warnings, "WARNING");
+    if !errors.is_empty() {
+        print_errors(session.sources(), &errors);
+        std::process::exit(1);
+    }
+    session
+}
 
-    let ctx = ExecContext {
-        parse_id: 0,
-        options: SessionOptions::default(),
+/// `--redact` and `--schema FILE`, taken by the commands that print values.
+#[derive(Default)]
+struct RedactOptions {
+    redact: bool,
+    schema: Option<String>,
+}
+
+impl RedactOptions {
+    /// Take `arg`, and the file after `--schema`, if it is one of ours.
+    fn parse<'a>(&mut self, arg: &str, args: &mut impl Iterator<Item = &'a String>) -> bool {
+        match arg {
+            "--redact" => self.redact = true,
+            "--schema" => match args.next() {
+                Some(file) => self.schema = Some(file.clone()),
+                None => usage_error("--schema needs a file"),
+            },
+            _ => return false,
+        }
+        true
+    }
+
+    /// The secrets of `session` if redacting, with the `SecretString`
+    /// properties of the schema. Call before `resolve_env`.
+    fn secrets(&self, session: &mut MOTLYSession) -> Option<Secrets> {
+        if let Some(file) = &self.schema {
+            let text = read_input(std::slice::from_ref(file));
+            let errors = session.parse_schema(&text).errors;
+            if !errors.is_empty() {
+                for err in errors {
+                    print_source_error(file, &text, err);
+                }
+                std::process::exit(1);
+            }
+        }
+        self.redact.then(|| session.secrets())
+    }
+}
+
+/// `node`, the node at `path`, with secret values replaced.
+fn redact_node(node: &MOTLYNode, secrets: &Secrets, path: &[String]) -> MOTLYNode {
+    match node {
+        MOTLYNode::Data(data) => MOTLYNode::Data(secrets.redact(data, path)),
+        link => link.clone(),
+    }
+}
+
+/// `change` with secret values replaced, `old` for the before side and
+/// `new` for the after side.
+fn redact_change(change: &Change, old: &Secrets, new: &Secrets) -> Change {
+    let mut before_path = change.path.clone();
+    if let (ChangeKind::ElementMoved { from, .. }, Some(last)) = (&change.kind, before_path.last_mut()) {
+        *last = format!("[{}]", from);
+    }
+    Change {
+        before: change.before.as_ref().map(|node| redact_node(node, old, &before_path)),
+        after: change.after.as_ref().map(|node| redact_node(node, new, &change.path)),
+        ..change.clone()
+    }
+}
+
+fn run_json(args: &[String]) {
+    let mut plain = false;
+    let mut redact = RedactOptions::default();
+    // The process environment, with --env-file and --env applied in order
+    let mut env: Option<HashMap<String, String>> = None;
+    let mut files = Vec::new();
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--format" => match args.next().map(String::as_str) {
+                Some("json") => plain = false,
+                Some("plain") => plain = true,
+                Some(other) => usage_error(&format!("unknown output format '{}'", other)),
+                None => usage_error("--format needs a format"),
+            },
+            "--env-file" => {
+                let Some(file) = args.next() else {
+                    usage_error("--env-file needs a file");
+                };
+                let text = read_input(std::slice::from_ref(file));
+                let dotenv = DotEnv::parse(&text).unwrap_or_else(|err| {
+                    print_source_error(file, &text, err);
+                    std::process::exit(1);
+                });
+                env.get_or_insert_with(|| std::env::vars().collect()).extend(dotenv.vars);
+            }
+            "--env" => {
+                let Some((name, value)) = args.next().and_then(|var| var.split_once('=')) else {
+                    usage_error("--env needs NAME=VALUE");
+                };
+                env.get_or_insert_with(|| std::env::vars().collect()).insert(name.to_string(), value.to_string());
+            }
+            flag if redact.parse(flag, &mut args) => {}
+            flag if flag.starts_with('-') => usage_error(&format!("unknown option '{}'", flag)),
+            file => files.push(file.to_string()),
+        }
+    }
+    let mut session = load_session(&files, SessionOptions::default());
+    let secrets = redact.secrets(&mut session);
+    if plain && env.is_none() {
+        env = Some(std::env::vars().collect());
+    }
+    if let Some(env) = &env {
+        let errors = session.resolve_env(env, &EnvOptions::default());
+        if !errors.is_empty() {
+            print_errors(session.sources(), &errors);
+            std::process::exit(1);
+        }
+    }
+    if plain {
+        let options = PlainOptions { style: JsonStyle::Pretty, redact: secrets, ..PlainOptions::default() };
+        println!("{}", to_plain_json(session.value(), &options));
+    } else if let Some(secrets) = &secrets {
+        println!("{}", to_json_redacted(session.value(), secrets, JsonStyle::Pretty));
+    } else {
+        println!("{}", session.value().to_json_pretty());
+    }
+}
+
+fn run_explain(args: &[String]) {
+    let mut redact = RedactOptions::default();
+    let mut rest = Vec::new();
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            flag if redact.parse(flag, &mut args) => {}
+            flag if flag.starts_with('-') => usage_error(&format!("unknown option '{}'", flag)),
+            other => rest.push(other.to_string()),
+        }
+    }
+    let Some((path, files)) = rest.split_first() else {
+        usage_error("explain needs a path");
+    };
+    let mut session = load_session(files, SessionOptions { trace: true, ..SessionOptions::default() });
+    let secrets = redact.secrets(&mut session);
+    let path: Vec<String> = path.split('.').map(str::to_string).collect();
+    let sources = session.sources();
+
+    println!("{}", path.join("."));
+    let mut rows = Vec::new();
+    for (at, entry) in session.provenance().history(&path) {
+        let text = sources
+            .get(entry.parse_id)
+            .map(|file| &file.text[entry.span.begin.offset..entry.span.end.offset])
+            .and_then(|text| text.lines().next())
+            .unwrap_or("");
+        // A statement on a node with a secret in it may spell out the value
+        let hidden = secrets.as_ref().is_some_and(|secrets| secrets.within(at));
+        let mut detail = if hidden { REDACTED.to_string() } else { text.trim().to_string() };
+        if at.len() < path.len() {
+            detail.push_str(&format!("  (on {})", display_path(at)));
+        }
+        if let Some(source) = &entry.clone_source {
+            detail.push_str(&format!("  (copy of {})", source));
+        }
+        rows.push((sources.format_position(Some(entry.parse_id), &entry.span.begin), op_name(&entry.op), detail));
+    }
+    let width = rows.iter().map(|(at, _, _)| at.len()).max().unwrap_or(0);
+    for (at, op, detail) in rows {
+        println!("  {:<width$}  {:<16}  {}", at, op, detail, width = width);
+    }
+    match lookup(session.value(), &path) {
+        Some(MOTLYNode::Data(node)) if node.deleted => println!("= (deleted)"),
+        Some(MOTLYNode::Data(node)) => match &secrets {
+            Some(secrets) => println!("= {}", secrets.redact(node, &path).to_json()),
+            None => println!("= {}", node.to_json()),
+        },
+        Some(MOTLYNode::Ref { link_to, link_ups, .. }) => println!("= {}", format_ref_display(*link_ups, link_to)),
+        None => println!("= (not present)"),
+    }
+}
+
+fn op_name(op: &TransformerOp) -> &'static str {
+    match op {
+        TransformerOp::SetValue { .. } => "set",
+        TransformerOp::AssignValue { .. } => "assign",
+        TransformerOp::ClearProperties => "clear-properties",
+        TransformerOp::ClearAll => "clear-all",
+        TransformerOp::Define => "define",
+        TransformerOp::Delete => "delete",
+        TransformerOp::Link { .. } => "link",
+        TransformerOp::Clone { .. } => "clone",
+    }
+}
+
+fn display_path(path: &[String]) -> String {
+    if path.is_empty() {
+        "the root".to_string()
+    } else {
+        path.join(".")
+    }
+}
+
+fn lookup<'a>(root: &'a MOTLYDataNode, path: &[String]) -> Option<&'a MOTLYNode> {
+    let (last, parents) = path.split_last()?;
+    let mut node = root;
+    for key in parents {
+        node = node.properties.as_ref()?.get(key)?.as_data_node()?;
+    }
+    node.properties.as_ref()?.get(last)
+}
+
+fn run_convert(args: &[String]) {
+    let mut from = "motly".to_string();
+    let mut to = "motly".to_string();
+    let mut json_options = PlainJsonOptions::default();
+    let mut redact = RedactOptions::default();
+    let mut files = Vec::new();
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--from" | "--to" => {
+                let Some(format) = args.next() else {
+                    usage_error(&format!("{} needs a format", arg));
+                };
+                if arg == "--from" { from = format.clone() } else { to = format.clone() }
+            }
+            "--date-strings" => json_options.detect_dates = false,
+            flag if redact.parse(flag, &mut args) => {}
+            flag if flag.starts_with('-') => usage_error(&format!("unknown option '{}'", flag)),
+            file => files.push(file.to_string()),
+        }
+    }
+    if files.len() > 1 {
+        usage_error("convert takes one file");
+    }
+    let name = files.first().cloned().unwrap_or_else(|| "<stdin>".to_string());
+
+    let source: Option<String> = match from.as_str() {
+        "motly" => None,
+        "json" => Some(convert_source(&name, &read_input(&files), |text| plain_json_to_motly(text, &json_options))),
+        #[cfg(feature = "yaml")]
+        "yaml" => Some(convert_source(&name, &read_input(&files), motly_rust::yaml::from_yaml)),
+        #[cfg(feature = "toml")]
+        "toml" => Some(convert_source(&name, &read_input(&files), motly_rust::toml::from_toml)),
+        other => usage_error(&format!("cannot convert from '{}'", other)),
     };
-    let result = parse_motly(&input, MOTLYDataNode::new(), &ctx);
-    if result.errors.is_empty() {
-        println!("{}", result.value.to_json_pretty());
+    let mut session = match &source {
+        // Converted source is interpreted like any other
+        Some(source) => {
+            let mut session = MOTLYSession::new(SessionOptions::default());
+            let mut errors = session.parse_named(&format!("{} (as MOTLY)", name), source).errors;
+            errors.extend(session.finish());
+            if !errors.is_empty() {
+                print_errors(session.sources(), &errors);
+                std::process::exit(1);
+            }
+            session
+        }
+        None => load_session(&files, SessionOptions::default()),
+    };
+    let secrets = redact.secrets(&mut session);
+    let tree = match &secrets {
+        Some(secrets) => secrets.redact(session.value(), &[]),
+        None => session.value().clone(),
+    };
+
+    match to.as_str() {
+        // Converted source is printed as is, unless values were redacted
+        "motly" => print!("{}", source.filter(|_| secrets.is_none()).unwrap_or_else(|| to_motly(&tree))),
+        "json" => println!("{}", tree.to_json_pretty()),
+        #[cfg(feature = "yaml")]
+        "yaml" => print!("{}", motly_rust::yaml::to_yaml(&tree)),
+        #[cfg(feature = "toml")]
+        "toml" => {
+            let output = motly_rust::toml::to_toml(&tree);
+            print!("{}", output.text);
+            for loss in &output.losses {
+                let path = if loss.path.is_empty() { "(root)".to_string() } else { format_path(&loss.path) };
+                eprintln!("motly: warning: {}: {}", path, loss.kind.description());
+            }
+        }
+        other => usage_error(&format!("cannot convert to '{}'", other)),
+    }
+}
+
+/// The text of the only file, or stdin.
+fn read_input(files: &[String]) -> String {
+    match files.first() {
+        None => read_stdin(),
+        Some(file) => std::fs::read_to_string(file).unwrap_or_else(|err| {
+            eprintln!("{}: {}", file, err);
+            std::process::exit(1);
+        }),
+    }
+}
+
+/// MOTLY source converted from another format; exits on errors.
+fn convert_source(name: &str, text: &str, convert: impl Fn(&str) -> Result<String, MOTLYError>) -> String {
+    convert(text).unwrap_or_else(|err| {
+        print_source_error(name, text, err);
+        std::process::exit(1);
+    })
+}
+
+fn run_get(args: &[String]) {
+    let mut motly = false;
+    let mut redact = RedactOptions::default();
+    let mut rest = Vec::new();
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--motly" => motly = true,
+            flag if redact.parse(flag, &mut args) => {}
+            flag if flag.starts_with('-') => usage_error(&format!("unknown option '{}'", flag)),
+            other => rest.push(other.to_string()),
+        }
+    }
+    let Some((text, files)) = rest.split_first() else {
+        usage_error("get needs a query");
+    };
+    let query = match Query::parse(text) {
+        Ok(query) => query,
+        Err(err) => {
+            eprintln!("query:{}: {}", err.begin.column + 1, err.message);
+            std::process::exit(2);
+        }
+    };
+    let mut session = load_session(files, SessionOptions::default());
+    let secrets = redact.secrets(&mut session);
+    let mut matches = query.select(session.value());
+    if let Some(secrets) = &secrets {
+        for m in &mut matches {
+            m.node = secrets.redact(&m.node, &m.path);
+        }
+    }
+    if motly {
+        print!("{}", query_matches_to_motly(&matches));
+    } else {
+        println!("{}", query_matches_to_json(&matches));
+    }
+    if matches.is_empty() {
+        std::process::exit(1);
+    }
+}
+
+fn run_diff(args: &[String]) {
+    let mut json = false;
+    let mut redact = RedactOptions::default();
+    let mut files = Vec::new();
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--json" => json = true,
+            flag if redact.parse(flag, &mut args) => {}
+            flag if flag.starts_with('-') => usage_error(&format!("unknown option '{}'", flag)),
+            file => files.push(file.to_string()),
+        }
+    }
+    let [old, new] = files.as_slice() else {
+        usage_error("diff needs two files");
+    };
+    let mut old = load_session(std::slice::from_ref(old), SessionOptions::default());
+    let mut new = load_session(std::slice::from_ref(new), SessionOptions::default());
+    let secrets = redact.secrets(&mut old).zip(redact.secrets(&mut new));
+    let changes = diff(old.value(), new.value());
+
+    if json {
+        let shown: Vec<Change> = match &secrets {
+            Some((old, new)) => changes.iter().map(|change| redact_change(change, old, new)).collect(),
+            None => changes.clone(),
+        };
+        println!("{}", changes_to_json(&shown, Some(old.sources()), Some(new.sources())));
+    } else {
+        for change in &changes {
+            let shown = secrets.as_ref().map(|(old, new)| redact_change(change, old, new));
+            println!("{}", describe_change(change, shown.as_ref(), old.sources(), new.sources()));
+        }
+    }
+    if !changes.is_empty() {
+        std::process::exit(1);
+    }
+}
+
+fn run_patch(args: &[String]) {
+    if let Some(flag) = args.iter().find(|arg| arg.starts_with('-')) {
+        usage_error(&format!("unknown option '{}'", flag));
+    }
+    let [old, new] = args else {
+        usage_error("patch needs two files");
+    };
+    let old = load_session(std::slice::from_ref(old), SessionOptions::default());
+    let new = load_session(std::slice::from_ref(new), SessionOptions::default());
+    print!("{}", patch_source(old.value(), new.value()));
+}
+
+fn run_merge(args: &[String]) {
+    let mut out = None;
+    let mut files = Vec::new();
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "-o" => match args.next() {
+                Some(file) => out = Some(file.clone()),
+                None => usage_error("-o needs a file"),
+            },
+            flag if flag.starts_with('-') => usage_error(&format!("unknown option '{}'", flag)),
+            file => files.push(file.to_string()),
+        }
+    }
+    let [base, ours, theirs] = files.as_slice() else {
+        usage_error("merge needs three files");
+    };
+    let base = load_session(std::slice::from_ref(base), SessionOptions::default());
+    let ours = load_session(std::slice::from_ref(ours), SessionOptions::default());
+    let theirs = load_session(std::slice::from_ref(theirs), SessionOptions::default());
+    let result = merge(base.value(), ours.value(), theirs.value());
+
+    let text = to_motly(&result.value);
+    match &out {
+        Some(file) => {
+            if let Err(err) = std::fs::write(file, &text) {
+                eprintln!("{}: {}", file, err);
+                std::process::exit(1);
+            }
+        }
+        None => print!("{}", text),
+    }
+    for conflict in &result.conflicts {
+        eprintln!("{}", describe_conflict(conflict, ours.sources(), theirs.sources()));
+    }
+    if !result.conflicts.is_empty() {
+        std::process::exit(1);
+    }
+}
+
+/// `! server.port: ours 8080, theirs 9090, base 80  (ours.motly:2:3, theirs.motly:2:3)`.
+fn describe_conflict(conflict: &Conflict, ours: &SourceMap, theirs: &SourceMap) -> String {
+    let side = |node: &Option<MOTLYNode>| match node {
+        None => "(absent)".to_string(),
+        Some(MOTLYNode::Data(data)) if data.deleted => "(deleted)".to_string(),
+        Some(node) if conflict.kind == ConflictKind::Node => format!("{} {}", type_name(node), summary(node)),
+        Some(node) => summary(node),
+    };
+    let mut line = format!(
+        "! {}: ours {}, theirs {}, base {}",
+        format_path(&conflict.path),
+        side(&conflict.ours),
+        side(&conflict.theirs),
+        side(&conflict.base),
+    );
+    let locations: Vec<String> = [
+        conflict.ours_location().map(|loc| ours.format_location(loc)),
+        conflict.theirs_location().map(|loc| theirs.format_location(loc)),
+    ]
+    .into_iter()
+    .flatten()
+    .collect();
+    if !locations.is_empty() {
+        line.push_str(&format!("  ({})", locations.join(", ")));
+    }
+    line
+}
+
+/// One line per change: `~ server.port: 80 -> 8080  (old.motly:2:3, new.motly:2:3)`.
+/// Values are shown from `redacted`, the change with secrets replaced, if given.
+fn describe_change(change: &Change, redacted: Option<&Change>, old: &SourceMap, new: &SourceMap) -> String {
+    let path = format_path(&change.path);
+    let shown = redacted.unwrap_or(change);
+    let before = shown.before.as_ref().map(summary).unwrap_or_default();
+    let after = shown.after.as_ref().map(summary).unwrap_or_default();
+    let mut line = match &change.kind {
+        ChangeKind::Added | ChangeKind::ElementInserted => format!("+ {} = {}", path, after),
+        ChangeKind::Removed | ChangeKind::ElementRemoved => format!("- {} = {}", path, before),
+        ChangeKind::ValueChanged | ChangeKind::LinkRetargeted => format!("~ {}: {} -> {}", path, before, after),
+        ChangeKind::TypeChanged => {
+            let (from, to) = (change.before.as_ref().unwrap(), change.after.as_ref().unwrap());
+            format!("~ {}: {} {} -> {} {}", path, type_name(from), before, type_name(to), after)
+        }
+        ChangeKind::ElementMoved { from, to } => format!("> {}: moved from [{}] to [{}]", path, from, to),
+    };
+    let locations: Vec<String> = [
+        change.before_location().map(|loc| old.format_location(loc)),
+        change.after_location().map(|loc| new.format_location(loc)),
+    ]
+    .into_iter()
+    .flatten()
+    .collect();
+    if !locations.is_empty() {
+        line.push_str(&format!("  ({})", locations.join(", ")));
+    }
+    line
+}
+
+fn run_fmt(args: &[String]) {
+    let mut check = false;
+    let mut files = Vec::new();
+    for arg in args {
+        match arg.as_str() {
+            "--check" => check = true,
+            flag if flag.starts_with('-') => usage_error(&format!("unknown option '{}'", flag)),
+            file => files.push(file),
+        }
+    }
+    let options = FormatOptions::default();
+
+    if files.is_empty() {
+        let input = read_stdin();
+        match format(&input, &options) {
+            Ok(formatted) if check => {
+                if formatted != input {
+                    eprintln!("<stdin> is not formatted");
+                    std::process::exit(1);
+                }
+            }
+            Ok(formatted) => print!("{}", formatted),
+            Err(err) => {
+                print_source_error("<stdin>", &input, err);
+                std::process::exit(1);
+            }
+        }
         return;
     }
 
-    let lines: Vec<&str> = input.lines().collect();
+    let mut failed = false;
+    for file in files {
+        let input = match std::fs::read_to_string(file) {
+            Ok(input) => input,
+            Err(err) => {
+                eprintln!("{}: {}", file, err);
+                failed = true;
+                continue;
+            }
+        };
+        let formatted = match format(&input, &options) {
+            Ok(formatted) => formatted,
+            Err(err) => {
+                print_source_error(file, &input, err);
+                failed = true;
+                continue;
+            }
+        };
+        if formatted == input {
+            continue;
+        }
+        if check {
+            eprintln!("{} is not formatted", file);
+            failed = true;
+        } else if let Err(err) = std::fs::write(file, formatted) {
+            eprintln!("{}: {}", file, err);
+            failed = true;
+        }
+    }
+    if failed {
+        std::process::exit(1);
+    }
+}
 
-    for err in &result.errors {
-        let line_num = err.begin.line;
-        let line_text = lines.get(line_num).unwrap_or(&"");
+/// Report an error in a single source that was not parsed by a session.
+fn print_source_error(name: &str, text: &str, err: MOTLYError) {
+    let mut sources = SourceMap::new();
+    sources.add(0, name, text);
+    print_errors(&sources, &[MOTLYError { parse_id: Some(0), ..err }]);
+}
 
-        eprintln!("ERROR AT LINE {}:", line_num + 1);
+fn print_errors(sources: &SourceMap, errors: &[MOTLYError]) {
+    print_diagnostics(sources, errors, "ERROR");
+}
+
+/// Print each error under `label` (ERROR or WARNING), with its line.
+fn print_diagnostics(sources: &SourceMap, errors: &[MOTLYError], label: &str) {
+    for err in errors {
+        let Some(parse_id) = err.parse_id else {
+            eprintln!("{}:", label);
+            eprintln!("{}", err.message);
+            eprintln!();
+            continue;
+        };
+        let line_text = sources.line_text(parse_id, &err.begin).unwrap_or("");
+
+        eprintln!("{} AT {}:", label, sources.format_position(Some(parse_id), &err.begin));
         eprintln!("{}", line_text);
 
         // Build the underline
@@ -53,6 +740,4 @@ fn main() {
         eprintln!("{}", err.message);
         eprintln!();
     }
-
-    std::process::exit(1);
 }
@@ -0,0 +1,170 @@
+/// Three-way merge of interpreted trees.
+///
+/// `merge(base, ours, theirs)` combines two trees that were both edited from
+/// `base`. A node's value and its properties are merged separately, so one
+/// side changing `server = prod` while the other adds `server.port` is not
+/// a conflict. Properties are merged key by key, recursively. Arrays and
+/// references are merged as whole values.
+///
+/// Where both sides changed the same slot differently, the merge keeps
+/// ours and reports a `Conflict`. Changes of kind (data, reference,
+/// deleted, absent) cannot be combined with other edits of the node, so
+/// they conflict as a whole node.
+use std::collections::{BTreeMap, BTreeSet};
+
+use crate::diff::{same, same_eq};
+use crate::tree::{MOTLYDataNode, MOTLYLocation, MOTLYNode};
+
+/// Which part of a node both sides changed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConflictKind {
+    /// Both changed the value slot, to different values.
+    Value,
+    /// The changes cannot be merged slot by slot: one side removed the
+    /// node, or changed what kind of node it is, and the other edited it.
+    Node,
+}
+
+/// A path both sides changed incompatibly. The nodes are as each tree has
+/// them; `None` means absent.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Conflict {
+    pub kind: ConflictKind,
+    /// Absolute path, as in `diff::Change`.
+    pub path: Vec<String>,
+    pub base: Option<MOTLYNode>,
+    pub ours: Option<MOTLYNode>,
+    pub theirs: Option<MOTLYNode>,
+}
+
+impl Conflict {
+    pub fn ours_location(&self) -> Option<&MOTLYLocation> {
+        self.ours.as_ref().and_then(MOTLYNode::location)
+    }
+
+    pub fn theirs_location(&self) -> Option<&MOTLYLocation> {
+        self.theirs.as_ref().and_then(MOTLYNode::location)
+    }
+}
+
+/// The merged tree, which takes ours wherever there is a conflict.
+#[derive(Debug, Clone, PartialEq)]
+pub struct MergeResult {
+    pub value: MOTLYDataNode,
+    pub conflicts: Vec<Conflict>,
+}
+
+/// Merge the changes `ours` and `theirs` made to `base`.
+pub fn merge(base: &MOTLYDataNode, ours: &MOTLYDataNode, theirs: &MOTLYDataNode) -> MergeResult {
+    let mut conflicts = Vec::new();
+    let value = merge_data(Some(base), ours, theirs, &mut Vec::new(), &mut conflicts);
+    MergeResult { value, conflicts }
+}
+
+type Side<'a> = Option<&'a MOTLYNode>;
+
+fn same_side(x: Side, y: Side) -> bool {
+    match (x, y) {
+        (Some(x), Some(y)) => same(x, y),
+        (None, None) => true,
+        _ => false,
+    }
+}
+
+fn live_data(node: Side<'_>) -> Option<&MOTLYDataNode> {
+    match node {
+        Some(MOTLYNode::Data(data)) if !data.deleted => Some(data),
+        _ => None,
+    }
+}
+
+/// The merged node at `path`; None if it ends up absent.
+fn merge_node(
+    base: Side,
+    ours: Side,
+    theirs: Side,
+    path: &mut Vec<String>,
+    conflicts: &mut Vec<Conflict>,
+) -> Option<MOTLYNode> {
+    if same_side(ours, theirs) || same_side(base, theirs) {
+        return ours.cloned();
+    }
+    if same_side(base, ours) {
+        return theirs.cloned();
+    }
+    // Both changed it. Data nodes (or a node both added) merge slot by slot
+    let base_data = live_data(base);
+    if let (Some(o), Some(t)) = (live_data(ours), live_data(theirs)) {
+        if base.is_none() || base_data.is_some() {
+            return Some(MOTLYNode::Data(merge_data(base_data, o, t, path, conflicts)));
+        }
+    }
+    conflicts.push(Conflict {
+        kind: ConflictKind::Node,
+        path: path.clone(),
+        base: base.cloned(),
+        ours: ours.cloned(),
+        theirs: theirs.cloned(),
+    });
+    ours.cloned()
+}
+
+fn merge_data(
+    base: Option<&MOTLYDataNode>,
+    ours: &MOTLYDataNode,
+    theirs: &MOTLYDataNode,
+    path: &mut Vec<String>,
+    conflicts: &mut Vec<Conflict>,
+) -> MOTLYDataNode {
+    let none = None;
+    let base_eq = base.map_or(&none, |b| &b.eq);
+    let eq = if same_eq(&ours.eq, &theirs.eq) || same_eq(base_eq, &theirs.eq) {
+        ours.eq.clone()
+    } else if same_eq(base_eq, &ours.eq) {
+        theirs.eq.clone()
+    } else {
+        conflicts.push(Conflict {
+            kind: ConflictKind::Value,
+            path: path.clone(),
+            base: base.map(value_only),
+            ours: Some(value_only(ours)),
+            theirs: Some(value_only(theirs)),
+        });
+        ours.eq.clone()
+    };
+
+    let empty = BTreeMap::new();
+    let base_props = base.and_then(|b| b.properties.as_ref());
+    let ours_props = ours.properties.as_ref().unwrap_or(&empty);
+    let theirs_props = theirs.properties.as_ref().unwrap_or(&empty);
+    let mut properties = BTreeMap::new();
+    let keys: BTreeSet<&String> = ours_props.keys().chain(theirs_props.keys()).collect();
+    for key in keys {
+        path.push(key.clone());
+        let base_child = base_props.and_then(|p| p.get(key));
+        if let Some(node) = merge_node(base_child, ours_props.get(key), theirs_props.get(key), path, conflicts) {
+            properties.insert(key.clone(), node);
+        }
+        path.pop();
+    }
+    // An empty properties slot is kept as the side that changed it has it
+    let has_props = |node: &MOTLYDataNode| node.properties.is_some();
+    let keep = !properties.is_empty()
+        || if has_props(ours) == has_props(theirs) || base.map(has_props) == Some(has_props(theirs)) {
+            has_props(ours)
+        } else {
+            has_props(theirs)
+        };
+
+    MOTLYDataNode {
+        eq,
+        properties: keep.then_some(properties),
+        deleted: false,
+        location: ours.location.or(theirs.location),
+    }
+}
+
+/// The node with its value slot only, for reporting a value conflict.
+fn value_only(node: &MOTLYDataNode) -> MOTLYNode {
+    MOTLYNode::Data(MOTLYDataNode { eq: node.eq.clone(), properties: None, deleted: false, location: node.location })
+}
@@ -0,0 +1,289 @@
+/// The resolved, consumer-facing read interface to parsed MOTLY data.
+///
+/// A `Mot` is an owned tree built from a `MOTLYDataNode`: references have
+/// been followed (the target is copied in), `@env` values substituted and
+/// deleted nodes dropped. Following a reference back into a node that is
+/// still being built would never terminate, so that back-edge resolves to
+/// nothing instead, which is what the TypeScript Mot shows for pure cycles.
+///
+/// Dates are kept as their ISO 8601 text; see docs/mot-api-rust.md.
+use std::collections::{BTreeMap, HashMap};
+
+use crate::tree::{EqValue, MOTLYDataNode, MOTLYNode, RefSegment, Scalar};
+
+/// A resolved node. The only non-existent Mot is the root of an empty
+/// build or an array element whose reference did not resolve; missing
+/// properties are reported as `None` by `get`.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct Mot {
+    inner: Option<MotData>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+struct MotData {
+    value: Option<MotValue>,
+    properties: BTreeMap<String, Mot>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum MotValue {
+    String(String),
+    Number(f64),
+    Boolean(bool),
+    Date(String),
+    Array(Vec<Mot>),
+}
+
+/// The type of a Mot's value slot.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ValueType {
+    String,
+    Number,
+    Boolean,
+    Date,
+    Array,
+}
+
+impl Mot {
+    fn value(&self) -> Option<&MotValue> {
+        self.inner.as_ref()?.value.as_ref()
+    }
+
+    // --- Existence ---
+
+    pub fn exists(&self) -> bool {
+        self.inner.is_some()
+    }
+
+    pub fn has(&self, path: &[&str]) -> bool {
+        self.get_path(path).is_some()
+    }
+
+    // --- Navigation ---
+
+    /// Single-step navigation. Returns None if the property does not exist.
+    pub fn get(&self, key: &str) -> Option<&Mot> {
+        self.inner.as_ref()?.properties.get(key)
+    }
+
+    /// Multi-step convenience. Equivalent to chained get()?.get()?.
+    pub fn get_path(&self, path: &[&str]) -> Option<&Mot> {
+        let mut current = self;
+        for key in path {
+            current = current.get(key)?;
+        }
+        Some(current)
+    }
+
+    // --- Value type ---
+
+    pub fn value_type(&self) -> Option<ValueType> {
+        Some(match self.value()? {
+            MotValue::String(_) => ValueType::String,
+            MotValue::Number(_) => ValueType::Number,
+            MotValue::Boolean(_) => ValueType::Boolean,
+            MotValue::Date(_) => ValueType::Date,
+            MotValue::Array(_) => ValueType::Array,
+        })
+    }
+
+    // --- Typed accessors ---
+
+    pub fn text(&self) -> Option<&str> {
+        match self.value()? {
+            MotValue::String(s) => Some(s),
+            _ => None,
+        }
+    }
+
+    pub fn numeric(&self) -> Option<f64> {
+        match self.value()? {
+            MotValue::Number(n) => Some(*n),
+            _ => None,
+        }
+    }
+
+    pub fn boolean(&self) -> Option<bool> {
+        match self.value()? {
+            MotValue::Boolean(b) => Some(*b),
+            _ => None,
+        }
+    }
+
+    /// The date as written, e.g. `2024-01-15` or `2024-01-15T10:00:00+05:00`.
+    pub fn date(&self) -> Option<&str> {
+        match self.value()? {
+            MotValue::Date(d) => Some(d),
+            _ => None,
+        }
+    }
+
+    // --- Array access ---
+
+    pub fn values(&self) -> Option<&[Mot]> {
+        match self.value()? {
+            MotValue::Array(items) => Some(items),
+            _ => None,
+        }
+    }
+
+    /// All elements as strings, or None if any element is not a string.
+    pub fn texts(&self) -> Option<Vec<&str>> {
+        self.values()?.iter().map(Mot::text).collect()
+    }
+
+    /// All elements as numbers, or None if any element is not a number.
+    pub fn numerics(&self) -> Option<Vec<f64>> {
+        self.values()?.iter().map(Mot::numeric).collect()
+    }
+
+    /// All elements as booleans, or None if any element is not a boolean.
+    pub fn booleans(&self) -> Option<Vec<bool>> {
+        self.values()?.iter().map(Mot::boolean).collect()
+    }
+
+    /// All elements as dates, or None if any element is not a date.
+    pub fn dates(&self) -> Option<Vec<&str>> {
+        self.values()?.iter().map(Mot::date).collect()
+    }
+
+    // --- Property enumeration ---
+
+    pub fn keys(&self) -> impl Iterator<Item = &str> {
+        self.entries().map(|(key, _)| key)
+    }
+
+    pub fn entries(&self) -> impl Iterator<Item = (&str, &Mot)> {
+        self.inner
+            .iter()
+            .flat_map(|data| data.properties.iter().map(|(k, v)| (k.as_str(), v)))
+    }
+}
+
+/// Construct a Mot from an interpreted tree. `@env.NAME` values are looked
+/// up in `env`; unset variables leave the node without a value.
+pub fn build_mot(root: &MOTLYDataNode, env: Option<&HashMap<String, String>>) -> Mot {
+    let mut builder = Builder { root, env, building: Vec::new() };
+    // The root is its own first ancestor, as in validate_references
+    builder.node(root, &[root])
+}
+
+struct Builder<'a> {
+    root: &'a MOTLYDataNode,
+    env: Option<&'a HashMap<String, String>>,
+    /// Nodes currently being built, for detecting reference cycles.
+    building: Vec<*const MOTLYDataNode>,
+}
+
+impl<'a> Builder<'a> {
+    /// `ancestors` is the chain above `node`, not including it.
+    fn node(&mut self, node: &'a MOTLYDataNode, ancestors: &[&'a MOTLYDataNode]) -> Mot {
+        let ptr = node as *const MOTLYDataNode;
+        if node.deleted || self.building.contains(&ptr) {
+            return Mot::default();
+        }
+        self.building.push(ptr);
+
+        let value = match &node.eq {
+            None => None,
+            Some(EqValue::Scalar(Scalar::String(s))) => Some(MotValue::String(s.clone())),
+            Some(EqValue::Scalar(Scalar::Number(n))) => Some(MotValue::Number(*n)),
+            Some(EqValue::Scalar(Scalar::Boolean(b))) => Some(MotValue::Boolean(*b)),
+            Some(EqValue::Scalar(Scalar::Date(d))) => Some(MotValue::Date(d.clone())),
+            Some(EqValue::EnvRef(name)) => self.env.and_then(|env| env.get(name)).cloned().map(MotValue::String),
+            Some(EqValue::Array(items)) => {
+                // Array elements are children of node
+                let mut inner = ancestors.to_vec();
+                inner.push(node);
+                Some(MotValue::Array(items.iter().map(|item| self.child(item, &inner, node)).collect()))
+            }
+        };
+
+        let mut properties = BTreeMap::new();
+        if let Some(props) = &node.properties {
+            for (key, child) in props {
+                let mot = self.child(child, ancestors, node);
+                if mot.exists() {
+                    properties.insert(key.clone(), mot);
+                }
+            }
+        }
+
+        self.building.pop();
+        Mot { inner: Some(MotData { value, properties }) }
+    }
+
+    /// A property or array element of `parent`. References resolve against
+    /// `ancestors` (the chain above `parent`); data children get `parent` added.
+    fn child(&mut self, child: &'a MOTLYNode, ancestors: &[&'a MOTLYDataNode], parent: &'a MOTLYDataNode) -> Mot {
+        match child {
+            MOTLYNode::Ref { .. } => {
+                let mut visiting = Vec::new();
+                match navigate(self.root, child, ancestors, &mut visiting) {
+                    Some((target, target_ancestors)) => self.node(target, &target_ancestors),
+                    None => Mot::default(),
+                }
+            }
+            MOTLYNode::Data(node) => {
+                let mut inner = ancestors.to_vec();
+                inner.push(parent);
+                self.node(node, &inner)
+            }
+        }
+    }
+}
+
+pub(crate) type Target<'a> = (&'a MOTLYDataNode, Vec<&'a MOTLYDataNode>);
+
+/// Follow a reference to the data node it designates, with that node's
+/// ancestor chain. References met on the way are followed too; None if the
+/// path does not exist or the references loop.
+pub(crate) fn navigate<'a>(
+    root: &'a MOTLYDataNode,
+    reference: &'a MOTLYNode,
+    ancestors: &[&'a MOTLYDataNode],
+    visiting: &mut Vec<*const MOTLYNode>,
+) -> Option<Target<'a>> {
+    let MOTLYNode::Ref { link_to, link_ups, .. } = reference else {
+        return None;
+    };
+    let key = reference as *const MOTLYNode;
+    if visiting.contains(&key) {
+        return None;
+    }
+    visiting.push(key);
+
+    let (mut current, mut chain) = if *link_ups == 0 {
+        (root, Vec::new())
+    } else {
+        let idx = ancestors.len().checked_sub(*link_ups)?;
+        (ancestors[idx], ancestors[..idx].to_vec())
+    };
+    let mut parent = current;
+
+    for (i, seg) in link_to.iter().enumerate() {
+        let next = match seg {
+            RefSegment::Name(name) => current.properties.as_ref()?.get(name)?,
+            RefSegment::Index(index) => match &current.eq {
+                Some(EqValue::Array(items)) => items.get(*index)?,
+                _ => return None,
+            },
+        };
+        if i > 0 {
+            chain.push(parent);
+        }
+        parent = current;
+        current = match next {
+            MOTLYNode::Data(node) => node,
+            MOTLYNode::Ref { .. } => {
+                let (target, target_chain) = navigate(root, next, &chain, visiting)?;
+                chain = target_chain;
+                parent = target;
+                target
+            }
+        };
+    }
+
+    visiting.pop();
+    Some((current, chain))
+}
@@ -2,15 +2,54 @@ use crate::ast::*;
 #[allow(unused_imports)]
 use crate::error::{MOTLYError, Position};
 
+/// Limits for parsing untrusted input. `None` means unlimited.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct ParseLimits {
+    /// Maximum nesting depth. Every dotted path segment and array level
+    /// counts, so `a.b { c = [1] }` has depth 4 (`a`, `b`, `c`, `[`). A
+    /// properties block adds nothing of its own: its statements are
+    /// already nested under the key it follows.
+    pub max_depth: Option<usize>,
+    /// Maximum input length in bytes.
+    pub max_input_bytes: Option<usize>,
+    /// Maximum number of statements, counting those nested in blocks.
+    pub max_statements: Option<usize>,
+}
+
 /// Parser state: tracks position in the input string.
 struct Parser<'a> {
     input: &'a str,
     pos: usize,
+    limits: ParseLimits,
+    depth: usize,
+    statements: usize,
+}
+
+impl<'a> Parser<'a> {
+    fn new(input: &'a str, pos: usize, limits: ParseLimits) -> Self {
+        Parser { input, pos, limits, depth: 0, statements: 0 }
+    }
 }
 
 /// Parse a MOTLY input string into a list of statements.
 pub fn parse(input: &str) -> Result<Vec<Statement>, MOTLYError> {
-    let mut parser = Parser { input, pos: 0 };
+    parse_with_limits(input, &ParseLimits::default())
+}
+
+/// Parse a MOTLY input string, failing with `input-limit-exceeded`,
+/// `depth-limit-exceeded` or `statement-limit-exceeded` when the input
+/// goes over `limits`.
+pub fn parse_with_limits(input: &str, limits: &ParseLimits) -> Result<Vec<Statement>, MOTLYError> {
+    if let Some(max) = limits.max_input_bytes {
+        if input.len() > max {
+            return Err(MOTLYError::general(
+                "input-limit-exceeded",
+                format!("Input is {} bytes; the limit is {}", input.len(), max),
+            ));
+        }
+    }
+
+    let mut parser = Parser::new(input, 0, *limits);
     let mut statements = Vec::new();
 
     parser.skip_ws_and_commas();
@@ -23,6 +62,177 @@ pub fn parse(input: &str) -> Result<Vec<Statement>, MOTLYError> {
     Ok(statements)
 }
 
+/// Parse MOTLY source, recovering from syntax errors. Returns the
+/// statements that could be parsed along with every syntax error found;
+/// after an error, parsing resumes at the next statement boundary or
+/// closing brace. For valid input this is `parse` with no errors.
+/// Exceeding a limit is not recoverable: no statements are returned, and
+/// the limit's error follows the first syntax error.
+pub fn parse_recovering(input: &str, limits: &ParseLimits) -> (Vec<Statement>, Vec<MOTLYError>) {
+    let err = match parse_with_limits(input, limits) {
+        Ok(statements) => return (statements, Vec::new()),
+        Err(err) if err.code != "tag-parse-syntax-error" => return (Vec::new(), vec![err]),
+        Err(err) => err,
+    };
+    // The CST parser recurses like this one; don't let it past the depth
+    // limit. Each `{` or `[` is at least one level, so deeper brackets are
+    // over the limit however the statements would have parsed.
+    if let Some(max) = limits.max_depth {
+        if let Some(at) = bracket_over(input, max) {
+            let limit = MOTLYError::new(
+                "depth-limit-exceeded",
+                format!("Nesting is deeper than {} levels", max),
+                at.begin,
+                at.end,
+            );
+            return (Vec::new(), vec![err, limit]);
+        }
+    }
+    let tree = crate::cst::parse(input);
+    let statements = tree.recovered_statements();
+    // Dotted paths nest without brackets; check them as the parser would have
+    if let Some(max) = limits.max_depth {
+        if let Some(at) = statement_over(&statements, 0, max) {
+            let limit = MOTLYError::new(
+                "depth-limit-exceeded",
+                format!("Nesting is deeper than {} levels", max),
+                at.begin,
+                at.end,
+            );
+            return (Vec::new(), vec![err, limit]);
+        }
+    }
+    if let Some(max) = limits.max_statements.filter(|max| count_statements(&statements) > *max) {
+        let limit = MOTLYError::general("statement-limit-exceeded", format!("More than {} statements", max));
+        return (Vec::new(), vec![err, limit]);
+    }
+    (statements, tree.errors)
+}
+
+/// The first `{` or `[` nested more than `max` deep in the token stream.
+fn bracket_over(input: &str, max: usize) -> Option<Span> {
+    use crate::lexer::TokenKind;
+    let mut depth = 0usize;
+    for token in crate::lexer::tokenize(input) {
+        match token.kind {
+            TokenKind::LBrace | TokenKind::LBracket => {
+                depth += 1;
+                if depth > max {
+                    return Some(token.span);
+                }
+            }
+            TokenKind::RBrace | TokenKind::RBracket => depth = depth.saturating_sub(1),
+            _ => {}
+        }
+    }
+    None
+}
+
+/// The span of the first statement or array nested more than `max` deep,
+/// counting as `ParseLimits::max_depth` does from `depth`. Called once the
+/// brackets are known to be within the limit, so the recursion is too.
+fn statement_over(statements: &[Statement], depth: usize, max: usize) -> Option<Span> {
+    fn in_value(value: &TagValue, depth: usize, max: usize, at: Span) -> Option<Span> {
+        let TagValue::Array(elements) = value else {
+            return None;
+        };
+        if depth + 1 > max {
+            return Some(at);
+        }
+        elements.iter().find_map(|el| {
+            el.value
+                .as_ref()
+                .and_then(|value| in_value(value, depth + 1, max, el.span))
+                .or_else(|| el.properties.as_deref().and_then(|props| statement_over(props, depth + 1, max)))
+        })
+    }
+    statements.iter().find_map(|stmt| {
+        let (path, span) = match stmt {
+            Statement::SetEq { path, span, .. }
+            | Statement::AssignBoth { path, span, .. }
+            | Statement::ReplaceProperties { path, span, .. }
+            | Statement::UpdateProperties { path, span, .. }
+            | Statement::Define { path, span, .. } => (path, *span),
+            Statement::ClearAll { .. } => return None,
+        };
+        let depth = depth + path.len();
+        if depth > max {
+            return Some(span);
+        }
+        match stmt {
+            Statement::SetEq { value, properties, .. } | Statement::AssignBoth { value, properties, .. } => {
+                in_value(value, depth, max, span)
+                    .or_else(|| properties.as_deref().and_then(|props| statement_over(props, depth, max)))
+            }
+            Statement::ReplaceProperties { properties, .. } | Statement::UpdateProperties { properties, .. } => {
+                statement_over(properties, depth, max)
+            }
+            _ => None,
+        }
+    })
+}
+
+fn count_statements(statements: &[Statement]) -> usize {
+    fn in_value(value: &TagValue) -> usize {
+        match value {
+            TagValue::Array(elements) => elements
+                .iter()
+                .map(|el| {
+                    el.value.as_ref().map_or(0, in_value)
+                        + el.properties.as_deref().map_or(0, count_statements)
+                })
+                .sum(),
+            TagValue::Scalar(_) => 0,
+        }
+    }
+    statements
+        .iter()
+        .map(|stmt| {
+            1 + match stmt {
+                Statement::SetEq { value, properties, .. } | Statement::AssignBoth { value, properties, .. } => {
+                    in_value(value) + properties.as_deref().map_or(0, count_statements)
+                }
+                Statement::ReplaceProperties { properties, .. } | Statement::UpdateProperties { properties, .. } => {
+                    count_statements(properties)
+                }
+                _ => 0,
+            }
+        })
+        .sum()
+}
+
+/// Parse a single (non-array) value starting at byte `offset` of `input`.
+/// Returns the value and the offset just past it. Used by the CST to decode
+/// value tokens with exactly the parser's semantics and error messages.
+pub(crate) fn parse_value_at(input: &str, offset: usize) -> Result<(TagValue, usize), MOTLYError> {
+    let mut parser = Parser::new(input, offset, ParseLimits::default());
+    let value = parser.parse_eq_value(false)?;
+    Ok((value, parser.pos))
+}
+
+/// Parse a single bare or backtick identifier starting at byte `offset`.
+pub(crate) fn parse_identifier_at(input: &str, offset: usize) -> Result<(String, usize), MOTLYError> {
+    let mut parser = Parser::new(input, offset, ParseLimits::default());
+    let name = parser.parse_identifier()?;
+    Ok((name, parser.pos))
+}
+
+/// `text` as a date, if it is a date literal without its `@` and names a
+/// real month and day. Converters use it to spot dates in strings.
+pub(crate) fn parse_date(text: &str) -> Option<ScalarValue> {
+    if !text.starts_with(|c: char| c.is_ascii_digit()) {
+        return None;
+    }
+    let literal = format!("@{}", text);
+    let date = match parse_value_at(&literal, 0) {
+        Ok((TagValue::Scalar(date @ ScalarValue::Date(_)), end)) if end == literal.len() => date,
+        _ => return None,
+    };
+    let field = |range: std::ops::Range<usize>| text.get(range).and_then(|f| f.parse::<u32>().ok());
+    let month_day = (field(5..7)?, field(8..10)?);
+    matches!(month_day, (1..=12, 1..=31)).then_some(date)
+}
+
 impl<'a> Parser<'a> {
     // ── Helpers ──────────────────────────────────────────────────────
 
@@ -61,14 +271,21 @@ impl<'a> Parser<'a> {
 
     /// Current position in the source.
     fn position(&self) -> Position {
-        let consumed = &self.input[..self.pos];
-        let line = consumed.matches('\n').count();
-        let last_newline = consumed.rfind('\n').map(|i| i + 1).unwrap_or(0);
-        let column = self.pos - last_newline;
-        Position {
-            line,
-            column,
-            offset: self.pos,
+        Position::from_offset(self.input, self.pos)
+    }
+
+    fn limit_error(&self, code: &str, message: String, begin: Position) -> MOTLYError {
+        MOTLYError::new(code, message, begin, self.position())
+    }
+
+    fn check_depth(&self, depth: usize, begin: Position) -> Result<(), MOTLYError> {
+        match self.limits.max_depth {
+            Some(max) if depth > max => Err(self.limit_error(
+                "depth-limit-exceeded",
+                format!("Nesting is deeper than {} levels", max),
+                begin,
+            )),
+            _ => Ok(()),
         }
     }
 
@@ -124,6 +341,16 @@ impl<'a> Parser<'a> {
 
     fn parse_statement(&mut self) -> Result<Statement, MOTLYError> {
         let begin = self.position();
+        self.statements += 1;
+        if let Some(max) = self.limits.max_statements {
+            if self.statements > max {
+                return Err(self.limit_error(
+                    "statement-limit-exceeded",
+                    format!("More than {} statements", max),
+                    begin,
+                ));
+            }
+        }
 
         // -... (clearAll)
         if self.starts_with("-...") {
@@ -137,6 +364,7 @@ impl<'a> Parser<'a> {
         if self.peek_char() == Some('-') {
             self.advance(1);
             let path = self.parse_prop_name()?;
+            self.check_depth(self.depth + path.len(), begin)?;
             return Ok(Statement::Define {
                 path,
                 deleted: true,
@@ -144,8 +372,19 @@ impl<'a> Parser<'a> {
             });
         }
 
-        // Parse the property path
+        // Parse the property path; everything after it is nested under it
         let path = self.parse_prop_name()?;
+        let levels = path.len();
+        self.check_depth(self.depth + levels, begin)?;
+        self.depth += levels;
+        let result = self.parse_statement_body(path, begin);
+        self.depth -= levels;
+        result
+    }
+
+    /// The rest of a statement after its property path.
+    fn parse_statement_body(&mut self, path: Vec<String>, begin: Position) -> Result<Statement, MOTLYError> {
+        let path_end = self.position();
         self.skip_ws();
 
         // Check := FIRST (before : alone)
@@ -155,6 +394,7 @@ impl<'a> Parser<'a> {
 
             // := requires a value
             let value = self.parse_eq_value(true)?;
+            let value_end = self.position();
             self.skip_ws();
 
             // Optional { props } block
@@ -166,9 +406,9 @@ impl<'a> Parser<'a> {
 
             return Ok(Statement::AssignBoth {
                 path,
+                span: self.statement_span(begin, value_end, &properties),
                 value,
                 properties,
-                span: Span { begin, end: self.position() },
             });
         }
 
@@ -189,6 +429,7 @@ impl<'a> Parser<'a> {
 
                 // `= value` (setEq)
                 let value = self.parse_eq_value(true)?;
+                let value_end = self.position();
                 self.skip_ws();
 
                 // Optionally followed by `{ statements }` (MERGE semantics)
@@ -200,9 +441,9 @@ impl<'a> Parser<'a> {
 
                 Ok(Statement::SetEq {
                     path,
+                    span: self.statement_span(begin, value_end, &properties),
                     value,
                     properties,
-                    span: Span { begin, end: self.position() },
                 })
             }
             Some(':') => {
@@ -226,11 +467,19 @@ impl<'a> Parser<'a> {
             _ => Ok(Statement::Define {
                 path,
                 deleted: false,
-                span: Span { begin, end: self.position() },
+                span: Span { begin, end: path_end },
             }),
         }
     }
 
+    /// The span of a statement whose value ended at `value_end`: the
+    /// whitespace after the value belongs to it only if a properties
+    /// block follows.
+    fn statement_span(&self, begin: Position, value_end: Position, properties: &Option<Vec<Statement>>) -> Span {
+        let end = if properties.is_some() { self.position() } else { value_end };
+        Span { begin, end }
+    }
+
     // ── Property Name (dotted path) ─────────────────────────────────
 
     fn parse_prop_name(&mut self) -> Result<Vec<String>, MOTLYError> {
@@ -931,6 +1180,14 @@ impl<'a> Parser<'a> {
 
     fn parse_array(&mut self) -> Result<Vec<ArrayElement>, MOTLYError> {
         let begin = self.position();
+        self.check_depth(self.depth + 1, begin)?;
+        self.depth += 1;
+        let result = self.parse_array_elements(begin);
+        self.depth -= 1;
+        result
+    }
+
+    fn parse_array_elements(&mut self, begin: Position) -> Result<Vec<ArrayElement>, MOTLYError> {
         self.expect_char('[')?;
         self.skip_ws();
 
@@ -979,6 +1236,7 @@ impl<'a> Parser<'a> {
             }
             Some('[') => {
                 let elements = self.parse_array()?;
+                let value_end = self.position();
                 self.skip_ws();
                 if self.peek_char() == Some('{') {
                     let props = self.parse_properties_block()?;
@@ -991,12 +1249,13 @@ impl<'a> Parser<'a> {
                     Ok(ArrayElement {
                         value: Some(TagValue::Array(elements)),
                         properties: None,
-                        span: Span { begin, end: self.position() },
+                        span: Span { begin, end: value_end },
                     })
                 }
             }
             _ => {
                 let value = self.parse_eq_value(false)?;
+                let value_end = self.position();
                 self.skip_ws();
                 if self.peek_char() == Some('{') {
                     let props = self.parse_properties_block()?;
@@ -1009,7 +1268,7 @@ impl<'a> Parser<'a> {
                     Ok(ArrayElement {
                         value: Some(value),
                         properties: None,
-                        span: Span { begin, end: self.position() },
+                        span: Span { begin, end: value_end },
                     })
                 }
             }
@@ -1109,7 +1368,7 @@ impl<'a> Parser<'a> {
 }
 
 /// Check if a character is valid in a bare string / identifier.
-fn is_bare_char(ch: char) -> bool {
+pub(crate) fn is_bare_char(ch: char) -> bool {
     ch.is_ascii_alphanumeric()
         || ch == '_'
         || ('\u{00C0}'..='\u{024F}').contains(&ch)
@@ -0,0 +1,145 @@
+/// Patches: the MOTLY statements that turn one tree into another.
+///
+/// `patch(a, b)` is the smallest set of statements this module can find
+/// which, parsed on top of `a`, leaves exactly `b` (locations aside). Nodes
+/// that did not change get no statement. Changed values are set with `=`,
+/// which keeps the node's properties; properties are only rebuilt with `:`
+/// on a node that lost a key, since no statement removes one. A node whose
+/// kind changed (data, link, deleted) is replaced with `:=` or `-name`.
+///
+/// The patch is computed from the trees, not the source. Applied as a later
+/// parse of the session that built `a`, it gives `b` as long as `a` has no
+/// clone of a path the patch changes: such a clone was already copied.
+use std::collections::BTreeMap;
+
+use crate::ast::{ScalarValue, Statement, TagValue};
+use crate::diff::{same, same_eq};
+use crate::tree::{EqValue, MOTLYDataNode, MOTLYNode};
+use crate::writer::{node_statements, tag_value, tree_statements, write_statements, NO_SPAN};
+
+/// The statements that turn `a` into `b`.
+pub fn patch(a: &MOTLYDataNode, b: &MOTLYDataNode) -> Vec<Statement> {
+    let empty = BTreeMap::new();
+    let before = a.properties.as_ref().unwrap_or(&empty);
+    let after = b.properties.as_ref().unwrap_or(&empty);
+    if before.keys().any(|key| !after.contains_key(key)) {
+        // Only clearing everything removes a top-level key
+        let mut stmts = tree_statements(b);
+        stmts.insert(0, Statement::ClearAll { span: NO_SPAN });
+        return stmts;
+    }
+    children_patch(before, after)
+}
+
+/// `patch` as MOTLY source; empty when the trees are the same.
+pub fn patch_source(a: &MOTLYDataNode, b: &MOTLYDataNode) -> String {
+    let stmts = patch(a, b);
+    if stmts.is_empty() {
+        String::new()
+    } else {
+        write_statements(&stmts)
+    }
+}
+
+type Properties = BTreeMap<String, MOTLYNode>;
+
+/// Statements, relative to their node, that turn properties `before` into
+/// `after`, which has every key `before` has.
+fn children_patch(before: &Properties, after: &Properties) -> Vec<Statement> {
+    after.iter().flat_map(|(key, target)| key_patch(vec![key.clone()], before.get(key), target)).collect()
+}
+
+/// Statements that turn `current` (absent if None) at relative path `path`
+/// into `target`.
+fn key_patch(path: Vec<String>, current: Option<&MOTLYNode>, target: &MOTLYNode) -> Vec<Statement> {
+    match (current, target) {
+        (None, target) => node_statements(path, target, false)
+            .into_iter()
+            .map(|stmt| match stmt {
+                Statement::UpdateProperties { path, properties, .. } => collapse(path, properties),
+                stmt => stmt,
+            })
+            .collect(),
+        (Some(MOTLYNode::Data(a)), MOTLYNode::Data(b)) if a.deleted && b.deleted => Vec::new(),
+        (Some(current), MOTLYNode::Ref { .. }) if same(current, target) => Vec::new(),
+        (Some(MOTLYNode::Data(a)), MOTLYNode::Data(b)) if !a.deleted && !b.deleted => in_place(path, a, b),
+        (Some(_), target) => node_statements(path, target, true),
+    }
+}
+
+/// Statements that turn data node `a` into data node `b` without
+/// replacing it.
+fn in_place(path: Vec<String>, a: &MOTLYDataNode, b: &MOTLYDataNode) -> Vec<Statement> {
+    let value = (!same_eq(&a.eq, &b.eq)).then(|| tag_value_or_none(&b.eq));
+    let empty = Properties::new();
+    let mut stmts = Vec::new();
+
+    match (&a.properties, &b.properties) {
+        (_, Some(after)) if after.is_empty() => {
+            if a.properties.as_ref().is_some_and(|p| p.is_empty()) {
+                stmts.extend(value.map(|value| set_eq(path, value, None)));
+            } else {
+                // `-...` empties the value as well
+                let clear = vec![Statement::ClearAll { span: NO_SPAN }];
+                stmts.push(Statement::UpdateProperties { path: path.clone(), properties: clear, span: NO_SPAN });
+                if b.eq.is_some() {
+                    stmts.push(set_eq(path, tag_value_or_none(&b.eq), None));
+                }
+            }
+        }
+        (Some(before), Some(after)) if before.keys().any(|key| !after.contains_key(key)) => {
+            stmts.extend(value.map(|value| set_eq(path.clone(), value, None)));
+            let properties = tree_statements(b);
+            stmts.push(Statement::ReplaceProperties { path, properties, span: NO_SPAN });
+        }
+        (before, Some(after)) => {
+            let children = children_patch(before.as_ref().unwrap_or(&empty), after);
+            match (value, children.is_empty()) {
+                (Some(value), true) => stmts.push(set_eq(path, value, None)),
+                (Some(value), false) => stmts.push(set_eq(path, value, Some(children))),
+                (None, false) => stmts.push(collapse(path, children)),
+                (None, true) => {}
+            }
+        }
+        (before, None) => {
+            stmts.extend(value.map(|value| set_eq(path.clone(), value, None)));
+            if before.is_some() {
+                stmts.push(Statement::ReplaceProperties { path, properties: Vec::new(), span: NO_SPAN });
+            }
+        }
+    }
+    stmts
+}
+
+fn tag_value_or_none(eq: &Option<EqValue>) -> TagValue {
+    match eq {
+        Some(eq) => tag_value(eq),
+        None => TagValue::Scalar(ScalarValue::None),
+    }
+}
+
+fn set_eq(path: Vec<String>, value: TagValue, properties: Option<Vec<Statement>>) -> Statement {
+    Statement::SetEq { path, value, properties, span: NO_SPAN }
+}
+
+/// `a { b = 1 }` as `a.b = 1`: a block with a single statement that names
+/// a path is written as that statement with a longer path.
+fn collapse(path: Vec<String>, mut children: Vec<Statement>) -> Statement {
+    if let [Statement::UpdateProperties { path: inner, properties, .. }] = children.as_mut_slice() {
+        children = vec![collapse(std::mem::take(inner), std::mem::take(properties))];
+    }
+    if let [child] = children.as_mut_slice() {
+        match child {
+            Statement::SetEq { path: rest, .. }
+            | Statement::AssignBoth { path: rest, .. }
+            | Statement::ReplaceProperties { path: rest, .. }
+            | Statement::UpdateProperties { path: rest, .. }
+            | Statement::Define { path: rest, .. } => {
+                rest.splice(0..0, path);
+                return children.pop().unwrap();
+            }
+            Statement::ClearAll { .. } => {}
+        }
+    }
+    Statement::UpdateProperties { path, properties: children, span: NO_SPAN }
+}
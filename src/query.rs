@@ -0,0 +1,550 @@
+/// Path queries over interpreted trees (`motly get`).
+///
+/// ```text
+/// server.port            a property
+/// servers[0].host        an array element
+/// servers.*.port         every property of servers
+/// items[*].name          every element of items
+/// ..host                 host at any depth
+/// servers[?region == "us"].host
+///                        the properties (or elements) of servers whose
+///                        region is "us"
+/// ```
+///
+/// Names are written as in MOTLY: bare, or in backticks. A leading `$` is
+/// allowed, so any absolute reference is also a query. Filters compare a
+/// relative path against a MOTLY value with `==`, `!=`, `<`, `<=`, `>` or
+/// `>=`; `[?tls]` keeps the nodes where the path exists.
+///
+/// References are followed, as `Mot` follows them: a query that steps
+/// through a link continues at its target, and matches are returned with
+/// their links resolved. Recursive descent (`..`) does not enter links,
+/// which would otherwise find the same node twice.
+use std::cmp::Ordering;
+use std::collections::HashMap;
+
+use crate::ast::{ScalarValue, TagValue};
+use crate::error::{MOTLYError, Position};
+use crate::mot::navigate;
+use crate::parser;
+use crate::tree::{EqValue, MOTLYDataNode, MOTLYNode, Scalar};
+
+/// A parsed query.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Query {
+    steps: Vec<Step>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Step {
+    Name(String),
+    Index(usize),
+    AllProperties,
+    AllElements,
+    /// `..name`, or `..*` for every descendant.
+    Descendants(Option<String>),
+    Filter(Filter),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+struct Filter {
+    path: Vec<String>,
+    test: Option<(Comparison, ScalarValue)>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Comparison {
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+}
+
+/// A node a query selected: the path the query took to it (array
+/// elements as `[i]`) and the node, with its links resolved.
+#[derive(Debug, Clone, PartialEq)]
+pub struct QueryMatch {
+    pub path: Vec<String>,
+    pub node: MOTLYDataNode,
+}
+
+/// Parse `query` and select from `root`.
+pub fn query(root: &MOTLYDataNode, query: &str) -> Result<Vec<QueryMatch>, MOTLYError> {
+    Ok(Query::parse(query)?.select(root))
+}
+
+impl Query {
+    /// Parse a query. Errors are `invalid-query`, positioned in `text`.
+    pub fn parse(text: &str) -> Result<Query, MOTLYError> {
+        QueryParser { text, pos: 0 }.parse()
+    }
+
+    /// The nodes this query selects from `root`, in tree order.
+    pub fn select(&self, root: &MOTLYDataNode) -> Vec<QueryMatch> {
+        let mut cursors = vec![Cursor { node: root, ancestors: Vec::new(), path: Vec::new() }];
+        for step in &self.steps {
+            cursors = cursors.iter().flat_map(|cursor| cursor.step(root, step)).collect();
+        }
+        // One graph for all the matches, which often share their targets
+        let mut graph = LinkGraph { states: Vec::new(), ids: HashMap::new() };
+        cursors
+            .into_iter()
+            .map(|mut cursor| {
+                let path = std::mem::take(&mut cursor.path);
+                let id = graph.add(root, cursor);
+                QueryMatch { node: graph.resolve(id), path }
+            })
+            .collect()
+    }
+}
+
+// ── Evaluation ──────────────────────────────────────────────────────
+
+/// A selected node, with the ancestor chain its references resolve against
+/// (as in `mot::build_mot`).
+#[derive(Clone)]
+struct Cursor<'a> {
+    node: &'a MOTLYDataNode,
+    ancestors: Vec<&'a MOTLYDataNode>,
+    path: Vec<String>,
+}
+
+impl<'a> Cursor<'a> {
+    fn step(&self, root: &'a MOTLYDataNode, step: &Step) -> Vec<Cursor<'a>> {
+        match step {
+            Step::Name(name) => self.property(root, name).into_iter().collect(),
+            Step::Index(i) => self.element(root, *i).into_iter().collect(),
+            Step::AllProperties => self.properties(root),
+            Step::AllElements => self.elements(root),
+            Step::Descendants(name) => {
+                let mut out = Vec::new();
+                self.descendants(root, name.as_deref(), &mut out);
+                out
+            }
+            Step::Filter(filter) => {
+                let children = if matches!(self.node.eq, Some(EqValue::Array(_))) {
+                    self.elements(root)
+                } else {
+                    self.properties(root)
+                };
+                children.into_iter().filter(|child| filter.matches(root, child)).collect()
+            }
+        }
+    }
+
+    fn property(&self, root: &'a MOTLYDataNode, name: &str) -> Option<Cursor<'a>> {
+        let child = self.node.properties.as_ref()?.get(name)?;
+        let (node, ancestors) = match child {
+            MOTLYNode::Ref { .. } => navigate(root, child, &self.ancestors, &mut Vec::new())?,
+            MOTLYNode::Data(data) => (data, self.with_node()),
+        };
+        (!node.deleted).then(|| self.to(node, ancestors, name.to_string()))
+    }
+
+    fn element(&self, root: &'a MOTLYDataNode, index: usize) -> Option<Cursor<'a>> {
+        let Some(EqValue::Array(items)) = &self.node.eq else {
+            return None;
+        };
+        // Array elements are children of the node, as in build_mot
+        let inner = self.with_node();
+        let item = items.get(index)?;
+        let (node, ancestors) = match item {
+            MOTLYNode::Ref { .. } => navigate(root, item, &inner, &mut Vec::new())?,
+            MOTLYNode::Data(data) => {
+                let mut ancestors = inner.clone();
+                ancestors.push(self.node);
+                (data, ancestors)
+            }
+        };
+        (!node.deleted).then(|| self.to(node, ancestors, format!("[{}]", index)))
+    }
+
+    fn properties(&self, root: &'a MOTLYDataNode) -> Vec<Cursor<'a>> {
+        let keys = self.node.properties.iter().flat_map(|props| props.keys());
+        keys.filter_map(|key| self.property(root, key)).collect()
+    }
+
+    fn elements(&self, root: &'a MOTLYDataNode) -> Vec<Cursor<'a>> {
+        let len = match &self.node.eq {
+            Some(EqValue::Array(items)) => items.len(),
+            _ => 0,
+        };
+        (0..len).filter_map(|i| self.element(root, i)).collect()
+    }
+
+    /// Every data node below this one, depth first, that `name` selects.
+    fn descendants(&self, root: &'a MOTLYDataNode, name: Option<&str>, out: &mut Vec<Cursor<'a>>) {
+        let mut children = self.elements(root);
+        children.extend(self.properties(root));
+        for child in children {
+            if self.is_link(&child) {
+                continue;
+            }
+            if name.is_none_or(|name| child.path.last().is_some_and(|last| last == name)) {
+                out.push(child.clone());
+            }
+            child.descendants(root, name, out);
+        }
+    }
+
+    /// Whether `child` was reached through a reference.
+    fn is_link(&self, child: &Cursor) -> bool {
+        let last = child.path.last().map(String::as_str).unwrap_or("");
+        let direct = match last.strip_prefix('[').and_then(|i| i.strip_suffix(']')) {
+            Some(index) => match &self.node.eq {
+                Some(EqValue::Array(items)) => index.parse().ok().and_then(|i: usize| items.get(i)),
+                _ => None,
+            },
+            None => self.node.properties.as_ref().and_then(|props| props.get(last)),
+        };
+        matches!(direct, Some(MOTLYNode::Ref { .. }))
+    }
+
+    fn with_node(&self) -> Vec<&'a MOTLYDataNode> {
+        let mut ancestors = self.ancestors.clone();
+        ancestors.push(self.node);
+        ancestors
+    }
+
+    fn to(&self, node: &'a MOTLYDataNode, ancestors: Vec<&'a MOTLYDataNode>, segment: String) -> Cursor<'a> {
+        let mut path = self.path.clone();
+        path.push(segment);
+        Cursor { node, ancestors, path }
+    }
+}
+
+impl Filter {
+    fn matches<'a>(&self, root: &'a MOTLYDataNode, candidate: &Cursor<'a>) -> bool {
+        let mut cursor = candidate.clone();
+        for name in &self.path {
+            match cursor.property(root, name) {
+                Some(next) => cursor = next,
+                None => return false,
+            }
+        }
+        let Some((comparison, expected)) = &self.test else {
+            return true;
+        };
+        let ordering = match (&cursor.node.eq, expected) {
+            (Some(EqValue::Scalar(Scalar::String(x))), ScalarValue::String(y)) => x.cmp(y),
+            (Some(EqValue::Scalar(Scalar::Date(x))), ScalarValue::Date(y)) => x.cmp(y),
+            (Some(EqValue::Scalar(Scalar::Number(x))), ScalarValue::Number(y)) => match x.partial_cmp(y) {
+                Some(ordering) => ordering,
+                None => return *comparison == Comparison::Ne,
+            },
+            (Some(EqValue::Scalar(Scalar::Boolean(x))), ScalarValue::Boolean(y)) => x.cmp(y),
+            (None, ScalarValue::None) => Ordering::Equal,
+            // Values of different types are only ever unequal
+            _ => return *comparison == Comparison::Ne,
+        };
+        match comparison {
+            Comparison::Eq => ordering == Ordering::Equal,
+            Comparison::Ne => ordering != Ordering::Equal,
+            Comparison::Lt => ordering == Ordering::Less,
+            Comparison::Le => ordering != Ordering::Greater,
+            Comparison::Gt => ordering == Ordering::Greater,
+            Comparison::Ge => ordering != Ordering::Less,
+        }
+    }
+}
+
+/// A copy of `root` with every reference replaced by its target.
+pub(crate) fn resolve_links(root: &MOTLYDataNode) -> MOTLYDataNode {
+    let graph = LinkGraph::new(root);
+    graph.resolve(0)
+}
+
+/// The tree as reading it with links followed sees it. A node is stored
+/// once for each ancestor chain it is reached with (the chain decides where
+/// its references point), so a node that many links reach is one state and
+/// walking the graph is linear in the tree, where walking every path
+/// through the links can be exponential.
+pub(crate) struct LinkGraph<'a> {
+    /// State 0 is the root.
+    states: Vec<LinkState<'a>>,
+    ids: HashMap<StateKey, usize>,
+}
+
+type StateKey = (*const MOTLYDataNode, Vec<*const MOTLYDataNode>);
+
+pub(crate) struct LinkState<'a> {
+    pub node: &'a MOTLYDataNode,
+    /// A state for each array element, None where it does not resolve.
+    pub elements: Vec<Option<usize>>,
+    /// The properties that resolve, in key order.
+    pub properties: Vec<(String, usize)>,
+}
+
+impl<'a> LinkGraph<'a> {
+    pub fn new(root: &'a MOTLYDataNode) -> LinkGraph<'a> {
+        let mut graph = LinkGraph { states: Vec::new(), ids: HashMap::new() };
+        graph.add(root, Cursor { node: root, ancestors: Vec::new(), path: Vec::new() });
+        graph
+    }
+
+    fn key(cursor: &Cursor<'a>) -> StateKey {
+        (cursor.node, cursor.ancestors.iter().map(|node| *node as *const MOTLYDataNode).collect())
+    }
+
+    fn add(&mut self, root: &'a MOTLYDataNode, cursor: Cursor<'a>) -> usize {
+        let key = Self::key(&cursor);
+        if let Some(&id) = self.ids.get(&key) {
+            return id;
+        }
+        let id = self.states.len();
+        self.states.push(LinkState { node: cursor.node, elements: Vec::new(), properties: Vec::new() });
+        self.ids.insert(key, id);
+        let count = match &cursor.node.eq {
+            Some(EqValue::Array(items)) => items.len(),
+            _ => 0,
+        };
+        // Children only need the node and its chain, not the path to it
+        let at = Cursor { node: cursor.node, ancestors: cursor.ancestors, path: Vec::new() };
+        let elements = (0..count).map(|i| at.element(root, i).map(|child| self.add(root, child))).collect();
+        let properties = at
+            .properties(root)
+            .into_iter()
+            .map(|child| {
+                let key = child.path[0].clone();
+                (key, self.add(root, child))
+            })
+            .collect();
+        self.states[id].elements = elements;
+        self.states[id].properties = properties;
+        id
+    }
+
+    pub fn state(&self, id: usize) -> &LinkState<'a> {
+        &self.states[id]
+    }
+
+    pub fn len(&self) -> usize {
+        self.states.len()
+    }
+
+    /// The state a step from `id` leads to: `[i]` for an element, otherwise
+    /// a property.
+    pub fn child(&self, id: usize, segment: &str) -> Option<usize> {
+        let state = &self.states[id];
+        let index = segment.strip_prefix('[').and_then(|s| s.strip_suffix(']')).and_then(|i| i.parse::<usize>().ok());
+        match index {
+            Some(index) if !state.elements.is_empty() => state.elements.get(index).copied().flatten(),
+            _ => state.properties.iter().find(|(key, _)| key == segment).map(|(_, child)| *child),
+        }
+    }
+
+    /// The children of `id` with their path segments, elements first.
+    pub fn children(&self, id: usize) -> impl Iterator<Item = (String, usize)> + '_ {
+        let state = &self.states[id];
+        let elements = state.elements.iter().enumerate().filter_map(|(i, child)| Some((format!("[{}]", i), (*child)?)));
+        elements.chain(state.properties.iter().cloned())
+    }
+
+    /// The state at `path` from the root. As in `resolve_links`, a path
+    /// that comes back to a node it already went through leads nowhere.
+    pub fn lookup(&self, path: &[String]) -> Option<usize> {
+        let mut id = 0;
+        let mut on_path = vec![self.states[0].node as *const MOTLYDataNode];
+        for segment in path {
+            id = self.child(id, segment)?;
+            let node = self.states[id].node as *const MOTLYDataNode;
+            if on_path.contains(&node) {
+                return None;
+            }
+            on_path.push(node);
+        }
+        Some(id)
+    }
+
+    /// A copy of the node of state `id` with its references replaced by
+    /// their targets. A reference back into a node being copied, or one
+    /// that does not resolve, is left out.
+    pub fn resolve(&self, id: usize) -> MOTLYDataNode {
+        self.copy(id, &mut Vec::new(), &mut HashMap::new()).0
+    }
+
+    /// The copy, and whether a reference was left out for looping back
+    /// (which makes the copy depend on `on_path`, so it is not reused).
+    fn copy(
+        &self,
+        id: usize,
+        on_path: &mut Vec<*const MOTLYDataNode>,
+        done: &mut HashMap<usize, MOTLYDataNode>,
+    ) -> (MOTLYDataNode, bool) {
+        if let Some(copy) = done.get(&id) {
+            return (copy.clone(), false);
+        }
+        let state = &self.states[id];
+        on_path.push(state.node);
+        let mut looped = false;
+        let mut child = |child: usize, on_path: &mut Vec<*const MOTLYDataNode>| {
+            if on_path.contains(&(self.states[child].node as *const MOTLYDataNode)) {
+                looped = true;
+                return None;
+            }
+            let (copy, child_looped) = self.copy(child, on_path, done);
+            looped |= child_looped;
+            Some(copy)
+        };
+        let eq = match &state.node.eq {
+            Some(EqValue::Array(_)) => Some(EqValue::Array(
+                state
+                    .elements
+                    .iter()
+                    .map(|element| element.and_then(|element| child(element, on_path)).unwrap_or_default())
+                    .map(MOTLYNode::Data)
+                    .collect(),
+            )),
+            eq => eq.clone(),
+        };
+        let properties = state.node.properties.as_ref().map(|_| {
+            state
+                .properties
+                .iter()
+                .filter_map(|(key, id)| Some((key.clone(), MOTLYNode::Data(child(*id, on_path)?))))
+                .collect()
+        });
+        on_path.pop();
+        let copy = MOTLYDataNode { eq, properties, deleted: false, location: state.node.location };
+        if !looped {
+            done.insert(id, copy.clone());
+        }
+        (copy, looped)
+    }
+}
+
+// ── Parsing ─────────────────────────────────────────────────────────
+
+struct QueryParser<'a> {
+    text: &'a str,
+    pos: usize,
+}
+
+impl QueryParser<'_> {
+    fn parse(mut self) -> Result<Query, MOTLYError> {
+        let mut steps = Vec::new();
+        self.eat("$");
+        if !self.at_end() && !self.text[self.pos..].starts_with(['.', '[']) {
+            steps.push(self.name_step()?);
+        }
+        while !self.at_end() {
+            if self.eat("..") {
+                if self.eat("*") {
+                    steps.push(Step::Descendants(None));
+                } else {
+                    steps.push(Step::Descendants(Some(self.name()?)));
+                }
+            } else if self.eat(".") {
+                steps.push(self.name_step()?);
+            } else if self.eat("[") {
+                steps.push(self.bracket_step()?);
+            } else {
+                return Err(self.error("Expected '.', '..' or '['"));
+            }
+        }
+        Ok(Query { steps })
+    }
+
+    fn name_step(&mut self) -> Result<Step, MOTLYError> {
+        if self.eat("*") {
+            Ok(Step::AllProperties)
+        } else {
+            Ok(Step::Name(self.name()?))
+        }
+    }
+
+    fn bracket_step(&mut self) -> Result<Step, MOTLYError> {
+        let step = if self.eat("*") {
+            Step::AllElements
+        } else if self.eat("?") {
+            Step::Filter(self.filter()?)
+        } else {
+            let digits = self.take_while(|c| c.is_ascii_digit());
+            Step::Index(digits.parse().map_err(|_| self.error("Expected an index, '*' or '?'"))?)
+        };
+        self.skip_spaces();
+        if !self.eat("]") {
+            return Err(self.error("Expected ']'"));
+        }
+        Ok(step)
+    }
+
+    fn filter(&mut self) -> Result<Filter, MOTLYError> {
+        self.skip_spaces();
+        let mut path = vec![self.name()?];
+        while self.eat(".") {
+            path.push(self.name()?);
+        }
+        self.skip_spaces();
+        let comparisons = [
+            ("==", Comparison::Eq),
+            ("!=", Comparison::Ne),
+            ("<=", Comparison::Le),
+            (">=", Comparison::Ge),
+            ("<", Comparison::Lt),
+            (">", Comparison::Gt),
+        ];
+        let Some(&(_, comparison)) = comparisons.iter().find(|(op, _)| self.eat(op)) else {
+            return Ok(Filter { path, test: None });
+        };
+        self.skip_spaces();
+        let start = self.pos;
+        let value = match parser::parse_value_at(self.text, self.pos) {
+            Ok((TagValue::Scalar(value), end)) if !matches!(value, ScalarValue::Reference { .. } | ScalarValue::Env { .. }) => {
+                self.pos = end;
+                value
+            }
+            _ => {
+                self.pos = start;
+                return Err(self.error("Expected a value to compare with"));
+            }
+        };
+        Ok(Filter { path, test: Some((comparison, value)) })
+    }
+
+    fn name(&mut self) -> Result<String, MOTLYError> {
+        match parser::parse_identifier_at(self.text, self.pos) {
+            Ok((name, end)) if end > self.pos => {
+                self.pos = end;
+                Ok(name)
+            }
+            _ => Err(self.error("Expected a property name")),
+        }
+    }
+
+    fn eat(&mut self, s: &str) -> bool {
+        let found = self.text[self.pos..].starts_with(s);
+        if found {
+            self.pos += s.len();
+        }
+        found
+    }
+
+    fn take_while(&mut self, f: impl Fn(char) -> bool) -> &str {
+        let start = self.pos;
+        while let Some(c) = self.text[self.pos..].chars().next().filter(|c| f(*c)) {
+            self.pos += c.len_utf8();
+        }
+        &self.text[start..self.pos]
+    }
+
+    fn skip_spaces(&mut self) {
+        self.take_while(|c| c == ' ');
+    }
+
+    fn at_end(&self) -> bool {
+        self.pos >= self.text.len()
+    }
+
+    fn error(&self, message: &str) -> MOTLYError {
+        let at = |offset: usize| Position { line: 0, column: offset, offset };
+        MOTLYError::new(
+            "invalid-query",
+            message.to_string(),
+            at(self.pos),
+            at((self.pos + 1).min(self.text.len()).max(self.pos)),
+        )
+    }
+}
@@ -0,0 +1,223 @@
+/// Secrets: values that should not be printed.
+///
+/// A value is secret if it comes from the environment (`@env.NAME`), or
+/// if the schema declares its property with the type `SecretString`, the
+/// application-level type VISION.md describes (directly or through other
+/// named types). A secret stays secret through clones, which copy the
+/// `@env` reference or, for a declared property, are followed back to it
+/// through the interpreter's `Provenance`, and through links: every path
+/// that reaches a secret node, directly or through a link, is in `Secrets`.
+///
+/// `to_json_redacted`, `to_plain_json` with `PlainOptions::redact` and
+/// `motly --redact` print `***` for their values; `Secrets::redact` makes a
+/// copy of a tree with them replaced, for the other serializers. Find
+/// secrets before `env::resolve_env`: once resolved, a value from the
+/// environment is a string like any other. `MOTLYSession::secrets` does
+/// this for you.
+use std::collections::{HashMap, HashSet};
+
+use crate::interpreter::Provenance;
+use crate::query::LinkGraph;
+use crate::tree::{EqValue, MOTLYDataNode, MOTLYNode, Scalar};
+use crate::validate::schema_fields;
+
+/// The type name that marks a property as secret in a schema.
+pub const SECRET_TYPE: &str = "SecretString";
+
+/// What redacted serializers print for a secret value.
+pub const REDACTED: &str = "***";
+
+/// The paths of secret values in a tree, with links followed (paths as in
+/// `diff::Change`, with `[i]` for array elements).
+///
+/// Links can reach a node along exponentially many paths, so the paths are
+/// not listed: this keeps the tree as `query::LinkGraph` sees it, cut down
+/// to the parts a secret can be reached from, and `contains` walks it.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Secrets {
+    /// State 0 is the root; empty when nothing is secret.
+    states: Vec<SecretState>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+struct SecretState {
+    /// The same number for every state of the same node.
+    node: usize,
+    secret: bool,
+    children: Vec<(String, usize)>,
+}
+
+impl Secrets {
+    /// The secrets of `root`, whose `@env` references are not yet resolved,
+    /// with the properties `schema` types as `SecretString`.
+    pub fn find(root: &MOTLYDataNode, schema: Option<&MOTLYDataNode>) -> Secrets {
+        Secrets::find_with_provenance(root, schema, &Provenance::default())
+    }
+
+    /// `find`, also marking the copies that `provenance`'s clones made of
+    /// the `SecretString` properties. A copy that is written over later is
+    /// still marked.
+    pub fn find_with_provenance(root: &MOTLYDataNode, schema: Option<&MOTLYDataNode>, provenance: &Provenance) -> Secrets {
+        let graph = LinkGraph::new(root);
+        let mut paths: Vec<Vec<String>> = schema
+            .map(schema_fields)
+            .unwrap_or_default()
+            .into_iter()
+            .filter(|field| field.types.iter().any(|t| t == SECRET_TYPE))
+            .map(|field| field.path)
+            .collect();
+        for (path, source) in &provenance.clones {
+            let copies: Vec<Vec<String>> = paths
+                .iter()
+                .filter(|secret| secret.starts_with(source))
+                .map(|secret| path.iter().chain(&secret[source.len()..]).cloned().collect())
+                .collect();
+            paths.extend(copies);
+        }
+        // The nodes at the declared paths are secret wherever they are reached from
+        let declared: HashSet<*const MOTLYDataNode> = paths
+            .iter()
+            .filter_map(|path| graph.lookup(path))
+            .map(|id| graph.state(id).node as *const MOTLYDataNode)
+            .collect();
+        let is_secret = |node: &MOTLYDataNode| {
+            matches!(node.eq, Some(EqValue::EnvRef(_))) || declared.contains(&(node as *const MOTLYDataNode))
+        };
+
+        // Keep the secret states and those they can be reached from
+        let mut parents = vec![Vec::new(); graph.len()];
+        for id in 0..graph.len() {
+            for (_, child) in graph.children(id) {
+                parents[child].push(id);
+            }
+        }
+        let mut keep = vec![false; graph.len()];
+        let mut queue: Vec<usize> = (0..graph.len()).filter(|&id| is_secret(graph.state(id).node)).collect();
+        for &id in &queue {
+            keep[id] = true;
+        }
+        while let Some(id) = queue.pop() {
+            for &parent in &parents[id] {
+                if !keep[parent] {
+                    keep[parent] = true;
+                    queue.push(parent);
+                }
+            }
+        }
+        if !keep[0] {
+            return Secrets::default();
+        }
+
+        let mut next = 0;
+        let renumbered: Vec<Option<usize>> = keep
+            .iter()
+            .map(|&kept| {
+                next += kept as usize;
+                kept.then_some(next - 1)
+            })
+            .collect();
+        let mut nodes = HashMap::new();
+        let states = (0..graph.len())
+            .filter(|&id| keep[id])
+            .map(|id| {
+                let node = graph.state(id).node;
+                let count = nodes.len();
+                SecretState {
+                    node: *nodes.entry(node as *const MOTLYDataNode).or_insert(count),
+                    secret: is_secret(node),
+                    children: graph.children(id).filter_map(|(segment, child)| Some((segment, renumbered[child]?))).collect(),
+                }
+            })
+            .collect();
+        Secrets { states }
+    }
+
+    /// Whether the value at `path` is secret.
+    pub fn contains(&self, path: &[String]) -> bool {
+        self.walk(path).is_some_and(|id| self.states[id].secret)
+    }
+
+    /// Whether a value at or below `path` is secret.
+    pub fn within(&self, path: &[String]) -> bool {
+        self.walk(path).is_some()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.states.is_empty()
+    }
+
+    /// The secret paths, in order. There can be very many when links
+    /// reach a secret in many ways.
+    pub fn paths(&self) -> Vec<Vec<String>> {
+        let mut paths = Vec::new();
+        if !self.states.is_empty() {
+            self.collect_paths(0, &mut Vec::new(), &mut vec![self.states[0].node], &mut paths);
+        }
+        paths.sort();
+        paths
+    }
+
+    fn collect_paths(&self, id: usize, path: &mut Vec<String>, on_path: &mut Vec<usize>, paths: &mut Vec<Vec<String>>) {
+        if self.states[id].secret {
+            paths.push(path.clone());
+        }
+        for (segment, child) in &self.states[id].children {
+            if on_path.contains(&self.states[*child].node) {
+                continue;
+            }
+            path.push(segment.clone());
+            on_path.push(self.states[*child].node);
+            self.collect_paths(*child, path, on_path, paths);
+            on_path.pop();
+            path.pop();
+        }
+    }
+
+    /// The state at `path`, if a secret can be reached from it. A path
+    /// that comes back to a node it went through leads nowhere, as in
+    /// `query::LinkGraph::lookup`.
+    fn walk(&self, path: &[String]) -> Option<usize> {
+        let mut id = 0;
+        let mut on_path = vec![self.states.first()?.node];
+        for segment in path {
+            id = self.states[id].children.iter().find(|(key, _)| key == segment)?.1;
+            if on_path.contains(&self.states[id].node) {
+                return None;
+            }
+            on_path.push(self.states[id].node);
+        }
+        Some(id)
+    }
+
+    /// A copy of `node`, the node at `path`, with the string `***` as the
+    /// value of each secret in it. `@env` references are kept: they name
+    /// the variable, not its value.
+    pub fn redact(&self, node: &MOTLYDataNode, path: &[String]) -> MOTLYDataNode {
+        let mut path = path.to_vec();
+        let mut copy = node.clone();
+        self.redact_in(&mut copy, &mut path);
+        copy
+    }
+
+    fn redact_in(&self, node: &mut MOTLYDataNode, path: &mut Vec<String>) {
+        if self.contains(path) && !matches!(node.eq, None | Some(EqValue::EnvRef(_))) {
+            node.eq = Some(EqValue::Scalar(Scalar::String(REDACTED.to_string())));
+        }
+        if let Some(EqValue::Array(items)) = &mut node.eq {
+            for (i, item) in items.iter_mut().enumerate() {
+                if let MOTLYNode::Data(data) = item {
+                    path.push(format!("[{}]", i));
+                    self.redact_in(data, path);
+                    path.pop();
+                }
+            }
+        }
+        for (key, child) in node.properties.iter_mut().flatten() {
+            if let MOTLYNode::Data(data) = child {
+                path.push(key.clone());
+                self.redact_in(data, path);
+                path.pop();
+            }
+        }
+    }
+}
@@ -0,0 +1,394 @@
+/// Native MOTLY session: accumulate parses, interpret them together, then
+/// validate and read the result.
+///
+/// Mirrors the TypeScript `MOTLYSession`; the WASM FFI in lib.rs is a thin
+/// wrapper over it.
+use std::collections::{BTreeMap, HashMap};
+
+use crate::ast::{Span, Statement};
+use crate::env::{resolve_env, schema_hints, EnvOptions, EnvProvider};
+use crate::error::{MOTLYError, Position};
+use crate::include::{include_directives, SourceLoader};
+use crate::interpreter::{
+    chunk, execute_chunked_with_provenance, flatten, topo_sort, ExecContext, Provenance, SessionOptions, Transformer,
+};
+use crate::mot::{build_mot, Mot};
+use crate::secret::Secrets;
+use crate::source::SourceMap;
+use crate::spans::SpanTable;
+use crate::tree::MOTLYDataNode;
+use crate::validate::{validate_references, validate_schema, SchemaError, ValidationError};
+use crate::{parse_motly, parser};
+
+/// The result of one `parse` call.
+#[derive(Debug, Clone, PartialEq)]
+pub struct MOTLYParseResult {
+    pub parse_id: u32,
+    pub errors: Vec<MOTLYError>,
+}
+
+struct AccumulatedParse {
+    stmts: Vec<Statement>,
+    parse_id: u32,
+}
+
+/// A MOTLY parsing session.
+///
+/// Call `parse` (or `parse_named`) for each source, then `finish` to
+/// interpret everything in order. Statements from all parses are
+/// interpreted together, so a later source can refer to values defined
+/// in an earlier one. The session accepts no more input after `finish`.
+pub struct MOTLYSession {
+    accumulated: Vec<AccumulatedParse>,
+    value: MOTLYDataNode,
+    schema: Option<MOTLYDataNode>,
+    next_parse_id: u32,
+    options: SessionOptions,
+    sources: SourceMap,
+    /// Filled only with `SessionOptions::spans`.
+    spans: SpanTable,
+    /// The layer of each parse made while one was set.
+    layers: BTreeMap<u32, String>,
+    current_layer: Option<String>,
+    /// Flattened statements of every parse, kept after `finish`.
+    transformers: Vec<Transformer>,
+    provenance: Provenance,
+    finished: bool,
+    /// The secrets as they were before `resolve_env` replaced the `@env`
+    /// references that mark them.
+    resolved_secrets: Option<Secrets>,
+}
+
+impl Default for MOTLYSession {
+    fn default() -> Self {
+        MOTLYSession::new(SessionOptions::default())
+    }
+}
+
+impl MOTLYSession {
+    pub fn new(options: SessionOptions) -> Self {
+        MOTLYSession {
+            accumulated: Vec::new(),
+            value: MOTLYDataNode::new(),
+            schema: None,
+            next_parse_id: 0,
+            options,
+            sources: SourceMap::new(),
+            spans: SpanTable::new(),
+            layers: BTreeMap::new(),
+            current_layer: None,
+            transformers: Vec::new(),
+            provenance: Provenance::default(),
+            finished: false,
+            resolved_secrets: None,
+        }
+    }
+
+    pub fn options(&self) -> &SessionOptions {
+        &self.options
+    }
+
+    /// The names and text of the sources given to `parse_named`.
+    pub fn sources(&self) -> &SourceMap {
+        &self.sources
+    }
+
+    /// Key, value and properties spans of every parsed statement, by path.
+    /// Empty unless the session was created with `SessionOptions::spans`.
+    pub fn spans(&self) -> &SpanTable {
+        &self.spans
+    }
+
+    /// Parse source and accumulate its statements (those recovered after a
+    /// syntax error included). Returns only syntax errors; everything else
+    /// is reported by `finish`.
+    pub fn parse(&mut self, source: &str) -> MOTLYParseResult {
+        self.parse_source(None, source, &[])
+    }
+
+    /// Like `parse`, recording `name` (typically a file path) as the source
+    /// of this parse so errors can be rendered as `name:line:col`.
+    pub fn parse_named(&mut self, name: &str, source: &str) -> MOTLYParseResult {
+        self.parse_source(Some(name), source, &[])
+    }
+
+    /// Parse one source whose statements will apply under `mount`.
+    fn parse_source(&mut self, name: Option<&str>, source: &str, mount: &[String]) -> MOTLYParseResult {
+        if self.finished {
+            return MOTLYParseResult {
+                parse_id: 0,
+                errors: vec![session_error("Session is spent after finish() — create a new session")],
+            };
+        }
+        let parse_id = self.take_parse_id(name, source);
+        if let Some(layer) = &self.current_layer {
+            self.layers.insert(parse_id, layer.clone());
+        }
+        let (stmts, mut errors) = parser::parse_recovering(source, &self.options.parse_limits());
+        for err in &mut errors {
+            err.parse_id = Some(parse_id);
+        }
+        // A limit error means the source was not parsed; don't parse it again
+        if self.options.spans && errors.iter().all(|e| e.code == "tag-parse-syntax-error") {
+            self.spans.add(parse_id, source, mount);
+        }
+        self.accumulated.push(AccumulatedParse { stmts, parse_id });
+        MOTLYParseResult { parse_id, errors }
+    }
+
+    /// Parse `source` (named `name`) along with everything it includes
+    /// through `#! include` directives, loaded by `loader`. Each included
+    /// source is its own parse and is interpreted before the file that
+    /// includes it. Returns one result per source, in parse_id order;
+    /// problems with an include are reported on the including source.
+    pub fn parse_with_includes(&mut self, name: &str, source: &str, loader: &dyn SourceLoader) -> Vec<MOTLYParseResult> {
+        let mut results = Vec::new();
+        self.parse_included(name, source, &[], loader, &mut Vec::new(), &mut results);
+        results.sort_by_key(|r| r.parse_id);
+        results
+    }
+
+    fn parse_included(
+        &mut self,
+        name: &str,
+        source: &str,
+        mount: &[String],
+        loader: &dyn SourceLoader,
+        including: &mut Vec<String>,
+        results: &mut Vec<MOTLYParseResult>,
+    ) {
+        let mut result = self.parse_source(Some(name), source, mount);
+        if self.finished {
+            results.push(result);
+            return;
+        }
+        // The includes run first, so this file's statements go after theirs
+        let own = self.accumulated.pop().unwrap();
+
+        let (includes, mut errors) = include_directives(source);
+        including.push(name.to_string());
+        for include in includes {
+            let target = loader.resolve(name, &include.target);
+            let problem = if including.contains(&target) {
+                Some(("include-cycle", format!("Include cycle: {} -> {}", including.join(" -> "), target)))
+            } else {
+                match loader.load(&target) {
+                    Ok(text) => {
+                        let mut at = mount.to_vec();
+                        at.extend(include.at);
+                        self.parse_included(&target, &text, &at, loader, including, results);
+                        None
+                    }
+                    Err(reason) => Some(("include-not-found", format!("Cannot include {}: {}", target, reason))),
+                }
+            };
+            if let Some((code, message)) = problem {
+                errors.push(MOTLYError::new(code, message, include.span.begin, include.span.end));
+            }
+        }
+        including.pop();
+
+        for err in &mut errors {
+            err.parse_id = Some(result.parse_id);
+        }
+        result.errors.extend(errors);
+        self.accumulated.push(AccumulatedParse { stmts: mounted(own.stmts, mount, source), parse_id: own.parse_id });
+        results.push(result);
+    }
+
+    fn take_parse_id(&mut self, name: Option<&str>, source: &str) -> u32 {
+        let parse_id = self.next_parse_id;
+        self.next_parse_id += 1;
+        if let Some(name) = name {
+            self.sources.add(parse_id, name, source);
+        }
+        parse_id
+    }
+
+    /// Interpret all accumulated statements using the four-phase engine,
+    /// validate references (unless disabled), and store the result.
+    pub fn finish(&mut self) -> Vec<MOTLYError> {
+        if self.finished {
+            return vec![session_error("finish() has already been called on this session")];
+        }
+        self.finished = true;
+        let accumulated = std::mem::take(&mut self.accumulated);
+
+        // Phase 1: Flatten all accumulated statements into transformers.
+        // They are kept so layers can be left out later without reparsing.
+        for ap in &accumulated {
+            let ctx = ExecContext { parse_id: ap.parse_id, options: self.options.clone() };
+            self.transformers.extend(flatten(&ap.stmts, &ctx));
+        }
+
+        let (root, errors, provenance) = self.interpret(&self.transformers);
+        self.value = root;
+        self.provenance = provenance;
+        errors
+    }
+
+    /// Phases 2–4 over already-flattened transformers, then reference validation.
+    fn interpret(&self, transformers: &[Transformer]) -> (MOTLYDataNode, Vec<MOTLYError>, Provenance) {
+        // Phase 2: Chunk
+        let chunk_result = chunk(transformers);
+
+        // Phase 3: Topo-sort
+        let sort_result = topo_sort(&chunk_result.deps);
+
+        // Phase 4: Execute
+        let mut root = MOTLYDataNode::new();
+        let mut provenance = if self.options.trace { Provenance::traced() } else { Provenance::default() };
+        let mut errors = execute_chunked_with_provenance(
+            transformers,
+            &chunk_result.splits,
+            &sort_result.order,
+            &mut root,
+            &self.options,
+            &mut provenance,
+        );
+
+        // Validate references (unless disabled)
+        if !self.options.disable_references {
+            errors.extend(validate_references(&root).into_iter().map(|err| MOTLYError::located(err.code, err.message, err.location.as_ref())));
+        }
+        (root, errors, provenance)
+    }
+
+    // ── Layers ──────────────────────────────────────────────────────
+
+    /// Put the following parses (and the files they include) in the layer
+    /// `name`, e.g. "defaults", "team", "prod", "local". Parses made before
+    /// the first call belong to no layer.
+    pub fn set_layer(&mut self, name: &str) {
+        self.current_layer = Some(name.to_string());
+    }
+
+    /// The layer names, in the order they were first used.
+    pub fn layers(&self) -> Vec<&str> {
+        let mut names: Vec<&str> = Vec::new();
+        for name in self.layers.values() {
+            if !names.contains(&name.as_str()) {
+                names.push(name);
+            }
+        }
+        names
+    }
+
+    /// The layer of a parse, if it was made in one.
+    pub fn layer_of_parse(&self, parse_id: u32) -> Option<&str> {
+        self.layers.get(&parse_id).map(String::as_str)
+    }
+
+    /// Where the nodes of the finished value came from. Includes the full
+    /// trace when the session was created with `SessionOptions::trace`.
+    pub fn provenance(&self) -> &Provenance {
+        &self.provenance
+    }
+
+    /// The parse whose statement last wrote the node at `path` in the
+    /// finished value.
+    pub fn writer_of(&self, path: &[&str]) -> Option<u32> {
+        let key: Vec<String> = path.iter().map(|s| s.to_string()).collect();
+        self.provenance.writers.get(&key).copied()
+    }
+
+    /// The layer that last wrote the node at `path`.
+    pub fn layer_of(&self, path: &[&str]) -> Option<&str> {
+        self.layer_of_parse(self.writer_of(path)?)
+    }
+
+    /// What the value would be if layer `name` had never been parsed,
+    /// re-interpreting the statements of the other layers. Returns the
+    /// value and the errors interpreting it produces.
+    pub fn value_without_layer(&self, name: &str) -> (MOTLYDataNode, Vec<MOTLYError>) {
+        let kept: Vec<Transformer> = self
+            .transformers
+            .iter()
+            .filter(|t| self.layer_of_parse(t.parse_id) != Some(name))
+            .cloned()
+            .collect();
+        let (root, errors, _) = self.interpret(&kept);
+        (root, errors)
+    }
+
+    /// Parse MOTLY source as a schema and store it in the session. Schemas
+    /// may clone (`:=`) but not link (`= $ref`).
+    pub fn parse_schema(&mut self, source: &str) -> MOTLYParseResult {
+        let parse_id = self.take_parse_id(None, source);
+        let options = SessionOptions { disable_references: true, ..self.options.clone() };
+        let result = parse_motly(source, MOTLYDataNode::new(), &ExecContext { parse_id, options });
+        self.schema = Some(result.value);
+        MOTLYParseResult { parse_id, errors: result.errors }
+    }
+
+    /// The interpreted value; empty until `finish`.
+    pub fn value(&self) -> &MOTLYDataNode {
+        &self.value
+    }
+
+    /// Reset the value to empty, keeping the schema.
+    pub fn reset(&mut self) {
+        self.value = MOTLYDataNode::new();
+        self.resolved_secrets = None;
+    }
+
+    /// Validate references in the value.
+    pub fn validate_references(&self) -> Vec<ValidationError> {
+        validate_references(&self.value)
+    }
+
+    /// Validate the value against the stored schema. Empty if no schema has been set.
+    pub fn validate_schema(&self) -> Vec<SchemaError> {
+        match &self.schema {
+            Some(schema) => validate_schema(&self.value, schema),
+            None => Vec::new(),
+        }
+    }
+
+    /// Replace the `@env` references in the value with values from
+    /// `provider`, reading them as the stored schema's types where it has
+    /// one (see `env::schema_hints`); `options.hints` take precedence.
+    pub fn resolve_env(&mut self, provider: &dyn EnvProvider, options: &EnvOptions) -> Vec<MOTLYError> {
+        let mut options = options.clone();
+        if let Some(schema) = &self.schema {
+            for (path, hint) in schema_hints(schema) {
+                options.hints.entry(path).or_insert(hint);
+            }
+        }
+        if self.resolved_secrets.is_none() {
+            self.resolved_secrets = Some(self.secrets());
+        }
+        resolve_env(&mut self.value, provider, &options)
+    }
+
+    /// The secret values of the value (see `secret::Secrets`), with the
+    /// stored schema's `SecretString` properties. After `resolve_env`, as
+    /// they were when it was first called.
+    pub fn secrets(&self) -> Secrets {
+        match &self.resolved_secrets {
+            Some(secrets) => secrets.clone(),
+            None => Secrets::find_with_provenance(&self.value, self.schema.as_ref(), &self.provenance),
+        }
+    }
+
+    /// A resolved view of the value, with `@env` references looked up in `env`.
+    pub fn get_mot(&self, env: Option<&HashMap<String, String>>) -> Mot {
+        build_mot(&self.value, env)
+    }
+}
+
+/// Statements of an included source, moved under `mount`.
+fn mounted(stmts: Vec<Statement>, mount: &[String], source: &str) -> Vec<Statement> {
+    if mount.is_empty() {
+        return stmts;
+    }
+    let span = Span {
+        begin: Position::ZERO,
+        end: Position::from_offset(source, source.len()),
+    };
+    vec![Statement::UpdateProperties { path: mount.to_vec(), properties: stmts, span }]
+}
+
+fn session_error(message: &str) -> MOTLYError {
+    MOTLYError::general("session-error", message.to_string())
+}
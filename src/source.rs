@@ -0,0 +1,69 @@
+/// Source identities for multi-file sessions.
+///
+/// Every parse in a session gets a parse_id; the source map remembers which
+/// file (or other named source) each one came from, and its text, so errors
+/// can be reported as `file:line:col` and excerpts shown.
+use std::collections::BTreeMap;
+
+use crate::error::{MOTLYError, Position};
+use crate::tree::MOTLYLocation;
+
+/// One parsed source: its display name and text.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SourceFile {
+    pub name: String,
+    pub text: String,
+}
+
+/// parse_id → source file.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct SourceMap {
+    files: BTreeMap<u32, SourceFile>,
+}
+
+impl SourceMap {
+    pub fn new() -> Self {
+        SourceMap::default()
+    }
+
+    /// Record the source for `parse_id`, replacing any previous entry.
+    pub fn add(&mut self, parse_id: u32, name: impl Into<String>, text: impl Into<String>) {
+        self.files.insert(parse_id, SourceFile { name: name.into(), text: text.into() });
+    }
+
+    pub fn get(&self, parse_id: u32) -> Option<&SourceFile> {
+        self.files.get(&parse_id)
+    }
+
+    /// The display name for `parse_id`: the recorded name, or `<parse N>`.
+    pub fn name(&self, parse_id: u32) -> String {
+        match self.get(parse_id) {
+            Some(file) => file.name.clone(),
+            None => format!("<parse {}>", parse_id),
+        }
+    }
+
+    /// `file:line:col` with 1-based line and column. Without a parse_id
+    /// only `line:col` can be given.
+    pub fn format_position(&self, parse_id: Option<u32>, pos: &Position) -> String {
+        match parse_id {
+            Some(pid) => format!("{}:{}:{}", self.name(pid), pos.line + 1, pos.column + 1),
+            None => format!("{}:{}", pos.line + 1, pos.column + 1),
+        }
+    }
+
+    /// Where a node was defined, as `file:line:col`.
+    pub fn format_location(&self, location: &MOTLYLocation) -> String {
+        self.format_position(Some(location.parse_id), &location.begin)
+    }
+
+    /// `file:line:col: message (code)`.
+    pub fn format_error(&self, err: &MOTLYError) -> String {
+        format!("{}: {} ({})", self.format_position(err.parse_id, &err.begin), err.message, err.code)
+    }
+
+    /// The text of the line `pos` is on, if the source is known.
+    pub fn line_text(&self, parse_id: u32, pos: &Position) -> Option<&str> {
+        self.get(parse_id)?.text.lines().nth(pos.line)
+    }
+}
@@ -0,0 +1,185 @@
+/// Fine-grained source spans for the nodes of a tree.
+///
+/// A node's `MOTLYLocation` is one region: its first appearance. Editors
+/// need more — where the key is written, where the value and the properties
+/// block are, and every statement that touched the node. A `SpanTable`
+/// records these from the source text, keyed by absolute path; array
+/// elements use `[i]` segments, as validation errors do.
+///
+/// The table is syntactic. It lists every statement that names a path, in
+/// the order they were added, including statements a later one overrode.
+/// The contents of a clone have no entries of their own; the clone
+/// statement's value span points at the reference.
+use std::collections::BTreeMap;
+
+use crate::ast::Span;
+use crate::cst::{self, NodeKind, SyntaxNode};
+use crate::lexer::TokenKind;
+use crate::parser;
+
+/// Which part of a statement a position is in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SpanPart {
+    Key,
+    Value,
+    Properties,
+    Statement,
+}
+
+/// The spans of one statement (or array element) that names a node.
+#[derive(Debug, Clone, PartialEq)]
+pub struct NodeSpans {
+    pub parse_id: u32,
+    /// The whole statement, without trailing whitespace.
+    pub statement: Span,
+    /// The path segment that names this node. For `a.b = 1` the entry for
+    /// `a` has the span of `a`. None for array elements and `-...`.
+    pub key: Option<Span>,
+    /// The value after `=` or `:=`, or an array element's value. Only on
+    /// the node the statement assigns, not on the nodes of its dotted path.
+    pub value: Option<Span>,
+    /// The `{ ... }` block, braces included.
+    pub properties: Option<Span>,
+}
+
+impl NodeSpans {
+    /// The part of this statement that byte `offset` is in, if any. An
+    /// offset just past a key or value still counts as inside it, which is
+    /// where an editor cursor sits after typing it.
+    pub fn part_at(&self, offset: usize) -> Option<SpanPart> {
+        let within = |span: &Option<Span>| span.is_some_and(|s| contains(&s, offset));
+        if !contains(&self.statement, offset) {
+            None
+        } else if within(&self.key) {
+            Some(SpanPart::Key)
+        } else if within(&self.value) {
+            Some(SpanPart::Value)
+        } else if within(&self.properties) {
+            Some(SpanPart::Properties)
+        } else {
+            Some(SpanPart::Statement)
+        }
+    }
+}
+
+fn contains(span: &Span, offset: usize) -> bool {
+    span.begin.offset <= offset && offset <= span.end.offset
+}
+
+fn len(span: &Span) -> usize {
+    span.end.offset - span.begin.offset
+}
+
+/// absolute path → the statements that name it.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct SpanTable {
+    nodes: BTreeMap<Vec<String>, Vec<NodeSpans>>,
+}
+
+impl SpanTable {
+    pub fn new() -> Self {
+        SpanTable::default()
+    }
+
+    /// Record the statements of `source`, parsed as `parse_id`, with their
+    /// paths under `mount` (empty for the root). Statements the parser could
+    /// not understand are skipped.
+    pub fn add(&mut self, parse_id: u32, source: &str, mount: &[String]) {
+        let tree = cst::parse(source);
+        self.add_list(parse_id, &tree.root, mount);
+    }
+
+    /// Every statement that names `path`, in the order added.
+    pub fn get(&self, path: &[&str]) -> &[NodeSpans] {
+        let key: Vec<String> = path.iter().map(|s| s.to_string()).collect();
+        self.nodes.get(&key).map(Vec::as_slice).unwrap_or_default()
+    }
+
+    /// Where `path` was first named.
+    pub fn definition(&self, path: &[&str]) -> Option<&NodeSpans> {
+        self.get(path).first()
+    }
+
+    /// The node named at byte `offset` of parse `parse_id`: the innermost
+    /// statement containing it, and which part of that statement it is in.
+    /// For a dotted key the segment under the cursor wins.
+    pub fn find(&self, parse_id: u32, offset: usize) -> Option<(&[String], &NodeSpans, SpanPart)> {
+        self.iter()
+            .flat_map(|(path, entries)| entries.iter().map(move |e| (path, e)))
+            .filter(|(_, e)| e.parse_id == parse_id)
+            .filter_map(|(path, e)| Some((path, e, e.part_at(offset)?)))
+            .min_by_key(|(path, e, part)| (len(&e.statement), *part == SpanPart::Statement, usize::MAX - path.len()))
+    }
+
+    /// All paths with their statements, in path order.
+    pub fn iter(&self) -> impl Iterator<Item = (&[String], &[NodeSpans])> {
+        self.nodes.iter().map(|(path, entries)| (path.as_slice(), entries.as_slice()))
+    }
+
+    fn record(&mut self, path: &[String], spans: NodeSpans) {
+        self.nodes.entry(path.to_vec()).or_default().push(spans);
+    }
+
+    fn add_list(&mut self, parse_id: u32, list: &SyntaxNode, prefix: &[String]) {
+        for stmt in list.child_nodes().filter(|n| n.kind.is_statement()) {
+            let Some(statement) = stmt.span() else { continue };
+            if stmt.kind == NodeKind::ClearAll {
+                let spans = NodeSpans { parse_id, statement, key: None, value: None, properties: None };
+                self.record(prefix, spans);
+                continue;
+            }
+
+            let keys = key_tokens(stmt);
+            let mut path = prefix.to_vec();
+            for (i, (name, key)) in keys.iter().enumerate() {
+                path.push(name.clone());
+                let last = i + 1 == keys.len();
+                let spans = NodeSpans {
+                    parse_id,
+                    statement,
+                    key: Some(*key),
+                    value: if last { cst::value_node(stmt).and_then(SyntaxNode::span) } else { None },
+                    properties: if last { stmt.child(NodeKind::Properties).and_then(SyntaxNode::span) } else { None },
+                };
+                self.record(&path, spans);
+            }
+            self.add_contents(parse_id, stmt, &path);
+        }
+    }
+
+    /// The array elements and properties block of a statement or element.
+    fn add_contents(&mut self, parse_id: u32, node: &SyntaxNode, path: &[String]) {
+        if let Some(array) = cst::value_node(node).filter(|v| v.kind == NodeKind::Array) {
+            let elements = array.child_nodes().filter(|n| n.kind == NodeKind::ArrayElement);
+            for (i, el) in elements.enumerate() {
+                let Some(statement) = el.span() else { continue };
+                let mut el_path = path.to_vec();
+                el_path.push(format!("[{}]", i));
+                let spans = NodeSpans {
+                    parse_id,
+                    statement,
+                    key: None,
+                    value: cst::value_node(el).and_then(SyntaxNode::span),
+                    properties: el.child(NodeKind::Properties).and_then(SyntaxNode::span),
+                };
+                self.record(&el_path, spans);
+                self.add_contents(parse_id, el, &el_path);
+            }
+        }
+        if let Some(block) = node.child(NodeKind::Properties) {
+            self.add_list(parse_id, block, path);
+        }
+    }
+}
+
+/// The decoded segments of a statement's property name, with their spans.
+fn key_tokens(stmt: &SyntaxNode) -> Vec<(String, Span)> {
+    let Some(name) = stmt.child(NodeKind::PropName) else {
+        return Vec::new();
+    };
+    name.tokens()
+        .into_iter()
+        .filter(|t| t.kind != TokenKind::Dot)
+        .filter_map(|t| parser::parse_identifier_at(&t.text, 0).ok().map(|(name, _)| (name, t.span)))
+        .collect()
+}
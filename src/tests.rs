@@ -1,25 +1,28 @@
 use crate::tree::*;
-use crate::validate::{validate_references, validate_schema};
+use crate::validate::validate_schema;
 
 /// Strip all location fields from a MOTLYDataNode tree (for fixture comparison).
 fn strip_locations(node: &mut MOTLYDataNode) {
     node.location = None;
     if let Some(EqValue::Array(ref mut arr)) = node.eq {
         for pv in arr.iter_mut() {
-            if let MOTLYNode::Data(ref mut child) = pv {
-                strip_locations(child);
-            }
+            strip_node_locations(pv);
         }
     }
     if let Some(ref mut props) = node.properties {
         for pv in props.values_mut() {
-            if let MOTLYNode::Data(ref mut child) = pv {
-                strip_locations(child);
-            }
+            strip_node_locations(pv);
         }
     }
 }
 
+fn strip_node_locations(pv: &mut MOTLYNode) {
+    match pv {
+        MOTLYNode::Data(child) => strip_locations(child),
+        MOTLYNode::Ref { location, .. } => *location = None,
+    }
+}
+
 // ── Shared fixture runners ──────────────────────────────────────────
 
 /// Embed fixture files at compile time.
@@ -60,7 +63,7 @@ fn test_fixture_parse() {
 
         let input_refs: Vec<&str> = inputs.iter().map(|s| s.as_str()).collect();
         // Include reference validation (matches TS MOTLYSession.finish() behavior)
-        let (value, errors) = crate::session_finish_ex(&input_refs, SessionOptions::default(), true);
+        let (value, errors) = crate::session_finish(&input_refs, SessionOptions::default());
 
         if expect_errors {
             assert!(
@@ -804,6 +807,97 @@ fn test_loc_session_parse_ids() {
     assert_eq!(prop_loc(&node, &["c"]).unwrap().parse_id, 2);
 }
 
+#[test]
+fn test_loc_circular_clone_lists_each_clone() {
+    let (_, errors) = crate::session_finish(&["x = 1\na := $b\nb := $a"], crate::interpreter::SessionOptions::default());
+    assert_eq!(errors.len(), 1, "{:?}", errors);
+    let err = &errors[0];
+    assert_eq!(err.code, "circular-reference");
+    assert_eq!(err.parse_id, Some(0));
+    // At the clone that failed first and starts the message, `b := $a`
+    assert_eq!((err.begin.line, err.begin.column, err.end.line, err.end.column), (2, 0, 2, 7), "{:?}", err);
+    assert!(err.message.starts_with("Circular clone dependency: b clones $a"), "{}", err.message);
+    assert!(err.message.contains("$b (at 2:1)"), "{}", err.message);
+    assert!(err.message.contains("$a (at 3:1)"), "{}", err.message);
+}
+
+#[test]
+fn test_loc_reference_errors_point_at_statement() {
+    let src = "x {\n  y := $nope\n}\nz = $missing\narr = [1, $gone]\nr = [$z { p = 1 }]";
+    let (_, errors) = crate::session_finish(&[src], crate::interpreter::SessionOptions::default());
+    let at = |code: &str| -> Vec<(usize, usize)> {
+        errors.iter().filter(|e| e.code == code).map(|e| (e.begin.line, e.begin.column)).collect()
+    };
+    assert_eq!(at("unresolved-clone-reference"), vec![(1, 2)]);
+    assert_eq!(at("ref-with-properties"), vec![(5, 5)]);
+    let mut refs = at("unresolved-reference");
+    refs.sort();
+    assert_eq!(refs, vec![(3, 0), (4, 10)]);
+    assert!(errors.iter().all(|e| e.parse_id == Some(0)), "{:?}", errors);
+}
+
+#[test]
+fn test_loc_ref_location_round_trips_on_wire() {
+    let result = crate::parse_motly_n("a = 1\nb = $a", MOTLYDataNode::new(), 3);
+    let wire = crate::json::to_wire(&result.value);
+    let back = crate::from_json::from_wire(&wire).unwrap();
+    assert_eq!(back, result.value);
+    let b = back.properties.as_ref().unwrap().get("b").unwrap();
+    let loc = b.location().expect("ref should have a location");
+    assert_eq!((loc.parse_id, loc.begin.line), (3, 1));
+}
+
+#[test]
+fn test_loc_ref_location_ends_at_the_reference() {
+    let result = crate::parse_motly_n("a = 1\nd = $a\n\nlist = [\n  $a\n]\nnext = 2", MOTLYDataNode::new(), 0);
+    assert!(result.errors.is_empty(), "{:?}", result.errors);
+    let props = result.value.properties.as_ref().unwrap();
+    let d = props.get("d").unwrap().location().unwrap();
+    assert_eq!((d.begin.line, d.begin.column, d.end.line, d.end.column), (1, 0, 1, 6));
+    let Some(MOTLYNode::Data(list)) = props.get("list") else { panic!("expected list data") };
+    let Some(EqValue::Array(items)) = &list.eq else { panic!("expected an array") };
+    let item = items[0].location().unwrap();
+    assert_eq!((item.begin.line, item.begin.column, item.end.line, item.end.column), (4, 2, 4, 4));
+}
+
+#[test]
+fn test_loc_statements_end_at_their_last_token() {
+    use crate::ast::{Statement, TagValue};
+    let statements = [
+        "a = 1",
+        "b = 2 { c = 3 }",
+        "d := $a",
+        "e := $a { f = 4 }",
+        "g",
+        "-h",
+        "i: { j = 5 }",
+        "k { l = 6 }",
+        "-...",
+        "list = [1 , [2]  , { m = 7 }, n { o = 8 }  ]",
+    ];
+    // Whitespace and a comment follow each statement; none of it is in the span
+    let source: String = statements.iter().map(|s| format!("{}   # note\n", s)).collect();
+    let parsed = crate::parser::parse(&source).unwrap();
+    assert_eq!(crate::cst::parse(&source).to_statements().unwrap(), parsed);
+    let text = |span: &crate::ast::Span| &source[span.begin.offset..span.end.offset];
+    let spans: Vec<&str> = parsed
+        .iter()
+        .map(|stmt| match stmt {
+            Statement::SetEq { span, .. }
+            | Statement::AssignBoth { span, .. }
+            | Statement::ReplaceProperties { span, .. }
+            | Statement::UpdateProperties { span, .. }
+            | Statement::Define { span, .. }
+            | Statement::ClearAll { span } => text(span),
+        })
+        .collect();
+    assert_eq!(spans, statements);
+
+    let Statement::SetEq { value: TagValue::Array(elements), .. } = &parsed[9] else { panic!("expected an array") };
+    let elements: Vec<&str> = elements.iter().map(|el| text(&el.span)).collect();
+    assert_eq!(elements, ["1", "[2]", "{ m = 7 }", "n { o = 8 }"]);
+}
+
 // ── Meta-schema self-validation ─────────────────────────────────────
 
 #[test]
@@ -829,8 +923,8 @@ fn test_meta_schema_validates_itself() {
 #[test]
 fn test_allow_refs_false_rejects_eq_ref() {
     use crate::interpreter::SessionOptions;
-    let options = SessionOptions { disable_references: true };
-    let (root, errors) = crate::session_finish_ex(&["a = hello\nb = $a"], options, false);
+    let options = SessionOptions { disable_references: true, ..SessionOptions::default() };
+    let (root, errors) = crate::session_finish(&["a = hello\nb = $a"], options);
     assert_eq!(errors.len(), 1);
     assert_eq!(errors[0].code, "ref-not-allowed");
     // a should still be set
@@ -843,8 +937,8 @@ fn test_allow_refs_false_rejects_eq_ref() {
 #[test]
 fn test_allow_refs_false_rejects_array_ref() {
     use crate::interpreter::SessionOptions;
-    let options = SessionOptions { disable_references: true };
-    let (root, errors) = crate::session_finish_ex(&["items = [hello, $foo]"], options, false);
+    let options = SessionOptions { disable_references: true, ..SessionOptions::default() };
+    let (root, errors) = crate::session_finish(&["items = [hello, $foo]"], options);
     assert_eq!(errors.len(), 1);
     assert_eq!(errors[0].code, "ref-not-allowed");
     // The ref should still be in the tree
@@ -864,8 +958,8 @@ fn test_allow_refs_false_rejects_array_ref() {
 #[test]
 fn test_allow_refs_false_allows_clone() {
     use crate::interpreter::SessionOptions;
-    let options = SessionOptions { disable_references: true };
-    let (root, errors) = crate::session_finish_ex(&["a = hello\nb := $a"], options, false);
+    let options = SessionOptions { disable_references: true, ..SessionOptions::default() };
+    let (root, errors) = crate::session_finish(&["a = hello\nb := $a"], options);
     assert!(errors.is_empty(), "clone should be allowed: {:?}", errors);
     let b = root.properties.as_ref().unwrap().get("b").unwrap();
     match b {
@@ -883,3 +977,1565 @@ fn test_allow_refs_default_allows_refs() {
     let b = result.value.properties.as_ref().unwrap().get("b").unwrap();
     assert!(matches!(b, MOTLYNode::Ref { .. }));
 }
+
+// ── Lossless CST ────────────────────────────────────────────────────
+
+/// Every string in every fixture file, plus the sample documents. Not all of
+/// them are MOTLY, which is fine: the CST must round-trip anything.
+fn all_fixture_strings() -> Vec<String> {
+    fn collect(v: &serde_json::Value, out: &mut Vec<String>) {
+        match v {
+            serde_json::Value::String(s) => out.push(s.clone()),
+            serde_json::Value::Array(arr) => arr.iter().for_each(|x| collect(x, out)),
+            serde_json::Value::Object(obj) => obj.values().for_each(|x| collect(x, out)),
+            _ => {}
+        }
+    }
+    let mut out = vec![
+        include_str!("../test-data/k8s-deployment-sample.motly").to_string(),
+        include_str!("../test-data/k8s-deployment-schema.motly").to_string(),
+        include_str!("../test-data/motly-schema.motly").to_string(),
+    ];
+    for file in [PARSE_FIXTURES, PARSE_ERROR_FIXTURES, SCHEMA_FIXTURES, REF_FIXTURES, SESSION_FIXTURES] {
+        let json: serde_json::Value = serde_json::from_str(file).unwrap();
+        collect(&json, &mut out);
+    }
+    out
+}
+
+#[test]
+fn test_cst_round_trips_every_byte() {
+    for input in all_fixture_strings() {
+        let tree = crate::cst::parse(&input);
+        assert_eq!(tree.text(), input, "CST did not round-trip {:?}", input);
+    }
+}
+
+#[test]
+fn test_cst_statements_match_parser() {
+    for input in all_fixture_strings() {
+        let tree = crate::cst::parse(&input);
+        match crate::parser::parse(&input) {
+            Ok(expected) => {
+                assert!(tree.errors.is_empty(), "CST errors for {:?}: {:?}", input, tree.errors);
+                assert_eq!(tree.to_statements().unwrap(), expected, "statement mismatch for {:?}", input);
+            }
+            Err(expected) => {
+                assert_eq!(tree.errors.first(), Some(&expected), "error mismatch for {:?}", input);
+            }
+        }
+    }
+}
+
+#[test]
+fn test_cst_keeps_comments_and_trivia() {
+    use crate::cst::{NodeKind, SyntaxElement};
+    use crate::lexer::TokenKind;
+    let input = "# header\na = 1 # trailing\nb { c = \"x\", -d }\n";
+    let tree = crate::cst::parse(input);
+    assert!(tree.errors.is_empty());
+    assert_eq!(tree.text(), input);
+    let comments: Vec<_> = tree.root.tokens().into_iter().filter(|t| t.kind == TokenKind::Comment).map(|t| t.text.clone()).collect();
+    assert_eq!(comments, vec!["# header", "# trailing"]);
+    let kinds: Vec<_> = tree.root.child_nodes().map(|n| n.kind).collect();
+    assert_eq!(kinds, vec![NodeKind::SetEq, NodeKind::UpdateProperties]);
+    let block = tree.root.child_nodes().nth(1).unwrap().child(NodeKind::Properties).unwrap();
+    assert!(matches!(block.children.first(), Some(SyntaxElement::Token(t)) if t.kind == TokenKind::LBrace));
+    let inner: Vec<_> = block.child_nodes().map(|n| n.kind).collect();
+    assert_eq!(inner, vec![NodeKind::SetEq, NodeKind::Delete]);
+}
+
+#[test]
+fn test_cst_invalid_input_still_round_trips() {
+    let input = "a = 1\nb = [1, 2\nc = {\n";
+    let tree = crate::cst::parse(input);
+    assert_eq!(tree.text(), input);
+    assert!(!tree.errors.is_empty());
+    assert!(tree.to_statements().is_err());
+}
+
+// ── Formatter ───────────────────────────────────────────────────────
+
+#[test]
+fn test_format_fixtures_idempotent_and_equivalent() {
+    use crate::format::{format, FormatOptions};
+    let options = FormatOptions::default();
+    for input in all_fixture_strings() {
+        if crate::parser::parse(&input).is_err() {
+            assert!(format(&input, &options).is_err(), "formatted invalid input {:?}", input);
+            continue;
+        }
+        let once = format(&input, &options).unwrap();
+        let twice = format(&once, &options).unwrap();
+        assert_eq!(once, twice, "format is not idempotent for {:?}", input);
+
+        let mut before = crate::parse_motly_0(&input, MOTLYDataNode::new());
+        let mut after = crate::parse_motly_0(&once, MOTLYDataNode::new());
+        strip_locations(&mut before.value);
+        strip_locations(&mut after.value);
+        assert_eq!(before.value, after.value, "format changed meaning of {:?}", input);
+    }
+}
+
+#[test]
+fn test_format_layout() {
+    use crate::format::{format, FormatOptions};
+    let input = "# Server\nserver: { host=localhost, port = 8080 # main\n tags=[ web,api ]\n\n\n -legacy }\nx   {}";
+    let expected = "# Server\nserver: {\n  host = localhost\n  port = 8080 # main\n  tags = [web, api]\n\n  -legacy\n}\nx {}\n";
+    assert_eq!(format(input, &FormatOptions::default()).unwrap(), expected);
+    assert!(format("x = {a = 1}", &FormatOptions::default()).is_err());
+}
+
+#[test]
+fn test_format_wraps_long_arrays() {
+    use crate::format::{format, FormatOptions};
+    let options = FormatOptions { indent_width: 2, max_width: 20 };
+    let input = "items = [alpha, beta, # second\n gamma, { name = d }]";
+    let expected = "items = [\n  alpha,\n  beta, # second\n  gamma,\n  {\n    name = d\n  },\n]\n";
+    assert_eq!(format(input, &options).unwrap(), expected);
+    assert_eq!(format("short = [1, 2]", &options).unwrap(), "short = [1, 2]\n");
+}
+
+// ── Source edits ────────────────────────────────────────────────────
+
+fn edited(source: &str, edits: Result<Vec<crate::edit::TextEdit>, crate::error::MOTLYError>) -> String {
+    crate::edit::apply_edits(source, &edits.unwrap())
+}
+
+fn string_value(s: &str) -> crate::ast::ScalarValue {
+    crate::ast::ScalarValue::String(s.to_string())
+}
+
+#[test]
+fn test_edit_set_value_in_place() {
+    use crate::edit::set_value;
+    let source = "# deploy\nimage {\n  name = web   # the app\n  tag = \"1.0\" # bump me\n}\n";
+    let edits = set_value(source, &["image", "tag"], &string_value("1.1")).unwrap();
+    assert_eq!(edits.len(), 1);
+    assert_eq!(edits[0].begin.line, 3);
+    assert_eq!(
+        crate::edit::apply_edits(source, &edits),
+        "# deploy\nimage {\n  name = web   # the app\n  tag = \"1.1\" # bump me\n}\n"
+    );
+
+    // The last writer is the one that counts
+    let source = "image.tag = a\nimage { tag = b }\n";
+    assert_eq!(edited(source, set_value(source, &["image", "tag"], &string_value("c"))), "image.tag = a\nimage { tag = c }\n");
+
+    // A heredoc is replaced up to `>>>`, keeping its line break
+    let source = "a = <<<\n  foo\n>>>\nb = 1";
+    assert_eq!(edited(source, set_value(source, &["a"], &string_value("x"))), "a = x\nb = 1");
+    let source = "a = <<<\r\n  foo\r\n>>>\r\nb = 1";
+    assert_eq!(edited(source, set_value(source, &["a"], &string_value("x"))), "a = x\r\nb = 1");
+    let source = "a = <<<\n  foo\n>>>";
+    assert_eq!(edited(source, set_value(source, &["a"], &string_value("x"))), "a = x");
+}
+
+#[test]
+fn test_edit_set_value_inserts_missing() {
+    use crate::edit::set_value;
+    let source = "image {\n  name = web\n}\n";
+    assert_eq!(
+        edited(source, set_value(source, &["image", "tag"], &string_value("has space"))),
+        "image {\n  name = web\n  tag = \"has space\"\n}\n"
+    );
+    let source = "image { name = web }";
+    assert_eq!(
+        edited(source, set_value(source, &["image", "tag"], &crate::ast::ScalarValue::Number(3.0))),
+        "image { name = web, tag = 3 }"
+    );
+    let source = "a = 1";
+    assert_eq!(
+        edited(source, set_value(source, &["image", "tag"], &crate::ast::ScalarValue::Boolean(true))),
+        "a = 1\nimage.tag = @true\n"
+    );
+    // A deleted value is not edited in place
+    let source = "image.tag = a\n-image\n";
+    assert_eq!(edited(source, set_value(source, &["image", "tag"], &string_value("b"))), "image.tag = a\n-image\nimage.tag = b\n");
+}
+
+#[test]
+fn test_edit_set_value_result_parses() {
+    use crate::edit::set_value;
+    let source = "a = 1";
+    for value in ["", "123", "@true", "two words", "quote\"and\\back", "line\nbreak"] {
+        let out = edited(source, set_value(source, &["a"], &string_value(value)));
+        let result = crate::parse_motly_0(&out, MOTLYDataNode::new());
+        assert!(result.errors.is_empty(), "{:?}", out);
+        let a = result.value.properties.as_ref().unwrap().get("a").unwrap();
+        match a {
+            MOTLYNode::Data(node) => assert_eq!(node.eq, Some(EqValue::Scalar(Scalar::String(value.to_string())))),
+            MOTLYNode::Ref { .. } => panic!("expected data"),
+        }
+    }
+}
+
+#[test]
+fn test_edit_add_and_delete_property() {
+    use crate::edit::{add_property, delete_property};
+    let source = "server {\n  # keep me\n  port = 80 # http\n  host = x, debug\n}\n";
+    assert_eq!(
+        edited(source, add_property(source, &["server"], "tls", &crate::ast::ScalarValue::Boolean(false))),
+        "server {\n  # keep me\n  port = 80 # http\n  host = x, debug\n  tls = @false\n}\n"
+    );
+    assert_eq!(
+        add_property(source, &["server"], "port", &string_value("1")).unwrap_err().code,
+        "edit-property-exists"
+    );
+    assert_eq!(
+        edited(source, delete_property(source, &["server", "port"])),
+        "server {\n  # keep me\n  host = x, debug\n}\n"
+    );
+    assert_eq!(
+        edited(source, delete_property(source, &["server", "host"])),
+        "server {\n  # keep me\n  port = 80 # http\n  debug\n}\n"
+    );
+    assert_eq!(edited(source, delete_property(source, &["server"])), "");
+    assert_eq!(delete_property(source, &["nope"]).unwrap_err().code, "edit-path-not-found");
+
+    // Heredocs, with and without a line break after `>>>`
+    let source = "b = 1\na = <<<\n  foo\n>>>\nc = 2\n";
+    assert_eq!(edited(source, delete_property(source, &["a"])), "b = 1\nc = 2\n");
+    let source = "b = 1\na = <<<\n  foo\n>>>";
+    assert_eq!(edited(source, delete_property(source, &["a"])), "b = 1\n");
+    let source = "b = 1\r\na = <<<\r\n  foo\r\n>>>\r\nc = 2\r\n";
+    assert_eq!(edited(source, delete_property(source, &["a"])), "b = 1\r\nc = 2\r\n");
+}
+
+// ── Error recovery ──────────────────────────────────────────────────
+
+#[test]
+fn test_recovery_reports_every_error() {
+    let input = "a = 1\nb = \"unclosed\nc = 3\nd = [1, 2\ne = 5\nf { g = = 1, h = 2 }\ni = @bogus\nj = 10\n";
+    let (stmts, errors) = crate::parser::parse_recovering(input, &Default::default());
+    let lines: Vec<usize> = errors.iter().map(|e| e.begin.line).collect();
+    assert_eq!(lines, vec![1, 4, 5, 6], "{:?}", errors);
+    let names: Vec<String> = stmts
+        .iter()
+        .map(|s| match s {
+            crate::ast::Statement::SetEq { path, .. } | crate::ast::Statement::UpdateProperties { path, .. } => path.join("."),
+            other => panic!("unexpected {:?}", other),
+        })
+        .collect();
+    assert_eq!(names, vec!["a", "c", "e", "f", "j"]);
+    assert_eq!(errors[0], crate::parser::parse(input).unwrap_err());
+}
+
+#[test]
+fn test_recovery_interpreter_runs_on_partial_statements() {
+    let result = crate::parse_motly_0("a = 1\nb = = 2\nc { d = 4, e = [ }\nf = $a", MOTLYDataNode::new());
+    assert_eq!(result.errors.len(), 2, "{:?}", result.errors);
+    let props = result.value.properties.as_ref().unwrap();
+    assert!(props.contains_key("a"));
+    assert!(!props.contains_key("b"));
+    assert!(matches!(props.get("f"), Some(MOTLYNode::Ref { .. })));
+    match props.get("c") {
+        Some(MOTLYNode::Data(c)) => assert!(c.properties.as_ref().unwrap().contains_key("d")),
+        other => panic!("expected c data node, got {:?}", other),
+    }
+}
+
+#[test]
+fn test_recovery_matches_parse_for_valid_input() {
+    for input in all_fixture_strings() {
+        if let Ok(expected) = crate::parser::parse(&input) {
+            let tree = crate::cst::parse(&input);
+            assert_eq!(tree.recovered_statements(), expected);
+        }
+    }
+}
+
+// ── Lexer ───────────────────────────────────────────────────────────
+
+#[test]
+fn test_lexer_token_kinds() {
+    use crate::lexer::{tokenize, TokenKind as K};
+    let input = "#! schema=app\n# note\nname = bare, -gone\n`a b`: { s = \"d\" t = 'e' }\nm := \"\"\"x\"\"\"\nn = '''y'''\nh = <<<\n  raw\n>>>\nv = [-1.5, @true, @false, @none, @2024-01-15T10:30:00Z, @env.HOME, $^^.p[0].q]\n-...\nx.y = ~";
+    let tokens: Vec<(K, &str)> = tokenize(input)
+        .iter()
+        .filter(|t| t.kind != K::Whitespace)
+        .map(|t| (t.kind, t.text(input)))
+        .collect();
+    assert_eq!(
+        tokens,
+        vec![
+            (K::Directive, "#! schema=app"),
+            (K::Comment, "# note"),
+            (K::Bare, "name"), (K::Equals, "="), (K::Bare, "bare"), (K::Comma, ","), (K::Minus, "-"), (K::Bare, "gone"),
+            (K::Backtick, "`a b`"), (K::Colon, ":"), (K::LBrace, "{"),
+            (K::Bare, "s"), (K::Equals, "="), (K::DoubleQuoted, "\"d\""),
+            (K::Bare, "t"), (K::Equals, "="), (K::SingleQuoted, "'e'"), (K::RBrace, "}"),
+            (K::Bare, "m"), (K::ColonEquals, ":="), (K::TripleDoubleQuoted, "\"\"\"x\"\"\""),
+            (K::Bare, "n"), (K::Equals, "="), (K::TripleSingleQuoted, "'''y'''"),
+            (K::Bare, "h"), (K::Equals, "="), (K::Heredoc, "<<<\n  raw\n>>>\n"),
+            (K::Bare, "v"), (K::Equals, "="), (K::LBracket, "["),
+            (K::Number, "-1.5"), (K::Comma, ","), (K::True, "@true"), (K::Comma, ","),
+            (K::False, "@false"), (K::Comma, ","), (K::None, "@none"), (K::Comma, ","),
+            (K::Date, "@2024-01-15T10:30:00Z"), (K::Comma, ","), (K::Env, "@env.HOME"), (K::Comma, ","),
+            (K::Reference, "$^^.p[0].q"), (K::RBracket, "]"),
+            (K::ClearAll, "-..."),
+            (K::Bare, "x"), (K::Dot, "."), (K::Bare, "y"), (K::Equals, "="), (K::Error, "~"),
+        ]
+    );
+}
+
+#[test]
+fn test_lexer_spans_cover_input() {
+    let input = "a = 1\n  b { c = \"x\" } # done\n";
+    let tokens = crate::lexer::tokenize(input);
+    let mut offset = 0;
+    for token in &tokens {
+        assert_eq!(token.span.begin.offset, offset);
+        assert_eq!(token.span.begin, crate::error::Position::from_offset(input, offset));
+        offset = token.span.end.offset;
+    }
+    assert_eq!(offset, input.len());
+    let b = tokens.iter().find(|t| t.text(input) == "b").unwrap();
+    assert_eq!((b.span.begin.line, b.span.begin.column), (1, 2));
+}
+
+#[test]
+fn test_lexer_tokens_end_where_the_parser_stops() {
+    use crate::cst::{NodeKind, SyntaxNode};
+    use crate::lexer::TokenKind;
+    // The lexer scans numbers, bare words and dates on its own; every value
+    // and name token must end exactly where the parser stops reading it
+    fn check(input: &str, node: &SyntaxNode) {
+        match node.kind {
+            // The CST groups tokens up to where the parser stopped, so a
+            // value is one token only if the lexer agrees on its end
+            NodeKind::Value => {
+                let tokens = node.tokens();
+                assert_eq!(tokens.len(), 1, "value {:?} in {:?} is {:?}", node.text(), input, tokens);
+                let (_, end) = crate::parser::parse_value_at(input, tokens[0].span.begin.offset).unwrap();
+                assert_eq!(end, tokens[0].span.end.offset, "value {:?} in {:?}", node.text(), input);
+            }
+            NodeKind::PropName => {
+                let tokens = node.tokens();
+                for (i, token) in tokens.iter().enumerate() {
+                    let kinds = if i % 2 == 0 { [TokenKind::Bare, TokenKind::Backtick] } else { [TokenKind::Dot; 2] };
+                    assert!(kinds.contains(&token.kind), "name {:?} in {:?} is {:?}", node.text(), input, tokens);
+                    if token.kind != TokenKind::Dot {
+                        let (_, end) = crate::parser::parse_identifier_at(input, token.span.begin.offset).unwrap();
+                        assert_eq!(end, token.span.end.offset, "name {:?} in {:?}", token.text, input);
+                    }
+                }
+            }
+            _ => {}
+        }
+        for child in node.child_nodes() {
+            check(input, child);
+        }
+    }
+    for input in all_fixture_strings() {
+        if crate::parser::parse(&input).is_ok() {
+            check(&input, &crate::cst::parse(&input).root);
+        }
+    }
+}
+
+// ── Resource limits ─────────────────────────────────────────────────
+
+fn limited_errors(input: &str, options: crate::interpreter::SessionOptions) -> Vec<String> {
+    let ctx = crate::ExecContext { parse_id: 0, options };
+    let result = crate::parse_motly(input, MOTLYDataNode::new(), &ctx);
+    result.errors.into_iter().map(|e| e.code).collect()
+}
+
+#[test]
+fn test_limit_depth() {
+    use crate::interpreter::SessionOptions;
+    let options = SessionOptions { max_depth: Some(3), ..SessionOptions::default() };
+    assert_eq!(limited_errors("a.b { c = [1] }", options.clone()), vec!["depth-limit-exceeded"]);
+    assert!(limited_errors("a { b { c = 1 } }", options.clone()).is_empty());
+    assert_eq!(limited_errors("a { b { c { d = 1 } } }", options.clone()), vec!["depth-limit-exceeded"]);
+    assert_eq!(limited_errors("a.b.c.d = 1", options.clone()), vec!["depth-limit-exceeded"]);
+    assert_eq!(limited_errors("a = [[[[1]]]]", options.clone()), vec!["depth-limit-exceeded"]);
+
+    // Far deeper than the stack could take without the limit
+    let deep = "a = ".to_string() + &"[".repeat(200_000);
+    let options = SessionOptions { max_depth: Some(64), ..SessionOptions::default() };
+    assert_eq!(limited_errors(&deep, options.clone()), vec!["depth-limit-exceeded"]);
+    let deep = "x = = 1\n".to_string() + &"a {".repeat(200_000);
+    assert_eq!(limited_errors(&deep, options), vec!["tag-parse-syntax-error", "depth-limit-exceeded"]);
+
+    // With a syntax error too, the limit still reports its own code
+    let options = SessionOptions { max_depth: Some(3), ..SessionOptions::default() };
+    assert_eq!(
+        limited_errors("a = = 1\nb = [[[[[[1]]]]]]", options.clone()),
+        vec!["tag-parse-syntax-error", "depth-limit-exceeded"]
+    );
+    // Dotted paths nest without brackets, inside blocks and arrays too
+    for input in ["!!\na.b.c.d = 1", "!!\na { b.c { d = 1 } }", "!!\na.b = [{ c = 1 }]", "!!\na.b = [[1]]"] {
+        assert_eq!(limited_errors(input, options.clone()), vec!["tag-parse-syntax-error", "depth-limit-exceeded"], "{}", input);
+    }
+    assert_eq!(limited_errors("!!\na.b.c = [1]", options), vec!["tag-parse-syntax-error", "depth-limit-exceeded"]);
+    let options = SessionOptions { max_depth: Some(3), ..SessionOptions::default() };
+    assert_eq!(limited_errors("!!\na.b = 1\nc { d = [1] }", options), vec!["tag-parse-syntax-error"]);
+
+    // A path far longer than the stack could take, after a syntax error
+    let segments: Vec<String> = (0..200_000).map(|i| format!("k{}", i)).collect();
+    let deep = format!("!!\n{} = 1", segments.join("."));
+    let options = SessionOptions { max_depth: Some(100), max_nodes: Some(1000), ..SessionOptions::default() };
+    let mut session = crate::MOTLYSession::new(options);
+    let codes: Vec<String> = session.parse(&deep).errors.into_iter().map(|e| e.code).collect();
+    assert_eq!(codes, vec!["tag-parse-syntax-error", "depth-limit-exceeded"]);
+    assert!(session.finish().is_empty());
+}
+
+#[test]
+fn test_limit_input_and_statements() {
+    use crate::interpreter::SessionOptions;
+    let options = SessionOptions { max_input_bytes: Some(10), ..SessionOptions::default() };
+    assert!(limited_errors("a = 1", options.clone()).is_empty());
+    assert_eq!(limited_errors("a = 1, b = 2, c = 3", options), vec!["input-limit-exceeded"]);
+
+    let options = SessionOptions { max_statements: Some(3), ..SessionOptions::default() };
+    assert!(limited_errors("a = 1, b { c = 2 }", options.clone()).is_empty());
+    assert_eq!(limited_errors("a = 1, b { c = 2, d = 3 }", options.clone()), vec!["statement-limit-exceeded"]);
+    assert_eq!(limited_errors("a = [{ x = 1 }, { y = 2 }, { z = 3 }]", options.clone()), vec!["statement-limit-exceeded"]);
+    assert_eq!(
+        limited_errors("e = = 5\na=1, b=2, c=3, d=4", options),
+        vec!["tag-parse-syntax-error", "statement-limit-exceeded"]
+    );
+}
+
+#[test]
+fn test_limit_nodes_and_clone_bytes() {
+    use crate::interpreter::SessionOptions;
+    let options = SessionOptions { max_nodes: Some(4), ..SessionOptions::default() };
+    assert!(limited_errors("a = 1, b = 2, c = 3", options.clone()).is_empty());
+    assert_eq!(limited_errors("a = 1, b = 2, c = 3, d = 4", options), vec!["node-limit-exceeded"]);
+
+    // Each line doubles the size of the tree
+    let mut input = "a0 = x".to_string();
+    for i in 1..40 {
+        input.push_str(&format!("\na{} {{ l := $a{}, r := $a{} }}", i, i - 1, i - 1));
+    }
+    let options = SessionOptions { max_clone_bytes: Some(1 << 20), ..SessionOptions::default() };
+    let errors = limited_errors(&input, options);
+    assert!(!errors.is_empty());
+    assert!(errors.iter().all(|code| code == "clone-limit-exceeded"), "{:?}", errors);
+
+    let options = SessionOptions { max_clone_bytes: Some(1 << 20), ..SessionOptions::default() };
+    assert!(limited_errors("a { b = 1 }\nc := $a", options).is_empty());
+}
+
+// ── Source identities ───────────────────────────────────────────────
+
+/// Call an FFI function returning a C string and take ownership of the text.
+fn ffi_string(ptr: *const u8) -> String {
+    let text = unsafe { std::ffi::CStr::from_ptr(ptr as *const std::ffi::c_char) }.to_str().unwrap().to_string();
+    unsafe { crate::dealloc(ptr as *mut u8, text.len() + 1) };
+    text
+}
+
+#[test]
+fn test_source_map_formats_file_line_col() {
+    use crate::source::SourceMap;
+    let mut sources = SourceMap::new();
+    sources.add(1, "conf/base.motly", "a = 1\nb = [\n");
+    let (_, errors) = crate::session_finish(&["x = 1", "a = 1\nb = [\n"], crate::SessionOptions::default());
+    assert_eq!(errors.len(), 1);
+    assert_eq!(errors[0].parse_id, Some(1));
+    assert_eq!(sources.format_position(errors[0].parse_id, &errors[0].begin), "conf/base.motly:3:1");
+    assert_eq!(sources.format_position(Some(7), &errors[0].begin), "<parse 7>:3:1");
+    assert_eq!(sources.format_position(None, &errors[0].begin), "3:1");
+    assert_eq!(sources.line_text(1, &errors[0].begin), None);
+    assert_eq!(sources.get(1).unwrap().text.lines().nth(1), Some("b = ["));
+}
+
+#[test]
+fn test_session_named_parses_report_sources() {
+    let id = crate::wasm_session_new();
+    let parse = |name: &str, src: &str| {
+        ffi_string(unsafe { crate::wasm_session_parse_named(id, name.as_ptr(), name.len(), src.as_ptr(), src.len()) })
+    };
+    let first = parse("base.motly", "a = 1");
+    assert_eq!(first, r#"{"parseId":0,"errors":[]}"#);
+    let second = parse("site.motly", "b = 2\nc = [");
+    assert!(second.contains(r#""parseId":1,"source":"site.motly","at":"site.motly:2:6""#), "{}", second);
+
+    let finish = ffi_string(crate::wasm_session_finish(id));
+    assert_eq!(finish, "[]");
+    crate::wasm_session_free(id);
+
+    let id = crate::wasm_session_new();
+    let src = "a = 1\no {\n  r = $missing\n}";
+    let name = "refs.motly";
+    ffi_string(unsafe { crate::wasm_session_parse_named(id, name.as_ptr(), name.len(), src.as_ptr(), src.len()) });
+    let finish = ffi_string(crate::wasm_session_finish(id));
+    assert!(finish.contains(r#""source":"refs.motly","at":"refs.motly:3:3""#), "{}", finish);
+    crate::wasm_session_free(id);
+}
+
+// ── Native session + Mot ────────────────────────────────────────────
+
+fn session_mot(inputs: &[&str], env: Option<&std::collections::HashMap<String, String>>) -> crate::Mot {
+    let mut session = crate::MOTLYSession::default();
+    for input in inputs {
+        assert!(session.parse(input).errors.is_empty());
+    }
+    let errors = session.finish();
+    assert!(errors.is_empty(), "{:?}", errors);
+    session.get_mot(env)
+}
+
+#[test]
+fn test_session_lifecycle() {
+    let mut session = crate::MOTLYSession::default();
+    assert_eq!(session.parse("a = 1").parse_id, 0);
+    let second = session.parse_named("site.motly", "b = $a\nc = [");
+    assert_eq!(second.parse_id, 1);
+    assert_eq!(second.errors.len(), 1);
+    assert_eq!(session.sources().name(1), "site.motly");
+
+    assert!(session.finish().is_empty());
+    assert!(session.value().properties.as_ref().unwrap().contains_key("b"));
+    assert!(session.validate_references().is_empty());
+    assert!(session.validate_schema().is_empty());
+
+    assert_eq!(session.parse("d = 1").errors[0].code, "session-error");
+    assert_eq!(session.finish()[0].code, "session-error");
+    session.reset();
+    assert!(session.value().properties.is_none());
+}
+
+#[test]
+fn test_mot_values_and_navigation() {
+    let mot = session_mot(
+        &["server { host = localhost, port = 8080, tls = @true }\nstarted = @2024-01-15", "tags = [a, b]\nmixed = [a, 1]"],
+        None,
+    );
+    assert!(mot.exists());
+    assert_eq!(mot.get("server").and_then(|m| m.get("host")).and_then(|m| m.text()), Some("localhost"));
+    assert_eq!(mot.get_path(&["server", "port"]).and_then(|m| m.numeric()), Some(8080.0));
+    assert_eq!(mot.get_path(&["server", "tls"]).and_then(|m| m.boolean()), Some(true));
+    assert_eq!(mot.get("started").and_then(|m| m.date()), Some("2024-01-15"));
+    assert_eq!(mot.get("started").and_then(|m| m.value_type()), Some(crate::ValueType::Date));
+    assert_eq!(mot.get("server").and_then(|m| m.value_type()), None);
+    assert_eq!(mot.get("tags").and_then(|m| m.texts()), Some(vec!["a", "b"]));
+    assert_eq!(mot.get("mixed").and_then(|m| m.texts()), None);
+    assert!(mot.has(&["server", "host"]));
+    assert!(!mot.has(&["server", "missing"]));
+    assert_eq!(mot.keys().collect::<Vec<_>>(), vec!["mixed", "server", "started", "tags"]);
+}
+
+#[test]
+fn test_mot_resolves_refs_env_and_deletes() {
+    let mut env = std::collections::HashMap::new();
+    env.insert("HOST".to_string(), "db.internal".to_string());
+    let mot = session_mot(
+        &[
+            "defaults { timeout = 30 }\nhost = @env.HOST\nmissing = @env.NOPE",
+            "server {\n  port = 1\n  endpoints { api { url = $^^.port } }\n}\napi = $defaults\nlist = [$defaults.timeout]\ngone = 1\n-gone",
+        ],
+        Some(&env),
+    );
+    assert_eq!(mot.get("host").and_then(|m| m.text()), Some("db.internal"));
+    assert!(mot.get("missing").is_some_and(|m| m.value_type().is_none()));
+    assert_eq!(mot.get_path(&["api", "timeout"]).and_then(|m| m.numeric()), Some(30.0));
+    assert_eq!(mot.get("list").and_then(|m| m.numerics()), Some(vec![30.0]));
+    assert_eq!(mot.get_path(&["server", "endpoints", "api", "url"]).and_then(|m| m.numeric()), Some(1.0));
+    assert!(!mot.has(&["gone"]));
+}
+
+#[test]
+fn test_mot_reference_cycle_terminates() {
+    let mut session = crate::MOTLYSession::default();
+    session.parse("a { name = x, self = $a }\nb = $c\nc = $b");
+    session.finish();
+    let mot = session.get_mot(None);
+    assert_eq!(mot.get_path(&["a", "name"]).and_then(|m| m.text()), Some("x"));
+    assert!(!mot.has(&["a", "self"]));
+    assert!(!mot.has(&["b"]));
+}
+
+#[test]
+fn test_tracked_mot_reports_unread_properties() {
+    let mut session = crate::MOTLYSession::default();
+    session.parse(
+        "defaults { timeout = 30, retries = 3 }\napi = $defaults\nserver {\n  host = localhost\n  prot = 8080\n}\nservers = [{ name = a, weight = 1 }]\ntags = [x, y]\nlegacy { a = 1, b = 2 }\nalias = $legacy\ngone = 1\n-gone",
+    );
+    assert!(session.finish().is_empty());
+    let mot = session.get_mot(None);
+    let tracker = crate::ReadTracker::new();
+    let config = tracker.track(&mot);
+
+    assert_eq!(config.get_path(&["server", "host"]).and_then(|m| m.text()), Some("localhost"));
+    assert_eq!(config.get_path(&["server", "port"]).and_then(|m| m.numeric()), None);
+    assert_eq!(config.get_path(&["api", "timeout"]).and_then(|m| m.numeric()), Some(30.0));
+    let servers = config.get("servers").and_then(|m| m.values()).unwrap();
+    assert_eq!(servers[0].get("name").and_then(|m| m.text()), Some("a"));
+    assert_eq!(config.get("tags").and_then(|m| m.texts()), Some(vec!["x", "y"]));
+    assert!(tracker.was_read(&["servers".to_string(), "[0]".to_string(), "name".to_string()]));
+
+    let unread: Vec<(String, usize)> = tracker
+        .unread(session.value())
+        .iter()
+        .map(|u| (crate::diff::format_path(&u.path), u.location.map_or(0, |l| l.begin.line)))
+        .collect();
+    assert_eq!(
+        unread,
+        vec![
+            ("alias".to_string(), 9),
+            ("defaults.retries".to_string(), 0),
+            ("legacy".to_string(), 8),
+            ("server.prot".to_string(), 4),
+            ("servers[0].weight".to_string(), 6),
+        ]
+    );
+
+    // Enumerating reads each property; handing out the Mot reads everything
+    assert_eq!(config.keys().len(), 7);
+    config.get("legacy").unwrap().mot();
+    config.get("server").unwrap().entries();
+    assert_eq!(tracker.unread(session.value()).len(), 2);
+}
+
+// ── Includes ────────────────────────────────────────────────────────
+
+#[test]
+fn test_include_directives() {
+    let (includes, errors) = crate::include::include_directives(
+        "#! schema=app\n#! include=\"base.motly\"\n#!include=\"db.motly\" at=\"services.db\"\n#! include=base.motly\na = 1\n#! included_by=ops\n#! include = \"spaced.motly\"\n",
+    );
+    let targets: Vec<(&str, Vec<String>)> = includes.iter().map(|i| (i.target.as_str(), i.at.clone())).collect();
+    assert_eq!(
+        targets,
+        vec![
+            ("base.motly", vec![]),
+            ("db.motly", vec!["services".to_string(), "db".to_string()]),
+            ("spaced.motly", vec![]),
+        ]
+    );
+    assert_eq!(includes[0].span.begin.line, 1);
+    assert_eq!(errors.len(), 1);
+    assert_eq!(errors[0].code, "invalid-include");
+    assert_eq!(errors[0].begin.line, 3);
+}
+
+#[test]
+fn test_include_composes_sources() {
+    use crate::include::SourceLoader;
+    let mut loader = crate::MemoryLoader::new();
+    loader.add("shared/base.motly", "name = base\nport = 80");
+    loader.add("shared/db.motly", "#! include=\"base.motly\" at=defaults\nhost = localhost\nport = 5432");
+    loader.add("env/prod.motly", "#! include=\"../shared/base.motly\"\n#! include=\"../shared/db.motly\" at=database\nport = 443");
+
+    let mut session = crate::MOTLYSession::default();
+    let results = session.parse_with_includes("env/prod.motly", &loader.load("env/prod.motly").unwrap(), &loader);
+    assert_eq!(results.iter().map(|r| r.parse_id).collect::<Vec<_>>(), vec![0, 1, 2, 3]);
+    assert!(results.iter().all(|r| r.errors.is_empty()), "{:?}", results);
+    assert_eq!(session.sources().name(3), "shared/base.motly");
+    assert!(session.finish().is_empty());
+
+    let mot = session.get_mot(None);
+    assert_eq!(mot.get("port").and_then(|m| m.numeric()), Some(443.0));
+    assert_eq!(mot.get("name").and_then(|m| m.text()), Some("base"));
+    assert_eq!(mot.get_path(&["database", "port"]).and_then(|m| m.numeric()), Some(5432.0));
+    assert_eq!(mot.get_path(&["database", "defaults", "port"]).and_then(|m| m.numeric()), Some(80.0));
+    let db = session.value().properties.as_ref().unwrap()["database"].as_data_node().unwrap();
+    assert_eq!(db.properties.as_ref().unwrap()["host"].as_data_node().unwrap().location.unwrap().parse_id, 2);
+}
+
+#[test]
+fn test_include_cycle_and_missing() {
+    use crate::include::SourceLoader;
+    let mut loader = crate::MemoryLoader::new();
+    loader.add("a.motly", "#! include=\"./b.motly\"\na = 1");
+    loader.add("b.motly", "#! include=\"a.motly\"\n#! include=\"nope.motly\"\nb = 1");
+
+    let mut session = crate::MOTLYSession::default();
+    let results = session.parse_with_includes("a.motly", &loader.load("a.motly").unwrap(), &loader);
+    assert!(results[0].errors.is_empty());
+    let codes: Vec<&str> = results[1].errors.iter().map(|e| e.code.as_str()).collect();
+    assert_eq!(codes, vec!["include-cycle", "include-not-found"]);
+    assert_eq!(results[1].errors[0].parse_id, Some(1));
+    assert_eq!(results[1].errors[1].begin.line, 1);
+    assert!(session.finish().is_empty());
+    assert!(session.get_mot(None).has(&["b"]));
+}
+
+// ── Layers ──────────────────────────────────────────────────────────
+
+#[test]
+fn test_layers_record_last_writer() {
+    let mut session = crate::MOTLYSession::default();
+    session.parse("unlayered = 1");
+    session.set_layer("defaults");
+    session.parse("server { host = localhost, port = 80 }\nreplicas = 1\nfeatures { a = @true, b = @true }");
+    session.set_layer("prod");
+    session.parse("server { host = prod.example.com }\nreplicas = 3\nfeatures: { a = @false }");
+    session.set_layer("local");
+    session.parse("server.port = 8080");
+    assert!(session.finish().is_empty());
+
+    assert_eq!(session.layers(), vec!["defaults", "prod", "local"]);
+    assert_eq!(session.layer_of(&["unlayered"]), None);
+    assert_eq!(session.writer_of(&["unlayered"]), Some(0));
+    assert_eq!(session.layer_of(&["server", "host"]), Some("prod"));
+    assert_eq!(session.layer_of(&["server", "port"]), Some("local"));
+    assert_eq!(session.layer_of(&["server"]), Some("defaults"));
+    assert_eq!(session.layer_of(&["features"]), Some("prod"));
+    assert_eq!(session.layer_of(&["features", "b"]), None);
+}
+
+#[test]
+fn test_value_without_layer() {
+    let mut session = crate::MOTLYSession::default();
+    session.set_layer("defaults");
+    session.parse("replicas = 1\nname = app\nlabel = $name\nbase_port = 80");
+    session.set_layer("prod");
+    session.parse("replicas = 3\nname = prod_app\nport = $base_port");
+    session.finish();
+
+    let without_prod = crate::build_mot(&session.value_without_layer("prod").0, None);
+    assert_eq!(without_prod.get("replicas").and_then(|m| m.numeric()), Some(1.0));
+    assert_eq!(without_prod.get("label").and_then(|m| m.text()), Some("app"));
+    assert_eq!(session.get_mot(None).get("label").and_then(|m| m.text()), Some("prod_app"));
+
+    let (value, errors) = session.value_without_layer("defaults");
+    assert_eq!(errors.len(), 1);
+    assert_eq!(errors[0].code, "unresolved-reference");
+    assert!(errors[0].message.contains("base_port"));
+    assert!(!value.properties.unwrap().contains_key("label"));
+}
+
+#[test]
+fn test_provenance_trace_history() {
+    use crate::interpreter::TransformerOp;
+    let options = crate::SessionOptions { trace: true, ..crate::SessionOptions::default() };
+    let mut session = crate::MOTLYSession::new(options);
+    session.parse("defaults { server { port = 80 } }\nserver := $defaults.server\nother { copy := $^.defaults }");
+    session.parse("server.port = 8080\nserver { port = 9090 }");
+    assert!(session.finish().is_empty());
+
+    let path = ["server".to_string(), "port".to_string()];
+    let history = session.provenance().history(&path);
+    let summary: Vec<(String, u32, Option<&str>)> = history
+        .iter()
+        .map(|(at, entry)| (at.join("."), entry.parse_id, entry.clone_source.as_deref()))
+        .collect();
+    assert_eq!(
+        summary,
+        vec![
+            ("server".to_string(), 0, Some("defaults.server")),
+            ("server.port".to_string(), 1, None),
+            ("server.port".to_string(), 1, None),
+        ]
+    );
+    assert!(matches!(history[0].1.op, TransformerOp::Clone { .. }));
+    assert_eq!(history[2].1.span.begin.line, 1);
+
+    let copy = session.provenance().history(&["other".to_string(), "copy".to_string()]);
+    assert_eq!(copy[0].1.clone_source.as_deref(), Some("defaults"));
+
+    // Tracing is opt-in
+    let mut session = crate::MOTLYSession::default();
+    session.parse("a = 1");
+    session.finish();
+    assert!(session.provenance().history(&["a".to_string()]).is_empty());
+    assert_eq!(session.writer_of(&["a"]), Some(0));
+}
+
+// ── Spans ───────────────────────────────────────────────────────────
+
+#[test]
+fn test_spans_keys_values_and_blocks() {
+    use crate::spans::SpanPart;
+    let src = "server {\n  host = \"example.com\"\n  ports = [80, { tls = @true }]\n}\nserver.host = other\n";
+    let mut table = crate::SpanTable::new();
+    table.add(4, src, &[]);
+    let text = |span: Option<crate::ast::Span>| span.map(|s| &src[s.begin.offset..s.end.offset]);
+
+    let host = table.get(&["server", "host"]);
+    assert_eq!(host.len(), 2);
+    assert_eq!(host[0].parse_id, 4);
+    assert_eq!(text(host[0].key), Some("host"));
+    assert_eq!(text(host[0].value), Some("\"example.com\""));
+    assert_eq!(text(Some(host[1].statement)), Some("server.host = other"));
+    assert_eq!(text(host[1].key), Some("host"));
+
+    let server = table.get(&["server"]);
+    assert_eq!(server.len(), 2);
+    assert!(text(server[0].properties).unwrap().starts_with("{\n  host"));
+    assert_eq!(server[1].value, None);
+    assert_eq!(text(table.definition(&["server"]).unwrap().key), Some("server"));
+
+    let element = table.get(&["server", "ports", "[1]"]);
+    assert_eq!(text(element[0].properties), Some("{ tls = @true }"));
+    assert_eq!(text(table.get(&["server", "ports", "[1]", "tls"])[0].value), Some("@true"));
+
+    let other = src.find("other").unwrap() + 2;
+    let (path, _, part) = table.find(4, other).unwrap();
+    assert_eq!((path.join("."), part), ("server.host".to_string(), SpanPart::Value));
+    let dotted = src.rfind("server").unwrap() + 1;
+    let (path, _, part) = table.find(4, dotted).unwrap();
+    assert_eq!((path.join("."), part), ("server".to_string(), SpanPart::Key));
+    assert!(table.find(5, dotted).is_none());
+}
+
+#[test]
+fn test_session_spans_follow_include_mounts() {
+    let mut loader = crate::MemoryLoader::new();
+    loader.add("db.motly", "host = localhost");
+    let main = "#! include=\"db.motly\" at=database\nname = app";
+    let options = crate::SessionOptions { spans: true, ..crate::SessionOptions::default() };
+    let mut session = crate::MOTLYSession::new(options);
+    session.parse_with_includes("main.motly", main, &loader);
+    assert!(session.finish().is_empty());
+    let host = session.spans().definition(&["database", "host"]).unwrap();
+    assert_eq!(session.sources().name(host.parse_id), "db.motly");
+    assert_eq!(session.spans().get(&["name"])[0].parse_id, 0);
+
+    // Spans are opt-in
+    let mut session = crate::MOTLYSession::default();
+    session.parse("a = 1");
+    assert!(session.spans().get(&["a"]).is_empty());
+}
+
+// ── Structural diff ─────────────────────────────────────────────────
+
+fn diff_sources(old: &str, new: &str) -> Vec<crate::Change> {
+    let value = |src: &str| {
+        let (value, errors) = crate::session_finish(&[src], crate::SessionOptions::default());
+        assert!(errors.is_empty(), "{:?}", errors);
+        value
+    };
+    crate::diff(&value(old), &value(new))
+}
+
+#[test]
+fn test_diff_ignores_order_and_formatting() {
+    let changes = diff_sources("a = 1\nb { c = x, d = [1, 2] }", "b {\n  d = [1, 2]\n  c = x\n}\na = 1\ngone = 1\n-gone");
+    assert!(changes.is_empty(), "{:?}", changes);
+}
+
+#[test]
+fn test_diff_reports_typed_changes() {
+    use crate::ChangeKind;
+    let changes = diff_sources(
+        "server {\n  port = 80\n  tls = @false\n  tags = [a, b, c]\n}\nalias = $server.port\nold = 1",
+        "server {\n  port = 8080\n  tls = \"no\"\n  tags = [c, a, b, d]\n}\nalias = $server.tls\nnew = 1",
+    );
+    let summary: Vec<(String, &str)> =
+        changes.iter().map(|c| (crate::diff::format_path(&c.path), c.kind.name())).collect();
+    assert_eq!(
+        summary,
+        vec![
+            ("alias".to_string(), "link-retargeted"),
+            ("new".to_string(), "added"),
+            ("old".to_string(), "removed"),
+            ("server.port".to_string(), "value-changed"),
+            ("server.tags[0]".to_string(), "element-moved"),
+            ("server.tags[3]".to_string(), "element-inserted"),
+            ("server.tls".to_string(), "type-changed"),
+        ]
+    );
+    assert_eq!(changes[4].kind, ChangeKind::ElementMoved { from: 2, to: 0 });
+    let port = &changes[3];
+    assert_eq!(port.before_location().unwrap().begin.line, 1);
+    assert_eq!(crate::diff::summary(port.after.as_ref().unwrap()), "8080");
+    assert_eq!(crate::diff::type_name(changes[6].before.as_ref().unwrap()), "boolean");
+}
+
+#[test]
+fn test_diff_compares_replaced_elements_in_place() {
+    let changes = diff_sources("list = [{ name = a, port = 1 }, x]", "list = [{ name = a, port = 2 }, x]");
+    assert_eq!(changes.len(), 1, "{:?}", changes);
+    assert_eq!(crate::diff::format_path(&changes[0].path), "list[0].port");
+    assert_eq!(changes[0].kind, crate::ChangeKind::ValueChanged);
+
+    let json = crate::json::changes_to_json(&changes, None, None);
+    let parsed: serde_json::Value = serde_json::from_str(&json).unwrap();
+    assert_eq!(parsed[0]["kind"], "value-changed");
+    assert_eq!(parsed[0]["path"], serde_json::json!(["list", "[0]", "port"]));
+    assert_eq!(parsed[0]["after"], serde_json::json!({"eq": 2}));
+    assert_eq!(parsed[0]["afterLocation"]["parseId"], 0);
+}
+
+// ── Writer and patches ──────────────────────────────────────────────
+
+fn value_of(src: &str) -> MOTLYDataNode {
+    value_of_all(&[src])
+}
+
+fn value_of_all(sources: &[&str]) -> MOTLYDataNode {
+    let (mut value, errors) = crate::session_finish(sources, crate::SessionOptions::default());
+    assert!(errors.is_empty(), "{:?}", errors);
+    strip_locations(&mut value);
+    value
+}
+
+/// The patch from `old` to `new`, checked to rebuild `new` exactly.
+fn patch_sources(old: &str, new: &str) -> String {
+    let (a, b) = (value_of(old), value_of(new));
+    let patch = crate::patch_source(&a, &b);
+    let ctx = crate::ExecContext { parse_id: 1, options: crate::SessionOptions::default() };
+    let mut result = crate::parse_motly(&patch, a, &ctx);
+    assert!(result.errors.is_empty(), "{:?} in patch:\n{}", result.errors, patch);
+    strip_locations(&mut result.value);
+    // A root cleared with `-...` has empty properties, one never set has
+    // none; no statement tells them apart
+    let root = |mut tree: crate::tree::MOTLYDataNode| {
+        if tree.properties.as_ref().is_some_and(|p| p.is_empty()) {
+            tree.properties = None;
+        }
+        tree
+    };
+    assert_eq!(root(result.value), b, "patch:\n{}", patch);
+    // The same, as a later parse of the session that built the old tree
+    assert_eq!(root(value_of_all(&[old, &patch])), b, "patch:\n{}", patch);
+    assert!(crate::diff(&value_of_all(&[old, &patch]), &b).is_empty(), "patch:\n{}", patch);
+    patch
+}
+
+#[test]
+fn test_writer_round_trips_fixture_trees() {
+    for input in all_fixture_strings() {
+        let (mut expected, errors) = crate::session_finish(&[input.as_str()], crate::SessionOptions::default());
+        if !errors.is_empty() {
+            continue;
+        }
+        strip_locations(&mut expected);
+        let written = crate::to_motly(&expected);
+        let (mut rebuilt, errors) = crate::session_finish(&[written.as_str()], crate::SessionOptions::default());
+        assert!(errors.is_empty(), "{:?} for {:?} written as:\n{}", errors, input, written);
+        strip_locations(&mut rebuilt);
+        assert_eq!(rebuilt, expected, "{:?} written as:\n{}", input, written);
+    }
+}
+
+#[test]
+fn test_patch_changes_only_what_differs() {
+    assert_eq!(patch_sources("a = 1\nb { c = x }", "b { c = x }\na = 1"), "");
+    assert_eq!(patch_sources("server { port = 80, host = h }", "server { port = 8080, host = h }"), "server.port = 8080\n");
+    assert_eq!(patch_sources("a = 1 { b = 2 }", "a = 3 { b = 2 }"), "a = 3\n");
+    assert_eq!(patch_sources("a = 1", "a = 1\nb.c.d = @true"), "b.c.d = @true\n");
+    assert_eq!(patch_sources("a = 1\nb = 2", "a = 1\nb = 2\n-b"), "-b\n");
+}
+
+#[test]
+fn test_patch_removes_keys_and_changes_kinds() {
+    let patch = patch_sources("s { keep = 1, gone = 2 }\nt = 5", "s { keep = 1 }\nt = 5");
+    assert_eq!(patch, "s: {\n  keep = 1\n}\n");
+    patch_sources("a = 1\nb = 2", "a = 1");
+    // Everything removed
+    assert_eq!(patch_sources("a = 1\nb { c = 2 }", ""), "-...\n");
+    patch_sources("x = 1\ny = $x", "x = 1\ny = 2 { z = 3 }");
+    patch_sources("x = 1\ny = 2 { z = 3 }", "x = 1\ny = $x");
+    patch_sources("x = 1\ny = $x", "x = 1\nw = 2\ny = $w");
+    patch_sources("a = 1 { b = 2 }", "a { -... }");
+    patch_sources("a { -... }", "a = 1 { -... }");
+    patch_sources("a = 1 { b = 2 }", "a = @none { b = 2 }");
+    patch_sources("a = 1 { b = 2 }", "a = 1\na: {}");
+    patch_sources("a = 1\n-b", "a = 1\nb = 2");
+    patch_sources("d = @2024-01-15\ne = @env.HOME", "d = @2024-02-01\ne = @env.USER");
+}
+
+#[test]
+fn test_patch_rewrites_arrays_whole() {
+    let patch = patch_sources("list = [1, 2] { n = 2 }", "list = [1, 2, { x = $list.n }] { n = 2 }");
+    assert!(patch.starts_with("list = [") && patch.contains("x = $list.n"), "{}", patch);
+    assert!(!patch.contains("n = 2"), "{}", patch);
+    patch_sources("list = [a, { b = 1 }]", "list = [{ b = 2 }, a]");
+    patch_sources("refs = [$a, $b.c]\na = 1\nb.c = 2", "refs = [$b.c]\na = 1\nb.c = 2");
+}
+
+// ── Three-way merge ─────────────────────────────────────────────────
+
+fn merge_sources(base: &str, ours: &str, theirs: &str) -> crate::MergeResult {
+    let mut result = crate::merge(&value_of(base), &value_of(ours), &value_of(theirs));
+    strip_locations(&mut result.value);
+    result
+}
+
+#[test]
+fn test_merge_combines_independent_edits() {
+    let result = merge_sources(
+        "server { port = 80, host = h }\nname = x\nold = 1",
+        "server = prod { port = 80, host = h }\nname = x\nold = 1\nmine = 1",
+        "server { port = 80, host = h2, tls = @true }\nname = y",
+    );
+    assert!(result.conflicts.is_empty(), "{:?}", result.conflicts);
+    let expected = value_of("server = prod { port = 80, host = h2, tls = @true }\nname = y\nmine = 1");
+    assert_eq!(result.value, expected);
+
+    // Both sides adding the same node merge its contents
+    let result = merge_sources("", "a { b = 1 }", "a { c = 2 }");
+    assert!(result.conflicts.is_empty());
+    assert_eq!(result.value, value_of("a { b = 1, c = 2 }"));
+}
+
+#[test]
+fn test_merge_reports_conflicts_and_keeps_ours() {
+    use crate::ConflictKind;
+    let (base, ours, theirs) = (
+        "port = 80\nlist = [1, 2]\ngone = 1 { x = 1 }\nlink = $port",
+        "port = 8080\nlist = [1, 2, 3]\nlink = $list",
+        "port = 9090\nlist = [2, 1]\ngone = 1 { x = 2 }\nlink = 5",
+    );
+    let located = |src: &str| crate::session_finish(&[src], crate::SessionOptions::default()).0;
+    let result = crate::merge(&located(base), &located(ours), &located(theirs));
+    let summary: Vec<(String, ConflictKind)> =
+        result.conflicts.iter().map(|c| (crate::diff::format_path(&c.path), c.kind)).collect();
+    assert_eq!(
+        summary,
+        vec![
+            ("gone".to_string(), ConflictKind::Node),
+            ("link".to_string(), ConflictKind::Node),
+            ("list".to_string(), ConflictKind::Value),
+            ("port".to_string(), ConflictKind::Value),
+        ]
+    );
+    let mut kept = result.value.clone();
+    strip_locations(&mut kept);
+    assert_eq!(kept, value_of(ours));
+    assert_eq!(result.conflicts[3].theirs_location().unwrap().begin.line, 0);
+    assert!(result.conflicts[0].ours.is_none());
+}
+
+// ── Path queries ────────────────────────────────────────────────────
+
+const QUERY_SOURCE: &str = "defaults { region = us, port = 80 }
+servers {
+  a := $defaults
+  a.host = \"a.example.com\"
+  b { region = eu, host = \"b.example.com\", port = 8080 }
+  c = $servers.a
+}
+items = [{ name = x, tags = [1, 2] }, { name = y, up = $^^^.defaults }]
+main = $servers.b";
+
+fn query_paths(q: &str) -> Vec<String> {
+    let matches = crate::query(&value_of(QUERY_SOURCE), q).unwrap();
+    matches.iter().map(|m| crate::diff::format_path(&m.path)).collect()
+}
+
+#[test]
+fn test_query_paths_wildcards_and_descent() {
+    assert_eq!(query_paths("servers.b.port"), vec!["servers.b.port"]);
+    assert_eq!(query_paths("$items[1].name"), vec!["items[1].name"]);
+    assert_eq!(query_paths("servers.*.port"), vec!["servers.a.port", "servers.b.port", "servers.c.port"]);
+    assert_eq!(query_paths("items[*].name"), vec!["items[0].name", "items[1].name"]);
+    assert_eq!(query_paths("..host"), vec!["servers.a.host", "servers.b.host"]);
+    assert_eq!(query_paths("items[0].tags[*]"), vec!["items[0].tags[0]", "items[0].tags[1]"]);
+    assert!(query_paths("servers.d").is_empty());
+}
+
+#[test]
+fn test_query_filters_and_follows_links() {
+    assert_eq!(query_paths("servers[?region == us].host"), vec!["servers.a.host", "servers.c.host"]);
+    assert_eq!(query_paths("servers[?region != \"us\"]"), vec!["servers.b"]);
+    assert_eq!(query_paths("servers[?port >= 80]"), vec!["servers.a", "servers.b", "servers.c"]);
+    assert_eq!(query_paths("servers[?host]"), vec!["servers.a", "servers.b", "servers.c"]);
+    assert_eq!(query_paths("items[?name == y].name"), vec!["items[1].name"]);
+
+    let matches = crate::query(&value_of(QUERY_SOURCE), "main").unwrap();
+    let port = matches[0].node.properties.as_ref().unwrap().get("port").unwrap();
+    assert_eq!(port.as_data_node().unwrap().eq, Some(EqValue::Scalar(Scalar::Number(8080.0))));
+    let matches = crate::query(&value_of(QUERY_SOURCE), "items[1]").unwrap();
+    let up = matches[0].node.properties.as_ref().unwrap().get("up").unwrap();
+    assert!(matches!(up, MOTLYNode::Data(data) if data.properties.as_ref().unwrap().contains_key("region")));
+
+    let written = crate::writer::query_matches_to_motly(&crate::query(&value_of(QUERY_SOURCE), "servers.*.host").unwrap());
+    assert_eq!(written, "servers.a.host = \"a.example.com\"\nservers.b.host = \"b.example.com\"\nservers.c.host = \"a.example.com\"\n");
+
+    let err = crate::Query::parse("servers[?port >]").unwrap_err();
+    assert_eq!((err.code.as_str(), err.begin.column), ("invalid-query", 15));
+}
+
+// ── YAML ────────────────────────────────────────────────────────────
+
+#[cfg(feature = "yaml")]
+#[test]
+fn test_yaml_maps_types_anchors_and_merges() {
+    let yaml = "defaults: &defaults\n  region: us\n  port: 80\nservers:\n  a:\n    <<: *defaults\n    port: 8080\n  b: *defaults\n  list: [1, \"two\", 2024-01-15, true]\nhome: !env HOME\nboth:\n  $value: 5\n  extra: x\nempty:\n";
+    let source = crate::yaml::from_yaml(yaml).unwrap();
+    assert!(source.contains("a := $defaults"), "{}", source);
+    assert!(source.contains("b = $defaults"), "{}", source);
+    let (mut tree, errors) = crate::yaml::parse_yaml(yaml);
+    assert!(errors.is_empty(), "{:?}", errors);
+    strip_locations(&mut tree);
+    let expected = value_of(
+        "defaults { region = us, port = 80 }
+servers {
+  a := $defaults
+  a.port = 8080
+  b = $defaults
+  list = [1, two, @2024-01-15, @true]
+}
+home = @env.HOME
+both = 5 { extra = x }
+empty",
+    );
+    assert_eq!(tree, expected);
+
+    // Sequences in sequences are nested arrays, both ways
+    let (mut tree, errors) = crate::yaml::parse_yaml("a: [[1, 2], [x, [y]], {b: 3}]");
+    assert!(errors.is_empty(), "{:?}", errors);
+    strip_locations(&mut tree);
+    assert_eq!(tree, value_of("a = [[1, 2], [x, [y]], { b = 3 }]"));
+    let (mut back, _) = crate::yaml::parse_yaml(&crate::yaml::to_yaml(&tree));
+    strip_locations(&mut back);
+    assert_eq!(back, tree);
+
+    let err = crate::yaml::from_yaml("a:\n  $value: [1]").unwrap_err();
+    assert_eq!((err.code.as_str(), err.begin.line, err.begin.column), ("invalid-yaml", 1, 10));
+}
+
+#[cfg(feature = "yaml")]
+#[test]
+fn test_yaml_export_round_trips() {
+    let sample = value_of(include_str!("../test-data/k8s-deployment-sample.motly"));
+    let yaml = crate::yaml::to_yaml(&sample);
+    let (mut back, errors) = crate::yaml::parse_yaml(&yaml);
+    assert!(errors.is_empty(), "{:?}", errors);
+    strip_locations(&mut back);
+    assert_eq!(back, sample, "{}", yaml);
+
+    // Links are written as copies; both slots through `$value`
+    let yaml = crate::yaml::to_yaml(&value_of("a = 1 { b = \"yes\" }\nc = $a\nd = [{ e = @none }]"));
+    assert_eq!(yaml, "a:\n  \"$value\": 1\n  b: \"yes\"\nc:\n  \"$value\": 1\n  b: \"yes\"\nd:\n  - e: null\n");
+}
+
+#[cfg(feature = "toml")]
+#[test]
+fn test_toml_maps_tables_arrays_and_datetimes() {
+    let toml = "title = \"demo\"\nreleased = 1979-05-27T07:32:00-08:00\nday = 1979-05-27\nalarm = 07:32:00\nmatrix = [[1, 2], [\"a\"]]\n\n[owner]\nname = \"Tom\"\nempty = {}\n\n[[servers]]\nhost = \"alpha\"\nports = [8000, 8001]\n\n[[servers]]\nhost = \"beta\"\n";
+    let (mut tree, errors) = crate::toml::parse_toml(toml);
+    assert!(errors.is_empty(), "{:?}", errors);
+    strip_locations(&mut tree);
+    let expected = value_of(
+        "title = demo
+released = @1979-05-27T07:32:00-08:00
+day = @1979-05-27
+alarm = \"07:32:00\"
+matrix = [[1, 2], [a]]
+owner { name = Tom, empty { -... } }
+servers = [{ host = alpha, ports = [8000, 8001] }, { host = beta }]",
+    );
+    assert_eq!(tree, expected);
+
+    let err = crate::toml::from_toml("a = 1\na = 2").unwrap_err();
+    assert_eq!((err.code.as_str(), err.begin.line), ("invalid-toml", 1));
+    let err = crate::toml::from_toml("[a]\nb = nan").unwrap_err();
+    assert!(err.message.starts_with("a.b:"), "{}", err.message);
+}
+
+#[cfg(feature = "toml")]
+#[test]
+fn test_toml_export_round_trips_and_reports_losses() {
+    let sample = value_of(include_str!("../test-data/k8s-deployment-sample.motly"));
+    let output = crate::toml::to_toml(&sample);
+    assert_eq!(output.losses, Vec::new());
+    let (mut back, errors) = crate::toml::parse_toml(&output.text);
+    assert!(errors.is_empty(), "{:?}", errors);
+    strip_locations(&mut back);
+    assert_eq!(back, sample, "{}", output.text);
+
+    let output = crate::toml::to_toml(&value_of("a = 1 { b = 2 }\nc = $a\nd = @env.HOME\ne\nf = [{ g = @2024-01-15 }]"));
+    assert_eq!(output.text, "[a]\nb = 2\n\n[c]\nb = 2\n\n[e]\n\n[[f]]\ng = 2024-01-15\n");
+    let losses: Vec<(String, crate::toml::LossKind)> =
+        output.losses.iter().map(|loss| (crate::diff::format_path(&loss.path), loss.kind)).collect();
+    use crate::toml::LossKind::*;
+    assert_eq!(
+        losses,
+        vec![
+            ("a".to_string(), Value),
+            ("c".to_string(), Link),
+            ("c".to_string(), Value),
+            ("d".to_string(), Env),
+            ("e".to_string(), Empty),
+        ]
+    );
+}
+
+#[test]
+fn test_plain_json_converts_to_idiomatic_motly() {
+    use crate::from_json::{from_plain_json, plain_json_to_motly, PlainJsonOptions};
+    let json = r#"{"server": {"port": 8080, "tls": null, "since": "2024-01-15T10:30:00Z"},
+        "tags": ["x", {"k": true}, [1, 2]], "empty": {}, "month": "2024-13-01", "odd key": 1.5}"#;
+    let options = PlainJsonOptions::default();
+    let expected = value_of(
+        "server { port = 8080, tls, since = @2024-01-15T10:30:00Z }
+tags = [x, { k = @true }, [1, 2]]
+empty { -... }
+month = \"2024-13-01\"
+`odd key` = 1.5",
+    );
+    assert_eq!(from_plain_json(json, &options).unwrap(), expected);
+    let source = plain_json_to_motly(json, &options).unwrap();
+    assert!(source.contains("since = @2024-01-15T10:30:00Z"), "{}", source);
+    assert_eq!(value_of(&source), expected);
+
+    let strings = PlainJsonOptions { detect_dates: false };
+    let tree = from_plain_json(r#"{"since": "2024-01-15"}"#, &strings).unwrap();
+    assert_eq!(tree, value_of("since = \"2024-01-15\""));
+
+    let err = from_plain_json(" [1]", &options).unwrap_err();
+    assert_eq!((err.code.as_str(), err.begin.column), ("invalid-json", 1));
+}
+
+#[test]
+fn test_plain_json_projection() {
+    use crate::json::{to_plain_json, PlainOptions, ValueAndProperties};
+    let tree = value_of(
+        "a = 1 { b = @2024-01-15 }
+c = $a
+home = @env.HOME
+unset = @env.UNSET
+e
+list = [{ g = @true }, $a.b, x]
+gone = 1
+-gone",
+    );
+    let mut options = PlainOptions::default();
+    options.env.insert("HOME".to_string(), "/home/me".to_string());
+    assert_eq!(
+        to_plain_json(&tree, &options),
+        r#"{"a":{"$value":1,"b":"2024-01-15"},"c":{"$value":1,"b":"2024-01-15"},"e":null,"home":"/home/me","list":[{"g":true},"2024-01-15","x"],"unset":null}"#
+    );
+
+    let both = value_of("a = 1 { b = 2 }");
+    let policy = |value_and_properties| to_plain_json(&both, &PlainOptions { value_and_properties, ..PlainOptions::default() });
+    assert_eq!(policy(ValueAndProperties::Key("=".to_string())), r#"{"a":{"=":1,"b":2}}"#);
+    assert_eq!(policy(ValueAndProperties::Properties), r#"{"a":{"b":2}}"#);
+    assert_eq!(policy(ValueAndProperties::Value), r#"{"a":1}"#);
+}
+
+#[test]
+fn test_dotenv_files() {
+    use crate::env::{DotEnv, EnvProvider};
+    let text = "# settings\nexport PORT=8080\nNAME=\"a \\\"b\\\"\\n\"\nRAW='$x # y'\nPLAIN = some value # note\n\nEMPTY=\n";
+    let env = DotEnv::parse(text).unwrap();
+    assert_eq!(env.get("PORT").as_deref(), Some("8080"));
+    assert_eq!(env.get("NAME").as_deref(), Some("a \"b\"\n"));
+    assert_eq!(env.get("RAW").as_deref(), Some("$x # y"));
+    assert_eq!(env.get("PLAIN").as_deref(), Some("some value"));
+    assert_eq!(env.get("EMPTY").as_deref(), Some(""));
+    assert_eq!(env.get("OTHER"), None);
+
+    let err = DotEnv::parse("A=1\n  2B=x").unwrap_err();
+    assert_eq!((err.code.as_str(), err.begin.line, err.begin.column), ("invalid-env-file", 1, 2));
+    assert!(DotEnv::parse("A=\"open").is_err());
+}
+
+#[test]
+fn test_resolve_env_strict_lenient_and_hints() {
+    use crate::env::{resolve_env, EnvOptions};
+    use crate::mot::ValueType;
+    use std::collections::HashMap;
+    let source = "port = @env.PORT\ndebug = @env.DEBUG\nhost = @env.HOST\nlist = [@env.PORT]\nmissing = @env.MISSING";
+    let env: HashMap<String, String> =
+        [("PORT", "8080"), ("DEBUG", "yes"), ("HOST", "db")].iter().map(|(k, v)| (k.to_string(), v.to_string())).collect();
+
+    let mut tree = value_of(source);
+    assert!(resolve_env(&mut tree, &env, &EnvOptions::default()).is_empty());
+    assert_eq!(
+        tree,
+        value_of("port = \"8080\"\ndebug = yes\nhost = db\nlist = [\"8080\"]\nmissing = @env.MISSING")
+    );
+
+    let (mut tree, _) = crate::session_finish(&[source], crate::SessionOptions::default());
+    let mut hints = HashMap::new();
+    hints.insert(vec!["port".to_string()], ValueType::Number);
+    hints.insert(vec!["list".to_string(), "[0]".to_string()], ValueType::Number);
+    hints.insert(vec!["debug".to_string()], ValueType::Boolean);
+    let errors = resolve_env(&mut tree, &env, &EnvOptions { strict: true, hints });
+    let codes: Vec<(&str, usize)> = errors.iter().map(|e| (e.code.as_str(), e.begin.line)).collect();
+    assert_eq!(codes, vec![("env-type", 1), ("env-unset", 4)]);
+    strip_locations(&mut tree);
+    assert_eq!(tree, value_of("port = 8080\ndebug = yes\nhost = db\nlist = [8080]\nmissing = @env.MISSING"));
+}
+
+#[test]
+fn test_session_resolve_env_uses_schema_types() {
+    use std::collections::HashMap;
+    let mut session = crate::MOTLYSession::new(crate::SessionOptions::default());
+    let schema = "Types: { Server: { Required: { port=number } Optional: { tls=boolean } } }
+Required: { server=Server, started=date }
+Optional: { limits: { Optional: { max=number } } }";
+    assert!(session.parse_schema(schema).errors.is_empty());
+    session.parse("server { port = @env.PORT, tls = @env.TLS }\nstarted = @env.START\nlimits.max = @env.MAX\nname = @env.PORT");
+    assert!(session.finish().is_empty());
+    let env: HashMap<String, String> = [("PORT", "443"), ("TLS", "true"), ("START", "2024-01-15"), ("MAX", "2.5")]
+        .iter()
+        .map(|(k, v)| (k.to_string(), v.to_string()))
+        .collect();
+    assert!(session.resolve_env(&env, &crate::EnvOptions::default()).is_empty());
+    let mut value = session.value().clone();
+    strip_locations(&mut value);
+    assert_eq!(
+        value,
+        value_of("server { port = 443, tls = @true }\nstarted = @2024-01-15\nlimits.max = 2.5\nname = \"443\"")
+    );
+}
+
+#[test]
+fn test_secrets_follow_clones_links_and_schema_types() {
+    use crate::json::{to_json_redacted, to_plain_json, JsonStyle, PlainOptions};
+    use crate::secret::Secrets;
+    let mut session = crate::MOTLYSession::new(crate::SessionOptions::default());
+    let schema = "TYPES { SecretString { VALUE = string }, Token = SecretString, Db { REQUIRED { password = SecretString } } }
+REQUIRED { db = Db, api { OPTIONAL { token = Token, port = integer } } }";
+    assert!(session.parse_schema(schema).errors.is_empty());
+    session.parse(
+        "db { password = hunter2, user = admin }
+api { token = @env.TOKEN, port = @env.PORT }
+copy := $api
+link = $db
+list = [@env.TOKEN, plain]
+backup := $db
+pass := $db.password",
+    );
+    assert!(session.finish().is_empty());
+
+    let secrets = session.secrets();
+    let paths: Vec<String> = secrets.paths().iter().map(|p| crate::diff::format_path(p)).collect();
+    assert_eq!(
+        paths,
+        vec![
+            "api.port",
+            "api.token",
+            "backup.password",
+            "copy.port",
+            "copy.token",
+            "db.password",
+            "link.password",
+            "list[0]",
+            "pass"
+        ]
+    );
+
+    // Secrets are kept from before the env references were resolved
+    let env: std::collections::HashMap<String, String> =
+        [("TOKEN", "t0k3n"), ("PORT", "8080")].iter().map(|(k, v)| (k.to_string(), v.to_string())).collect();
+    assert!(session.resolve_env(&env, &crate::EnvOptions::default()).is_empty());
+    assert_eq!(session.secrets(), secrets);
+
+    let json = to_json_redacted(session.value(), &secrets, JsonStyle::Compact);
+    assert!(!json.contains("hunter2") && !json.contains("t0k3n") && !json.contains("8080"), "{}", json);
+    assert!(json.contains(r#""user":{"eq":"admin"}"#), "{}", json);
+    let options = PlainOptions { redact: Some(secrets), ..PlainOptions::default() };
+    assert_eq!(
+        to_plain_json(session.value(), &options),
+        concat!(
+            r#"{"api":{"port":"***","token":"***"},"backup":{"password":"***","user":"admin"},"copy":{"port":"***","token":"***"},"#,
+            r#""db":{"password":"***","user":"admin"},"link":{"password":"***","user":"admin"},"list":["***","plain"],"pass":"***"}"#
+        )
+    );
+
+    // Unresolved references name the variable, which is not secret
+    let tree = value_of("key = @env.KEY");
+    let json = to_json_redacted(&tree, &Secrets::find(&tree, None), JsonStyle::Compact);
+    assert_eq!(json, r#"{"properties":{"key":{"eq":{"env":"KEY"}}}}"#);
+}
+
+#[test]
+fn test_links_sharing_targets_are_walked_once() {
+    // Each line links twice to the one before: 2^40 paths reach `a0`
+    let mut source = "a0 { v = @env.TOKEN }\n".to_string();
+    for i in 1..40 {
+        source.push_str(&format!("a{i} {{ x = $a{j}, y = $a{j} }}\n", i = i, j = i - 1));
+    }
+    let mut session = crate::MOTLYSession::new(crate::SessionOptions::default());
+    session.parse(&source);
+    assert!(session.finish().is_empty());
+    let env: std::collections::HashMap<String, String> = [("TOKEN".to_string(), "t".to_string())].into_iter().collect();
+    assert!(session.resolve_env(&env, &crate::EnvOptions::default()).is_empty());
+
+    let secrets = session.secrets();
+    let mut path: Vec<String> = vec!["a39".to_string()];
+    path.extend((0..39).map(|i| if i % 2 == 0 { "x" } else { "y" }.to_string()));
+    path.push("v".to_string());
+    assert!(secrets.contains(&path));
+    assert!(secrets.within(&path[..3]));
+    path.pop();
+    assert!(!secrets.contains(&path));
+
+    // A Mot copies every link target, so read through one built from the
+    // first lines only; the reads are paths and apply to the whole tree
+    let mut head = crate::MOTLYSession::new(crate::SessionOptions::default());
+    head.parse(&source.lines().take(3).collect::<Vec<_>>().join("\n"));
+    assert!(head.finish().is_empty());
+    let tracker = crate::ReadTracker::new();
+    let mot = head.get_mot(None);
+    let tracked = tracker.track(&mot);
+    assert!(tracked.get("a1").and_then(|a1| a1.get("x")).is_some());
+    let unread: Vec<String> = tracker.unread(session.value()).iter().map(|u| crate::diff::format_path(&u.path)).collect();
+    assert_eq!(unread.len(), 40);
+    assert_eq!(&unread[..3], &["a0.v", "a1.y", "a10"]);
+}
+
+#[test]
+fn test_redacted_copies_hide_secrets_from_every_serializer() {
+    use crate::secret::Secrets;
+    let schema = value_of("TYPES { SecretString { VALUE = string } }\nOPTIONAL { db { OPTIONAL { password = SecretString } } }");
+    let old = value_of("db { password = hunter2, user = admin }\nlink = $db\nlist = [@env.TOKEN, plain]");
+    let new = value_of("db { password = hunter3, user = admin }\nlink = $db\nlist = [@env.TOKEN, plain]");
+    let (old_secrets, new_secrets) = (Secrets::find(&old, Some(&schema)), Secrets::find(&new, Some(&schema)));
+
+    // The whole tree: values replaced, env references and links kept
+    let text = crate::writer::to_motly(&new_secrets.redact(&new, &[]));
+    assert_eq!(text, "db {\n  password = \"***\"\n  user = admin\n}\nlink = $db\nlist = [@env.TOKEN, plain]\n");
+
+    // A query match is redacted at its own path, which may go through a link
+    let mut matches = crate::query::query(&new, "link").unwrap();
+    for m in &mut matches {
+        m.node = new_secrets.redact(&m.node, &m.path);
+    }
+    let json = crate::json::query_matches_to_json(&matches);
+    assert!(!json.contains("hunter3") && json.contains("admin"), "{}", json);
+
+    // Both sides of a diff, each with the secrets of its own tree
+    let changes = crate::diff::diff(&old, &new);
+    assert_eq!(changes.len(), 1);
+    let redact = |node: &Option<MOTLYNode>, secrets: &Secrets| match node {
+        Some(MOTLYNode::Data(data)) => crate::diff::summary(&MOTLYNode::Data(secrets.redact(data, &changes[0].path))),
+        _ => panic!("expected a value"),
+    };
+    assert_eq!(redact(&changes[0].before, &old_secrets), "\"***\"");
+    assert_eq!(redact(&changes[0].after, &new_secrets), "\"***\"");
+}
+
+#[test]
+fn test_schema_hints_read_both_dialects() {
+    use crate::mot::ValueType;
+    let current = value_of(
+        "TYPES { Port { VALUE = integer }, Server { REQUIRED { port = Port } OPTIONAL { tls { VALUE = boolean } } } }
+REQUIRED { server = Server } OPTIONAL { since = date, name = string }",
+    );
+    let first = value_of("Types: { Server: { Required: { port=number } Optional: { tls=boolean } } }\nRequired: { server=Server, since=date }");
+    for schema in [current, first] {
+        let mut hints: Vec<(String, ValueType)> =
+            crate::env::schema_hints(&schema).into_iter().map(|(path, hint)| (path.join("."), hint)).collect();
+        hints.sort_by(|a, b| a.0.cmp(&b.0));
+        assert_eq!(
+            hints,
+            vec![
+                ("server.port".to_string(), ValueType::Number),
+                ("server.tls".to_string(), ValueType::Boolean),
+                ("since".to_string(), ValueType::Date),
+            ]
+        );
+    }
+}
+
+// ── Language server ─────────────────────────────────────────────────
+
+/// Frame `messages` as a client would send them.
+#[cfg(feature = "lsp")]
+fn lsp_script(messages: &[serde_json::Value]) -> Vec<u8> {
+    let mut input = Vec::new();
+    for message in messages {
+        crate::lsp::write_message(&mut input, &message.to_string()).unwrap();
+    }
+    input
+}
+
+#[cfg(feature = "lsp")]
+#[test]
+fn test_lsp_scripted_session() {
+    use serde_json::json;
+    let base = "file:///cfg/base.motly";
+    let app = "file:///cfg/app%20config.motly";
+    let open = |uri: &str, text: &str| {
+        json!({"jsonrpc": "2.0", "method": "textDocument/didOpen",
+               "params": {"textDocument": {"uri": uri, "languageId": "motly", "version": 1, "text": text}}})
+    };
+    let input = lsp_script(&[
+        json!({"jsonrpc": "2.0", "id": 1, "method": "initialize", "params": {"capabilities": {}}}),
+        json!({"jsonrpc": "2.0", "method": "initialized", "params": {}}),
+        open(base, "defaults { port = 80 }\n"),
+        open(app, "#! include=\"base.motly\"\n#! schema=app url=\"app-schema.motly\"\nserver {\n  port = $defaults.port\n  host = $defaults.hots\n}\nnote = \"é\" ]\n"),
+        json!({"jsonrpc": "2.0", "method": "textDocument/didChange",
+               "params": {"textDocument": {"uri": app, "version": 2},
+                          "contentChanges": [{"text": "#! include=\"base.motly\"\nserver {\n  port = $defaults.port\n  tags = [\n    a\n  ]\n}\n"}]}}),
+        json!({"jsonrpc": "2.0", "id": 2, "method": "textDocument/documentSymbol", "params": {"textDocument": {"uri": app}}}),
+        json!({"jsonrpc": "2.0", "id": 3, "method": "textDocument/foldingRange", "params": {"textDocument": {"uri": app}}}),
+        json!({"jsonrpc": "2.0", "id": 4, "method": "textDocument/hover", "params": {}}),
+        json!({"jsonrpc": "2.0", "id": 5, "method": "shutdown"}),
+        json!({"jsonrpc": "2.0", "method": "exit"}),
+    ]);
+    let mut output = Vec::new();
+    let code = crate::lsp::run(&input[..], &mut output).unwrap();
+    assert_eq!(code, 0);
+
+    let mut replies = Vec::new();
+    let mut reader = &output[..];
+    while let Some(body) = crate::lsp::read_message(&mut reader).unwrap() {
+        replies.push(serde_json::from_str::<serde_json::Value>(&body).unwrap());
+    }
+    assert_eq!(replies[0]["id"], 1);
+    assert_eq!(replies[0]["result"]["capabilities"]["textDocumentSync"]["change"], 1);
+
+    // Opening app checks it with base, which is open, included
+    let diagnostics: Vec<&serde_json::Value> = replies
+        .iter()
+        .filter(|r| r["method"] == "textDocument/publishDiagnostics" && r["params"]["uri"] == app)
+        .map(|r| &r["params"]["diagnostics"])
+        .collect();
+    assert_eq!(diagnostics.len(), 2);
+    let found: Vec<(&str, u64, u64)> = diagnostics[0]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|d| (d["code"].as_str().unwrap(), d["range"]["start"]["line"].as_u64().unwrap(), d["range"]["start"]["character"].as_u64().unwrap()))
+        .collect();
+    // Columns count UTF-16 units: `é` is one, not two bytes
+    assert_eq!(
+        found,
+        vec![("tag-parse-syntax-error", 6, 11), ("unresolved-reference", 4, 2), ("schema-not-found", 1, 0)]
+    );
+    assert_eq!(diagnostics[1], &json!([]));
+
+    let symbols = &replies.iter().find(|r| r["id"] == 2).unwrap()["result"];
+    assert_eq!(symbols[0]["name"], "server");
+    assert_eq!(symbols[0]["kind"], 19);
+    assert_eq!(symbols[0]["children"][0]["detail"], "$defaults.port");
+    assert_eq!(symbols[0]["children"][1]["range"]["end"], json!({"line": 5, "character": 3}));
+    let folds = &replies.iter().find(|r| r["id"] == 3).unwrap()["result"];
+    assert_eq!(folds, &json!([{"startLine": 1, "endLine": 5}, {"startLine": 3, "endLine": 4}]));
+    assert_eq!(replies.iter().find(|r| r["id"] == 4).unwrap()["error"]["code"], -32601);
+    assert_eq!(replies.last().unwrap(), &json!({"jsonrpc": "2.0", "id": 5, "result": null}));
+
+    // Without shutdown, exit is an error
+    let input = lsp_script(&[json!({"jsonrpc": "2.0", "method": "exit"})]);
+    assert_eq!(crate::lsp::run(&input[..], Vec::new()).unwrap(), 1);
+}
+
+#[cfg(feature = "lsp")]
+#[test]
+fn test_lsp_symbols_folding_and_schema_checks() {
+    use crate::lsp::{check, document_symbols, folding_ranges, FoldingRange, SymbolKind};
+    let text = "db.host = localhost\nflag\nservers = [\n  { name = a },\n  b\n]\n-gone\nnote = <<<\n  one\n  two\n  >>>\nwhen = @2024-01-15\n";
+    let symbols = document_symbols(text);
+    let outline: Vec<(&str, SymbolKind, Option<&str>)> =
+        symbols.iter().map(|s| (s.name.as_str(), s.kind, s.detail.as_deref())).collect();
+    assert_eq!(
+        outline,
+        vec![
+            ("db.host", SymbolKind::String, Some("localhost")),
+            ("flag", SymbolKind::Property, None),
+            ("servers", SymbolKind::Array, None),
+            ("note", SymbolKind::String, None),
+            ("when", SymbolKind::Constant, Some("@2024-01-15")),
+        ]
+    );
+    let element = &symbols[2].children[0];
+    assert_eq!((element.name.as_str(), element.kind, element.children[0].name.as_str()), ("[0]", SymbolKind::Object, "name"));
+    assert_eq!(
+        folding_ranges(text),
+        vec![FoldingRange { start_line: 2, end_line: 4 }, FoldingRange { start_line: 7, end_line: 9 }]
+    );
+
+    let mut loader = crate::MemoryLoader::new();
+    loader.add("cfg/schema.motly", "REQUIRED { port = number }");
+    loader.add("cfg/broken.motly", "REQUIRED {");
+    let errors = check("cfg/app.motly", "#! schema=app url=\"schema.motly\"\nport = 1\n", &loader);
+    assert_eq!(errors, Vec::new());
+    let errors = check("cfg/app.motly", "#! schema=app url=\"broken.motly\"\nport = 1\n", &loader);
+    assert_eq!(errors.iter().map(|e| e.code.as_str()).collect::<Vec<_>>(), vec!["invalid-schema"]);
+}
@@ -0,0 +1,270 @@
+/// Conversion between TOML and MOTLY (cargo feature `toml`).
+///
+/// `from_toml` turns a TOML document into MOTLY source; `parse_toml`
+/// interprets that source into a tree. `to_toml` goes the other way.
+///
+/// | TOML                              | MOTLY                                  |
+/// |-----------------------------------|----------------------------------------|
+/// | table, `[table]`, inline table    | properties                             |
+/// | array of tables, `[[table]]`      | array of elements with properties only |
+/// | array                             | array                                  |
+/// | string, integer, float, boolean   | string, number, boolean                |
+/// | offset, local date-time, date     | date                                   |
+/// | local time                        | string                                 |
+///
+/// Everything in TOML has a MOTLY form except `nan` and `inf`, which are
+/// an error. The other way, TOML has no form for a node with both a value
+/// and properties, for links, for environment references or for a node
+/// with neither value nor properties. `to_toml` writes what it can (the
+/// properties, a copy of the link target, an empty table) and returns
+/// each such node as a `Loss`, so callers can tell the TOML is not the
+/// whole tree.
+use std::str::FromStr;
+
+use ::toml::value::{Array, Datetime, Table, Value};
+
+use crate::ast::{ArrayElement, ScalarValue, Statement, TagValue};
+use crate::error::{MOTLYError, Position};
+use crate::session::MOTLYSession;
+use crate::tree::{EqValue, MOTLYDataNode, MOTLYLocation, MOTLYNode, Scalar};
+use crate::writer::{write_statements, NO_SPAN};
+use crate::SessionOptions;
+
+/// MOTLY source for the TOML document `text`.
+pub fn from_toml(text: &str) -> Result<String, MOTLYError> {
+    Ok(write_statements(&toml_statements(text)?))
+}
+
+/// The tree the TOML document `text` describes, with any errors from
+/// reading the TOML or interpreting it.
+pub fn parse_toml(text: &str) -> (MOTLYDataNode, Vec<MOTLYError>) {
+    let source = match from_toml(text) {
+        Ok(source) => source,
+        Err(err) => return (MOTLYDataNode::new(), vec![err]),
+    };
+    let mut session = MOTLYSession::new(SessionOptions::default());
+    let mut errors = session.parse(&source).errors;
+    errors.extend(session.finish());
+    (session.value().clone(), errors)
+}
+
+/// The statements for the TOML document `text`.
+pub fn toml_statements(text: &str) -> Result<Vec<Statement>, MOTLYError> {
+    let table = Table::from_str(text).map_err(|err| {
+        let span = err.span().unwrap_or(0..0);
+        let message = err.message().trim_end().to_string();
+        error(message, Position::from_offset(text, span.start), Position::from_offset(text, span.end))
+    })?;
+    table_statements(&table, &mut Vec::new())
+}
+
+fn error(message: String, begin: Position, end: Position) -> MOTLYError {
+    MOTLYError::new("invalid-toml", message, begin, end)
+}
+
+// ── Converting to statements ────────────────────────────────────────
+
+/// The statements for the entries of `table`, at `path` (for errors).
+fn table_statements(table: &Table, path: &mut Vec<String>) -> Result<Vec<Statement>, MOTLYError> {
+    let mut stmts = Vec::new();
+    for (key, value) in table {
+        path.push(key.clone());
+        let name = vec![key.clone()];
+        stmts.push(match value {
+            Value::Table(table) => Statement::UpdateProperties { path: name, properties: properties(table, path)?, span: NO_SPAN },
+            value => Statement::SetEq { path: name, value: tag_value(value, path)?, properties: None, span: NO_SPAN },
+        });
+        path.pop();
+    }
+    Ok(stmts)
+}
+
+/// The block for a table. `-...` gives an empty table its (empty)
+/// properties.
+fn properties(table: &Table, path: &mut Vec<String>) -> Result<Vec<Statement>, MOTLYError> {
+    if table.is_empty() {
+        return Ok(vec![Statement::ClearAll { span: NO_SPAN }]);
+    }
+    table_statements(table, path)
+}
+
+/// The value of anything but a table.
+fn tag_value(value: &Value, path: &mut Vec<String>) -> Result<TagValue, MOTLYError> {
+    let scalar = match value {
+        Value::String(s) => ScalarValue::String(s.clone()),
+        Value::Integer(i) => ScalarValue::Number(*i as f64),
+        Value::Float(f) if f.is_finite() => ScalarValue::Number(*f),
+        Value::Float(_) => {
+            let message = format!("{}: nan and inf have no MOTLY form", crate::diff::format_path(path));
+            return Err(MOTLYError::general("invalid-toml", message));
+        }
+        Value::Boolean(b) => ScalarValue::Boolean(*b),
+        Value::Datetime(datetime) if datetime.date.is_some() => ScalarValue::Date(datetime.to_string()),
+        Value::Datetime(time) => ScalarValue::String(time.to_string()),
+        Value::Array(items) => return Ok(TagValue::Array(elements(items, path)?)),
+        Value::Table(_) => unreachable!("tables are written as properties"),
+    };
+    Ok(TagValue::Scalar(scalar))
+}
+
+fn elements(items: &Array, path: &mut Vec<String>) -> Result<Vec<ArrayElement>, MOTLYError> {
+    let mut elements = Vec::new();
+    for (i, item) in items.iter().enumerate() {
+        path.push(format!("[{}]", i));
+        elements.push(match item {
+            Value::Table(table) => ArrayElement { value: None, properties: Some(properties(table, path)?), span: NO_SPAN },
+            value => ArrayElement { value: Some(tag_value(value, path)?), properties: None, span: NO_SPAN },
+        });
+        path.pop();
+    }
+    Ok(elements)
+}
+
+// ── Writing TOML ────────────────────────────────────────────────────
+
+/// What a node lost on its way to TOML.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LossKind {
+    /// A node with both a value and properties; only the properties are
+    /// written.
+    Value,
+    /// A link; a copy of its target is written.
+    Link,
+    /// An environment reference; left out.
+    Env,
+    /// A node with neither a value nor properties; written as an empty
+    /// table.
+    Empty,
+    /// A date TOML cannot read; written as a string.
+    Date,
+}
+
+impl LossKind {
+    pub fn description(&self) -> &'static str {
+        match self {
+            LossKind::Value => "value dropped, TOML tables have no value",
+            LossKind::Link => "link written as a copy of its target",
+            LossKind::Env => "environment reference left out",
+            LossKind::Empty => "node without a value written as an empty table",
+            LossKind::Date => "date written as a string",
+        }
+    }
+}
+
+/// A node `to_toml` could not write as it is.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Loss {
+    pub kind: LossKind,
+    /// Absolute path, as in `diff::Change`.
+    pub path: Vec<String>,
+    pub location: Option<MOTLYLocation>,
+}
+
+/// The TOML for a tree, and what it could not express.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TomlOutput {
+    pub text: String,
+    /// In path order.
+    pub losses: Vec<Loss>,
+}
+
+/// TOML for `node`'s properties.
+pub fn to_toml(node: &MOTLYDataNode) -> TomlOutput {
+    let mut losses = Vec::new();
+    links(node, &mut Vec::new(), &mut losses);
+    let resolved = crate::query::resolve_links(node);
+    if resolved.eq.is_some() {
+        losses.push(Loss { kind: LossKind::Value, path: Vec::new(), location: resolved.location });
+    }
+    let table = table(&resolved, &mut Vec::new(), &mut losses);
+    losses.sort_by(|a, b| a.path.cmp(&b.path));
+    TomlOutput { text: table.to_string(), losses }
+}
+
+/// Record a `Link` loss for every reference in `node`.
+fn links(node: &MOTLYDataNode, path: &mut Vec<String>, losses: &mut Vec<Loss>) {
+    let properties = node.properties.iter().flatten().map(|(key, child)| (key.clone(), child));
+    let elements = match &node.eq {
+        Some(EqValue::Array(items)) => items.iter().enumerate().map(|(i, item)| (format!("[{}]", i), item)).collect(),
+        _ => Vec::new(),
+    };
+    for (segment, child) in elements.into_iter().chain(properties) {
+        path.push(segment);
+        match child {
+            MOTLYNode::Ref { location, .. } => {
+                losses.push(Loss { kind: LossKind::Link, path: path.clone(), location: *location })
+            }
+            MOTLYNode::Data(data) if !data.deleted => links(data, path, losses),
+            MOTLYNode::Data(_) => {}
+        }
+        path.pop();
+    }
+}
+
+/// The table of `node`'s properties that are not deleted.
+fn table(node: &MOTLYDataNode, path: &mut Vec<String>, losses: &mut Vec<Loss>) -> Table {
+    let mut table = Table::new();
+    for (key, child) in node.properties.iter().flatten() {
+        let Some(child) = child.as_data_node().filter(|c| !c.deleted) else {
+            continue;
+        };
+        path.push(key.clone());
+        if let Some(value) = value(child, path, losses) {
+            table.insert(key.clone(), value);
+        }
+        path.pop();
+    }
+    table
+}
+
+/// The TOML for a node; None if it is left out.
+fn value(node: &MOTLYDataNode, path: &mut Vec<String>, losses: &mut Vec<Loss>) -> Option<Value> {
+    let mut lose = |kind| losses.push(Loss { kind, path: path.clone(), location: node.location });
+    if node.properties.is_some() {
+        if node.eq.is_some() {
+            lose(LossKind::Value);
+        }
+        return Some(Value::Table(table(node, path, losses)));
+    }
+    Some(match node.eq.as_ref() {
+        None => {
+            lose(LossKind::Empty);
+            Value::Table(Table::new())
+        }
+        Some(EqValue::Scalar(Scalar::String(s))) => Value::String(s.clone()),
+        Some(EqValue::Scalar(Scalar::Number(n))) => number(*n),
+        Some(EqValue::Scalar(Scalar::Boolean(b))) => Value::Boolean(*b),
+        Some(EqValue::Scalar(Scalar::Date(d))) => match Datetime::from_str(d) {
+            Ok(datetime) => Value::Datetime(datetime),
+            Err(_) => {
+                lose(LossKind::Date);
+                Value::String(d.clone())
+            }
+        },
+        Some(EqValue::EnvRef(_)) => {
+            lose(LossKind::Env);
+            return None;
+        }
+        Some(EqValue::Array(items)) => {
+            let mut array = Array::new();
+            for (i, item) in items.iter().enumerate() {
+                path.push(format!("[{}]", i));
+                // Links were resolved, and elements are never deleted
+                if let Some(value) = item.as_data_node().and_then(|data| value(data, path, losses)) {
+                    array.push(value);
+                }
+                path.pop();
+            }
+            Value::Array(array)
+        }
+    })
+}
+
+/// Whole numbers as integers, the rest as floats.
+fn number(n: f64) -> Value {
+    if n.fract() == 0.0 && n.abs() < 9_007_199_254_740_992.0 {
+        Value::Integer(n as i64)
+    } else {
+        Value::Float(n)
+    }
+}
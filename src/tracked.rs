@@ -0,0 +1,243 @@
+/// Read tracking: which parts of a configuration the application used.
+///
+/// `ReadTracker::track` wraps a `Mot` in a `TrackedMot`, which reads the
+/// same way but records the path of every node reached through it. After
+/// startup, `ReadTracker::unread` lists the properties the source set but
+/// nothing read, with their locations: likely typos or dead settings.
+/// This is what Malloy does for tags with its read-tracked `Tag`.
+///
+/// Reaching a node reads it: `get`, `get_path`, `has`, the array accessors
+/// (which read each element) and the enumerations (which read each
+/// property). A property read through a link also reads the link's
+/// target, so `api = $defaults` with `api.timeout` read leaves only the
+/// other properties of `defaults` unread.
+use std::collections::{BTreeSet, HashSet};
+use std::sync::{Mutex, MutexGuard};
+
+use crate::mot::{Mot, ValueType};
+use crate::tree::{EqValue, MOTLYDataNode, MOTLYLocation, MOTLYNode};
+
+/// The paths read through the `TrackedMot`s it hands out (paths as in
+/// `diff::Change`, with `[i]` for array elements).
+#[derive(Debug, Default)]
+pub struct ReadTracker {
+    reads: Mutex<BTreeSet<Vec<String>>>,
+}
+
+/// A property that was never read.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Unread {
+    /// Absolute path, as in `diff::Change`.
+    pub path: Vec<String>,
+    pub location: Option<MOTLYLocation>,
+}
+
+impl ReadTracker {
+    pub fn new() -> ReadTracker {
+        ReadTracker::default()
+    }
+
+    /// A view of `mot`, the root, that records what is read through it.
+    pub fn track<'a>(&'a self, mot: &'a Mot) -> TrackedMot<'a> {
+        TrackedMot::new(mot, Vec::new(), self)
+    }
+
+    /// Whether the node at `path` has been read.
+    pub fn was_read(&self, path: &[String]) -> bool {
+        self.lock().contains(path)
+    }
+
+    /// The paths read so far, in order.
+    pub fn reads(&self) -> Vec<Vec<String>> {
+        self.lock().iter().cloned().collect()
+    }
+
+    /// The nodes of `root`, the tree the tracked Mot was built from, that
+    /// were never read, in key order. Only the outermost is reported: an
+    /// unread `db` is one entry, not one per property inside it.
+    pub fn unread(&self, root: &MOTLYDataNode) -> Vec<Unread> {
+        let reads = self.lock();
+        // The nodes read at some path, directly or through a link
+        let graph = crate::query::LinkGraph::new(root);
+        let nodes: HashSet<*const MOTLYDataNode> = reads
+            .iter()
+            .filter_map(|path| graph.lookup(path))
+            .map(|id| graph.state(id).node as *const MOTLYDataNode)
+            .collect();
+        let mut unread = Vec::new();
+        collect_unread(root, &mut Vec::new(), &reads, &nodes, &mut unread);
+        unread
+    }
+
+    fn record(&self, path: &[String]) {
+        let mut reads = self.lock();
+        if !reads.contains(path) {
+            reads.insert(path.to_vec());
+        }
+    }
+
+    fn lock(&self) -> MutexGuard<'_, BTreeSet<Vec<String>>> {
+        // Reads are recorded one insert at a time, so a poisoned set is whole
+        self.reads.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+fn collect_unread(
+    node: &MOTLYDataNode,
+    path: &mut Vec<String>,
+    reads: &BTreeSet<Vec<String>>,
+    nodes: &HashSet<*const MOTLYDataNode>,
+    unread: &mut Vec<Unread>,
+) {
+    let elements = match &node.eq {
+        Some(EqValue::Array(items)) => items.iter().enumerate().map(|(i, item)| (format!("[{}]", i), item)).collect(),
+        _ => Vec::new(),
+    };
+    let properties = node.properties.iter().flatten().map(|(key, child)| (key.clone(), child));
+    for (segment, child) in elements.into_iter().chain(properties) {
+        path.push(segment);
+        match child {
+            MOTLYNode::Ref { location, .. } if !reads.contains(path.as_slice()) => {
+                unread.push(Unread { path: path.clone(), location: *location });
+            }
+            MOTLYNode::Ref { .. } => {}
+            MOTLYNode::Data(data) if data.deleted => {}
+            MOTLYNode::Data(data) if reads.contains(path.as_slice()) || nodes.contains(&(data as *const MOTLYDataNode)) => {
+                collect_unread(data, path, reads, nodes, unread);
+            }
+            MOTLYNode::Data(data) => unread.push(Unread { path: path.clone(), location: data.location }),
+        }
+        path.pop();
+    }
+}
+
+/// A `Mot` that records the nodes read through it in a `ReadTracker`.
+/// The accessors are `Mot`'s; navigation returns tracked views.
+#[derive(Debug, Clone)]
+pub struct TrackedMot<'a> {
+    mot: &'a Mot,
+    path: Vec<String>,
+    tracker: &'a ReadTracker,
+}
+
+impl<'a> TrackedMot<'a> {
+    fn new(mot: &'a Mot, path: Vec<String>, tracker: &'a ReadTracker) -> TrackedMot<'a> {
+        tracker.record(&path);
+        TrackedMot { mot, path, tracker }
+    }
+
+    fn child(&self, segment: String, mot: &'a Mot) -> TrackedMot<'a> {
+        let mut path = self.path.clone();
+        path.push(segment);
+        TrackedMot::new(mot, path, self.tracker)
+    }
+
+    /// The path of this node from the root.
+    pub fn path(&self) -> &[String] {
+        &self.path
+    }
+
+    /// The untracked Mot. Everything under it counts as read, since the
+    /// tracker can no longer see what is.
+    pub fn mot(&self) -> &'a Mot {
+        self.read_all(self.mot, &mut self.path.clone());
+        self.mot
+    }
+
+    fn read_all(&self, mot: &Mot, path: &mut Vec<String>) {
+        self.tracker.record(path);
+        for (i, item) in mot.values().into_iter().flatten().enumerate() {
+            path.push(format!("[{}]", i));
+            self.read_all(item, path);
+            path.pop();
+        }
+        for (key, child) in mot.entries() {
+            path.push(key.to_string());
+            self.read_all(child, path);
+            path.pop();
+        }
+    }
+
+    // --- Existence ---
+
+    pub fn exists(&self) -> bool {
+        self.mot.exists()
+    }
+
+    /// Reads each node of `path` that exists.
+    pub fn has(&self, path: &[&str]) -> bool {
+        self.get_path(path).is_some()
+    }
+
+    // --- Navigation ---
+
+    pub fn get(&self, key: &str) -> Option<TrackedMot<'a>> {
+        Some(self.child(key.to_string(), self.mot.get(key)?))
+    }
+
+    pub fn get_path(&self, path: &[&str]) -> Option<TrackedMot<'a>> {
+        let mut current = self.clone();
+        for key in path {
+            current = current.get(key)?;
+        }
+        Some(current)
+    }
+
+    // --- Value type ---
+
+    pub fn value_type(&self) -> Option<ValueType> {
+        self.mot.value_type()
+    }
+
+    // --- Typed accessors ---
+
+    pub fn text(&self) -> Option<&'a str> {
+        self.mot.text()
+    }
+
+    pub fn numeric(&self) -> Option<f64> {
+        self.mot.numeric()
+    }
+
+    pub fn boolean(&self) -> Option<bool> {
+        self.mot.boolean()
+    }
+
+    pub fn date(&self) -> Option<&'a str> {
+        self.mot.date()
+    }
+
+    // --- Array access ---
+
+    pub fn values(&self) -> Option<Vec<TrackedMot<'a>>> {
+        let items = self.mot.values()?;
+        Some(items.iter().enumerate().map(|(i, item)| self.child(format!("[{}]", i), item)).collect())
+    }
+
+    pub fn texts(&self) -> Option<Vec<&'a str>> {
+        self.values()?.iter().map(TrackedMot::text).collect()
+    }
+
+    pub fn numerics(&self) -> Option<Vec<f64>> {
+        self.values()?.iter().map(TrackedMot::numeric).collect()
+    }
+
+    pub fn booleans(&self) -> Option<Vec<bool>> {
+        self.values()?.iter().map(TrackedMot::boolean).collect()
+    }
+
+    pub fn dates(&self) -> Option<Vec<&'a str>> {
+        self.values()?.iter().map(TrackedMot::date).collect()
+    }
+
+    // --- Property enumeration ---
+
+    /// Reads each property, though not what is inside it.
+    pub fn keys(&self) -> Vec<&'a str> {
+        self.entries().into_iter().map(|(key, _)| key).collect()
+    }
+
+    pub fn entries(&self) -> Vec<(&'a str, TrackedMot<'a>)> {
+        self.mot.entries().map(|(key, child)| (key, self.child(key.to_string(), child))).collect()
+    }
+}
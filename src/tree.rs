@@ -49,6 +49,8 @@ pub enum MOTLYNode {
     Ref {
         link_to: Vec<RefSegment>,
         link_ups: usize,
+        /// Source location of the statement that made the reference.
+        location: Option<MOTLYLocation>,
     },
 }
 
@@ -122,6 +124,15 @@ impl MOTLYNode {
         matches!(self, MOTLYNode::Ref { .. })
     }
 
+    /// Where this node was defined: a data node's first appearance, or the
+    /// statement that made the reference.
+    pub fn location(&self) -> Option<&MOTLYLocation> {
+        match self {
+            MOTLYNode::Data(n) => n.location.as_ref(),
+            MOTLYNode::Ref { location, .. } => location.as_ref(),
+        }
+    }
+
     /// Get a reference to the inner data node, if this is a Data variant.
     pub fn as_data_node(&self) -> Option<&MOTLYDataNode> {
         match self {
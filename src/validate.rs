@@ -53,18 +53,14 @@ fn walk_refs<'a>(
             path.push(key.clone());
 
             match child_pv {
-                MOTLYNode::Ref { ref link_to, link_ups } => {
+                MOTLYNode::Ref { ref link_to, link_ups, location } => {
                     if let Some(err_msg) = check_link(link_to, *link_ups, ancestors, root) {
-                        let mut err = ValidationError {
+                        errors.push(ValidationError {
                             message: err_msg,
                             path: path.clone(),
                             code: "unresolved-reference",
-                            location: None,
-                        };
-                        if let Some(loc) = node.location {
-                            err.location = Some(loc);
-                        }
-                        errors.push(err);
+                            location: location.or(node.location),
+                        });
                     }
                 }
                 MOTLYNode::Data(child) => {
@@ -92,13 +88,13 @@ fn walk_array_refs<'a>(
         path.push(idx_key);
 
         match elem_pv {
-            MOTLYNode::Ref { ref link_to, link_ups } => {
+            MOTLYNode::Ref { ref link_to, link_ups, location } => {
                 if let Some(err_msg) = check_link(link_to, *link_ups, ancestors, root) {
                     errors.push(ValidationError {
                         message: err_msg,
                         path: path.clone(),
                         code: "unresolved-reference",
-                        location: None,
+                        location: location.or(parent_node.location),
                     });
                 }
             }
@@ -215,3 +211,89 @@ enum ResolveTarget<'a> {
 pub fn validate_schema(_target: &MOTLYDataNode, _schema: &MOTLYDataNode) -> Vec<SchemaError> {
     Vec::new()
 }
+
+// ── Reading schemas ─────────────────────────────────────────────────
+
+/// A property a schema declares.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct SchemaField {
+    /// The property's path in the target document.
+    pub path: Vec<String>,
+    /// The named types it is declared with, outermost first: for
+    /// `password = SecretString` with `SecretString { VALUE = string }`,
+    /// `["SecretString", "string"]`.
+    pub types: Vec<String>,
+}
+
+impl SchemaField {
+    /// The value-slot primitive at the bottom of `types`, if any.
+    pub fn value_type(&self) -> Option<&str> {
+        self.types.iter().map(String::as_str).find(|t| ["string", "number", "integer", "boolean", "date"].contains(t))
+    }
+}
+
+/// The properties `schema` declares, through nested and named types. Reads
+/// both the current dialect (`REQUIRED`, `OPTIONAL`, `TYPES`, `VALUE`; see
+/// docs/schema_spec.md) and the first one (`Required`, `Optional`,
+/// `Types`). Arrays, unions and `ADDITIONAL` are not followed.
+pub(crate) fn schema_fields(schema: &MOTLYDataNode) -> Vec<SchemaField> {
+    let types = schema_property(schema, "TYPES").or_else(|| schema_property(schema, "Types"));
+    let mut fields = Vec::new();
+    collect_fields(schema, types, &mut Vec::new(), &mut Vec::new(), &mut fields);
+    fields
+}
+
+fn schema_property<'a>(node: &'a MOTLYDataNode, name: &str) -> Option<&'a MOTLYDataNode> {
+    node.properties.as_ref()?.get(name)?.as_data_node()
+}
+
+fn type_name(node: &MOTLYDataNode) -> Option<&str> {
+    match &node.eq {
+        Some(EqValue::Scalar(Scalar::String(name))) => Some(name),
+        _ => None,
+    }
+}
+
+fn collect_fields<'a>(
+    constraint: &'a MOTLYDataNode,
+    types: Option<&'a MOTLYDataNode>,
+    path: &mut Vec<String>,
+    expanding: &mut Vec<&'a str>,
+    fields: &mut Vec<SchemaField>,
+) {
+    for block in ["REQUIRED", "OPTIONAL", "Required", "Optional"] {
+        let Some(block) = schema_property(constraint, block) else {
+            continue;
+        };
+        for (name, field) in block.properties.iter().flatten() {
+            let Some(field) = field.as_data_node() else {
+                continue;
+            };
+            path.push(name.clone());
+            // `name = Type`, or an inline type whose `VALUE = Type`
+            let mut named = type_name(field).or_else(|| schema_property(field, "VALUE").and_then(type_name));
+            let mut chain = Vec::new();
+            let mut definitions = Vec::new();
+            while let Some(name) = named.filter(|name| !chain.contains(name)) {
+                chain.push(name);
+                let Some(definition) = types.and_then(|types| schema_property(types, name)) else {
+                    break;
+                };
+                definitions.push((name, definition));
+                named = schema_property(definition, "VALUE").and_then(type_name);
+            }
+            fields.push(SchemaField { path: path.clone(), types: chain.iter().map(|t| t.to_string()).collect() });
+
+            collect_fields(field, types, path, expanding, fields);
+            // A type that contains itself is only expanded once on a path
+            for (name, definition) in definitions {
+                if !expanding.contains(&name) {
+                    expanding.push(name);
+                    collect_fields(definition, types, path, expanding, fields);
+                    expanding.pop();
+                }
+            }
+            path.pop();
+        }
+    }
+}
@@ -0,0 +1,301 @@
+/// Writing MOTLY source.
+///
+/// `to_motly` renders an interpreted tree as source that rebuilds it, and
+/// `write_statements` renders any statement list; both come out in the
+/// layout `format` gives. The value writers are shared with source edits.
+use crate::ast::{ArrayElement, RefPathSegment, ScalarValue, Span, Statement, TagValue};
+use crate::error::Position;
+use crate::format::{format, FormatOptions};
+use crate::parser::{self, is_bare_char};
+use crate::tree::{EqValue, MOTLYDataNode, MOTLYNode, RefSegment, Scalar};
+
+/// MOTLY source that, parsed into an empty session, produces `node`
+/// (locations aside).
+pub fn to_motly(node: &MOTLYDataNode) -> String {
+    write_statements(&tree_statements(node))
+}
+
+/// The nodes a query selected, each as a statement that sets it at its
+/// path. A path through an array element has no MOTLY spelling, so it is
+/// written as a single quoted name, e.g. `` `items[0].name` = x ``.
+pub fn query_matches_to_motly(matches: &[crate::query::QueryMatch]) -> String {
+    let mut stmts = Vec::new();
+    for m in matches {
+        if m.path.is_empty() {
+            stmts.extend(tree_statements(&m.node));
+            continue;
+        }
+        let path = if m.path.iter().any(|seg| seg.starts_with('[')) {
+            vec![crate::diff::format_path(&m.path)]
+        } else {
+            m.path.clone()
+        };
+        stmts.extend(node_statements(path, &MOTLYNode::Data(m.node.clone()), false));
+    }
+    write_statements(&stmts)
+}
+
+/// The statements that build the properties of `node` from scratch.
+pub fn tree_statements(node: &MOTLYDataNode) -> Vec<Statement> {
+    let mut stmts = Vec::new();
+    if let Some(props) = &node.properties {
+        if props.is_empty() {
+            stmts.push(Statement::ClearAll { span: NO_SPAN });
+        }
+        for (key, child) in props {
+            stmts.extend(node_statements(vec![key.clone()], child, false));
+        }
+    }
+    stmts
+}
+
+/// Source for `stmts`, canonically formatted.
+pub fn write_statements(stmts: &[Statement]) -> String {
+    let mut raw = String::new();
+    for stmt in stmts {
+        write_statement(&mut raw, stmt);
+        raw.push('\n');
+    }
+    // Everything written here parses; fall back to the raw text regardless
+    format(&raw, &FormatOptions::default()).unwrap_or(raw)
+}
+
+/// Generated statements have no source.
+pub(crate) const NO_SPAN: Span = Span {
+    begin: Position::ZERO,
+    end: Position::ZERO,
+};
+
+/// The statements that make the node at `path` equal to `node`. With
+/// `replace`, whatever is at `path` now is discarded (`:=`); otherwise
+/// nothing may be there yet.
+pub(crate) fn node_statements(path: Vec<String>, node: &MOTLYNode, replace: bool) -> Vec<Statement> {
+    let data = match node {
+        MOTLYNode::Ref { link_to, link_ups, .. } => {
+            let value = TagValue::Scalar(ScalarValue::Reference { ups: *link_ups, path: ref_path(link_to) });
+            return vec![Statement::SetEq { path, value, properties: None, span: NO_SPAN }];
+        }
+        MOTLYNode::Data(data) if data.deleted => {
+            return vec![Statement::Define { path, deleted: true, span: NO_SPAN }];
+        }
+        MOTLYNode::Data(data) => data,
+    };
+
+    // `-...` clears the value too, so a value next to emptied properties
+    // is set by a second statement
+    let emptied = data.properties.as_ref().is_some_and(|p| p.is_empty());
+    let children = data.properties.as_ref().map(|_| tree_statements(data));
+    let value = data.eq.as_ref().map(tag_value);
+    let mut stmts = Vec::new();
+    match (replace, &value) {
+        (true, _) => {
+            let first = if emptied { None } else { value.clone() };
+            stmts.push(Statement::AssignBoth {
+                path: path.clone(),
+                value: first.unwrap_or(TagValue::Scalar(ScalarValue::None)),
+                properties: children,
+                span: NO_SPAN,
+            });
+        }
+        (false, Some(value)) if !emptied => {
+            stmts.push(Statement::SetEq { path: path.clone(), value: value.clone(), properties: children, span: NO_SPAN });
+        }
+        (false, _) => match children {
+            Some(properties) => stmts.push(Statement::UpdateProperties { path: path.clone(), properties, span: NO_SPAN }),
+            None => stmts.push(Statement::Define { path: path.clone(), deleted: false, span: NO_SPAN }),
+        },
+    }
+    if let (true, Some(value)) = (emptied, value) {
+        stmts.push(Statement::SetEq { path, value, properties: None, span: NO_SPAN });
+    }
+    stmts
+}
+
+/// A tree value as the statement value that produces it.
+pub(crate) fn tag_value(eq: &EqValue) -> TagValue {
+    match eq {
+        EqValue::Scalar(Scalar::String(s)) => TagValue::Scalar(ScalarValue::String(s.clone())),
+        EqValue::Scalar(Scalar::Number(n)) => TagValue::Scalar(ScalarValue::Number(*n)),
+        EqValue::Scalar(Scalar::Boolean(b)) => TagValue::Scalar(ScalarValue::Boolean(*b)),
+        EqValue::Scalar(Scalar::Date(d)) => TagValue::Scalar(ScalarValue::Date(d.clone())),
+        EqValue::EnvRef(name) => TagValue::Scalar(ScalarValue::Env { name: name.clone() }),
+        EqValue::Array(items) => TagValue::Array(items.iter().map(array_element).collect()),
+    }
+}
+
+fn array_element(item: &MOTLYNode) -> ArrayElement {
+    match item {
+        MOTLYNode::Ref { link_to, link_ups, .. } => ArrayElement {
+            value: Some(TagValue::Scalar(ScalarValue::Reference { ups: *link_ups, path: ref_path(link_to) })),
+            properties: None,
+            span: NO_SPAN,
+        },
+        MOTLYNode::Data(data) => ArrayElement {
+            value: data.eq.as_ref().map(tag_value),
+            properties: data.properties.as_ref().map(|_| tree_statements(data)),
+            span: NO_SPAN,
+        },
+    }
+}
+
+fn ref_path(segments: &[RefSegment]) -> Vec<RefPathSegment> {
+    segments
+        .iter()
+        .map(|seg| match seg {
+            RefSegment::Name(name) => RefPathSegment::Name(name.clone()),
+            RefSegment::Index(i) => RefPathSegment::Index(*i),
+        })
+        .collect()
+}
+
+// ── Writing source ──────────────────────────────────────────────────
+
+fn write_statement(out: &mut String, stmt: &Statement) {
+    match stmt {
+        Statement::SetEq { path, value, properties, .. } => {
+            out.push_str(&format!("{} = ", write_path(path)));
+            write_tag_value(out, value);
+            write_block(out, properties.as_deref());
+        }
+        Statement::AssignBoth { path, value, properties, .. } => {
+            out.push_str(&format!("{} := ", write_path(path)));
+            write_tag_value(out, value);
+            write_block(out, properties.as_deref());
+        }
+        Statement::ReplaceProperties { path, properties, .. } => {
+            out.push_str(&format!("{}:", write_path(path)));
+            write_block(out, Some(properties));
+        }
+        Statement::UpdateProperties { path, properties, .. } => {
+            out.push_str(&write_path(path));
+            write_block(out, Some(properties));
+        }
+        Statement::Define { path, deleted, .. } => {
+            if *deleted {
+                out.push('-');
+            }
+            out.push_str(&write_path(path));
+        }
+        Statement::ClearAll { .. } => out.push_str("-..."),
+    }
+}
+
+fn write_block(out: &mut String, properties: Option<&[Statement]>) {
+    let Some(stmts) = properties else { return };
+    out.push_str(" {");
+    for stmt in stmts {
+        out.push('\n');
+        write_statement(out, stmt);
+    }
+    out.push_str("\n}");
+}
+
+fn write_tag_value(out: &mut String, value: &TagValue) {
+    match value {
+        TagValue::Scalar(scalar) => out.push_str(&write_value(scalar)),
+        TagValue::Array(elements) => {
+            out.push('[');
+            for (i, el) in elements.iter().enumerate() {
+                if i > 0 {
+                    out.push_str(", ");
+                }
+                match &el.value {
+                    Some(value) => {
+                        write_tag_value(out, value);
+                        write_block(out, el.properties.as_deref());
+                    }
+                    None => {
+                        out.push('{');
+                        for stmt in el.properties.iter().flatten() {
+                            out.push('\n');
+                            write_statement(out, stmt);
+                        }
+                        out.push_str("\n}");
+                    }
+                }
+            }
+            out.push(']');
+        }
+    }
+}
+
+// ── Writing values ──────────────────────────────────────────────────
+
+/// MOTLY source for a scalar value.
+pub(crate) fn write_value(value: &ScalarValue) -> String {
+    match value {
+        ScalarValue::String(s) => write_string(s),
+        ScalarValue::Number(n) => n.to_string(),
+        ScalarValue::Boolean(b) => if *b { "@true" } else { "@false" }.to_string(),
+        ScalarValue::Date(d) => format!("@{}", d),
+        ScalarValue::None => "@none".to_string(),
+        ScalarValue::Env { name } => format!("@env.{}", write_identifier(name)),
+        ScalarValue::Reference { ups, path } => {
+            let mut out = "$".to_string();
+            out.push_str(&"^".repeat(*ups));
+            for (i, seg) in path.iter().enumerate() {
+                match seg {
+                    RefPathSegment::Name(name) => {
+                        if i > 0 || *ups > 0 {
+                            out.push('.');
+                        }
+                        out.push_str(&write_identifier(name));
+                    }
+                    RefPathSegment::Index(n) => out.push_str(&format!("[{}]", n)),
+                }
+            }
+            out
+        }
+    }
+}
+
+/// A string value: bare when it reads back as the same string, otherwise
+/// double-quoted.
+pub(crate) fn write_string(s: &str) -> String {
+    if !s.is_empty() && s.chars().all(is_bare_char) {
+        let reads_back = matches!(
+            parser::parse_value_at(s, 0),
+            Ok((crate::ast::TagValue::Scalar(ScalarValue::String(ref v)), end)) if v == s && end == s.len()
+        );
+        if reads_back {
+            return s.to_string();
+        }
+    }
+    quote(s, '"')
+}
+
+/// A property name: bare when possible, otherwise backtick-quoted.
+pub(crate) fn write_identifier(name: &str) -> String {
+    if !name.is_empty() && name.chars().all(is_bare_char) {
+        name.to_string()
+    } else {
+        quote(name, '`')
+    }
+}
+
+pub(crate) fn write_path(path: &[String]) -> String {
+    path.iter().map(|s| write_identifier(s)).collect::<Vec<_>>().join(".")
+}
+
+fn quote(s: &str, q: char) -> String {
+    let mut out = String::with_capacity(s.len() + 2);
+    out.push(q);
+    for ch in s.chars() {
+        match ch {
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            '\u{0008}' => out.push_str("\\b"),
+            '\u{000C}' => out.push_str("\\f"),
+            c if c == q => {
+                out.push('\\');
+                out.push(c);
+            }
+            c if c.is_control() => out.push_str(&format!("\\u{:04x}", c as u32)),
+            c => out.push(c),
+        }
+    }
+    out.push(q);
+    out
+}
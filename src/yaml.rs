@@ -0,0 +1,486 @@
+/// Conversion between YAML and MOTLY (cargo feature `yaml`).
+///
+/// `from_yaml` turns a YAML document into MOTLY source; `parse_yaml`
+/// interprets that source into a tree. `to_yaml` goes the other way.
+///
+/// | YAML                          | MOTLY                                 |
+/// |-------------------------------|---------------------------------------|
+/// | mapping                       | properties                            |
+/// | sequence                      | array (elements with properties for mappings) |
+/// | `"text"`, `'text'`, blocks    | string                                |
+/// | plain `8080`, `1.5`, `true`   | number, boolean                       |
+/// | plain `2024-01-15`            | date                                  |
+/// | `null`, `~`, empty            | a node with no value                  |
+/// | `!env NAME`                   | `@env.NAME`                           |
+/// | `$value` key in a mapping     | the value of a node with properties   |
+/// | `*alias`                      | `= $path` link to the anchored node   |
+/// | `<<: *alias`                  | `:= $path` clone, then the other keys |
+///
+/// A merge key clones when it is the only merge into a property; in array
+/// elements, at the root or with several sources the merged keys are
+/// copied instead. A sequence nested in a sequence is a nested array.
+///
+/// `to_yaml` follows links, writing a copy of the target, and writes
+/// `$value` for nodes with both a value and properties.
+use std::collections::HashMap;
+
+use yaml_rust2::parser::{Event, MarkedEventReceiver, Parser, Tag};
+use yaml_rust2::scanner::{Marker, TScalarStyle};
+use yaml_rust2::Yaml;
+
+use crate::ast::{ArrayElement, RefPathSegment, ScalarValue, Statement, TagValue};
+use crate::error::{MOTLYError, Position};
+use crate::parser;
+use crate::session::MOTLYSession;
+use crate::tree::{EqValue, MOTLYDataNode, Scalar};
+use crate::writer::{write_statements, NO_SPAN};
+use crate::SessionOptions;
+
+/// The key that holds the value of a mapping that also has properties.
+pub const VALUE_KEY: &str = "$value";
+
+/// MOTLY source for the first document of `text`.
+pub fn from_yaml(text: &str) -> Result<String, MOTLYError> {
+    Ok(write_statements(&yaml_statements(text)?))
+}
+
+/// The tree the first document of `text` describes, with any errors from
+/// reading the YAML or interpreting it.
+pub fn parse_yaml(text: &str) -> (MOTLYDataNode, Vec<MOTLYError>) {
+    let source = match from_yaml(text) {
+        Ok(source) => source,
+        Err(err) => return (MOTLYDataNode::new(), vec![err]),
+    };
+    let mut session = MOTLYSession::new(SessionOptions::default());
+    let mut errors = session.parse(&source).errors;
+    errors.extend(session.finish());
+    (session.value().clone(), errors)
+}
+
+/// The statements for the first document of `text`.
+pub fn yaml_statements(text: &str) -> Result<Vec<Statement>, MOTLYError> {
+    let mut builder = Builder { stack: Vec::new(), document: None };
+    let mut parser = Parser::new_from_str(text);
+    parser.load(&mut builder, false).map_err(|err| error(text, err.marker(), err.info()))?;
+    let Some(document) = builder.document else {
+        return Ok(Vec::new());
+    };
+    let mut converter = Converter { text, anchors: HashMap::new() };
+    match &document.kind {
+        Kind::Map(entries) => converter.entries(&document, entries, &[]),
+        Kind::Scalar { text: value, .. } if value.is_empty() => Ok(Vec::new()),
+        _ => Err(error(text, &document.mark, "The document must be a mapping")),
+    }
+}
+
+// ── Reading events ──────────────────────────────────────────────────
+
+struct Node {
+    kind: Kind,
+    anchor: usize,
+    mark: Marker,
+}
+
+enum Kind {
+    Scalar { text: String, style: TScalarStyle, tag: Option<Tag> },
+    Seq(Vec<Node>),
+    Map(Vec<(Node, Node)>),
+    Alias(usize),
+}
+
+/// Builds the `Node` tree of the first document from parser events.
+struct Builder {
+    /// Open collections, with a pending key for mappings.
+    stack: Vec<(Node, Option<Node>)>,
+    document: Option<Node>,
+}
+
+impl Builder {
+    fn add(&mut self, node: Node) {
+        match self.stack.last_mut() {
+            None => {
+                if self.document.is_none() {
+                    self.document = Some(node);
+                }
+            }
+            Some((Node { kind: Kind::Seq(items), .. }, _)) => items.push(node),
+            Some((Node { kind: Kind::Map(entries), .. }, key)) => match key.take() {
+                None => *key = Some(node),
+                Some(k) => entries.push((k, node)),
+            },
+            Some(_) => unreachable!("only collections are open"),
+        }
+    }
+}
+
+impl MarkedEventReceiver for Builder {
+    fn on_event(&mut self, ev: Event, mark: Marker) {
+        match ev {
+            Event::Scalar(text, style, anchor, tag) => {
+                self.add(Node { kind: Kind::Scalar { text, style, tag }, anchor, mark })
+            }
+            Event::Alias(id) => self.add(Node { kind: Kind::Alias(id), anchor: 0, mark }),
+            Event::SequenceStart(anchor, _) => self.stack.push((Node { kind: Kind::Seq(Vec::new()), anchor, mark }, None)),
+            Event::MappingStart(anchor, _) => self.stack.push((Node { kind: Kind::Map(Vec::new()), anchor, mark }, None)),
+            Event::SequenceEnd | Event::MappingEnd => {
+                if let Some((node, _)) = self.stack.pop() {
+                    self.add(node);
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+fn error(text: &str, mark: &Marker, message: &str) -> MOTLYError {
+    // Markers count characters; positions count bytes
+    let offset = text.char_indices().nth(mark.index()).map_or(text.len(), |(i, _)| i);
+    let at = Position { line: mark.line().saturating_sub(1), column: mark.col(), offset };
+    MOTLYError::new("invalid-yaml", message.to_string(), at, at)
+}
+
+// ── Converting to statements ────────────────────────────────────────
+
+struct Converter<'a> {
+    text: &'a str,
+    /// anchor id → the absolute path of the anchored node, and the node.
+    anchors: HashMap<usize, (Vec<RefPathSegment>, &'a Node)>,
+}
+
+impl<'a> Converter<'a> {
+    fn anchor(&mut self, node: &'a Node, path: &[RefPathSegment]) {
+        if node.anchor != 0 {
+            self.anchors.insert(node.anchor, (path.to_vec(), node));
+        }
+    }
+
+    fn target(&self, node: &Node, id: usize) -> Result<&(Vec<RefPathSegment>, &'a Node), MOTLYError> {
+        self.anchors.get(&id).ok_or_else(|| error(self.text, &node.mark, "Alias of an unknown anchor"))
+    }
+
+    /// The statements for the entries of mapping `map` at absolute `path`.
+    /// Keys from merge keys are copied in.
+    fn entries(&mut self, map: &'a Node, entries: &'a [(Node, Node)], path: &[RefPathSegment]) -> Result<Vec<Statement>, MOTLYError> {
+        self.anchor(map, path);
+        let mut stmts = Vec::new();
+        for (key, value) in self.effective(entries)? {
+            if key == VALUE_KEY || key == "<<" {
+                continue;
+            }
+            let mut child = path.to_vec();
+            child.push(RefPathSegment::Name(key.clone()));
+            stmts.extend(self.property(vec![key], value, &child, false)?);
+        }
+        Ok(stmts)
+    }
+
+    /// The entries of a mapping after merge keys: explicit keys first, then
+    /// merged keys not already present, earlier sources winning.
+    fn effective(&self, entries: &'a [(Node, Node)]) -> Result<Vec<(String, &'a Node)>, MOTLYError> {
+        let mut out: Vec<(String, &Node)> = Vec::new();
+        let mut merges = Vec::new();
+        for (key, value) in entries {
+            let key = self.key(key)?;
+            if key == "<<" {
+                match &value.kind {
+                    Kind::Seq(items) => merges.extend(items.iter()),
+                    _ => merges.push(value),
+                }
+            } else {
+                out.push((key, value));
+            }
+        }
+        for source in merges {
+            let source = match &source.kind {
+                Kind::Alias(id) => self.target(source, *id)?.1,
+                _ => source,
+            };
+            let Kind::Map(merged) = &source.kind else {
+                return Err(error(self.text, &source.mark, "A merge key must refer to mappings"));
+            };
+            for (key, value) in merged {
+                let key = self.key(key)?;
+                if !out.iter().any(|(k, _)| *k == key) {
+                    out.push((key, value));
+                }
+            }
+        }
+        Ok(out)
+    }
+
+    fn key(&self, key: &Node) -> Result<String, MOTLYError> {
+        match &key.kind {
+            Kind::Scalar { text, .. } => Ok(text.clone()),
+            _ => Err(error(self.text, &key.mark, "Mapping keys must be scalars")),
+        }
+    }
+
+    /// The statements that set property `name` (relative) to `node`, at
+    /// absolute `path`. With `replace`, the property replaces one a clone
+    /// brought in.
+    fn property(&mut self, name: Vec<String>, node: &'a Node, path: &[RefPathSegment], replace: bool) -> Result<Vec<Statement>, MOTLYError> {
+        self.anchor(node, path);
+        let set = |value: TagValue, properties: Option<Vec<Statement>>| {
+            if replace {
+                Statement::AssignBoth { path: name.clone(), value, properties, span: NO_SPAN }
+            } else {
+                Statement::SetEq { path: name.clone(), value, properties, span: NO_SPAN }
+            }
+        };
+        match &node.kind {
+            Kind::Alias(id) => {
+                let (target, _) = self.target(node, *id)?;
+                let reference = ScalarValue::Reference { ups: 0, path: target.clone() };
+                Ok(vec![Statement::SetEq { path: name, value: TagValue::Scalar(reference), properties: None, span: NO_SPAN }])
+            }
+            Kind::Scalar { .. } => Ok(vec![match self.scalar(node) {
+                Some(value) => set(TagValue::Scalar(value), None),
+                None if replace => set(TagValue::Scalar(ScalarValue::None), None),
+                None => Statement::Define { path: name, deleted: false, span: NO_SPAN },
+            }]),
+            Kind::Seq(items) => Ok(vec![set(TagValue::Array(self.elements(items, path)?), None)]),
+            Kind::Map(entries) => {
+                let value = self.map_value(entries)?;
+                if let Some(source) = self.clone_source(entries)? {
+                    // `name := $source`, then the explicit keys on top
+                    let (source_path, source_node) = source;
+                    let Kind::Map(source_entries) = &source_node.kind else { unreachable!() };
+                    let mut stmts = vec![Statement::AssignBoth {
+                        path: name.clone(),
+                        value: TagValue::Scalar(ScalarValue::Reference { ups: 0, path: source_path }),
+                        properties: None,
+                        span: NO_SPAN,
+                    }];
+                    let mut block = Vec::new();
+                    for (key, child) in entries {
+                        let key = self.key(key)?;
+                        if key == "<<" || key == VALUE_KEY {
+                            continue;
+                        }
+                        let overrides = source_entries.iter().any(|(k, _)| self.key(k).is_ok_and(|k| k == key));
+                        let mut child_path = path.to_vec();
+                        child_path.push(RefPathSegment::Name(key.clone()));
+                        block.extend(self.property(vec![key], child, &child_path, overrides)?);
+                    }
+                    if let Some(value) = value {
+                        stmts.push(Statement::SetEq { path: name.clone(), value, properties: None, span: NO_SPAN });
+                    }
+                    if !block.is_empty() {
+                        stmts.push(Statement::UpdateProperties { path: name, properties: block, span: NO_SPAN });
+                    }
+                    return Ok(stmts);
+                }
+                let properties = self.entries(node, entries, path)?;
+                Ok(vec![match value {
+                    Some(value) => set(value, Some(properties)),
+                    None if replace => set(TagValue::Scalar(ScalarValue::None), Some(properties)),
+                    None => Statement::UpdateProperties { path: name, properties, span: NO_SPAN },
+                }])
+            }
+        }
+    }
+
+    /// A mapping whose only merge is a single alias of a mapping is a clone
+    /// of that mapping.
+    fn clone_source(&self, entries: &[(Node, Node)]) -> Result<Option<(Vec<RefPathSegment>, &'a Node)>, MOTLYError> {
+        let merges: Vec<&Node> = entries.iter().filter(|(k, _)| self.key(k).is_ok_and(|k| k == "<<")).map(|(_, v)| v).collect();
+        let [source] = merges.as_slice() else {
+            return Ok(None);
+        };
+        let Kind::Alias(id) = source.kind else {
+            return Ok(None);
+        };
+        let (path, node) = self.target(source, id)?;
+        Ok(matches!(node.kind, Kind::Map(_)).then(|| (path.clone(), *node)))
+    }
+
+    /// The `$value` entry of a mapping.
+    fn map_value(&self, entries: &[(Node, Node)]) -> Result<Option<TagValue>, MOTLYError> {
+        for (key, value) in entries {
+            if self.key(key)? == VALUE_KEY {
+                return match &value.kind {
+                    Kind::Scalar { .. } => Ok(self.scalar(value).map(TagValue::Scalar)),
+                    _ => Err(error(self.text, &value.mark, "A $value must be a scalar")),
+                };
+            }
+        }
+        Ok(None)
+    }
+
+    fn elements(&mut self, items: &'a [Node], path: &[RefPathSegment]) -> Result<Vec<ArrayElement>, MOTLYError> {
+        let mut elements = Vec::new();
+        for (i, item) in items.iter().enumerate() {
+            let mut item_path = path.to_vec();
+            item_path.push(RefPathSegment::Index(i));
+            self.anchor(item, &item_path);
+            let element = match &item.kind {
+                Kind::Alias(id) => {
+                    let (target, _) = self.target(item, *id)?;
+                    let value = ScalarValue::Reference { ups: 0, path: target.clone() };
+                    ArrayElement { value: Some(TagValue::Scalar(value)), properties: None, span: NO_SPAN }
+                }
+                Kind::Scalar { .. } => {
+                    let value = self.scalar(item).unwrap_or(ScalarValue::None);
+                    ArrayElement { value: Some(TagValue::Scalar(value)), properties: None, span: NO_SPAN }
+                }
+                Kind::Map(entries) => ArrayElement {
+                    value: self.map_value(entries)?,
+                    properties: Some(self.entries(item, entries, &item_path)?),
+                    span: NO_SPAN,
+                },
+                Kind::Seq(inner) => ArrayElement {
+                    value: Some(TagValue::Array(self.elements(inner, &item_path)?)),
+                    properties: None,
+                    span: NO_SPAN,
+                },
+            };
+            elements.push(element);
+        }
+        Ok(elements)
+    }
+
+    /// The value of a scalar; None for null.
+    fn scalar(&self, node: &Node) -> Option<ScalarValue> {
+        let Kind::Scalar { text, style, tag } = &node.kind else {
+            return None;
+        };
+        match tag {
+            Some(tag) if tag.handle == "!" && tag.suffix == "env" => return Some(ScalarValue::Env { name: text.clone() }),
+            Some(tag) if tag.handle == "tag:yaml.org,2002:" && tag.suffix == "str" => {
+                return Some(ScalarValue::String(text.clone()))
+            }
+            _ => {}
+        }
+        if *style != TScalarStyle::Plain {
+            return Some(ScalarValue::String(text.clone()));
+        }
+        if let Some(date) = parser::parse_date(text) {
+            return Some(date);
+        }
+        match Yaml::from_str(text) {
+            Yaml::Null => None,
+            Yaml::Boolean(b) => Some(ScalarValue::Boolean(b)),
+            Yaml::Integer(i) => Some(ScalarValue::Number(i as f64)),
+            Yaml::Real(r) => r.parse().ok().map(ScalarValue::Number).or_else(|| Some(ScalarValue::String(text.clone()))),
+            _ => Some(ScalarValue::String(text.clone())),
+        }
+    }
+}
+
+// ── Writing YAML ────────────────────────────────────────────────────
+
+/// YAML for `node`'s properties (and its value, under `$value`).
+pub fn to_yaml(node: &MOTLYDataNode) -> String {
+    let resolved = crate::query::resolve_links(node);
+    let mut out = String::new();
+    let entries = properties(&resolved);
+    if entries.is_empty() && resolved.eq.is_none() {
+        out.push_str("{}\n");
+    } else {
+        write_map(&mut out, &resolved, &entries, 0);
+    }
+    out
+}
+
+/// The properties to write: data nodes that are not deleted.
+fn properties(node: &MOTLYDataNode) -> Vec<(&String, &MOTLYDataNode)> {
+    node.properties
+        .iter()
+        .flatten()
+        .filter_map(|(key, child)| child.as_data_node().filter(|c| !c.deleted).map(|c| (key, c)))
+        .collect()
+}
+
+/// The `$value` entry, if any, then the properties. The first line is
+/// written where `out` is; the others are indented by `indent`.
+fn write_map(out: &mut String, node: &MOTLYDataNode, entries: &[(&String, &MOTLYDataNode)], indent: usize) {
+    let value = node.eq.clone().map(|eq| MOTLYDataNode { eq: Some(eq), ..MOTLYDataNode::new() });
+    let value_entry = value.as_ref().map(|value| (VALUE_KEY, value));
+    let all = value_entry.into_iter().chain(entries.iter().map(|(key, child)| (key.as_str(), *child)));
+    for (i, (key, child)) in all.enumerate() {
+        if i > 0 {
+            out.push_str(&" ".repeat(indent));
+        }
+        out.push_str(&string(key));
+        out.push(':');
+        write_value(out, child, indent);
+    }
+}
+
+/// The part after `key:` or `-`, including the newline. `indent` is that
+/// of the line the key or dash is on.
+fn write_value(out: &mut String, node: &MOTLYDataNode, indent: usize) {
+    let entries = properties(node);
+    if node.properties.is_some() {
+        if entries.is_empty() && node.eq.is_none() {
+            out.push_str(" {}\n");
+        } else {
+            out.push('\n');
+            out.push_str(&" ".repeat(indent + 2));
+            write_map(out, node, &entries, indent + 2);
+        }
+        return;
+    }
+    match &node.eq {
+        Some(EqValue::Array(items)) if items.is_empty() => out.push_str(" []\n"),
+        Some(EqValue::Array(items)) => {
+            out.push('\n');
+            for item in items {
+                out.push_str(&" ".repeat(indent + 2));
+                out.push('-');
+                match item.as_data_node() {
+                    // A mapping starts on the dash's line
+                    Some(data) if data.properties.is_some() && !properties(data).is_empty() => {
+                        out.push(' ');
+                        write_map(out, data, &properties(data), indent + 4);
+                    }
+                    Some(data) => write_value(out, data, indent + 2),
+                    None => out.push_str(" null\n"),
+                }
+            }
+        }
+        eq => out.push_str(&format!(" {}\n", scalar(eq.as_ref()))),
+    }
+}
+
+fn scalar(eq: Option<&EqValue>) -> String {
+    match eq {
+        None => "null".to_string(),
+        Some(EqValue::Scalar(Scalar::String(s))) => string(s),
+        Some(EqValue::Scalar(Scalar::Number(n))) if n.is_nan() => ".nan".to_string(),
+        Some(EqValue::Scalar(Scalar::Number(n))) if n.is_infinite() => if *n > 0.0 { ".inf" } else { "-.inf" }.to_string(),
+        Some(EqValue::Scalar(Scalar::Number(n))) => n.to_string(),
+        Some(EqValue::Scalar(Scalar::Boolean(b))) => b.to_string(),
+        Some(EqValue::Scalar(Scalar::Date(d))) => d.clone(),
+        Some(EqValue::EnvRef(name)) => format!("!env {}", string(name)),
+        Some(EqValue::Array(_)) => unreachable!("arrays are written as sequences"),
+    }
+}
+
+/// A string as a YAML scalar: plain when every YAML reader gets the same
+/// string back, double-quoted otherwise.
+fn string(s: &str) -> String {
+    let plain = !s.is_empty()
+        && s.chars().all(|c| c.is_alphanumeric() || "_-./ ".contains(c))
+        && !s.starts_with(['-', ' ', '.'])
+        && !s.ends_with(' ')
+        && matches!(Yaml::from_str(s), Yaml::String(_))
+        && !["y", "n", "yes", "no", "on", "off"].contains(&s.to_ascii_lowercase().as_str())
+        && parser::parse_date(s).is_none();
+    if plain {
+        return s.to_string();
+    }
+    let mut out = String::from("\"");
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\t' => out.push_str("\\t"),
+            c if c.is_control() => out.push_str(&format!("\\u{:04x}", c as u32)),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    out
+}